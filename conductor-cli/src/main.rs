@@ -1,19 +1,35 @@
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr};
+use std::io::Read;
+use std::net::IpAddr;
 
-use chrono::{offset::Utc, Duration};
+use chrono::{offset::Utc, DateTime, NaiveDate, TimeZone};
 use clap::App;
-use rand::{thread_rng, Rng};
+use serde_json::Value;
 
-use conductor::{self, AccountInfo};
+use conductor::{auth, AccountInfo, AccountStatus, Database, SqliteDatabase, DEFAULT_DATABASE_PATH};
+use conductor::account::{AccountFilter, AccountPage};
+use conductor::app_version::VersionPolicy;
+use conductor::client::{AdminClient, Credentials, DEFAULT_ADMIN_URL};
+use conductor::report::{self, ProblemReport, ReportFilter, ReportPage};
+use conductor::wireguard::WireguardKey;
 
 pub const COMMIT_ID: &str = include_str!(concat!(env!("OUT_DIR"), "/git-commit-id.txt"));
 
 pub const COMMIT_DATE: &str = include_str!(concat!(env!("OUT_DIR"), "/git-commit-date.txt"));
 
+trait Command {
+    fn name(&self) -> &'static str;
+
+    fn clap_subcommand(&self) -> App<'static, 'static>;
+
+    /// `global` holds the arguments given before the subcommand.
+    fn run(&self, global: &clap::ArgMatches<'_>, matches: &clap::ArgMatches<'_>);
+}
+
+/// Manages accounts through the admin API of a conductor-daemon.
 struct Account;
 
-impl Account {
+impl Command for Account {
     fn name(&self) -> &'static str {
         "account"
     }
@@ -53,13 +69,87 @@ impl Account {
                             .required(true),
                     ))
             )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List accounts")
+                    .args(&vec!(
+                        clap::Arg::with_name("status")
+                            .help("Only list accounts with this status")
+                            .long("status")
+                            .takes_value(true)
+                            .possible_values(&["unused", "active", "expired", "suspended"]),
+                        clap::Arg::with_name("expires-before")
+                            .help("Only list accounts expiring before this date (YYYY-MM-DD or RFC 3339)")
+                            .long("expires-before")
+                            .takes_value(true),
+                        clap::Arg::with_name("expires-after")
+                            .help("Only list accounts expiring after this date (YYYY-MM-DD or RFC 3339)")
+                            .long("expires-after")
+                            .takes_value(true),
+                        clap::Arg::with_name("search")
+                            .help("Only list accounts starting with this number")
+                            .long("search")
+                            .takes_value(true),
+                        clap::Arg::with_name("offset")
+                            .help("Number of matching accounts to skip")
+                            .long("offset")
+                            .takes_value(true)
+                            .default_value("0"),
+                        clap::Arg::with_name("limit")
+                            .help("Maximum number of accounts to list, at most 1000")
+                            .long("limit")
+                            .takes_value(true)
+                            .default_value("100"),
+                    ))
+            )
+            .subcommand(
+                clap::SubCommand::with_name("show")
+                    .about("Show account information")
+                    .arg(
+                        clap::Arg::with_name("account")
+                            .help("The Mullvad account")
+                            .required(true),
+                    )
+            )
+            .subcommand(
+                clap::SubCommand::with_name("suspend")
+                    .about("Suspend account")
+                    .arg(
+                        clap::Arg::with_name("account")
+                            .help("The Mullvad account")
+                            .required(true),
+                    )
+            )
+            .subcommand(
+                clap::SubCommand::with_name("reactivate")
+                    .about("Lift the suspension of an account")
+                    .arg(
+                        clap::Arg::with_name("account")
+                            .help("The Mullvad account")
+                            .required(true),
+                    )
+            )
+            .subcommand(
+                clap::SubCommand::with_name("revoke-key")
+                    .about("Revoke a WireGuard key of an account and free its addresses")
+                    .args(&vec!(
+                        clap::Arg::with_name("account")
+                            .help("The Mullvad account")
+                            .required(true),
+                        clap::Arg::with_name("public_key")
+                            .help("The base64 encoded public key")
+                            .required(true),
+                    ))
+            )
     }
 
-    fn run(&self, matches: &clap::ArgMatches<'_>) {
+    fn run(&self, global: &clap::ArgMatches<'_>, matches: &clap::ArgMatches<'_>) {
+        let client = admin_client(global);
+
         if let Some(set_matches) = matches.subcommand_matches("create") {
             if let Some(days_str) = set_matches.value_of("days") {
-                if let Ok(days) = days_str.parse() {
-                    self.create_account(days);
+                if days_str.parse::<i64>().is_ok() {
+                    self.create_account(&client, days_str);
                 }
             }
         }
@@ -67,8 +157,8 @@ impl Account {
         if let Some(set_matches) = matches.subcommand_matches("update") {
             if let Some(account) = set_matches.value_of("account") {
                 if let Some(days_str) = set_matches.value_of("days") {
-                    if let Ok(days) = days_str.parse() {
-                        self.update_account(account, days);
+                    if days_str.parse::<i64>().is_ok() {
+                        self.update_account(&client, account, days_str);
                     }
                 }
             }
@@ -76,73 +166,904 @@ impl Account {
 
         if let Some(set_matches) = matches.subcommand_matches("remove") {
             if let Some(account) = set_matches.value_of("account") {
-                self.remove_account(account);
-            }
-        }
-    }
-
-    fn create_account(&self, days: i64) {
-        let mut rng = thread_rng();
-        let db = conductor::Database::new();
-        let mut account;
-        loop {
-            let vip_8 = rng.gen_range(67_837_953, 84_549_373).to_string();
-            let random_head = rng.gen_range(1_000, 9_999).to_string();
-            account = random_head + &vip_8;
-            match db.account_select(&account) {
-                Ok(_) => (),
-                Err(conductor::DbError::NoAccount) => break,
-                Err(e) => {
-                    println!("{:?}", e);
-                    return;
-                },
-            }
-        }
-        let expiry = Utc::now() + Duration::days(days);
-        let mut num: u64 = account.parse().unwrap();
-        num = num % 10_000_0000 + 100_000_000;
-        let vip_v4 = Ipv4Addr::from(num as u32);
-        let vip = IpAddr::from(vip_v4);
-        let info = AccountInfo {
-            expiry,
-            status:     "unused".to_string(),
-            vip,
-        };
-        match db.account_insert(&account, &info) {
-            Ok(_) => println!("{}", account),
-            Err(e) => println!("{:?}", e),
+                self.remove_account(&client, account);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("list") {
+            match parse_filter(set_matches) {
+                Ok(filter) => self.list_accounts(&client, &filter),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("show") {
+            if let Some(account) = set_matches.value_of("account") {
+                self.show_account(&client, account);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("suspend") {
+            if let Some(account) = set_matches.value_of("account") {
+                self.set_status(&client, "account_suspend", account);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("reactivate") {
+            if let Some(account) = set_matches.value_of("account") {
+                self.set_status(&client, "account_reactivate", account);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("revoke-key") {
+            if let (Some(account), Some(key)) =
+                (set_matches.value_of("account"), set_matches.value_of("public_key"))
+            {
+                self.revoke_key(&client, account, key);
+            }
+        }
+    }
+}
+
+impl Account {
+    fn create_account(&self, client: &AdminClient, days: &str) {
+        match client.call::<String>("account_create", vec![Value::from(days)]) {
+            Ok(account) => println!("{}", account),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn update_account(&self, client: &AdminClient, account: &str, days: &str) {
+        match client.call::<()>("account_update", vec![Value::from(account), Value::from(days)]) {
+            Ok(_) => println!("update account:{}", account),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn remove_account(&self, client: &AdminClient, account: &str) {
+        match client.call::<()>("account_remove", vec![Value::from(account)]) {
+            Ok(_) => println!("remove account:{}", account),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn list_accounts(&self, client: &AdminClient, filter: &AccountFilter) {
+        let params = vec![serde_json::to_value(filter).unwrap()];
+        match client.call::<AccountPage>("account_list", params) {
+            Ok(page) => {
+                for entry in &page.accounts {
+                    print_account(&entry.account, &entry.info);
+                }
+                println!(
+                    "Showing {} of {} accounts, starting at {}",
+                    page.accounts.len(),
+                    page.total,
+                    page.offset,
+                );
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn show_account(&self, client: &AdminClient, account: &str) {
+        match client.call::<AccountInfo>("account_show", vec![Value::from(account)]) {
+            Ok(info) => print_account(account, &info),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+        match client.call::<Vec<(String, IpAddr)>>("lease_list", vec![Value::from(account)]) {
+            Ok(leases) => {
+                for (pool, ip) in leases {
+                    println!("lease {:<10} {}", pool, ip);
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+        match client.call::<Vec<WireguardKey>>("wg_key_list", vec![Value::from(account)]) {
+            Ok(keys) => {
+                for key in keys {
+                    println!(
+                        "wireguard key {} {} {} created {}",
+                        key.public_key,
+                        key.addresses.ipv4_address,
+                        key.addresses.ipv6_address,
+                        key.created.to_rfc3339(),
+                    );
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn revoke_key(&self, client: &AdminClient, account: &str, key: &str) {
+        let params = vec![Value::from(account), Value::from(key)];
+        match client.call::<()>("wg_key_revoke", params) {
+            Ok(_) => println!("revoke key:{}", key),
+            Err(e) => eprintln!("{}", e),
         }
     }
 
-    fn update_account(&self, account:&str, days: i64) {
-        let db = conductor::Database::new();
-        if let Ok(mut info) = db.account_select(account) {
-            info.expiry = info.expiry + Duration::days(days);
-            if let Ok(_) = db.account_insert(&account, &info) {
-                println!("account:{}\naccount status:{:?}", account, info);
+    fn set_status(&self, client: &AdminClient, method: &str, account: &str) {
+        match client.call::<AccountInfo>(method, vec![Value::from(account)]) {
+            Ok(info) => print_account(account, &info),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// Manages the relay list served to clients.
+struct Relay;
+
+impl Command for Relay {
+    fn name(&self) -> &'static str {
+        "relay"
+    }
+
+    fn clap_subcommand(&self) -> App<'static, 'static> {
+        let code = |help: &'static str| clap::Arg::with_name("code").help(help).required(true);
+        let name = |help: &'static str| clap::Arg::with_name("name").help(help).required(true);
+        let country = clap::Arg::with_name("country")
+            .help("Two letter country code")
+            .required(true);
+        let file = clap::Arg::with_name("file")
+            .help("JSON file with the relay, or - to read standard input")
+            .required(true);
+
+        clap::SubCommand::with_name(self.name())
+            .about("Manage the relay list")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("show").about("Show the relay list"))
+            .subcommand(
+                clap::SubCommand::with_name("import")
+                    .about("Replace the relay list")
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("JSON file with the relay list, or - to read standard input")
+                            .required(true),
+                    )
+            )
+            .subcommand(
+                clap::SubCommand::with_name("country")
+                    .about("Manage countries")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Add a country")
+                            .args(&[code("Two letter country code"), name("Country name")])
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("update")
+                            .about("Rename a country")
+                            .args(&[code("Two letter country code"), name("Country name")])
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove")
+                            .about("Remove a country with all its cities and relays")
+                            .arg(code("Two letter country code"))
+                    )
+            )
+            .subcommand(
+                clap::SubCommand::with_name("city")
+                    .about("Manage cities")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Add a city")
+                            .setting(clap::AppSettings::AllowNegativeNumbers)
+                            .args(&[
+                                country.clone(),
+                                code("Three letter city code"),
+                                name("City name"),
+                                clap::Arg::with_name("latitude").required(true),
+                                clap::Arg::with_name("longitude").required(true),
+                            ])
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("update")
+                            .about("Change the name or position of a city")
+                            .setting(clap::AppSettings::AllowNegativeNumbers)
+                            .args(&[
+                                country.clone(),
+                                code("Three letter city code"),
+                                clap::Arg::with_name("name").long("name").takes_value(true),
+                                clap::Arg::with_name("latitude")
+                                    .long("latitude")
+                                    .takes_value(true)
+                                    .allow_hyphen_values(true),
+                                clap::Arg::with_name("longitude")
+                                    .long("longitude")
+                                    .takes_value(true)
+                                    .allow_hyphen_values(true),
+                            ])
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove")
+                            .about("Remove a city with all its relays")
+                            .args(&[country.clone(), code("Three letter city code")])
+                    )
+            )
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Add a relay to a city")
+                    .args(&[
+                        country,
+                        clap::Arg::with_name("city")
+                            .help("Three letter city code")
+                            .required(true),
+                        file.clone(),
+                    ])
+            )
+            .subcommand(
+                clap::SubCommand::with_name("update")
+                    .about("Replace the relay with the same hostname")
+                    .arg(file)
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove a relay")
+                    .arg(
+                        clap::Arg::with_name("hostname")
+                            .help("Hostname of the relay")
+                            .required(true),
+                    )
+            )
+    }
+
+    fn run(&self, global: &clap::ArgMatches<'_>, matches: &clap::ArgMatches<'_>) {
+        let client = admin_client(global);
+
+        if matches.subcommand_matches("show").is_some() {
+            match client.call::<Value>("relay_list_show", vec![]) {
+                Ok(version) => {
+                    println!("{}", serde_json::to_string_pretty(&version["relay_list"]).unwrap());
+                    println!("etag: {}", version["etag"].as_str().unwrap_or(""));
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("import") {
+            if let Some(relay_list) = read_json(set_matches.value_of("file").unwrap()) {
+                update_relay_list(&client, "relay_list_replace", vec![relay_list]);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("country") {
+            let (method, country_matches) = match set_matches.subcommand() {
+                ("add", Some(m)) => ("relay_country_add", m),
+                ("update", Some(m)) => ("relay_country_update", m),
+                ("remove", Some(m)) => ("relay_country_remove", m),
+                _ => return,
             };
+            update_relay_list(&client, method, string_params(country_matches, &["code", "name"]));
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("city") {
+            let (method, city_matches) = match set_matches.subcommand() {
+                ("add", Some(m)) => ("relay_city_add", m),
+                ("update", Some(m)) => ("relay_city_update", m),
+                ("remove", Some(m)) => ("relay_city_remove", m),
+                _ => return,
+            };
+            let mut params = string_params(city_matches, &["country", "code", "name"]);
+            for coordinate in &["latitude", "longitude"] {
+                match city_matches.value_of(coordinate).map(str::parse::<f64>) {
+                    Some(Ok(value)) => params.push(Value::from(value)),
+                    Some(Err(_)) => {
+                        eprintln!("Invalid {}", coordinate);
+                        return;
+                    }
+                    None => params.push(Value::Null),
+                }
+            }
+            while params.last() == Some(&Value::Null) {
+                params.pop();
+            }
+            update_relay_list(&client, method, params);
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("add") {
+            if let Some(relay) = read_json(set_matches.value_of("file").unwrap()) {
+                let mut params = string_params(set_matches, &["country", "city"]);
+                params.push(relay);
+                update_relay_list(&client, "relay_add", params);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("update") {
+            if let Some(relay) = read_json(set_matches.value_of("file").unwrap()) {
+                update_relay_list(&client, "relay_update", vec![relay]);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("remove") {
+            update_relay_list(&client, "relay_remove", string_params(set_matches, &["hostname"]));
         }
     }
+}
+
+/// Calls a method changing the relay list and prints the ETag of the new list.
+fn update_relay_list(client: &AdminClient, method: &str, params: Vec<Value>) {
+    match client.call::<String>(method, params) {
+        Ok(etag) => println!("relay list etag: {}", etag),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Values of the given arguments, with absent ones as null.
+fn string_params(matches: &clap::ArgMatches<'_>, names: &[&str]) -> Vec<Value> {
+    names
+        .iter()
+        .map(|name| matches.value_of(name).map(Value::from).unwrap_or(Value::Null))
+        .collect()
+}
+
+/// Reads a JSON document from `path`, or from standard input if it is `-`.
+fn read_json(path: &str) -> Option<Value> {
+    let content = if path == "-" {
+        let mut content = String::new();
+        std::io::stdin().read_to_string(&mut content).map(|_| content)
+    } else {
+        std::fs::read_to_string(path)
+    };
+    let content = match content {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", path, e);
+            return None;
+        }
+    };
+    match serde_json::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            eprintln!("Invalid JSON in {}: {}", path, e);
+            None
+        }
+    }
+}
 
-    fn remove_account(&self, account:&str) {
-        let db = conductor::Database::new();
-        if let Ok(_) = db.account_delete(&account) {
-            println!("remove account:{}", account);
+/// Browses the problem reports sent by clients.
+struct Report;
+
+impl Command for Report {
+    fn name(&self) -> &'static str {
+        "report"
+    }
+
+    fn clap_subcommand(&self) -> App<'static, 'static> {
+        let id = clap::Arg::with_name("id")
+            .help("Id of the report")
+            .required(true);
+
+        clap::SubCommand::with_name(self.name())
+            .about("Browse problem reports")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List reports, newest first")
+                    .args(&vec!(
+                        clap::Arg::with_name("account")
+                            .help("Only list reports attached to this account")
+                            .long("account")
+                            .takes_value(true),
+                        clap::Arg::with_name("client")
+                            .help("Only list reports sent from this address")
+                            .long("client")
+                            .takes_value(true),
+                        clap::Arg::with_name("received-after")
+                            .help("Only list reports received after this date (YYYY-MM-DD or RFC 3339)")
+                            .long("received-after")
+                            .takes_value(true),
+                        clap::Arg::with_name("received-before")
+                            .help("Only list reports received before this date (YYYY-MM-DD or RFC 3339)")
+                            .long("received-before")
+                            .takes_value(true),
+                        clap::Arg::with_name("offset")
+                            .help("Number of matching reports to skip")
+                            .long("offset")
+                            .takes_value(true)
+                            .default_value("0"),
+                        clap::Arg::with_name("limit")
+                            .help("Maximum number of reports to list")
+                            .long("limit")
+                            .takes_value(true)
+                            .default_value("100"),
+                    ))
+            )
+            .subcommand(
+                clap::SubCommand::with_name("show")
+                    .about("Show the metadata and message of a report")
+                    .arg(id.clone())
+            )
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about("Write a report in the format of the app's problem report files")
+                    .args(&vec!(
+                        id.clone(),
+                        clap::Arg::with_name("output")
+                            .help("File to write the report to, instead of standard output")
+                            .long("output")
+                            .short("o")
+                            .takes_value(true),
+                    ))
+            )
+            .subcommand(
+                clap::SubCommand::with_name("attach")
+                    .about("Attach a report to an account")
+                    .args(&vec!(
+                        id.clone(),
+                        clap::Arg::with_name("account")
+                            .help("The Mullvad account")
+                            .required(true),
+                    ))
+            )
+            .subcommand(
+                clap::SubCommand::with_name("detach")
+                    .about("Detach a report from its account")
+                    .arg(id)
+            )
+    }
+
+    fn run(&self, global: &clap::ArgMatches<'_>, matches: &clap::ArgMatches<'_>) {
+        let client = admin_client(global);
+
+        if let Some(set_matches) = matches.subcommand_matches("list") {
+            match parse_report_filter(set_matches) {
+                Ok(filter) => self.list_reports(&client, &filter),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("show") {
+            if let Some(id) = parse_id(set_matches) {
+                self.show_report(&client, id);
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("export") {
+            if let Some(id) = parse_id(set_matches) {
+                self.export_report(&client, id, set_matches.value_of("output"));
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("attach") {
+            if let Some(id) = parse_id(set_matches) {
+                self.attach_report(&client, id, set_matches.value_of("account"));
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("detach") {
+            if let Some(id) = parse_id(set_matches) {
+                self.attach_report(&client, id, None);
+            }
+        }
+    }
+}
+
+impl Report {
+    fn list_reports(&self, client: &AdminClient, filter: &ReportFilter) {
+        let params = vec![serde_json::to_value(filter).unwrap()];
+        match client.call::<ReportPage>("report_list", params) {
+            Ok(page) => {
+                for summary in &page.reports {
+                    println!(
+                        "{:<8} {} {:<16} {:<16} {}",
+                        summary.id,
+                        summary.received.to_rfc3339(),
+                        summary.client,
+                        summary.account.as_ref().map(String::as_str).unwrap_or("-"),
+                        summary.email,
+                    );
+                }
+                println!(
+                    "Showing {} of {} reports, starting at {}",
+                    page.reports.len(),
+                    page.total,
+                    page.offset,
+                );
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn fetch_report(&self, client: &AdminClient, id: i64) -> Option<ProblemReport> {
+        match client.call::<ProblemReport>("report_show", vec![Value::from(id)]) {
+            Ok(problem_report) => Some(problem_report),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        }
+    }
+
+    fn show_report(&self, client: &AdminClient, id: i64) {
+        let problem_report = match self.fetch_report(client, id) {
+            Some(problem_report) => problem_report,
+            None => return,
+        };
+        let summary = &problem_report.summary;
+        println!("id:       {}", summary.id);
+        println!("received: {}", summary.received.to_rfc3339());
+        println!("client:   {}", summary.client);
+        println!("account:  {}", summary.account.as_ref().map(String::as_str).unwrap_or("-"));
+        println!("email:    {}", summary.email);
+        println!("log size: {} bytes", summary.log_size);
+        for (key, value) in &summary.metadata {
+            println!("metadata {}: {}", key, value);
+        }
+        println!();
+        println!("{}", problem_report.message);
+    }
+
+    fn export_report(&self, client: &AdminClient, id: i64, output: Option<&str>) {
+        let problem_report = match self.fetch_report(client, id) {
+            Some(problem_report) => problem_report,
+            None => return,
         };
+        let text = report::render(&problem_report);
+        match output {
+            Some(path) => {
+                if let Err(e) = std::fs::write(path, text) {
+                    eprintln!("Unable to write {}: {}", path, e);
+                }
+            }
+            None => print!("{}", text),
+        }
+    }
+
+    fn attach_report(&self, client: &AdminClient, id: i64, account: Option<&str>) {
+        let params = vec![Value::from(id), account.map(Value::from).unwrap_or(Value::Null)];
+        match client.call::<()>("report_attach", params) {
+            Ok(_) => match account {
+                Some(account) => println!("attach report {} to account:{}", id, account),
+                None => println!("detach report {}", id),
+            },
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// Manages the app version policy answered to `app_version_check`.
+struct AppVersion;
+
+impl Command for AppVersion {
+    fn name(&self) -> &'static str {
+        "version"
+    }
+
+    fn clap_subcommand(&self) -> App<'static, 'static> {
+        let platform = clap::Arg::with_name("platform")
+            .help("Platform sent by the clients, e.g. linux, macos, windows or android")
+            .required(true);
+        let version = clap::Arg::with_name("version")
+            .help("App version, e.g. 2019.8 or 2019.9-beta1")
+            .required(true);
+
+        clap::SubCommand::with_name(self.name())
+            .about("Manage the app version policy of each platform")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("list").about("List the policies"))
+            .subcommand(
+                clap::SubCommand::with_name("show")
+                    .about("Show the policy of a platform")
+                    .arg(platform.clone())
+            )
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Set the released versions of a platform, keeping its blocked versions")
+                    .args(&vec!(
+                        platform.clone(),
+                        clap::Arg::with_name("latest-stable")
+                            .help("Latest stable release")
+                            .required(true),
+                        clap::Arg::with_name("latest-beta")
+                            .help("Latest beta, offered when it is newer than the stable release")
+                            .long("latest-beta")
+                            .takes_value(true),
+                        clap::Arg::with_name("min-supported")
+                            .help("Oldest version reported as supported")
+                            .long("min-supported")
+                            .takes_value(true),
+                    ))
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove the policy of a platform, supporting every version")
+                    .arg(platform.clone())
+            )
+            .subcommand(
+                clap::SubCommand::with_name("block")
+                    .about("Report a version as unsupported")
+                    .args(&[platform.clone(), version.clone()])
+            )
+            .subcommand(
+                clap::SubCommand::with_name("unblock")
+                    .about("Stop reporting a blocked version as unsupported")
+                    .args(&[platform, version])
+            )
+    }
+
+    fn run(&self, global: &clap::ArgMatches<'_>, matches: &clap::ArgMatches<'_>) {
+        let client = admin_client(global);
+
+        if matches.subcommand_matches("list").is_some() {
+            match client.call::<Vec<(String, VersionPolicy)>>("version_policy_list", vec![]) {
+                Ok(policies) => {
+                    for (platform, policy) in policies {
+                        print_version_policy(&platform, &policy);
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("show") {
+            self.call_policy(&client, set_matches, "version_policy_show", &["platform"]);
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let names = ["platform", "latest-stable", "latest-beta", "min-supported"];
+            self.call_policy(&client, set_matches, "version_policy_set", &names);
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("remove") {
+            if let Some(platform) = set_matches.value_of("platform") {
+                match client.call::<()>("version_policy_remove", vec![Value::from(platform)]) {
+                    Ok(_) => println!("remove version policy:{}", platform),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("block") {
+            self.call_policy(&client, set_matches, "version_block", &["platform", "version"]);
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("unblock") {
+            self.call_policy(&client, set_matches, "version_unblock", &["platform", "version"]);
+        }
+    }
+}
+
+impl AppVersion {
+    /// Calls a method returning the policy of the platform, and prints it.
+    fn call_policy(
+        &self,
+        client: &AdminClient,
+        matches: &clap::ArgMatches<'_>,
+        method: &str,
+        names: &[&str],
+    ) {
+        match client.call::<VersionPolicy>(method, string_params(matches, names)) {
+            Ok(policy) => print_version_policy(matches.value_of("platform").unwrap(), &policy),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// Manages admin API tokens. Works on the database file directly, so it has to run on the
+/// conductor host.
+struct Token;
+
+impl Command for Token {
+    fn name(&self) -> &'static str {
+        "token"
+    }
+
+    fn clap_subcommand(&self) -> App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage admin API tokens in the local conductor database")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .arg(
+                clap::Arg::with_name("database")
+                    .help("Path of the conductor database")
+                    .long("database")
+                    .takes_value(true)
+                    .default_value(DEFAULT_DATABASE_PATH),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("create")
+                    .about("Create a token and print its secret")
+                    .arg(
+                        clap::Arg::with_name("name")
+                            .help("Name identifying the token holder")
+                            .required(true),
+                    )
+            )
+            .subcommand(clap::SubCommand::with_name("list").about("List tokens"))
+            .subcommand(
+                clap::SubCommand::with_name("revoke")
+                    .about("Revoke a token")
+                    .arg(
+                        clap::Arg::with_name("name")
+                            .help("Name of the token")
+                            .required(true),
+                    )
+            )
+    }
+
+    fn run(&self, _: &clap::ArgMatches<'_>, matches: &clap::ArgMatches<'_>) {
+        let db = open_database(matches.value_of("database").unwrap());
+
+        if let Some(set_matches) = matches.subcommand_matches("create") {
+            if let Some(name) = set_matches.value_of("name") {
+                match auth::create_token(&db, name) {
+                    Ok(token) => println!("{}", token),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        }
+
+        if matches.subcommand_matches("list").is_some() {
+            match db.admin_token_list() {
+                Ok(tokens) => {
+                    for token in tokens {
+                        println!("{:<20} {}", token.name, token.created.to_rfc3339());
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        if let Some(set_matches) = matches.subcommand_matches("revoke") {
+            if let Some(name) = set_matches.value_of("name") {
+                match db.admin_token_delete(name) {
+                    Ok(_) => println!("revoke token:{}", name),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        }
+    }
+}
+
+fn print_account(account: &str, info: &AccountInfo) {
+    println!("{:<16} {:<10} {} {}", account, info.status, info.expiry.to_rfc3339(), info.vip);
+}
+
+fn print_version_policy(platform: &str, policy: &VersionPolicy) {
+    println!(
+        "{:<10} stable {:<10} beta {:<16} min supported {:<10} blocked {}",
+        platform,
+        policy.latest_stable,
+        policy.latest_beta.as_ref().map(String::as_str).unwrap_or("-"),
+        policy.min_supported.as_ref().map(String::as_str).unwrap_or("-"),
+        policy.blocked.join(","),
+    );
+}
+
+fn parse_filter(matches: &clap::ArgMatches<'_>) -> Result<AccountFilter, String> {
+    let mut filter = AccountFilter::default();
+    if let Some(status) = matches.value_of("status") {
+        filter.status = Some(status.parse::<AccountStatus>()?);
+    }
+    if let Some(date) = matches.value_of("expires-before") {
+        filter.expires_before = Some(parse_date(date)?);
+    }
+    if let Some(date) = matches.value_of("expires-after") {
+        filter.expires_after = Some(parse_date(date)?);
+    }
+    filter.prefix = matches.value_of("search").map(str::to_string);
+    if let Some(offset) = matches.value_of("offset") {
+        filter.offset = offset.parse().map_err(|_| format!("Invalid offset \"{}\"", offset))?;
+    }
+    if let Some(limit) = matches.value_of("limit") {
+        filter.limit = limit.parse().map_err(|_| format!("Invalid limit \"{}\"", limit))?;
+    }
+    Ok(filter)
+}
+
+fn parse_report_filter(matches: &clap::ArgMatches<'_>) -> Result<ReportFilter, String> {
+    let mut filter = ReportFilter::default();
+    filter.account = matches.value_of("account").map(str::to_string);
+    filter.client = matches.value_of("client").map(str::to_string);
+    if let Some(date) = matches.value_of("received-after") {
+        filter.received_after = Some(parse_date(date)?);
+    }
+    if let Some(date) = matches.value_of("received-before") {
+        filter.received_before = Some(parse_date(date)?);
+    }
+    if let Some(offset) = matches.value_of("offset") {
+        filter.offset = offset.parse().map_err(|_| format!("Invalid offset \"{}\"", offset))?;
+    }
+    if let Some(limit) = matches.value_of("limit") {
+        filter.limit = limit.parse().map_err(|_| format!("Invalid limit \"{}\"", limit))?;
+    }
+    Ok(filter)
+}
+
+/// Parses the report id argument, printing an error if it isn't a number.
+fn parse_id(matches: &clap::ArgMatches<'_>) -> Option<i64> {
+    let id = matches.value_of("id")?;
+    match id.parse() {
+        Ok(id) => Some(id),
+        Err(_) => {
+            eprintln!("Invalid report id \"{}\"", id);
+            None
+        }
+    }
+}
+
+fn parse_date(date: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| Utc.from_utc_datetime(&date.and_hms(0, 0, 0)))
+        .map_err(|_| format!("Invalid date \"{}\"", date))
+}
+
+fn admin_client(global: &clap::ArgMatches<'_>) -> AdminClient {
+    let credentials = Credentials {
+        token:          global.value_of("token").map(str::to_string),
+        certificate:    global.value_of("cert").map(Into::into),
+        private_key:    global.value_of("key").map(Into::into),
+        ca:             global.value_of("ca").map(Into::into),
+    };
+    let url = global.value_of("url").unwrap();
+    match AdminClient::new(url, credentials) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Unable to set up conductor client for {}: {}", url, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn open_database(path: &str) -> SqliteDatabase {
+    match SqliteDatabase::open(path) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Unable to open database {}: {}", path, e);
+            std::process::exit(1);
+        }
     }
 }
 
 fn main() {
-    let mut commands = HashMap::new();
-    commands.insert(Account{}.name(), Account{});
+    let mut commands: HashMap<&'static str, Box<dyn Command>> = HashMap::new();
+    commands.insert(Account.name(), Box::new(Account));
+    commands.insert(AppVersion.name(), Box::new(AppVersion));
+    commands.insert(Relay.name(), Box::new(Relay));
+    commands.insert(Report.name(), Box::new(Report));
+    commands.insert(Token.name(), Box::new(Token));
     let matches =  App::new("conductor")
         .version(&format!("\nCommit date: {}\nCommit id: {}", COMMIT_DATE, COMMIT_ID).to_string()[..])
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .args(&[
+            clap::Arg::with_name("url")
+                .help("URL of the conductor admin API")
+                .long("url")
+                .env("CONDUCTOR_URL")
+                .takes_value(true)
+                .default_value(DEFAULT_ADMIN_URL),
+            clap::Arg::with_name("token")
+                .help("Admin API bearer token")
+                .long("token")
+                .env("CONDUCTOR_TOKEN")
+                .hide_env_values(true)
+                .takes_value(true),
+            clap::Arg::with_name("cert")
+                .help("Client certificate chain (PEM), for conductors requiring mutual TLS")
+                .long("cert")
+                .takes_value(true)
+                .requires("key"),
+            clap::Arg::with_name("key")
+                .help("Private key of the client certificate (PEM)")
+                .long("key")
+                .takes_value(true)
+                .requires("cert"),
+            clap::Arg::with_name("ca")
+                .help("CA certificates (PEM) to verify the conductor with")
+                .long("ca")
+                .takes_value(true),
+        ])
         .subcommands(commands.values().map(|cmd| cmd.clap_subcommand()))
         .get_matches();
 
     let (subcommand_name, subcommand_matches) = matches.subcommand();
     if let Some(cmd) = commands.get(subcommand_name) {
-        cmd.run(subcommand_matches.expect("No command matched"))
+        cmd.run(&matches, subcommand_matches.expect("No command matched"))
     }
 }
@@ -0,0 +1,182 @@
+//! Account lifecycle operations shared by conductor-daemon and conductor-cli.
+//!
+//! Status transitions are only ever made through the functions in this module, which check them
+//! against `AccountStatus::can_transition_to` inside a single database transaction.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use chrono::{offset::Utc, DateTime, Duration};
+use rand::{thread_rng, Rng};
+
+use crate::database::{Database, Error, Result};
+use crate::ipam::AddressPool;
+use crate::types::{AccountInfo, AccountStatus};
+
+/// Number of accounts returned by `list` when the filter doesn't specify a limit.
+pub const DEFAULT_PAGE_LIMIT: u64 = 100;
+/// Most accounts `list` returns at once, whatever the limit of the filter.
+pub const MAX_PAGE_LIMIT: u64 = 1000;
+
+/// Selects a page of accounts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountFilter {
+    /// Only include accounts with this status.
+    pub status:         Option<AccountStatus>,
+    /// Only include accounts expiring before this time.
+    pub expires_before: Option<DateTime<Utc>>,
+    /// Only include accounts expiring after this time.
+    pub expires_after:  Option<DateTime<Utc>>,
+    /// Only include accounts whose number starts with this string.
+    pub prefix:         Option<String>,
+    pub offset:         u64,
+    /// Capped at `MAX_PAGE_LIMIT`.
+    pub limit:          u64,
+}
+
+impl Default for AccountFilter {
+    fn default() -> Self {
+        AccountFilter {
+            status:         None,
+            expires_before: None,
+            expires_after:  None,
+            prefix:         None,
+            offset:         0,
+            limit:          DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AccountEntry {
+    pub account:    String,
+    pub info:       AccountInfo,
+}
+
+/// One page of a filtered account listing.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AccountPage {
+    /// Number of accounts matching the filter, across all pages.
+    pub total:      u64,
+    pub offset:     u64,
+    pub accounts:   Vec<AccountEntry>,
+}
+
+/// Creates a new unused account that is valid for `days` days, with the lowest free VIP of
+/// `pool`.
+pub fn create(db: &dyn Database, pool: &AddressPool, days: i64) -> Result<String> {
+    let mut rng = thread_rng();
+    let expiry = Utc::now() + Duration::days(days);
+    loop {
+        let account = rng.gen_range(100_000_000_000u64, 1_000_000_000_000).to_string();
+        // Not in any pool, so that the account gets a free address.
+        let info = AccountInfo {
+            expiry,
+            status:     AccountStatus::Unused,
+            vip:        IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        };
+        match db.account_insert(&account, &info, pool) {
+            Ok(_) => return Ok(account),
+            Err(Error::AccountExists) => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Moves the expiry of an account `days` days forward. An expired account whose new expiry lies
+/// in the future becomes active again.
+pub fn extend(db: &dyn Database, account: &str, days: i64) -> Result<AccountInfo> {
+    let now = Utc::now();
+    db.account_update(account, &mut |info| {
+        info.expiry = info.expiry + Duration::days(days);
+        refresh_expiry(info, now)
+    })
+}
+
+pub fn remove(db: &dyn Database, account: &str) -> Result<()> {
+    db.account_delete(account)
+}
+
+/// Returns the account with its status as of the current time. Nothing is written, the stored
+/// status catches up the next time the account is modified or used.
+pub fn show(db: &dyn Database, account: &str) -> Result<AccountInfo> {
+    let mut info = db.account_select(account)?;
+    refresh_expiry(&mut info, Utc::now())?;
+    Ok(info)
+}
+
+pub fn list(db: &dyn Database, filter: &AccountFilter) -> Result<AccountPage> {
+    db.account_expire_overdue(Utc::now())?;
+    let filter = AccountFilter {
+        limit: filter.limit.min(MAX_PAGE_LIMIT),
+        ..filter.clone()
+    };
+    db.account_search(&filter)
+}
+
+pub fn suspend(db: &dyn Database, account: &str) -> Result<AccountInfo> {
+    db.account_update(account, &mut |info| transition(info, AccountStatus::Suspended))
+}
+
+/// Lifts a suspension. The account becomes active, or expired if its expiry passed while it was
+/// suspended.
+pub fn reactivate(db: &dyn Database, account: &str) -> Result<AccountInfo> {
+    let now = Utc::now();
+    db.account_update(account, &mut |info| {
+        if info.status != AccountStatus::Suspended {
+            return Err(Error::InvalidStatusTransition(info.status, AccountStatus::Active));
+        }
+        let next = if info.is_expired(now) {
+            AccountStatus::Expired
+        } else {
+            AccountStatus::Active
+        };
+        transition(info, next)
+    })
+}
+
+/// Checks that a client may use the account, and marks unused accounts as active.
+pub fn activate(db: &dyn Database, account: &str) -> Result<AccountInfo> {
+    let now = Utc::now();
+    let mut refused = None;
+    let info = db.account_update(account, &mut |info| {
+        refresh_expiry(info, now)?;
+        match info.status {
+            AccountStatus::Suspended => refused = Some(Error::AccountSuspended),
+            AccountStatus::Expired => refused = Some(Error::AccountExpired),
+            AccountStatus::Unused => transition(info, AccountStatus::Active)?,
+            AccountStatus::Active => (),
+        }
+        Ok(())
+    })?;
+    match refused {
+        Some(e) => Err(e),
+        None => Ok(info),
+    }
+}
+
+/// Like `activate`, and guarantees that the returned VIP is leased to the account from `pool`.
+pub fn authorize(db: &dyn Database, pool: &AddressPool, account: &str) -> Result<AccountInfo> {
+    activate(db, account)?;
+    db.account_assign_vip(account, pool)
+}
+
+fn transition(info: &mut AccountInfo, next: AccountStatus) -> Result<()> {
+    if !info.status.can_transition_to(next) {
+        return Err(Error::InvalidStatusTransition(info.status, next));
+    }
+    info.status = next;
+    Ok(())
+}
+
+/// Moves the account between expired and non-expired states according to its expiry time.
+/// Suspended accounts keep their status.
+fn refresh_expiry(info: &mut AccountInfo, now: DateTime<Utc>) -> Result<()> {
+    match info.status {
+        AccountStatus::Unused | AccountStatus::Active if info.is_expired(now) => {
+            transition(info, AccountStatus::Expired)
+        }
+        AccountStatus::Expired if !info.is_expired(now) => transition(info, AccountStatus::Active),
+        _ => Ok(()),
+    }
+}
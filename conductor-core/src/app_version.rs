@@ -0,0 +1,297 @@
+//! Version policy answering `app_version_check`.
+//!
+//! App versions look like `2019.8` or `2019.8-beta1`, see `PRODUCT_VERSION` in mullvad-daemon.
+//! Each platform has its own policy, and clients on a platform without one are told that their
+//! version is supported and up to date.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use mullvad_types::version::AppVersionInfo;
+
+use crate::database::{Database, Error, Result};
+
+/// A parsed app version.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Version {
+    pub year:   u32,
+    pub number: u32,
+    /// Number of the beta, or `None` for a stable release.
+    pub beta:   Option<u32>,
+}
+
+impl Version {
+    pub fn is_beta(&self) -> bool {
+        self.beta.is_some()
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let invalid = || format!("Invalid app version \"{}\"", s);
+        let mut parts = s.splitn(2, '-');
+        let release = parts.next().unwrap_or("");
+        let beta = match parts.next() {
+            Some(suffix) if suffix.starts_with("beta") => {
+                Some(suffix["beta".len()..].parse().map_err(|_| invalid())?)
+            }
+            Some(_) => return Err(invalid()),
+            None => None,
+        };
+        let mut numbers = release.splitn(2, '.');
+        let year = numbers.next().unwrap_or("").parse().map_err(|_| invalid())?;
+        let number = numbers.next().unwrap_or("").parse().map_err(|_| invalid())?;
+        Ok(Version { year, number, beta })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.year, self.number)?;
+        if let Some(beta) = self.beta {
+            write!(f, "-beta{}", beta)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    /// Betas come before the stable release with the same number.
+    fn cmp(&self, other: &Self) -> Ordering {
+        let key = |v: &Version| (v.year, v.number, v.beta.unwrap_or(u32::max_value()));
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Release and support state of the app on one platform.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VersionPolicy {
+    pub latest_stable:  String,
+    /// Newest beta, offered to clients as the latest version when it is newer than
+    /// `latest_stable`.
+    pub latest_beta:    Option<String>,
+    /// Older versions are reported as unsupported.
+    pub min_supported:  Option<String>,
+    /// Versions reported as unsupported regardless of `min_supported`.
+    pub blocked:        Vec<String>,
+}
+
+impl VersionPolicy {
+    /// Answers `app_version_check` for a client running `current`. A version that can't be
+    /// parsed is only unsupported if it is blocked.
+    pub fn check(&self, current: &str) -> AppVersionInfo {
+        let parsed = parse_or_none(current);
+        let blocked = self.blocked.iter().any(|blocked| {
+            blocked == current || (parsed.is_some() && parse_or_none(blocked) == parsed)
+        });
+        let min_supported = self.min_supported.as_ref().and_then(|min| min.parse().ok());
+        let too_old = match (parsed, min_supported) {
+            (Some(current), Some(min)) => current < min,
+            _ => false,
+        };
+
+        let latest = match self.latest_beta {
+            Some(ref beta) if parse_or_none(beta) > parse_or_none(&self.latest_stable) => beta,
+            _ => &self.latest_stable,
+        };
+        AppVersionInfo {
+            current_is_supported: !blocked && !too_old,
+            latest_stable:        self.latest_stable.clone(),
+            latest:               latest.clone(),
+        }
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        let latest_stable: Version = self.latest_stable.parse()?;
+        if latest_stable.is_beta() {
+            return Err(format!("{} is not a stable release", latest_stable));
+        }
+        if let Some(ref beta) = self.latest_beta {
+            if !beta.parse::<Version>()?.is_beta() {
+                return Err(format!("{} is not a beta", beta));
+            }
+        }
+        if let Some(ref min) = self.min_supported {
+            if min.parse::<Version>()? > latest_stable {
+                return Err(format!(
+                    "The minimum supported version {} is newer than the latest stable version",
+                    min
+                ));
+            }
+        }
+        for blocked in &self.blocked {
+            if blocked.parse::<Version>()? == latest_stable {
+                return Err(format!("The latest stable version {} is blocked", blocked));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_or_none(version: &str) -> Option<Version> {
+    version.parse().ok()
+}
+
+/// Answers `app_version_check` for a client running `current` on `platform`.
+pub fn check(db: &dyn Database, platform: &str, current: &str) -> Result<AppVersionInfo> {
+    Ok(match db.version_policy_select(platform)? {
+        Some(policy) => policy.check(current),
+        None => AppVersionInfo {
+            current_is_supported: true,
+            latest_stable:        current.to_string(),
+            latest:               current.to_string(),
+        },
+    })
+}
+
+/// Returns all policies, ordered by platform.
+pub fn list(db: &dyn Database) -> Result<Vec<(String, VersionPolicy)>> {
+    db.version_policy_list()
+}
+
+pub fn show(db: &dyn Database, platform: &str) -> Result<VersionPolicy> {
+    db.version_policy_select(platform)?
+        .ok_or_else(|| Error::NoVersionPolicy(platform.to_string()))
+}
+
+/// Sets the released versions for `platform`, creating its policy if there is none. Blocked
+/// versions are kept.
+pub fn set_releases(
+    db: &dyn Database,
+    platform: &str,
+    latest_stable: &str,
+    latest_beta: Option<&str>,
+    min_supported: Option<&str>,
+) -> Result<VersionPolicy> {
+    if !is_platform(platform) {
+        return Err(Error::InvalidVersionPolicy(format!(
+            "Invalid platform \"{}\"",
+            platform
+        )));
+    }
+    modify(db, platform, &mut |policy| {
+        let blocked = policy.map(|policy| policy.blocked).unwrap_or_default();
+        Ok(VersionPolicy {
+            latest_stable: latest_stable.to_string(),
+            latest_beta: latest_beta.map(str::to_string),
+            min_supported: min_supported.map(str::to_string),
+            blocked,
+        })
+    })
+}
+
+/// Reports `version` as unsupported on `platform`.
+pub fn block(db: &dyn Database, platform: &str, version: &str) -> Result<VersionPolicy> {
+    modify(db, platform, &mut |policy| {
+        let mut policy = policy.ok_or_else(|| Error::NoVersionPolicy(platform.to_string()))?;
+        if !policy.blocked.iter().any(|blocked| blocked == version) {
+            policy.blocked.push(version.to_string());
+        }
+        Ok(policy)
+    })
+}
+
+pub fn unblock(db: &dyn Database, platform: &str, version: &str) -> Result<VersionPolicy> {
+    modify(db, platform, &mut |policy| {
+        let mut policy = policy.ok_or_else(|| Error::NoVersionPolicy(platform.to_string()))?;
+        let count = policy.blocked.len();
+        policy.blocked.retain(|blocked| blocked != version);
+        if policy.blocked.len() == count {
+            return Err(Error::InvalidVersionPolicy(format!("{} is not blocked", version)));
+        }
+        Ok(policy)
+    })
+}
+
+/// Removes the policy of `platform`, so that every version is supported again.
+pub fn remove(db: &dyn Database, platform: &str) -> Result<()> {
+    db.version_policy_delete(platform)
+}
+
+/// Runs `update` on the stored policy and validates the result before it is written back.
+fn modify(
+    db: &dyn Database,
+    platform: &str,
+    update: &mut dyn FnMut(Option<VersionPolicy>) -> Result<VersionPolicy>,
+) -> Result<VersionPolicy> {
+    db.version_policy_update(platform, &mut |policy| {
+        let policy = update(policy)?;
+        policy.validate().map_err(Error::InvalidVersionPolicy)?;
+        Ok(policy)
+    })
+}
+
+/// Platform names are sent by the clients, e.g. `linux` or `windows`.
+fn is_platform(platform: &str) -> bool {
+    !platform.is_empty() && platform.chars().all(|c| c.is_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> VersionPolicy {
+        VersionPolicy {
+            latest_stable: "2019.8".to_string(),
+            latest_beta: Some("2019.9-beta1".to_string()),
+            min_supported: Some("2019.5".to_string()),
+            blocked: vec!["2019.6".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let version: Version = "2019.8-beta2".parse().unwrap();
+        assert_eq!(version.beta, Some(2));
+        assert_eq!(version.to_string(), "2019.8-beta2");
+        assert!(version < "2019.8".parse().unwrap());
+        assert!("2019.10".parse::<Version>().unwrap() > "2019.9".parse().unwrap());
+        assert!("2019".parse::<Version>().is_err());
+        assert!("2019.8-dev".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = policy();
+        assert_eq!(policy.validate(), Ok(()));
+
+        let info = policy.check("2019.7");
+        assert!(info.current_is_supported);
+        assert_eq!(info.latest_stable, "2019.8");
+        assert_eq!(info.latest, "2019.9-beta1");
+
+        assert!(!policy.check("2019.6").current_is_supported);
+        assert!(!policy.check("2019.4").current_is_supported);
+        assert!(!policy.check("2019.5-beta3").current_is_supported);
+        assert!(policy.check("2019.5").current_is_supported);
+        assert!(policy.check("custom-build").current_is_supported);
+
+        let mut policy = policy;
+        policy.latest_beta = Some("2019.8-beta3".to_string());
+        assert_eq!(policy.check("2019.8").latest, "2019.8");
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut invalid = policy();
+        invalid.latest_stable = "2019.8-beta1".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = policy();
+        invalid.min_supported = Some("2019.9".to_string());
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = policy();
+        invalid.blocked.push("2019.8".to_string());
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! Bearer tokens for the admin API.
+//!
+//! A token is handed out once by `create_token`. Only its SHA-256 hash is kept in the database,
+//! so a copy of the database file can't be used to call admin methods.
+
+use chrono::{offset::Utc, DateTime};
+use rand::{thread_rng, Rng};
+
+use crate::database::{Database, Result};
+
+/// Number of random bytes in a token. Tokens are hex encoded, so twice as many characters long.
+const TOKEN_BYTES: usize = 32;
+
+/// A stored admin token, without the secret.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AdminToken {
+    pub name:       String,
+    pub created:    DateTime<Utc>,
+}
+
+/// Creates a new token called `name` and returns its secret. Fails with `Error::TokenExists` if
+/// the name is taken.
+pub fn create_token(db: &dyn Database, name: &str) -> Result<String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    thread_rng().fill(&mut bytes);
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    db.admin_token_insert(name, &hash_token(&token), Utc::now())?;
+    Ok(token)
+}
+
+/// Returns the name of the token with the secret `token`, if there is one.
+pub fn verify_token(db: &dyn Database, token: &str) -> Result<Option<String>> {
+    db.admin_token_lookup(&hash_token(token))
+}
+
+fn hash_token(token: &str) -> Vec<u8> {
+    openssl::sha::sha256(token.as_bytes()).to_vec()
+}
@@ -0,0 +1,248 @@
+//! Blocking client for the admin API of a remote conductor-daemon.
+//!
+//! Every call opens a new TLS connection and sends a single JSON-RPC request over HTTP/1.1.
+//! That is enough for conductor-cli, which makes one call per invocation.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::str;
+
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::convention::{self, ErrorData, Params};
+
+/// Address of the admin API of a conductor-daemon on the same host.
+pub const DEFAULT_ADMIN_URL: &str = "https://127.0.0.1:50072/admin/";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Invalid conductor URL {}", _0)]
+    InvalidUrl(String),
+    #[error(display = "Unable to set up TLS")]
+    TlsSetup(#[error(cause)] openssl::error::ErrorStack),
+    #[error(display = "Unable to connect to the conductor")]
+    Connect(#[error(cause)] io::Error),
+    #[error(display = "TLS handshake with the conductor failed: {}", _0)]
+    Handshake(String),
+    #[error(display = "Failed to communicate with the conductor")]
+    Io(#[error(cause)] io::Error),
+    #[error(display = "Invalid response from the conductor: {}", _0)]
+    InvalidResponse(String),
+    #[error(display = "The conductor rejected the admin credentials")]
+    Unauthorized,
+    #[error(display = "The conductor returned an error: {}", _0)]
+    Rpc(#[error(cause)] ErrorData),
+}
+
+/// How the client authenticates itself and the server.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    /// Bearer token created with `auth::create_token`.
+    pub token:          Option<String>,
+    /// Client certificate chain and key in PEM format, for servers requiring mutual TLS.
+    pub certificate:    Option<PathBuf>,
+    pub private_key:    Option<PathBuf>,
+    /// CA certificates the server certificate is checked against, instead of the system ones.
+    pub ca:             Option<PathBuf>,
+}
+
+pub struct AdminClient {
+    host:       String,
+    port:       u16,
+    path:       String,
+    connector:  SslConnector,
+    token:      Option<String>,
+}
+
+impl AdminClient {
+    pub fn new(url: &str, credentials: Credentials) -> Result<Self> {
+        let (host, port, path) = parse_url(url)?;
+
+        let mut builder = SslConnector::builder(SslMethod::tls()).map_err(Error::TlsSetup)?;
+        if let Some(ca) = &credentials.ca {
+            builder.set_ca_file(ca).map_err(Error::TlsSetup)?;
+        }
+        if let Some(certificate) = &credentials.certificate {
+            builder
+                .set_certificate_chain_file(certificate)
+                .map_err(Error::TlsSetup)?;
+        }
+        if let Some(private_key) = &credentials.private_key {
+            builder
+                .set_private_key_file(private_key, SslFiletype::PEM)
+                .map_err(Error::TlsSetup)?;
+            builder.check_private_key().map_err(Error::TlsSetup)?;
+        }
+
+        Ok(AdminClient {
+            host,
+            port,
+            path,
+            connector: builder.build(),
+            token: credentials.token,
+        })
+    }
+
+    /// Calls `method` and deserializes its result.
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: Vec<Value>) -> Result<T> {
+        let request = convention::Request {
+            jsonrpc: convention::JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params: Params::Array(params),
+            id: Some(Value::from(1)),
+        };
+        let (status, body) = self.post(&request.dump())?;
+        if status == 401 {
+            return Err(Error::Unauthorized);
+        }
+        let response: convention::Response = serde_json::from_slice(&body).map_err(|_| {
+            Error::InvalidResponse(format!("HTTP status {} without a JSON-RPC response", status))
+        })?;
+        let result = response.outcome.map_err(Error::Rpc)?;
+        serde_json::from_value(result)
+            .map_err(|e| Error::InvalidResponse(format!("Unexpected result: {}", e)))
+    }
+
+    /// Sends `body` in a POST request and returns the status code and body of the response.
+    fn post(&self, body: &str) -> Result<(u16, Vec<u8>)> {
+        let tcp = TcpStream::connect((&self.host[..], self.port)).map_err(Error::Connect)?;
+        let mut stream = self
+            .connector
+            .connect(&self.host, tcp)
+            .map_err(|e| Error::Handshake(e.to_string()))?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            self.port,
+            body.len()
+        );
+        if let Some(token) = &self.token {
+            request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).map_err(Error::Io)?;
+
+        let mut response = vec![];
+        let mut buf = [0u8; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => response.extend_from_slice(&buf[..n]),
+                // The server may close the connection without a TLS close_notify once the
+                // response has been sent.
+                Err(_) if !response.is_empty() => break,
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+        parse_response(&response)
+    }
+}
+
+/// Splits a `https://host[:port][/path]` URL.
+fn parse_url(url: &str) -> Result<(String, u16, String)> {
+    let invalid = || Error::InvalidUrl(url.to_string());
+    if !url.starts_with("https://") {
+        return Err(invalid());
+    }
+    let rest = &url["https://".len()..];
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rfind(':') {
+        Some(index) if !authority[index..].contains(']') => (
+            &authority[..index],
+            authority[index + 1..].parse().map_err(|_| invalid())?,
+        ),
+        _ => (authority, 443),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Parses an HTTP/1.1 response whose body is either delimited by `Content-Length` or by the end
+/// of the connection.
+fn parse_response(response: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let invalid = |message: &str| Error::InvalidResponse(message.to_string());
+    let header_end = find(response, b"\r\n\r\n").ok_or_else(|| invalid("Truncated header"))?;
+    let head = str::from_utf8(&response[..header_end]).map_err(|_| invalid("Invalid header"))?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| invalid("Invalid status line"))?;
+
+    let mut body = &response[header_end + 4..];
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().unwrap_or("").trim();
+        if name == "transfer-encoding" && value != "identity" {
+            return Err(invalid("Unsupported transfer encoding"));
+        }
+        if name == "content-length" {
+            let length: usize = value.parse().map_err(|_| invalid("Invalid content length"))?;
+            if body.len() < length {
+                return Err(invalid("Truncated body"));
+            }
+            body = &body[..length];
+        }
+    }
+    Ok((status, body.to_vec()))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("https://127.0.0.1:50072/admin/").unwrap(),
+            ("127.0.0.1".to_string(), 50072, "/admin/".to_string())
+        );
+        assert_eq!(
+            parse_url("https://conductor.example").unwrap(),
+            ("conductor.example".to_string(), 443, "/".to_string())
+        );
+        assert_eq!(
+            parse_url("https://[::1]:8443/admin/").unwrap(),
+            ("::1".to_string(), 8443, "/admin/".to_string())
+        );
+        assert!(parse_url("http://127.0.0.1/").is_err());
+        assert!(parse_url("https://host:port/").is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 2\r\n\r\n{}trailing";
+        let (status, body) = parse_response(response).unwrap();
+        assert_eq!(status, 401);
+        assert_eq!(body, b"{}");
+
+        let response = b"HTTP/1.1 200 OK\r\nconnection: close\r\n\r\n{\"id\":1}";
+        assert_eq!(parse_response(response).unwrap().1, b"{\"id\":1}".to_vec());
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n{}").is_err());
+    }
+}
@@ -3,8 +3,10 @@
 use std::error;
 use std::fmt;
 
+use serde::de::{Deserializer, DeserializeOwned, Error as DeError};
+use serde::ser::{SerializeStruct, Serializer};
 use serde_derive::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
 pub static JSONRPC_VERSION: &str = "2.0";
 
@@ -23,6 +25,7 @@ pub struct ErrorData {
     /// about the error. This may be omitted. The value of this member is
     /// defined by the Server (e.g. detailed error information, nested errors
     /// etc.).
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub data: Value,
 }
 
@@ -53,6 +56,13 @@ impl ErrorData {
         }
     }
 
+    /// An "Invalid params" error explaining what is wrong with them.
+    pub fn invalid_params(message: &str) -> Self {
+        let mut error = ErrorData::std(-32602);
+        error.data = Value::from(message);
+        error
+    }
+
     /// Prints out the value as JSON string.
     pub fn dump(&self) -> String {
         serde_json::to_string(self).expect("Should never failed")
@@ -79,59 +89,209 @@ pub struct Request {
 
     /// A Structured value that holds the parameter values to be used during the invocation of the method. This member
     /// MAY be omitted.
-    pub params: Vec<Value>,
+    #[serde(default)]
+    pub params: Params,
 
     /// An identifier established by the Client that MUST contain a String, Number, or NULL value if included. If it is
     /// not included it is assumed to be a notification. The value SHOULD normally not be Null [1] and Numbers SHOULD
     /// NOT contain fractional parts.
-    pub id: Value,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<Value>,
 }
 
 impl Request {
+    /// Returns whether the Client expects no Response.
+    pub fn is_notification(&self) -> bool {
+        self.id.is_none()
+    }
+
     /// Prints out the value as JSON string.
     pub fn dump(&self) -> String {
         serde_json::to_string(self).expect("Should never failed")
     }
 }
 
+/// If present, parameters for the rpc call MUST be provided as a Structured value. Either by-position through an
+/// Array or by-name through an Object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Params {
+    /// Values in the Server expected order.
+    Array(Vec<Value>),
+    /// Member names that match the Server expected parameter names.
+    Object(Map<String, Value>),
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::Array(vec![])
+    }
+}
+
+impl Params {
+    /// Deserializes the parameter at `index` when given by-position, or the member `name` when
+    /// given by-name. Missing and mistyped parameters are reported as "Invalid params".
+    pub fn get<T: DeserializeOwned>(&self, index: usize, name: &str) -> Result<T, ErrorData> {
+        match self.get_optional(index, name)? {
+            Some(value) => Ok(value),
+            None => Err(ErrorData::invalid_params(&format!("Missing parameter {}", name))),
+        }
+    }
+
+    /// Like `get`, but a missing or null parameter is returned as `None`.
+    pub fn get_optional<T: DeserializeOwned>(
+        &self,
+        index: usize,
+        name: &str,
+    ) -> Result<Option<T>, ErrorData> {
+        let value = match self {
+            Params::Array(values) => values.get(index),
+            Params::Object(members) => members.get(name),
+        };
+        match value {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => serde_json::from_value(value.clone()).map(Some).map_err(|e| {
+                ErrorData::invalid_params(&format!("Invalid parameter {}: {}", name, e))
+            }),
+        }
+    }
+}
+
 /// When a rpc call is made, the Server MUST reply with a Response, except for in the case of Notifications. The
 /// Response is expressed as a single JSON Object, with the following members:
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// - `jsonrpc`, a String specifying the version of the JSON-RPC protocol. MUST be exactly "2.0".
+/// - `result`, REQUIRED on success and MUST NOT exist if there was an error invoking the method.
+/// - `error`, REQUIRED on error and MUST NOT exist if there was no error triggered during invocation.
+/// - `id`, REQUIRED. It MUST be the same as the value of the id member in the Request Object. If there was an error
+///   in detecting the id in the Request object (e.g. Parse error/Invalid Request), it MUST be Null.
+///
+/// Holding the outcome as a `Result` makes it impossible to produce both or neither of `result` and `error`.
+#[derive(Debug)]
 pub struct Response {
-    /// A String specifying the version of the JSON-RPC protocol. MUST be exactly "2.0".
     pub jsonrpc: String,
-
-    /// This member is REQUIRED on success.
-    /// This member MUST NOT exist if there was an error invoking the method.
-    /// The value of this member is determined by the method invoked on the Server.
-    pub result: Value,
-
-    // This member is REQUIRED on error.
-    // This member MUST NOT exist if there was no error triggered during invocation.
-    // The value for this member MUST be an Object as defined in section 5.1.
-    pub error: Option<ErrorData>,
-
-    /// This member is REQUIRED.
-    /// It MUST be the same as the value of the id member in the Request Object.
-    /// If there was an error in detecting the id in the Request object (e.g. Parse error/Invalid Request),
-    /// it MUST be Null.
+    pub outcome: Result<Value, ErrorData>,
     pub id: Value,
 }
 
 impl Response {
+    pub fn new(id: Value, outcome: Result<Value, ErrorData>) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.into(),
+            outcome,
+            id,
+        }
+    }
+
+    /// A Response to a Request whose id could not be determined.
+    pub fn error(error: ErrorData) -> Self {
+        Self::new(Value::Null, Err(error))
+    }
+
     /// Prints out the value as JSON string.
     pub fn dump(&self) -> String {
         serde_json::to_string(self).expect("Should never failed")
     }
 }
 
-impl Default for Response {
-    fn default() -> Self {
-        Self {
-            jsonrpc: JSONRPC_VERSION.into(),
-            result: Value::Null,
-            error: None,
-            id: Value::Null,
+impl serde::Serialize for Response {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Response", 3)?;
+        state.serialize_field("jsonrpc", &self.jsonrpc)?;
+        match &self.outcome {
+            Ok(result) => state.serialize_field("result", result)?,
+            Err(error) => state.serialize_field("error", error)?,
         }
+        state.serialize_field("id", &self.id)?;
+        state.end()
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Response {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct RawResponse {
+            jsonrpc: String,
+            #[serde(default, deserialize_with = "deserialize_present")]
+            result: Option<Value>,
+            #[serde(default)]
+            error: Option<ErrorData>,
+            #[serde(default)]
+            id: Value,
+        }
+
+        let raw = <RawResponse as serde::Deserialize>::deserialize(deserializer)?;
+        let outcome = match (raw.result, raw.error) {
+            (Some(result), None) => Ok(result),
+            (None, Some(error)) => Err(error),
+            _ => {
+                return Err(D::Error::custom(
+                    "exactly one of result and error must be present",
+                ))
+            }
+        };
+        Ok(Response {
+            jsonrpc: raw.jsonrpc,
+            outcome,
+            id: raw.id,
+        })
+    }
+}
+
+/// Deserializes a member that is present as `Some`, even when its value is null, so that it can
+/// be told apart from a missing one.
+fn deserialize_present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    <Value as serde::Deserialize>::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id() {
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc": "2.0", "method": "m", "id": null}"#).unwrap();
+        assert_eq!(request.id, Some(Value::Null));
+        assert_eq!(request.params, Params::Array(vec![]));
+
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc": "2.0", "method": "m", "params": {"a": 1}}"#)
+                .unwrap();
+        assert!(request.is_notification());
+        assert_eq!(request.params.get::<u32>(0, "a").unwrap(), 1);
+
+        assert!(serde_json::from_str::<Request>(r#"{"jsonrpc": "2.0", "method": "m", "params": 1}"#)
+            .is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_params() {
+        let params = Params::Array(vec![Value::from("1234"), Value::Null]);
+        assert_eq!(params.get::<String>(0, "account").unwrap(), "1234");
+        assert_eq!(params.get_optional::<String>(1, "filter").unwrap(), None);
+        assert_eq!(params.get::<u32>(0, "account").unwrap_err().code, -32602);
+        assert_eq!(params.get::<String>(2, "days").unwrap_err().code, -32602);
+    }
+
+    #[test]
+    fn test_response_has_result_or_error() {
+        let response = Response::new(Value::from(1), Ok(Value::Null));
+        assert_eq!(response.dump(), r#"{"jsonrpc":"2.0","result":null,"id":1}"#);
+
+        let response = Response::error(ErrorData::std(-32700));
+        assert_eq!(
+            response.dump(),
+            r#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"Parse error"},"id":null}"#
+        );
+
+        let parsed: Response =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","result":null,"id":1}"#).unwrap();
+        assert_eq!(parsed.outcome.unwrap(), Value::Null);
+        assert!(serde_json::from_str::<Response>(r#"{"jsonrpc":"2.0","id":1}"#).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Schema versioning for the conductor database.
+//!
+//! The schema version is kept in SQLite's `user_version` header field. Each entry in
+//! `MIGRATIONS` upgrades the schema by exactly one version and runs inside the same transaction
+//! as the version bump, so a crash half way through a migration leaves the database untouched.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, TransactionBehavior, NO_PARAMS};
+use serde_json::Value;
+
+use mullvad_types::relay_list::RelayList;
+
+use super::{sqlite, Error, Result};
+use crate::types::AccountInfo;
+
+/// Account file written by the JSON backend used before the schema was introduced.
+const LEGACY_ACCOUNT_FILENAME: &str = "account.json";
+/// Relay list file served by `relay_list_v2` before the schema was introduced.
+const LEGACY_RELAY_FILENAME: &str = "relay.json";
+/// Suffix appended to legacy files once their content has been imported.
+const LEGACY_MIGRATED_SUFFIX: &str = ".migrated";
+
+type Migration = fn(&Connection, &Path, &mut Vec<PathBuf>) -> Result<()>;
+
+/// Migrations indexed by the version they upgrade from.
+const MIGRATIONS: &[Migration] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+];
+
+/// The schema version this build reads and writes. Must equal `MIGRATIONS.len()`.
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// Brings the schema up to `SCHEMA_VERSION`. Legacy JSON files found in `legacy_dir` are imported
+/// when a fresh database is created, and renamed afterwards so they are only imported once.
+pub fn run(conn: &mut Connection, legacy_dir: &Path) -> Result<()> {
+    let mut imported = vec![];
+    {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+        let version: u32 = tx.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(Error::UnsupportedSchema(version));
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::info!("Migrating conductor database from schema version {}", from);
+            migration(&tx, legacy_dir, &mut imported)?;
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+        tx.commit()?;
+    }
+
+    for path in imported {
+        let mut migrated = path.clone().into_os_string();
+        migrated.push(LEGACY_MIGRATED_SUFFIX);
+        if let Err(e) = fs::rename(&path, &migrated) {
+            log::warn!("Unable to rename imported file {}: {}", path.display(), e);
+        } else {
+            log::info!("Imported {} into the conductor database", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn migrate_v0_to_v1(
+    conn: &Connection,
+    legacy_dir: &Path,
+    imported: &mut Vec<PathBuf>,
+) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE accounts (
+            account     TEXT PRIMARY KEY NOT NULL,
+            expiry      INTEGER NOT NULL,
+            status      TEXT NOT NULL,
+            vip         TEXT NOT NULL
+        );
+        CREATE TABLE relay_list (
+            id          INTEGER PRIMARY KEY CHECK (id = 0),
+            data        TEXT NOT NULL
+        );",
+    )?;
+
+    let account_path = legacy_dir.join(LEGACY_ACCOUNT_FILENAME);
+    if let Some(accounts) = read_legacy_json::<HashMap<String, AccountInfo>>(&account_path)? {
+        for (account, info) in accounts.iter() {
+            sqlite::insert_account(conn, account, info)
+                .map_err(|e| Error::LegacyMigration(account_path.display().to_string(), Box::new(e)))?;
+        }
+        imported.push(account_path);
+    }
+
+    let relay_path = legacy_dir.join(LEGACY_RELAY_FILENAME);
+    if let Some(relay_list) = read_legacy_json::<RelayList>(&relay_path)? {
+        sqlite::write_relay_list(conn, &relay_list)
+            .map_err(|e| Error::LegacyMigration(relay_path.display().to_string(), Box::new(e)))?;
+        imported.push(relay_path);
+    }
+    Ok(())
+}
+
+/// Adds the lease table. VIPs of existing accounts were derived from the account number and are
+/// not recorded as leases; they are leased again the next time the account is used.
+fn migrate_v1_to_v2(conn: &Connection, _: &Path, _: &mut Vec<PathBuf>) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE leases (
+            address     BLOB PRIMARY KEY NOT NULL,
+            pool        TEXT NOT NULL,
+            account     TEXT NOT NULL
+        );
+        CREATE INDEX leases_account ON leases (account, pool);",
+    )?;
+    Ok(())
+}
+
+/// Adds the admin token table. Tokens are identified by the SHA-256 hash of their secret.
+fn migrate_v2_to_v3(conn: &Connection, _: &Path, _: &mut Vec<PathBuf>) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE admin_tokens (
+            name        TEXT PRIMARY KEY NOT NULL,
+            hash        BLOB NOT NULL UNIQUE,
+            created     INTEGER NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+/// Adds the WireGuard key table. The addresses of a key are also recorded as leases held by its
+/// account.
+fn migrate_v3_to_v4(conn: &Connection, _: &Path, _: &mut Vec<PathBuf>) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE wg_keys (
+            public_key  BLOB PRIMARY KEY NOT NULL,
+            account     TEXT NOT NULL,
+            ipv4        BLOB NOT NULL,
+            ipv6        BLOB NOT NULL,
+            created     INTEGER NOT NULL
+        );
+        CREATE INDEX wg_keys_account ON wg_keys (account);",
+    )?;
+    Ok(())
+}
+
+/// Adds the problem report table. Metadata is stored as a JSON object.
+fn migrate_v4_to_v5(conn: &Connection, _: &Path, _: &mut Vec<PathBuf>) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE problem_reports (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            received    INTEGER NOT NULL,
+            client      TEXT NOT NULL,
+            account     TEXT,
+            email       TEXT NOT NULL,
+            metadata    TEXT NOT NULL,
+            message     TEXT NOT NULL,
+            log         TEXT NOT NULL
+        );
+        CREATE INDEX problem_reports_client ON problem_reports (client, received);
+        CREATE INDEX problem_reports_account ON problem_reports (account);",
+    )?;
+    Ok(())
+}
+
+/// Adds the app version policy table. Blocked versions are stored as a JSON array.
+fn migrate_v5_to_v6(conn: &Connection, _: &Path, _: &mut Vec<PathBuf>) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE version_policies (
+            platform        TEXT PRIMARY KEY NOT NULL,
+            latest_stable   TEXT NOT NULL,
+            latest_beta     TEXT,
+            min_supported   TEXT,
+            blocked         TEXT NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+/// Reads a legacy JSON file. Missing files and the `{}` placeholder the old backend created on
+/// startup are treated as having nothing to import.
+fn read_legacy_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let wrap = |e| Error::LegacyMigration(path.display().to_string(), Box::new(e));
+
+    let buf = fs::read(path).map_err(|e| wrap(Error::IoError(e)))?;
+    let value: Value = serde_json::from_slice(&buf).map_err(|e| wrap(Error::JsonError(e)))?;
+    if value.as_object().map(|object| object.is_empty()).unwrap_or(false) {
+        return Ok(None);
+    }
+    serde_json::from_value(value)
+        .map(Some)
+        .map_err(|e| wrap(Error::JsonError(e)))
+}
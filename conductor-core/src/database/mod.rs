@@ -0,0 +1,215 @@
+use std::io;
+use std::net::IpAddr;
+
+use chrono::{offset::Utc, DateTime};
+use mullvad_types::relay_list::RelayList;
+use mullvad_types::wireguard::AssociatedAddresses;
+use talpid_types::net::wireguard::PublicKey;
+
+use crate::account::{AccountFilter, AccountPage};
+use crate::app_version::VersionPolicy;
+use crate::auth::AdminToken;
+use crate::ipam::AddressPool;
+use crate::relay::VersionedRelayList;
+use crate::report::{ProblemReport, ReportFilter, ReportPage};
+use crate::types::{AccountInfo, AccountStatus};
+use crate::wireguard::{WireguardConfig, WireguardKey};
+
+mod migration;
+mod sqlite;
+pub use self::sqlite::SqliteDatabase;
+
+/// Default location of the conductor database, relative to the working directory.
+pub const DEFAULT_DATABASE_PATH: &str = "./conductor.db";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "IO ERROR")]
+    IoError(#[error(cause)] io::Error),
+    #[error(display = "JSON ERROR")]
+    JsonError(#[error(cause)] serde_json::Error),
+    #[error(display = "Database error")]
+    SqliteError(#[error(cause)] rusqlite::Error),
+    #[error(display = "No such user.")]
+    NoAccount,
+    #[error(display = "Account already exists.")]
+    AccountExists,
+    #[error(display = "Account is suspended.")]
+    AccountSuspended,
+    #[error(display = "Account has expired.")]
+    AccountExpired,
+    #[error(display = "Account can not change status from {} to {}", _0, _1)]
+    InvalidStatusTransition(AccountStatus, AccountStatus),
+    #[error(display = "Address {} is leased to another account", _0)]
+    AddressInUse(IpAddr),
+    #[error(display = "No free address left in pool {}", _0)]
+    PoolExhausted(String),
+    #[error(display = "Pool {} has the wrong address family", _0)]
+    WrongAddressFamily(String),
+    #[error(display = "Too many WireGuard keys")]
+    TooManyWireguardKeys,
+    #[error(display = "WireGuard key is registered to another account")]
+    WireguardKeyInUse,
+    #[error(display = "No such WireGuard key.")]
+    NoWireguardKey,
+    #[error(display = "The {} of the problem report is too large", _0)]
+    ReportTooLarge(&'static str),
+    #[error(display = "Too many problem reports, try again later")]
+    ReportRateLimited,
+    #[error(display = "No such problem report.")]
+    NoReport,
+    #[error(display = "Invalid version policy: {}", _0)]
+    InvalidVersionPolicy(String),
+    #[error(display = "No version policy for {}", _0)]
+    NoVersionPolicy(String),
+    #[error(display = "Invalid value stored for account {}", _0)]
+    InvalidRecord(String),
+    #[error(display = "Database schema version {} is newer than supported", _0)]
+    UnsupportedSchema(u32),
+    #[error(display = "No such admin token.")]
+    NoToken,
+    #[error(display = "Admin token already exists.")]
+    TokenExists,
+    #[error(display = "Invalid relay list: {}", _0)]
+    InvalidRelayList(String),
+    #[error(display = "No such {} in the relay list", _0)]
+    NoRelayEntry(String),
+    #[error(display = "The relay list already contains {}", _0)]
+    RelayEntryExists(String),
+    #[error(display = "Failed to migrate legacy file {}", _0)]
+    LegacyMigration(String, #[error(cause)] Box<Error>),
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::SqliteError(error)
+    }
+}
+
+/// Storage backend of the conductor.
+///
+/// Every method is a single transaction, so concurrent RPC handlers can share one instance
+/// without losing each other's writes.
+pub trait Database: Send + Sync {
+    /// Stores a new account and leases its VIP from `pool`. If `info.vip` belongs to the pool it
+    /// is leased as is, failing with `Error::AddressInUse` if it is taken, otherwise the lowest
+    /// free address is used. Fails with `Error::AccountExists` if the account is already present.
+    fn account_insert(
+        &self,
+        account: &str,
+        info: &AccountInfo,
+        pool: &AddressPool,
+    ) -> Result<AccountInfo>;
+
+    /// Makes sure the VIP of the account is leased to it from `pool`, allocating a new one if
+    /// it isn't. Accounts created before leases were recorded get their VIP this way.
+    fn account_assign_vip(&self, account: &str, pool: &AddressPool) -> Result<AccountInfo>;
+
+    /// Atomically reads, modifies and writes back a single account row.
+    /// Returns the stored value after `update` has been applied.
+    fn account_update(
+        &self,
+        account: &str,
+        update: &mut dyn FnMut(&mut AccountInfo) -> Result<()>,
+    ) -> Result<AccountInfo>;
+
+    /// Removes the account with its WireGuard keys, frees all addresses leased to it and
+    /// detaches its problem reports.
+    fn account_delete(&self, account: &str) -> Result<()>;
+
+    fn account_select(&self, account: &str) -> Result<AccountInfo>;
+
+    /// Returns all accounts, ordered by account number.
+    fn account_list(&self) -> Result<Vec<(String, AccountInfo)>>;
+
+    /// Returns the page of accounts matching `filter`, ordered by account number.
+    fn account_search(&self, filter: &AccountFilter) -> Result<AccountPage>;
+
+    /// Marks unused and active accounts whose expiry is before `now` as expired.
+    /// Returns the number of accounts that changed status.
+    fn account_expire_overdue(&self, now: DateTime<Utc>) -> Result<usize>;
+
+    /// Returns the address leased to the account from `pool`, allocating one if needed.
+    fn lease_allocate(&self, account: &str, pool: &AddressPool) -> Result<IpAddr>;
+
+    /// Returns the pool names and addresses leased to the account.
+    fn lease_list(&self, account: &str) -> Result<Vec<(String, IpAddr)>>;
+
+    /// Returns the relay list served to clients.
+    fn relay_list_select(&self) -> Result<RelayList>;
+
+    /// Returns the relay list served to clients in its stored form, with its ETag.
+    fn relay_list_versioned(&self) -> Result<VersionedRelayList>;
+
+    /// Atomically reads, modifies and writes back the relay list.
+    /// Returns the stored list after `modify` has been applied.
+    fn relay_list_modify(
+        &self,
+        modify: &mut dyn FnMut(&mut RelayList) -> Result<()>,
+    ) -> Result<VersionedRelayList>;
+
+    /// Registers a WireGuard key for the account and returns its addresses. If `replaces` is
+    /// registered to the account it is removed and its addresses are moved to `key`, otherwise
+    /// new ones are leased from the pools in `config`. Fails with `Error::TooManyWireguardKeys`
+    /// if that would take the account over `config.max_keys_per_account`.
+    fn wg_key_add(
+        &self,
+        account: &str,
+        key: &PublicKey,
+        replaces: Option<&PublicKey>,
+        config: &WireguardConfig,
+    ) -> Result<AssociatedAddresses>;
+
+    /// Returns the WireGuard keys of the account, oldest first.
+    fn wg_key_list(&self, account: &str) -> Result<Vec<WireguardKey>>;
+
+    /// Removes a WireGuard key of the account and frees its addresses.
+    fn wg_key_delete(&self, account: &str, key: &PublicKey) -> Result<()>;
+
+    /// Stores a problem report and returns its id, unless its client already sent `max_reports`
+    /// reports since `since`. The id and log size in `report.summary` are ignored.
+    fn report_insert(
+        &self,
+        report: &ProblemReport,
+        since: DateTime<Utc>,
+        max_reports: u64,
+    ) -> Result<i64>;
+
+    /// Returns the page of reports matching `filter`, newest first.
+    fn report_search(&self, filter: &ReportFilter) -> Result<ReportPage>;
+
+    fn report_select(&self, id: i64) -> Result<ProblemReport>;
+
+    /// Attaches a report to an account, or detaches it if `account` is `None`. Fails with
+    /// `Error::NoAccount` if the account doesn't exist.
+    fn report_set_account(&self, id: i64, account: Option<&str>) -> Result<()>;
+
+    /// Returns the app version policies of all platforms, ordered by platform.
+    fn version_policy_list(&self) -> Result<Vec<(String, VersionPolicy)>>;
+
+    fn version_policy_select(&self, platform: &str) -> Result<Option<VersionPolicy>>;
+
+    /// Atomically replaces the version policy of `platform` with the one returned by `update`,
+    /// which is given the stored policy if there is one.
+    fn version_policy_update(
+        &self,
+        platform: &str,
+        update: &mut dyn FnMut(Option<VersionPolicy>) -> Result<VersionPolicy>,
+    ) -> Result<VersionPolicy>;
+
+    fn version_policy_delete(&self, platform: &str) -> Result<()>;
+
+    /// Stores the hash of a new admin token. Fails with `Error::TokenExists` if a token with the
+    /// same name is present.
+    fn admin_token_insert(&self, name: &str, hash: &[u8], created: DateTime<Utc>) -> Result<()>;
+
+    /// Returns the name of the admin token with the given hash.
+    fn admin_token_lookup(&self, hash: &[u8]) -> Result<Option<String>>;
+
+    /// Returns all admin tokens, ordered by name.
+    fn admin_token_list(&self) -> Result<Vec<AdminToken>>;
+
+    fn admin_token_delete(&self, name: &str) -> Result<()>;
+}
@@ -0,0 +1,1253 @@
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{offset::Utc, DateTime, TimeZone};
+use rusqlite::{
+    params, Connection, OptionalExtension, Row, ToSql, TransactionBehavior, NO_PARAMS,
+};
+
+use mullvad_types::relay_list::RelayList;
+use mullvad_types::wireguard::AssociatedAddresses;
+use talpid_types::net::wireguard::PublicKey;
+
+use super::{migration, Database, Error, Result};
+use crate::account::{AccountEntry, AccountFilter, AccountPage};
+use crate::app_version::VersionPolicy;
+use crate::auth::AdminToken;
+use crate::ipam::{self, AddressPool};
+use crate::relay::VersionedRelayList;
+use crate::report::{ProblemReport, ReportFilter, ReportPage, ReportSummary};
+use crate::types::{AccountInfo, AccountStatus};
+use crate::wireguard::{self, WireguardConfig, WireguardKey};
+
+/// How long a statement waits for a lock held by another connection, e.g. conductor-cli
+/// operating on the same file as a running conductor-daemon.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `Database` backed by an SQLite file in WAL journaling mode.
+pub struct SqliteDatabase {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteDatabase {
+    /// Opens or creates the database at `path` and migrates it to the current schema.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        let journal_mode: String =
+            conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| row.get(0))?;
+        if journal_mode != "wal" {
+            log::warn!("Conductor database is using journal mode {}", journal_mode);
+        }
+        conn.execute_batch("PRAGMA synchronous = FULL;")?;
+
+        let legacy_dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        migration::run(&mut conn, legacy_dir)?;
+
+        Ok(SqliteDatabase {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl Database for SqliteDatabase {
+    fn account_insert(
+        &self,
+        account: &str,
+        info: &AccountInfo,
+        pool: &AddressPool,
+    ) -> Result<AccountInfo> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if select_account(&tx, account)?.is_some() {
+            return Err(Error::AccountExists);
+        }
+        let mut info = info.clone();
+        info.vip = if pool.contains(info.vip) {
+            if lease_holder(&tx, info.vip)?.is_some() {
+                return Err(Error::AddressInUse(info.vip));
+            }
+            insert_lease(&tx, account, pool, info.vip)?;
+            info.vip
+        } else {
+            allocate(&tx, account, pool)?
+        };
+        insert_account(&tx, account, &info)?;
+        tx.commit()?;
+        Ok(info)
+    }
+
+    fn account_assign_vip(&self, account: &str, pool: &AddressPool) -> Result<AccountInfo> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut info = select_account(&tx, account)?.ok_or(Error::NoAccount)?;
+        let leased = pool.contains(info.vip)
+            && lease_holder(&tx, info.vip)?.as_ref().map(String::as_str) == Some(account);
+        if !leased {
+            tx.execute(
+                "DELETE FROM leases WHERE account = ?1 AND pool = ?2",
+                params![account, pool.name],
+            )?;
+            let vip = allocate(&tx, account, pool)?;
+            log::info!("Leased {} to account {}, replacing {}", vip, account, info.vip);
+            info.vip = vip;
+            tx.execute(
+                "UPDATE accounts SET vip = ?2 WHERE account = ?1",
+                params![account, info.vip.to_string()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(info)
+    }
+
+    fn account_update(
+        &self,
+        account: &str,
+        update: &mut dyn FnMut(&mut AccountInfo) -> Result<()>,
+    ) -> Result<AccountInfo> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut info = select_account(&tx, account)?.ok_or(Error::NoAccount)?;
+        update(&mut info)?;
+        tx.execute(
+            "UPDATE accounts SET expiry = ?2, status = ?3, vip = ?4 WHERE account = ?1",
+            params![account, info.expiry.timestamp(), info.status.to_string(), info.vip.to_string()],
+        )?;
+        tx.commit()?;
+        Ok(info)
+    }
+
+    fn account_delete(&self, account: &str) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let deleted = tx.execute("DELETE FROM accounts WHERE account = ?1", params![account])?;
+        if deleted == 0 {
+            return Err(Error::NoAccount);
+        }
+        tx.execute("DELETE FROM leases WHERE account = ?1", params![account])?;
+        tx.execute("DELETE FROM wg_keys WHERE account = ?1", params![account])?;
+        tx.execute(
+            "UPDATE problem_reports SET account = NULL WHERE account = ?1",
+            params![account],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn account_select(&self, account: &str) -> Result<AccountInfo> {
+        let conn = self.conn.lock().unwrap();
+        select_account(&conn, account)?.ok_or(Error::NoAccount)
+    }
+
+    fn account_list(&self) -> Result<Vec<(String, AccountInfo)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT account, expiry, status, vip FROM accounts ORDER BY account")?;
+        let rows = stmt.query_map(NO_PARAMS, AccountRow::from_row)?;
+        let mut accounts = vec![];
+        for row in rows {
+            let row = row?;
+            let account = row.account.clone();
+            accounts.push((account, row.into_info()?));
+        }
+        Ok(accounts)
+    }
+
+    fn account_search(&self, filter: &AccountFilter) -> Result<AccountPage> {
+        let mut clauses: Vec<&str> = vec![];
+        let mut values: Vec<Box<dyn ToSql>> = vec![];
+        if let Some(status) = filter.status {
+            clauses.push("status = ?");
+            values.push(Box::new(status.to_string()));
+        }
+        if let Some(expires_before) = filter.expires_before {
+            clauses.push("expiry < ?");
+            values.push(Box::new(expires_before.timestamp()));
+        }
+        if let Some(expires_after) = filter.expires_after {
+            clauses.push("expiry > ?");
+            values.push(Box::new(expires_after.timestamp()));
+        }
+        if let Some(ref prefix) = filter.prefix {
+            clauses.push("substr(account, 1, length(?)) = ?");
+            values.push(Box::new(prefix.clone()));
+            values.push(Box::new(prefix.clone()));
+        }
+        let condition = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+
+        let conn = self.conn.lock().unwrap();
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM accounts {}", condition),
+            values.iter(),
+            |row| row.get(0),
+        )?;
+
+        values.push(Box::new(filter.limit as i64));
+        values.push(Box::new(filter.offset as i64));
+        let mut stmt = conn.prepare(&format!(
+            "SELECT account, expiry, status, vip FROM accounts {} ORDER BY account LIMIT ? OFFSET ?",
+            condition
+        ))?;
+        let rows = stmt.query_map(values.iter(), AccountRow::from_row)?;
+        let mut accounts = vec![];
+        for row in rows {
+            let row = row?;
+            let account = row.account.clone();
+            accounts.push(AccountEntry {
+                account,
+                info: row.into_info()?,
+            });
+        }
+        Ok(AccountPage {
+            total: total as u64,
+            offset: filter.offset,
+            accounts,
+        })
+    }
+
+    fn account_expire_overdue(&self, now: DateTime<Utc>) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let expired = conn.execute(
+            "UPDATE accounts SET status = ?1 WHERE status IN (?2, ?3) AND expiry < ?4",
+            params![
+                AccountStatus::Expired.to_string(),
+                AccountStatus::Unused.to_string(),
+                AccountStatus::Active.to_string(),
+                now.timestamp()
+            ],
+        )?;
+        Ok(expired)
+    }
+
+    fn lease_allocate(&self, account: &str, pool: &AddressPool) -> Result<IpAddr> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if select_account(&tx, account)?.is_none() {
+            return Err(Error::NoAccount);
+        }
+        let existing: Option<Vec<u8>> = tx
+            .query_row(
+                "SELECT address FROM leases WHERE account = ?1 AND pool = ?2",
+                params![account, pool.name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let ip = match existing.as_ref().and_then(|octets| ipam::from_octets(octets)) {
+            Some(ip) => ip,
+            None => allocate(&tx, account, pool)?,
+        };
+        tx.commit()?;
+        Ok(ip)
+    }
+
+    fn lease_list(&self, account: &str) -> Result<Vec<(String, IpAddr)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT pool, address FROM leases WHERE account = ?1 ORDER BY pool")?;
+        let rows = stmt.query_map(params![account], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        let mut leases = vec![];
+        for row in rows {
+            let (pool, octets) = row?;
+            let ip = ipam::from_octets(&octets)
+                .ok_or_else(|| Error::InvalidRecord(account.to_string()))?;
+            leases.push((pool, ip));
+        }
+        Ok(leases)
+    }
+
+    fn relay_list_select(&self) -> Result<RelayList> {
+        let conn = self.conn.lock().unwrap();
+        select_relay_list(&conn)
+    }
+
+    fn relay_list_versioned(&self) -> Result<VersionedRelayList> {
+        let conn = self.conn.lock().unwrap();
+        let data = select_relay_list_data(&conn)?;
+        match data {
+            Some(data) => Ok(VersionedRelayList::new(data)),
+            None => {
+                let data = serde_json::to_string(&RelayList::empty()).map_err(Error::JsonError)?;
+                Ok(VersionedRelayList::new(data))
+            }
+        }
+    }
+
+    fn relay_list_modify(
+        &self,
+        modify: &mut dyn FnMut(&mut RelayList) -> Result<()>,
+    ) -> Result<VersionedRelayList> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut relay_list = select_relay_list(&tx)?;
+        modify(&mut relay_list)?;
+        let data = write_relay_list(&tx, &relay_list)?;
+        tx.commit()?;
+        Ok(VersionedRelayList::new(data))
+    }
+
+    fn wg_key_add(
+        &self,
+        account: &str,
+        key: &PublicKey,
+        replaces: Option<&PublicKey>,
+        config: &WireguardConfig,
+    ) -> Result<AssociatedAddresses> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if select_account(&tx, account)?.is_none() {
+            return Err(Error::NoAccount);
+        }
+        if let Some(registered) = select_wg_key(&tx, key)? {
+            if registered.0 != account {
+                return Err(Error::WireguardKeyInUse);
+            }
+            return Ok(registered.1.addresses);
+        }
+
+        let replaced = match replaces {
+            Some(old) => select_wg_key(&tx, old)?.filter(|(owner, _)| owner == account),
+            None => None,
+        };
+        let addresses = match replaced {
+            Some((_, old)) => {
+                tx.execute(
+                    "DELETE FROM wg_keys WHERE public_key = ?1",
+                    params![&old.public_key.as_bytes()[..]],
+                )?;
+                old.addresses
+            }
+            None => {
+                let keys: i64 = tx.query_row(
+                    "SELECT COUNT(*) FROM wg_keys WHERE account = ?1",
+                    params![account],
+                    |row| row.get(0),
+                )?;
+                if keys as usize >= config.max_keys_per_account {
+                    return Err(Error::TooManyWireguardKeys);
+                }
+                let ipv4 = match allocate(&tx, account, &config.ipv4_pool)? {
+                    IpAddr::V4(ip) => ip,
+                    IpAddr::V6(_) => {
+                        return Err(Error::WrongAddressFamily(config.ipv4_pool.name.clone()))
+                    }
+                };
+                let ipv6 = match allocate(&tx, account, &config.ipv6_pool)? {
+                    IpAddr::V6(ip) => ip,
+                    IpAddr::V4(_) => {
+                        return Err(Error::WrongAddressFamily(config.ipv6_pool.name.clone()))
+                    }
+                };
+                wireguard::associated_addresses(ipv4, ipv6)
+            }
+        };
+
+        tx.execute(
+            "INSERT INTO wg_keys (public_key, account, ipv4, ipv6, created)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                &key.as_bytes()[..],
+                account,
+                &addresses.ipv4_address.ip().octets()[..],
+                &addresses.ipv6_address.ip().octets()[..],
+                Utc::now().timestamp()
+            ],
+        )?;
+        tx.commit()?;
+        Ok(addresses)
+    }
+
+    fn wg_key_list(&self, account: &str) -> Result<Vec<WireguardKey>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT account, public_key, ipv4, ipv6, created FROM wg_keys WHERE account = ?1
+                ORDER BY created, public_key",
+        )?;
+        let rows = stmt.query_map(params![account], WgKeyRow::from_row)?;
+        let mut keys = vec![];
+        for row in rows {
+            keys.push(row?.into_key()?.1);
+        }
+        Ok(keys)
+    }
+
+    fn wg_key_delete(&self, account: &str, key: &PublicKey) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let registered = match select_wg_key(&tx, key)? {
+            Some((owner, registered)) if owner == account => registered,
+            _ => return Err(Error::NoWireguardKey),
+        };
+        tx.execute(
+            "DELETE FROM wg_keys WHERE public_key = ?1",
+            params![&key.as_bytes()[..]],
+        )?;
+        tx.execute(
+            "DELETE FROM leases WHERE address IN (?1, ?2)",
+            params![
+                &registered.addresses.ipv4_address.ip().octets()[..],
+                &registered.addresses.ipv6_address.ip().octets()[..]
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn report_insert(
+        &self,
+        report: &ProblemReport,
+        since: DateTime<Utc>,
+        max_reports: u64,
+    ) -> Result<i64> {
+        let summary = &report.summary;
+        let metadata = serde_json::to_string(&summary.metadata).map_err(Error::JsonError)?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let count: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM problem_reports WHERE client = ?1 AND received >= ?2",
+            params![summary.client, since.timestamp()],
+            |row| row.get(0),
+        )?;
+        if count as u64 >= max_reports {
+            return Err(Error::ReportRateLimited);
+        }
+        tx.execute(
+            "INSERT INTO problem_reports (received, client, account, email, metadata, message, log)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                summary.received.timestamp(),
+                summary.client,
+                summary.account,
+                summary.email,
+                metadata,
+                report.message,
+                report.log
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.commit()?;
+        Ok(id)
+    }
+
+    fn report_search(&self, filter: &ReportFilter) -> Result<ReportPage> {
+        let mut clauses: Vec<&str> = vec![];
+        let mut values: Vec<Box<dyn ToSql>> = vec![];
+        if let Some(ref account) = filter.account {
+            clauses.push("account = ?");
+            values.push(Box::new(account.clone()));
+        }
+        if let Some(ref client) = filter.client {
+            clauses.push("client = ?");
+            values.push(Box::new(client.clone()));
+        }
+        if let Some(received_after) = filter.received_after {
+            clauses.push("received > ?");
+            values.push(Box::new(received_after.timestamp()));
+        }
+        if let Some(received_before) = filter.received_before {
+            clauses.push("received < ?");
+            values.push(Box::new(received_before.timestamp()));
+        }
+        let condition = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+
+        let conn = self.conn.lock().unwrap();
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM problem_reports {}", condition),
+            values.iter(),
+            |row| row.get(0),
+        )?;
+
+        values.push(Box::new(filter.limit as i64));
+        values.push(Box::new(filter.offset as i64));
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM problem_reports {} ORDER BY id DESC LIMIT ? OFFSET ?",
+            REPORT_SUMMARY_COLUMNS, condition
+        ))?;
+        let rows = stmt.query_map(values.iter(), ReportRow::from_row)?;
+        let mut reports = vec![];
+        for row in rows {
+            reports.push(row?.into_summary()?);
+        }
+        Ok(ReportPage {
+            total: total as u64,
+            offset: filter.offset,
+            reports,
+        })
+    }
+
+    fn report_select(&self, id: i64) -> Result<ProblemReport> {
+        let conn = self.conn.lock().unwrap();
+        let report = conn
+            .query_row(
+                &format!(
+                    "SELECT {}, message, log FROM problem_reports WHERE id = ?1",
+                    REPORT_SUMMARY_COLUMNS
+                ),
+                params![id],
+                |row| {
+                    Ok((
+                        ReportRow::from_row(row)?,
+                        row.get::<_, String>(7)?,
+                        row.get::<_, String>(8)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let (row, message, log) = report.ok_or(Error::NoReport)?;
+        Ok(ProblemReport {
+            summary: row.into_summary()?,
+            message,
+            log,
+        })
+    }
+
+    fn report_set_account(&self, id: i64, account: Option<&str>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if let Some(account) = account {
+            if select_account(&tx, account)?.is_none() {
+                return Err(Error::NoAccount);
+            }
+        }
+        let updated = tx.execute(
+            "UPDATE problem_reports SET account = ?1 WHERE id = ?2",
+            params![account, id],
+        )?;
+        if updated == 0 {
+            return Err(Error::NoReport);
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn version_policy_list(&self) -> Result<Vec<(String, VersionPolicy)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT platform, {} FROM version_policies ORDER BY platform",
+            VERSION_POLICY_COLUMNS
+        ))?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, VersionPolicyRow::from_row(row, 1)?))
+        })?;
+        let mut policies = vec![];
+        for row in rows {
+            let (platform, row) = row?;
+            policies.push((platform, row.into_policy()?));
+        }
+        Ok(policies)
+    }
+
+    fn version_policy_select(&self, platform: &str) -> Result<Option<VersionPolicy>> {
+        let conn = self.conn.lock().unwrap();
+        select_version_policy(&conn, platform)
+    }
+
+    fn version_policy_update(
+        &self,
+        platform: &str,
+        update: &mut dyn FnMut(Option<VersionPolicy>) -> Result<VersionPolicy>,
+    ) -> Result<VersionPolicy> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let policy = update(select_version_policy(&tx, platform)?)?;
+        let blocked = serde_json::to_string(&policy.blocked).map_err(Error::JsonError)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO version_policies
+                (platform, latest_stable, latest_beta, min_supported, blocked)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                platform,
+                policy.latest_stable,
+                policy.latest_beta,
+                policy.min_supported,
+                blocked
+            ],
+        )?;
+        tx.commit()?;
+        Ok(policy)
+    }
+
+    fn version_policy_delete(&self, platform: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM version_policies WHERE platform = ?1",
+            params![platform],
+        )?;
+        if deleted == 0 {
+            return Err(Error::NoVersionPolicy(platform.to_string()));
+        }
+        Ok(())
+    }
+
+    fn admin_token_insert(&self, name: &str, hash: &[u8], created: DateTime<Utc>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let exists = tx
+            .query_row(
+                "SELECT 1 FROM admin_tokens WHERE name = ?1",
+                params![name],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if exists {
+            return Err(Error::TokenExists);
+        }
+        tx.execute(
+            "INSERT INTO admin_tokens (name, hash, created) VALUES (?1, ?2, ?3)",
+            params![name, hash, created.timestamp()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn admin_token_lookup(&self, hash: &[u8]) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let name = conn
+            .query_row(
+                "SELECT name FROM admin_tokens WHERE hash = ?1",
+                params![hash],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(name)
+    }
+
+    fn admin_token_list(&self) -> Result<Vec<AdminToken>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name, created FROM admin_tokens ORDER BY name")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok(AdminToken {
+                name: row.get(0)?,
+                created: Utc.timestamp(row.get(1)?, 0),
+            })
+        })?;
+        let mut tokens = vec![];
+        for row in rows {
+            tokens.push(row?);
+        }
+        Ok(tokens)
+    }
+
+    fn admin_token_delete(&self, name: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute("DELETE FROM admin_tokens WHERE name = ?1", params![name])?;
+        if deleted == 0 {
+            return Err(Error::NoToken);
+        }
+        Ok(())
+    }
+}
+
+/// Raw account row, before the stored values have been validated.
+struct AccountRow {
+    account: String,
+    expiry: i64,
+    status: String,
+    vip: String,
+}
+
+impl AccountRow {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(AccountRow {
+            account: row.get(0)?,
+            expiry: row.get(1)?,
+            status: row.get(2)?,
+            vip: row.get(3)?,
+        })
+    }
+
+    fn into_info(self) -> Result<AccountInfo> {
+        let AccountRow {
+            account,
+            expiry,
+            status,
+            vip,
+        } = self;
+        let status = match AccountStatus::from_str(&status) {
+            Ok(status) => status,
+            Err(_) => return Err(Error::InvalidRecord(account)),
+        };
+        let vip = IpAddr::from_str(&vip).map_err(|_| Error::InvalidRecord(account))?;
+        Ok(AccountInfo {
+            expiry: Utc.timestamp(expiry, 0),
+            status,
+            vip,
+        })
+    }
+}
+
+/// Columns read by `VersionPolicyRow::from_row`.
+const VERSION_POLICY_COLUMNS: &str = "latest_stable, latest_beta, min_supported, blocked";
+
+struct VersionPolicyRow {
+    latest_stable: String,
+    latest_beta: Option<String>,
+    min_supported: Option<String>,
+    blocked: String,
+}
+
+impl VersionPolicyRow {
+    /// Reads `VERSION_POLICY_COLUMNS`, starting at column `first`.
+    fn from_row(row: &Row<'_>, first: usize) -> rusqlite::Result<Self> {
+        Ok(VersionPolicyRow {
+            latest_stable: row.get(first)?,
+            latest_beta: row.get(first + 1)?,
+            min_supported: row.get(first + 2)?,
+            blocked: row.get(first + 3)?,
+        })
+    }
+
+    fn into_policy(self) -> Result<VersionPolicy> {
+        Ok(VersionPolicy {
+            latest_stable: self.latest_stable,
+            latest_beta: self.latest_beta,
+            min_supported: self.min_supported,
+            blocked: serde_json::from_str(&self.blocked).map_err(Error::JsonError)?,
+        })
+    }
+}
+
+fn select_version_policy(conn: &Connection, platform: &str) -> Result<Option<VersionPolicy>> {
+    let row = conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM version_policies WHERE platform = ?1",
+                VERSION_POLICY_COLUMNS
+            ),
+            params![platform],
+            |row| VersionPolicyRow::from_row(row, 0),
+        )
+        .optional()?;
+    row.map(VersionPolicyRow::into_policy).transpose()
+}
+
+/// Columns read by `ReportRow::from_row`. The log size is counted in bytes.
+const REPORT_SUMMARY_COLUMNS: &str =
+    "id, received, client, account, email, metadata, length(CAST(log AS BLOB))";
+
+/// Raw problem report row, without the message and log.
+struct ReportRow {
+    id: i64,
+    received: i64,
+    client: String,
+    account: Option<String>,
+    email: String,
+    metadata: String,
+    log_size: i64,
+}
+
+impl ReportRow {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(ReportRow {
+            id: row.get(0)?,
+            received: row.get(1)?,
+            client: row.get(2)?,
+            account: row.get(3)?,
+            email: row.get(4)?,
+            metadata: row.get(5)?,
+            log_size: row.get(6)?,
+        })
+    }
+
+    fn into_summary(self) -> Result<ReportSummary> {
+        let metadata = serde_json::from_str(&self.metadata).map_err(Error::JsonError)?;
+        Ok(ReportSummary {
+            id: self.id,
+            received: Utc.timestamp(self.received, 0),
+            client: self.client,
+            account: self.account,
+            email: self.email,
+            metadata,
+            log_size: self.log_size as u64,
+        })
+    }
+}
+
+/// Raw WireGuard key row, before the stored values have been validated.
+struct WgKeyRow {
+    account: String,
+    public_key: Vec<u8>,
+    ipv4: Vec<u8>,
+    ipv6: Vec<u8>,
+    created: i64,
+}
+
+impl WgKeyRow {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(WgKeyRow {
+            account: row.get(0)?,
+            public_key: row.get(1)?,
+            ipv4: row.get(2)?,
+            ipv6: row.get(3)?,
+            created: row.get(4)?,
+        })
+    }
+
+    /// Returns the account holding the key, and the key.
+    fn into_key(self) -> Result<(String, WireguardKey)> {
+        let WgKeyRow {
+            account,
+            public_key,
+            ipv4,
+            ipv6,
+            created,
+        } = self;
+        if public_key.len() != 32 {
+            return Err(Error::InvalidRecord(account));
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&public_key);
+        let (ipv4, ipv6) = match (ipam::from_octets(&ipv4), ipam::from_octets(&ipv6)) {
+            (Some(IpAddr::V4(ipv4)), Some(IpAddr::V6(ipv6))) => (ipv4, ipv6),
+            _ => return Err(Error::InvalidRecord(account)),
+        };
+        let key = WireguardKey {
+            public_key: PublicKey::from(key),
+            addresses: wireguard::associated_addresses(ipv4, ipv6),
+            created: Utc.timestamp(created, 0),
+        };
+        Ok((account, key))
+    }
+}
+
+fn select_wg_key(conn: &Connection, key: &PublicKey) -> Result<Option<(String, WireguardKey)>> {
+    let row = conn
+        .query_row(
+            "SELECT account, public_key, ipv4, ipv6, created FROM wg_keys WHERE public_key = ?1",
+            params![&key.as_bytes()[..]],
+            WgKeyRow::from_row,
+        )
+        .optional()?;
+    row.map(WgKeyRow::into_key).transpose()
+}
+
+fn select_account(conn: &Connection, account: &str) -> Result<Option<AccountInfo>> {
+    let row = conn
+        .query_row(
+            "SELECT account, expiry, status, vip FROM accounts WHERE account = ?1",
+            params![account],
+            AccountRow::from_row,
+        )
+        .optional()?;
+    row.map(AccountRow::into_info).transpose()
+}
+
+pub(super) fn insert_account(conn: &Connection, account: &str, info: &AccountInfo) -> Result<()> {
+    conn.execute(
+        "INSERT INTO accounts (account, expiry, status, vip) VALUES (?1, ?2, ?3, ?4)",
+        params![account, info.expiry.timestamp(), info.status.to_string(), info.vip.to_string()],
+    )?;
+    Ok(())
+}
+
+fn lease_holder(conn: &Connection, ip: IpAddr) -> Result<Option<String>> {
+    let holder = conn
+        .query_row(
+            "SELECT account FROM leases WHERE address = ?1",
+            params![ipam::to_octets(ip)],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(holder)
+}
+
+fn insert_lease(conn: &Connection, account: &str, pool: &AddressPool, ip: IpAddr) -> Result<()> {
+    conn.execute(
+        "INSERT INTO leases (address, pool, account) VALUES (?1, ?2, ?3)",
+        params![ipam::to_octets(ip), pool.name, account],
+    )?;
+    Ok(())
+}
+
+/// Leases the lowest free address of the pool. Leases from other pools overlapping this one are
+/// taken into account.
+fn allocate(conn: &Connection, account: &str, pool: &AddressPool) -> Result<IpAddr> {
+    let (first, last) = pool.bounds();
+    let mut stmt = conn.prepare(
+        "SELECT address FROM leases WHERE length(address) = length(?1) AND address BETWEEN ?1 AND ?2
+            ORDER BY address",
+    )?;
+    let rows = stmt.query_map(params![ipam::to_octets(first), ipam::to_octets(last)], |row| {
+        row.get::<_, Vec<u8>>(0)
+    })?;
+    let mut leased = vec![];
+    for row in rows {
+        if let Some(ip) = ipam::from_octets(&row?) {
+            leased.push(ip);
+        }
+    }
+
+    let ip = pool
+        .first_free(&leased)
+        .ok_or_else(|| Error::PoolExhausted(pool.name.clone()))?;
+    insert_lease(conn, account, pool, ip)?;
+    Ok(ip)
+}
+
+fn select_relay_list_data(conn: &Connection) -> Result<Option<String>> {
+    let data = conn
+        .query_row("SELECT data FROM relay_list WHERE id = 0", NO_PARAMS, |row| {
+            row.get(0)
+        })
+        .optional()?;
+    Ok(data)
+}
+
+fn select_relay_list(conn: &Connection) -> Result<RelayList> {
+    match select_relay_list_data(conn)? {
+        Some(data) => serde_json::from_str(&data).map_err(Error::JsonError),
+        None => Ok(RelayList::empty()),
+    }
+}
+
+/// Stores the relay list and returns it in its stored form.
+pub(super) fn write_relay_list(conn: &Connection, relay_list: &RelayList) -> Result<String> {
+    let data = serde_json::to_string(relay_list).map_err(Error::JsonError)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO relay_list (id, data) VALUES (0, ?1)",
+        params![data],
+    )?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_version;
+    use crate::report::{self, ReportConfig};
+    use crate::{auth, relay};
+    use std::collections::BTreeMap;
+    use chrono::Duration;
+    use std::net::Ipv4Addr;
+
+    fn info(days: i64) -> AccountInfo {
+        AccountInfo {
+            expiry: Utc.timestamp(Utc::now().timestamp(), 0) + Duration::days(days),
+            status: AccountStatus::Unused,
+            vip: IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255)),
+        }
+    }
+
+    fn open_temp(dir: &Path) -> SqliteDatabase {
+        SqliteDatabase::open(dir.join("conductor.db")).expect("Failed to open database")
+    }
+
+    #[test]
+    fn test_account_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+
+        let pool = AddressPool::default_v4();
+        let account = db.account_insert("1234", &info(30), &pool).unwrap();
+        assert_eq!(account.vip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(db.account_select("1234").unwrap(), account);
+        assert!(match db.account_insert("1234", &account, &pool) {
+            Err(Error::AccountExists) => true,
+            _ => false,
+        });
+
+        let updated = db
+            .account_update("1234", &mut |info| {
+                info.status = AccountStatus::Active;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(updated.status, AccountStatus::Active);
+        assert_eq!(db.account_select("1234").unwrap(), updated);
+
+        db.account_delete("1234").unwrap();
+        assert!(match db.account_select("1234") {
+            Err(Error::NoAccount) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_legacy_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut accounts = std::collections::HashMap::new();
+        accounts.insert("5678".to_string(), info(1));
+        std::fs::write(
+            dir.path().join("account.json"),
+            serde_json::to_string(&accounts).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("relay.json"), "{}").unwrap();
+
+        let db = open_temp(dir.path());
+        assert_eq!(db.account_list().unwrap().len(), 1);
+        assert_eq!(db.account_select("5678").unwrap(), accounts["5678"]);
+        assert!(dir.path().join("account.json.migrated").is_file());
+        assert!(dir.path().join("relay.json").is_file());
+        assert!(db.relay_list_select().unwrap().countries.is_empty());
+
+        drop(db);
+        let db = open_temp(dir.path());
+        assert_eq!(db.account_list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_account_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+        for (account, days) in &[("1001", -1), ("1002", 10), ("2003", 20)] {
+            db.account_insert(account, &info(*days), &AddressPool::default_v4())
+                .unwrap();
+        }
+        assert_eq!(db.account_expire_overdue(Utc::now()).unwrap(), 1);
+
+        let page = db
+            .account_search(&AccountFilter {
+                prefix: Some("1".to_string()),
+                limit: 1,
+                ..AccountFilter::default()
+            })
+            .unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.accounts.len(), 1);
+        assert_eq!(page.accounts[0].account, "1001");
+        assert_eq!(page.accounts[0].info.status, AccountStatus::Expired);
+
+        let page = db
+            .account_search(&AccountFilter {
+                status: Some(AccountStatus::Unused),
+                ..AccountFilter::default()
+            })
+            .unwrap();
+        let accounts: Vec<_> = page.accounts.iter().map(|entry| &entry.account[..]).collect();
+        assert_eq!(accounts, vec!["1002", "2003"]);
+    }
+
+    #[test]
+    fn test_leases() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+        let pool = AddressPool::default_v4();
+
+        let mut requested = info(30);
+        requested.vip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        db.account_insert("1001", &requested, &pool).unwrap();
+        assert!(match db.account_insert("1002", &requested, &pool) {
+            Err(Error::AddressInUse(_)) => true,
+            _ => false,
+        });
+        let second = db.account_insert("1002", &info(30), &pool).unwrap();
+        assert_eq!(second.vip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+
+        let v6_pool = AddressPool::new(
+            "vip6",
+            "fd00::/64".parse().unwrap(),
+            vec![],
+        );
+        let v6 = db.lease_allocate("1002", &v6_pool).unwrap();
+        assert_eq!(v6, "fd00::1".parse::<IpAddr>().unwrap());
+        assert_eq!(db.lease_allocate("1002", &v6_pool).unwrap(), v6);
+        assert_eq!(db.lease_list("1002").unwrap().len(), 2);
+
+        db.account_delete("1001").unwrap();
+        assert!(db.lease_list("1001").unwrap().is_empty());
+        let third = db.account_insert("1003", &info(30), &pool).unwrap();
+        assert_eq!(third.vip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+    }
+
+    #[test]
+    fn test_admin_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+
+        let token = auth::create_token(&db, "ops").unwrap();
+        assert_eq!(auth::verify_token(&db, &token).unwrap(), Some("ops".to_string()));
+        assert_eq!(auth::verify_token(&db, "not a token").unwrap(), None);
+        assert!(match auth::create_token(&db, "ops") {
+            Err(Error::TokenExists) => true,
+            _ => false,
+        });
+        assert_eq!(db.admin_token_list().unwrap()[0].name, "ops");
+
+        db.admin_token_delete("ops").unwrap();
+        assert_eq!(auth::verify_token(&db, &token).unwrap(), None);
+        assert!(match db.admin_token_delete("ops") {
+            Err(Error::NoToken) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_relay_list_modify() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+        let empty = db.relay_list_versioned().unwrap();
+
+        let etag = relay::add_country(&db, "se", "Sweden").unwrap();
+        assert_ne!(etag, empty.etag);
+        relay::add_city(&db, "se", "got", "Gothenburg", 57.7, 11.97).unwrap();
+        assert!(match relay::add_city(&db, "se", "g", "Nowhere", 0.0, 0.0) {
+            Err(Error::InvalidRelayList(_)) => true,
+            _ => false,
+        });
+        assert!(match relay::remove_city(&db, "no", "osl") {
+            Err(Error::NoRelayEntry(_)) => true,
+            _ => false,
+        });
+
+        let current = db.relay_list_versioned().unwrap();
+        assert_eq!(relay::show(&db).unwrap().etag, current.etag);
+        let list = db.relay_list_select().unwrap();
+        assert_eq!(list.countries[0].cities[0].code, "got");
+
+        relay::remove_country(&db, "se").unwrap();
+        assert_eq!(db.relay_list_versioned().unwrap().etag, empty.etag);
+    }
+
+    #[test]
+    fn test_wireguard_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+        let mut config = WireguardConfig::default();
+        config.max_keys_per_account = 2;
+        db.account_insert("1001", &info(30), &AddressPool::default_v4())
+            .unwrap();
+        db.account_insert("1002", &info(30), &AddressPool::default_v4())
+            .unwrap();
+        let key = |n| PublicKey::from([n; 32]);
+
+        let first = wireguard::push_key(&db, &config, "1001", &key(1)).unwrap();
+        assert_eq!(first.ipv4_address.to_string(), "172.16.0.2/32");
+        assert_eq!(first.ipv6_address.to_string(), "fc00:bbbb:bbbb:bb01::2/128");
+        let again = wireguard::push_key(&db, &config, "1001", &key(1)).unwrap();
+        assert_eq!(again.ipv4_address, first.ipv4_address);
+        assert!(match wireguard::push_key(&db, &config, "1002", &key(1)) {
+            Err(Error::WireguardKeyInUse) => true,
+            _ => false,
+        });
+
+        wireguard::push_key(&db, &config, "1001", &key(2)).unwrap();
+        assert!(match wireguard::push_key(&db, &config, "1001", &key(3)) {
+            Err(Error::TooManyWireguardKeys) => true,
+            _ => false,
+        });
+
+        let replaced = wireguard::replace_key(&db, &config, "1001", &key(1), &key(3)).unwrap();
+        assert_eq!(replaced.ipv4_address, first.ipv4_address);
+        assert!(!wireguard::check_key(&db, "1001", &key(1)).unwrap());
+        assert!(wireguard::check_key(&db, "1001", &key(3)).unwrap());
+
+        wireguard::remove_key(&db, "1001", &key(3)).unwrap();
+        assert_eq!(wireguard::list_keys(&db, "1001").unwrap().len(), 1);
+        let reused = wireguard::push_key(&db, &config, "1002", &key(4)).unwrap();
+        assert_eq!(reused.ipv4_address, first.ipv4_address);
+
+        db.account_delete("1002").unwrap();
+        assert!(db.wg_key_list("1002").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_problem_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+        let mut config = ReportConfig::default();
+        config.max_reports_per_window = 2;
+        config.max_log_size = 64;
+        let log = "System information:\nos: Linux\n\nlog";
+
+        let submit = |client: &str, log: &str| {
+            report::submit(&db, &config, client, "a@b.c", "help", log, BTreeMap::new())
+        };
+        let first = submit("10.0.0.1", log).unwrap();
+        let second = submit("10.0.0.1", "no header").unwrap();
+        assert!(match submit("10.0.0.1", log) {
+            Err(Error::ReportRateLimited) => true,
+            _ => false,
+        });
+        assert!(match submit("10.0.0.2", &"x".repeat(65)) {
+            Err(Error::ReportTooLarge("log")) => true,
+            _ => false,
+        });
+
+        let stored = report::show(&db, first).unwrap();
+        assert_eq!(stored.summary.metadata["os"], "Linux");
+        assert_eq!(stored.summary.log_size, log.len() as u64);
+        assert_eq!(stored.log, log);
+        assert!(report::show(&db, second).unwrap().summary.metadata.is_empty());
+
+        db.account_insert("1001", &info(30), &AddressPool::default_v4())
+            .unwrap();
+        assert!(match report::attach(&db, first, Some("1002")) {
+            Err(Error::NoAccount) => true,
+            _ => false,
+        });
+        report::attach(&db, first, Some("1001")).unwrap();
+        let mut filter = ReportFilter::default();
+        filter.account = Some("1001".to_string());
+        let page = report::list(&db, &filter).unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.reports[0].id, first);
+        assert_eq!(report::list(&db, &ReportFilter::default()).unwrap().reports[0].id, second);
+
+        db.account_delete("1001").unwrap();
+        assert_eq!(report::show(&db, first).unwrap().summary.account, None);
+    }
+
+    #[test]
+    fn test_version_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+        assert!(app_version::check(&db, "linux", "2019.1").unwrap().current_is_supported);
+        assert!(match app_version::block(&db, "linux", "2019.1") {
+            Err(Error::NoVersionPolicy(_)) => true,
+            _ => false,
+        });
+
+        app_version::set_releases(&db, "linux", "2019.8", None, Some("2019.5")).unwrap();
+        app_version::block(&db, "linux", "2019.6").unwrap();
+        let policy =
+            app_version::set_releases(&db, "linux", "2019.9", Some("2019.10-beta1"), None)
+                .unwrap();
+        assert_eq!(policy.blocked, vec!["2019.6".to_string()]);
+        assert!(match app_version::set_releases(&db, "linux", "2019.6", None, None) {
+            Err(Error::InvalidVersionPolicy(_)) => true,
+            _ => false,
+        });
+
+        let info = app_version::check(&db, "linux", "2019.6").unwrap();
+        assert!(!info.current_is_supported);
+        assert_eq!(info.latest, "2019.10-beta1");
+        assert!(app_version::check(&db, "windows", "2019.6").unwrap().current_is_supported);
+
+        app_version::unblock(&db, "linux", "2019.6").unwrap();
+        assert_eq!(app_version::list(&db).unwrap().len(), 1);
+        app_version::remove(&db, "linux").unwrap();
+        assert!(app_version::list(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_assign_vip_ignores_account_number() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_temp(dir.path());
+        let pool = AddressPool::default_v4();
+        {
+            let conn = db.conn.lock().unwrap();
+            insert_account(&conn, "123467837953", &info(30)).unwrap();
+        }
+        let assigned = db.account_assign_vip("123467837953", &pool).unwrap();
+        assert_eq!(assigned.vip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(db.account_assign_vip("123467837953", &pool).unwrap(), assigned);
+    }
+}
@@ -0,0 +1,167 @@
+//! JSON-RPC 2.0 request dispatching.
+//!
+//! A `Dispatcher` maps method names to handlers and turns a raw request body, which may be a
+//! single call or a batch, into the body of the reply. Notifications are executed but never
+//! answered, so a body made up only of notifications produces no reply at all.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::convention::{ErrorData, Params, Request, Response, JSONRPC_VERSION};
+
+pub type MethodResult = Result<Value, ErrorData>;
+
+type Method<S> = Box<dyn Fn(&S, &Params) -> MethodResult + Send + Sync>;
+
+/// Registry of the methods served on one endpoint. `S` is the state handed to every method.
+pub struct Dispatcher<S> {
+    methods: HashMap<&'static str, Method<S>>,
+}
+
+impl<S> Default for Dispatcher<S> {
+    fn default() -> Self {
+        Dispatcher {
+            methods: HashMap::new(),
+        }
+    }
+}
+
+impl<S> Dispatcher<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `method` under `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a method with the same name is already registered.
+    pub fn add<F>(&mut self, name: &'static str, method: F) -> &mut Self
+    where
+        F: Fn(&S, &Params) -> MethodResult + Send + Sync + 'static,
+    {
+        if self.methods.insert(name, Box::new(method)).is_some() {
+            panic!("Multiple methods named {}", name);
+        }
+        self
+    }
+
+    pub fn has_method(&self, name: &str) -> bool {
+        self.methods.contains_key(name)
+    }
+
+    /// Handles a request body and returns the reply body, or `None` if nothing should be
+    /// returned because the body only contained notifications.
+    pub fn handle(&self, state: &S, body: &[u8]) -> Option<String> {
+        let value: Value = match serde_json::from_slice(body) {
+            Ok(value) => value,
+            Err(_) => return Some(Response::error(ErrorData::std(-32700)).dump()),
+        };
+        match value {
+            Value::Array(calls) => {
+                if calls.is_empty() {
+                    return Some(Response::error(ErrorData::std(-32600)).dump());
+                }
+                let responses: Vec<Response> = calls
+                    .into_iter()
+                    .filter_map(|call| self.handle_call(state, call))
+                    .collect();
+                if responses.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&responses).expect("Should never failed"))
+                }
+            }
+            call => self.handle_call(state, call).map(|response| response.dump()),
+        }
+    }
+
+    fn handle_call(&self, state: &S, call: Value) -> Option<Response> {
+        let request: Request = match serde_json::from_value(call) {
+            Ok(request) => request,
+            Err(_) => return Some(Response::error(ErrorData::std(-32600))),
+        };
+        if request.jsonrpc != JSONRPC_VERSION {
+            return request
+                .id
+                .map(|id| Response::new(id, Err(ErrorData::std(-32600))));
+        }
+
+        let outcome = match self.methods.get(request.method.as_str()) {
+            Some(method) => method(state, &request.params),
+            None => Err(ErrorData::std(-32601)),
+        };
+        request.id.map(|id| Response::new(id, outcome))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dispatcher() -> Dispatcher<u32> {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.add("add", |state: &u32, params: &Params| {
+            let value: u32 = params.get(0, "value")?;
+            Ok(Value::from(state + value))
+        });
+        dispatcher
+    }
+
+    fn reply(body: &str) -> Option<Value> {
+        dispatcher()
+            .handle(&1, body.as_bytes())
+            .map(|reply| serde_json::from_str(&reply).unwrap())
+    }
+
+    #[test]
+    fn test_single_call() {
+        let reply = reply(r#"{"jsonrpc": "2.0", "method": "add", "params": [2], "id": 7}"#);
+        assert_eq!(reply.unwrap(), serde_json::json!({"jsonrpc": "2.0", "result": 3, "id": 7}));
+
+        let reply = reply(r#"{"jsonrpc": "2.0", "method": "add", "params": {"value": 2}, "id": 8}"#);
+        assert_eq!(reply.unwrap()["result"], 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(reply("{").unwrap()["error"]["code"], -32700);
+        assert_eq!(reply("[]").unwrap()["error"]["code"], -32600);
+        assert_eq!(reply(r#"{"method": 1, "id": 1}"#).unwrap()["error"]["code"], -32600);
+        assert_eq!(
+            reply(r#"{"jsonrpc": "1.0", "method": "add", "id": 1}"#).unwrap()["error"]["code"],
+            -32600
+        );
+        assert_eq!(
+            reply(r#"{"jsonrpc": "2.0", "method": "sub", "id": 1}"#).unwrap()["error"]["code"],
+            -32601
+        );
+        let reply = reply(r#"{"jsonrpc": "2.0", "method": "add", "id": 1}"#).unwrap();
+        assert_eq!(reply["error"]["code"], -32602);
+        assert!(reply.get("result").is_none());
+    }
+
+    #[test]
+    fn test_batch_and_notifications() {
+        assert_eq!(reply(r#"{"jsonrpc": "2.0", "method": "add", "params": [1]}"#), None);
+        assert_eq!(
+            reply(r#"[{"jsonrpc": "2.0", "method": "add", "params": [1]}]"#),
+            None
+        );
+
+        let reply = reply(
+            r#"[
+                {"jsonrpc": "2.0", "method": "add", "params": [1], "id": 1},
+                {"jsonrpc": "2.0", "method": "add", "params": [1]},
+                1
+            ]"#,
+        )
+        .unwrap();
+        let replies = reply.as_array().unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["result"], 2);
+        assert_eq!(replies[1]["error"]["code"], -32600);
+        assert_eq!(replies[1]["id"], Value::Null);
+    }
+}
@@ -0,0 +1,217 @@
+//! Virtual IP address management.
+//!
+//! Addresses are handed out from an `AddressPool` and recorded as leases in the conductor
+//! database, so every address is owned by at most one account. Allocation is deterministic: an
+//! address preferred by the caller is used when it is free, otherwise the lowest free address in
+//! the pool is chosen.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use ipnetwork::IpNetwork;
+
+/// Name of the pool account VIPs are leased from.
+pub const ACCOUNT_POOL_NAME: &str = "vip";
+
+/// A range of addresses that leases are allocated from.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AddressPool {
+    /// Identifies the pool in the lease table.
+    pub name:       String,
+    pub network:    IpNetwork,
+    /// Addresses inside `network` that are never handed out, e.g. gateways.
+    #[serde(default)]
+    pub reserved:   Vec<IpNetwork>,
+}
+
+impl AddressPool {
+    pub fn new(name: &str, network: IpNetwork, reserved: Vec<IpNetwork>) -> Self {
+        AddressPool {
+            name: name.to_string(),
+            network,
+            reserved,
+        }
+    }
+
+    /// The default account pool: 10.0.0.0/8 without the proxy gateway 10.255.255.254.
+    pub fn default_v4() -> Self {
+        Self::new(
+            ACCOUNT_POOL_NAME,
+            IpNetwork::from_str("10.0.0.0/8").unwrap(),
+            vec![IpNetwork::from_str("10.255.255.254/32").unwrap()],
+        )
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.network.is_ipv4()
+    }
+
+    /// Returns whether `ip` may be leased from this pool. The network address, the IPv4
+    /// broadcast address and reserved ranges are excluded.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        if ip.is_ipv4() != self.is_ipv4() {
+            return false;
+        }
+        let ip = to_u128(ip);
+        let (first, last) = self.host_range();
+        first <= ip && ip <= last && self.reserved_range_containing(ip).is_none()
+    }
+
+    /// Returns the lowest address in the pool that isn't reserved and isn't in `leased`.
+    /// `leased` must be sorted in ascending order.
+    pub fn first_free(&self, leased: &[IpAddr]) -> Option<IpAddr> {
+        let (mut candidate, last) = self.host_range();
+        let mut leased = leased.iter().map(|ip| to_u128(*ip)).peekable();
+        while candidate <= last {
+            if let Some(reserved_end) = self.reserved_range_containing(candidate) {
+                candidate = reserved_end.checked_add(1)?;
+                continue;
+            }
+            while leased.peek().map(|ip| *ip < candidate).unwrap_or(false) {
+                leased.next();
+            }
+            if leased.peek() == Some(&candidate) {
+                candidate = candidate.checked_add(1)?;
+                continue;
+            }
+            return Some(self.to_addr(candidate));
+        }
+        None
+    }
+
+    /// First and last address of the pool's network, including excluded addresses.
+    pub(crate) fn bounds(&self) -> (IpAddr, IpAddr) {
+        let (first, last) = network_range(&self.network);
+        (self.to_addr(first), self.to_addr(last))
+    }
+
+    fn host_range(&self) -> (u128, u128) {
+        let (first, last) = network_range(&self.network);
+        let first = if last > first { first + 1 } else { first };
+        let last = if self.is_ipv4() && last > first { last - 1 } else { last };
+        (first, last)
+    }
+
+    fn reserved_range_containing(&self, ip: u128) -> Option<u128> {
+        self.reserved
+            .iter()
+            .filter(|network| network.is_ipv4() == self.is_ipv4())
+            .map(network_range)
+            .find(|(start, end)| *start <= ip && ip <= *end)
+            .map(|(_, end)| end)
+    }
+
+    fn to_addr(&self, ip: u128) -> IpAddr {
+        if self.is_ipv4() {
+            IpAddr::V4(Ipv4Addr::from(ip as u32))
+        } else {
+            IpAddr::V6(Ipv6Addr::from(ip))
+        }
+    }
+}
+
+/// Pools the conductor allocates from.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IpamConfig {
+    /// Pool account VIPs are leased from.
+    pub account_pool: AddressPool,
+}
+
+impl Default for IpamConfig {
+    fn default() -> Self {
+        IpamConfig {
+            account_pool: AddressPool::default_v4(),
+        }
+    }
+}
+
+/// Encodes an address as big-endian octets, which sort in address order.
+pub(crate) fn to_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+pub(crate) fn from_octets(octets: &[u8]) -> Option<IpAddr> {
+    match octets.len() {
+        4 => {
+            let mut buf = [0u8; 4];
+            buf.copy_from_slice(octets);
+            Some(IpAddr::V4(Ipv4Addr::from(buf)))
+        }
+        16 => {
+            let mut buf = [0u8; 16];
+            buf.copy_from_slice(octets);
+            Some(IpAddr::V6(Ipv6Addr::from(buf)))
+        }
+        _ => None,
+    }
+}
+
+fn to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(u32::from(ip)),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+fn network_range(network: &IpNetwork) -> (u128, u128) {
+    let bits: u32 = if network.is_ipv4() { 32 } else { 128 };
+    let host_bits = bits - u32::from(network.prefix());
+    let host_mask = if host_bits == 128 {
+        u128::max_value()
+    } else {
+        (1u128 << host_bits) - 1
+    };
+    let start = to_u128(network.ip()) & !host_mask;
+    (start, start | host_mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_first_free_skips_leased_and_reserved() {
+        let pool = AddressPool::new(
+            "test",
+            IpNetwork::from_str("10.0.0.0/29").unwrap(),
+            vec![IpNetwork::from_str("10.0.0.2/31").unwrap()],
+        );
+        assert_eq!(pool.first_free(&[]), Some(ip("10.0.0.1")));
+        assert_eq!(pool.first_free(&[ip("10.0.0.1")]), Some(ip("10.0.0.4")));
+        assert_eq!(
+            pool.first_free(&[ip("10.0.0.1"), ip("10.0.0.4"), ip("10.0.0.5")]),
+            Some(ip("10.0.0.6"))
+        );
+        assert_eq!(
+            pool.first_free(&[ip("10.0.0.1"), ip("10.0.0.4"), ip("10.0.0.5"), ip("10.0.0.6")]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_contains() {
+        let pool = AddressPool::default_v4();
+        assert!(pool.contains(ip("10.1.2.3")));
+        assert!(!pool.contains(ip("10.0.0.0")));
+        assert!(!pool.contains(ip("10.255.255.254")));
+        assert!(!pool.contains(ip("10.255.255.255")));
+        assert!(!pool.contains(ip("11.0.0.1")));
+        assert!(!pool.contains(ip("::1")));
+    }
+
+    #[test]
+    fn test_ipv6_pool() {
+        let pool = AddressPool::new("v6", IpNetwork::from_str("fd00::/120").unwrap(), vec![]);
+        assert_eq!(pool.first_free(&[]), Some(ip("fd00::1")));
+        assert_eq!(pool.first_free(&[ip("fd00::1")]), Some(ip("fd00::2")));
+        assert!(pool.contains(ip("fd00::ff")));
+        assert!(!pool.contains(ip("10.0.0.1")));
+    }
+}
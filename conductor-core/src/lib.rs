@@ -1,9 +1,18 @@
 #[macro_use]
 extern crate serde_derive;
 
+pub mod account;
+pub mod app_version;
+pub mod auth;
+pub mod client;
 mod database;
-pub use database::Database;
+pub mod ipam;
+pub mod relay;
+pub mod report;
+pub mod wireguard;
+pub use database::{Database, SqliteDatabase, DEFAULT_DATABASE_PATH};
 pub use database::Error as DbError;
 mod types;
-pub use types::AccountInfo;
-pub mod convention;
\ No newline at end of file
+pub use types::{AccountInfo, AccountStatus};
+pub mod convention;
+pub mod dispatcher;
@@ -0,0 +1,389 @@
+//! Relay list management.
+//!
+//! Every change is applied to the stored list inside a single transaction and checked with
+//! `validate` before it is written back, so clients are never served a list they can't use.
+//! Each function returns the ETag of the resulting list.
+
+use std::collections::HashSet;
+
+use mullvad_types::relay_list::{Relay, RelayList, RelayListCity, RelayListCountry};
+
+use crate::database::{Database, Error, Result};
+
+/// The relay list as served to clients.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VersionedRelayList {
+    /// The list serialized as JSON.
+    pub json:       String,
+    /// Identifies the content of `json`, see `etag`.
+    pub etag:       String,
+}
+
+impl VersionedRelayList {
+    pub fn new(json: String) -> Self {
+        let etag = etag(&json);
+        VersionedRelayList { json, etag }
+    }
+}
+
+/// Relay list together with its ETag, as returned to admin clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayListVersion {
+    pub etag:       String,
+    pub relay_list: RelayList,
+}
+
+pub fn show(db: &dyn Database) -> Result<RelayListVersion> {
+    let current = db.relay_list_versioned()?;
+    let relay_list = serde_json::from_str(&current.json).map_err(Error::JsonError)?;
+    Ok(RelayListVersion {
+        etag: current.etag,
+        relay_list,
+    })
+}
+
+/// Replaces the whole relay list.
+pub fn replace(db: &dyn Database, relay_list: RelayList) -> Result<String> {
+    modify(db, &mut |current| {
+        *current = relay_list.clone();
+        Ok(())
+    })
+}
+
+pub fn add_country(db: &dyn Database, code: &str, name: &str) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        if relay_list.countries.iter().any(|country| country.code == code) {
+            return Err(Error::RelayEntryExists(format!("country {}", code)));
+        }
+        relay_list.countries.push(RelayListCountry {
+            name: name.to_string(),
+            code: code.to_string(),
+            cities: vec![],
+        });
+        Ok(())
+    })
+}
+
+pub fn update_country(db: &dyn Database, code: &str, name: &str) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        find_country(relay_list, code)?.name = name.to_string();
+        Ok(())
+    })
+}
+
+/// Removes a country with all its cities and relays.
+pub fn remove_country(db: &dyn Database, code: &str) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        find_country(relay_list, code)?;
+        relay_list.countries.retain(|country| country.code != code);
+        Ok(())
+    })
+}
+
+pub fn add_city(
+    db: &dyn Database,
+    country: &str,
+    code: &str,
+    name: &str,
+    latitude: f64,
+    longitude: f64,
+) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        let country = find_country(relay_list, country)?;
+        if country.cities.iter().any(|city| city.code == code) {
+            return Err(Error::RelayEntryExists(format!("city {}", code)));
+        }
+        country.cities.push(RelayListCity {
+            name: name.to_string(),
+            code: code.to_string(),
+            latitude,
+            longitude,
+            relays: vec![],
+        });
+        Ok(())
+    })
+}
+
+/// Changes the name and position of a city. `None` keeps the current value.
+pub fn update_city(
+    db: &dyn Database,
+    country: &str,
+    code: &str,
+    name: Option<&str>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        let city = find_city(find_country(relay_list, country)?, code)?;
+        if let Some(name) = name {
+            city.name = name.to_string();
+        }
+        if let Some(latitude) = latitude {
+            city.latitude = latitude;
+        }
+        if let Some(longitude) = longitude {
+            city.longitude = longitude;
+        }
+        Ok(())
+    })
+}
+
+/// Removes a city with all its relays.
+pub fn remove_city(db: &dyn Database, country: &str, code: &str) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        let country = find_country(relay_list, country)?;
+        find_city(country, code)?;
+        country.cities.retain(|city| city.code != code);
+        Ok(())
+    })
+}
+
+pub fn add_relay(db: &dyn Database, country: &str, city: &str, relay: Relay) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        if relays(relay_list).any(|existing| existing.hostname == relay.hostname) {
+            return Err(Error::RelayEntryExists(format!("relay {}", relay.hostname)));
+        }
+        let city = find_city(find_country(relay_list, country)?, city)?;
+        city.relays.push(relay.clone());
+        Ok(())
+    })
+}
+
+/// Replaces the relay with the same hostname, keeping it in its city.
+pub fn update_relay(db: &dyn Database, relay: Relay) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        *find_relay(relay_list, &relay.hostname)? = relay.clone();
+        Ok(())
+    })
+}
+
+pub fn remove_relay(db: &dyn Database, hostname: &str) -> Result<String> {
+    modify(db, &mut |relay_list| {
+        find_relay(relay_list, hostname)?;
+        for country in &mut relay_list.countries {
+            for city in &mut country.cities {
+                city.relays.retain(|relay| relay.hostname != hostname);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Checks that the relay list is usable by clients: codes and hostnames are well formed and
+/// unique, coordinates are on the globe and every port can be connected to.
+pub fn validate(relay_list: &RelayList) -> std::result::Result<(), String> {
+    let mut country_codes = HashSet::new();
+    let mut hostnames = HashSet::new();
+    for country in &relay_list.countries {
+        if !is_code(&country.code, 2) {
+            return Err(format!("Invalid country code \"{}\"", country.code));
+        }
+        if !country_codes.insert(&country.code) {
+            return Err(format!("Duplicate country {}", country.code));
+        }
+        if country.name.trim().is_empty() {
+            return Err(format!("Country {} has no name", country.code));
+        }
+
+        let mut city_codes = HashSet::new();
+        for city in &country.cities {
+            if !is_code(&city.code, 3) {
+                return Err(format!("Invalid city code \"{}\"", city.code));
+            }
+            if !city_codes.insert(&city.code) {
+                return Err(format!("Duplicate city {} in {}", city.code, country.code));
+            }
+            if city.name.trim().is_empty() {
+                return Err(format!("City {} has no name", city.code));
+            }
+            let on_globe = city.latitude.abs() <= 90.0 && city.longitude.abs() <= 180.0;
+            if !on_globe {
+                return Err(format!("City {} has invalid coordinates", city.code));
+            }
+
+            for relay in &city.relays {
+                if !is_hostname(&relay.hostname) {
+                    return Err(format!("Invalid hostname \"{}\"", relay.hostname));
+                }
+                if !hostnames.insert(&relay.hostname) {
+                    return Err(format!("Duplicate relay {}", relay.hostname));
+                }
+                validate_relay(relay).map_err(|e| format!("Relay {}: {}", relay.hostname, e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_relay(relay: &Relay) -> std::result::Result<(), String> {
+    if relay.ipv4_addr_in.is_unspecified() || relay.ipv4_addr_in.is_broadcast() {
+        return Err(format!("invalid IPv4 address {}", relay.ipv4_addr_in));
+    }
+    if let Some(ipv6) = relay.ipv6_addr_in {
+        if ipv6.is_unspecified() {
+            return Err(format!("invalid IPv6 address {}", ipv6));
+        }
+    }
+    let ports = relay
+        .tunnels
+        .openvpn
+        .iter()
+        .map(|endpoint| endpoint.port)
+        .chain(relay.tunnels.tinc.iter().map(|endpoint| endpoint.port))
+        .chain(relay.bridges.shadowsocks.iter().map(|endpoint| endpoint.port));
+    for port in ports {
+        if port == 0 {
+            return Err("port 0 is not allowed".to_string());
+        }
+    }
+    for wireguard in &relay.tunnels.wireguard {
+        if wireguard.port_ranges.is_empty() {
+            return Err("WireGuard tunnel without ports".to_string());
+        }
+        for (first, last) in &wireguard.port_ranges {
+            if *first == 0 || first > last {
+                return Err(format!("invalid WireGuard port range {}-{}", first, last));
+            }
+        }
+    }
+    for shadowsocks in &relay.bridges.shadowsocks {
+        if shadowsocks.cipher.is_empty() {
+            return Err("Shadowsocks bridge without cipher".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Strong ETag of a serialized relay list: the first half of its SHA-256 hash, hex encoded.
+pub fn etag(json: &str) -> String {
+    openssl::sha::sha256(json.as_bytes())[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns whether an `If-None-Match` header value matches `etag`.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        let candidate = if candidate.starts_with("W/") {
+            &candidate[2..]
+        } else {
+            candidate
+        };
+        candidate == "*" || candidate.trim_matches('"') == etag
+    })
+}
+
+fn modify(
+    db: &dyn Database,
+    change: &mut dyn FnMut(&mut RelayList) -> Result<()>,
+) -> Result<String> {
+    let updated = db.relay_list_modify(&mut |relay_list| {
+        change(relay_list)?;
+        validate(relay_list).map_err(Error::InvalidRelayList)
+    })?;
+    Ok(updated.etag)
+}
+
+fn find_country<'a>(relay_list: &'a mut RelayList, code: &str) -> Result<&'a mut RelayListCountry> {
+    relay_list
+        .countries
+        .iter_mut()
+        .find(|country| country.code == code)
+        .ok_or_else(|| Error::NoRelayEntry(format!("country {}", code)))
+}
+
+fn find_city<'a>(country: &'a mut RelayListCountry, code: &str) -> Result<&'a mut RelayListCity> {
+    let country_code = &country.code;
+    country
+        .cities
+        .iter_mut()
+        .find(|city| city.code == code)
+        .ok_or_else(|| Error::NoRelayEntry(format!("city {} in {}", code, country_code)))
+}
+
+fn find_relay<'a>(relay_list: &'a mut RelayList, hostname: &str) -> Result<&'a mut Relay> {
+    relay_list
+        .countries
+        .iter_mut()
+        .flat_map(|country| country.cities.iter_mut())
+        .flat_map(|city| city.relays.iter_mut())
+        .find(|relay| relay.hostname == hostname)
+        .ok_or_else(|| Error::NoRelayEntry(format!("relay {}", hostname)))
+}
+
+fn relays<'a>(relay_list: &'a RelayList) -> impl Iterator<Item = &'a Relay> {
+    relay_list
+        .countries
+        .iter()
+        .flat_map(|country| country.cities.iter())
+        .flat_map(|city| city.relays.iter())
+}
+
+fn is_code(code: &str, len: usize) -> bool {
+    code.len() == len && code.chars().all(|c| c.is_ascii_lowercase())
+}
+
+fn is_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relay_list() -> RelayList {
+        serde_json::from_str(
+            r#"{"countries": [{"name": "Sweden", "code": "se", "cities": [
+                {"name": "Gothenburg", "code": "got", "latitude": 57.7, "longitude": 11.97,
+                 "relays": [{"hostname": "se-got-001", "ipv4_addr_in": "185.213.154.68",
+                             "include_in_country": true, "weight": 100,
+                             "tunnels": {"tinc": [{"port": 655, "protocol": "udp"}]}}]}
+            ]}]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut list = relay_list();
+        assert_eq!(validate(&list), Ok(()));
+
+        list.countries[0].cities[0].latitude = 91.0;
+        assert!(validate(&list).is_err());
+
+        let mut list = relay_list();
+        let duplicate = list.countries[0].cities[0].relays[0].clone();
+        list.countries[0].cities[0].relays.push(duplicate);
+        assert!(validate(&list).is_err());
+
+        let mut list = relay_list();
+        list.countries[0].cities[0].relays[0].tunnels.tinc[0].port = 0;
+        assert!(validate(&list).is_err());
+
+        let mut list = relay_list();
+        list.countries[0].code = "SWE".to_string();
+        assert!(validate(&list).is_err());
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let tag = etag("{}");
+        assert_eq!(tag.len(), 32);
+        assert!(etag_matches(&format!("\"{}\"", tag), &tag));
+        assert!(etag_matches(&format!("\"other\", W/\"{}\"", tag), &tag));
+        assert!(etag_matches("*", &tag));
+        assert!(!etag_matches("\"other\"", &tag));
+    }
+}
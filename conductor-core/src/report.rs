@@ -0,0 +1,238 @@
+//! Problem reports sent by the clients through `problem_report`.
+//!
+//! The log of a report is the file written by mullvad-problem-report, which starts with the
+//! system information of the client. That header is parsed into the metadata of the stored
+//! report, so reports can be browsed without reading their logs.
+
+use std::collections::BTreeMap;
+
+use chrono::{offset::Utc, DateTime, Duration};
+
+use crate::database::{Database, Error, Result};
+
+/// Number of reports returned by `list` when the filter doesn't specify a limit.
+pub const DEFAULT_PAGE_LIMIT: u64 = 100;
+/// Most reports `list` returns at once, whatever the limit of the filter.
+pub const MAX_PAGE_LIMIT: u64 = 1000;
+
+/// First line of a report written by mullvad-problem-report.
+const METADATA_HEADER: &str = "System information:";
+/// Separates keys from values in the metadata header.
+const METADATA_SEPARATOR: &str = ": ";
+
+/// Limits applied to incoming reports.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportConfig {
+    /// Largest accepted log in bytes. mullvad-problem-report sends at most 672 KiB, which can
+    /// grow a little when invalid UTF-8 is replaced.
+    pub max_log_size:           usize,
+    pub max_message_size:       usize,
+    pub max_email_size:         usize,
+    /// Largest accepted metadata, counting the bytes of all keys and values.
+    pub max_metadata_size:      usize,
+    /// Number of reports a single client address may send within `window_secs`.
+    pub max_reports_per_window: u64,
+    pub window_secs:            u64,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        ReportConfig {
+            max_log_size:           1024 * 1024,
+            max_message_size:       64 * 1024,
+            max_email_size:         254,
+            max_metadata_size:      16 * 1024,
+            max_reports_per_window: 5,
+            window_secs:            60 * 60,
+        }
+    }
+}
+
+/// Everything about a report except its message and log.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReportSummary {
+    pub id:         i64,
+    pub received:   DateTime<Utc>,
+    /// Address the report was sent from.
+    pub client:     String,
+    /// Account the report has been attached to by an operator.
+    pub account:    Option<String>,
+    pub email:      String,
+    pub metadata:   BTreeMap<String, String>,
+    /// Size of the log in bytes.
+    pub log_size:   u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProblemReport {
+    pub summary:    ReportSummary,
+    pub message:    String,
+    pub log:        String,
+}
+
+/// Selects a page of reports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReportFilter {
+    /// Only include reports attached to this account.
+    pub account:            Option<String>,
+    /// Only include reports sent from this address.
+    pub client:             Option<String>,
+    /// Only include reports received after this time.
+    pub received_after:     Option<DateTime<Utc>>,
+    /// Only include reports received before this time.
+    pub received_before:    Option<DateTime<Utc>>,
+    pub offset:             u64,
+    /// Capped at `MAX_PAGE_LIMIT`.
+    pub limit:              u64,
+}
+
+impl Default for ReportFilter {
+    fn default() -> Self {
+        ReportFilter {
+            account:            None,
+            client:             None,
+            received_after:     None,
+            received_before:    None,
+            offset:             0,
+            limit:              DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReportPage {
+    /// Number of reports matching the filter, across all pages.
+    pub total:      u64,
+    pub offset:     u64,
+    /// Matching reports, newest first.
+    pub reports:    Vec<ReportSummary>,
+}
+
+/// Stores a report sent by `client` and returns its id.
+///
+/// The metadata is taken from the header of `log` when it has one, and from `metadata`
+/// otherwise, like mullvad-problem-report does when it sends the report.
+pub fn submit(
+    db: &dyn Database,
+    config: &ReportConfig,
+    client: &str,
+    email: &str,
+    message: &str,
+    log: &str,
+    metadata: BTreeMap<String, String>,
+) -> Result<i64> {
+    let metadata = parse_metadata(log).unwrap_or(metadata);
+    let metadata_size: usize = metadata.iter().map(|(k, v)| k.len() + v.len()).sum();
+    if log.len() > config.max_log_size {
+        return Err(Error::ReportTooLarge("log"));
+    }
+    if message.len() > config.max_message_size {
+        return Err(Error::ReportTooLarge("message"));
+    }
+    if email.len() > config.max_email_size {
+        return Err(Error::ReportTooLarge("email"));
+    }
+    if metadata_size > config.max_metadata_size {
+        return Err(Error::ReportTooLarge("metadata"));
+    }
+
+    let now = Utc::now();
+    let window_start = now - Duration::seconds(config.window_secs as i64);
+    let report = ProblemReport {
+        summary: ReportSummary {
+            id: 0,
+            received: now,
+            client: client.to_string(),
+            account: None,
+            email: email.to_string(),
+            metadata,
+            log_size: log.len() as u64,
+        },
+        message: message.to_string(),
+        log: log.to_string(),
+    };
+    db.report_insert(&report, window_start, config.max_reports_per_window)
+}
+
+pub fn list(db: &dyn Database, filter: &ReportFilter) -> Result<ReportPage> {
+    let filter = ReportFilter {
+        limit: filter.limit.min(MAX_PAGE_LIMIT),
+        ..filter.clone()
+    };
+    db.report_search(&filter)
+}
+
+pub fn show(db: &dyn Database, id: i64) -> Result<ProblemReport> {
+    db.report_select(id)
+}
+
+/// Attaches the report to `account`, or detaches it from its account if `account` is `None`.
+pub fn attach(db: &dyn Database, id: i64, account: Option<&str>) -> Result<()> {
+    db.report_set_account(id, account)
+}
+
+/// Parses the metadata header of a report written by mullvad-problem-report.
+pub fn parse_metadata(report: &str) -> Option<BTreeMap<String, String>> {
+    mullvad_problem_report::parse_metadata(report)
+}
+
+/// Returns the report in the format written by mullvad-problem-report. Logs sent without a
+/// metadata header get one built from the stored metadata.
+pub fn render(report: &ProblemReport) -> String {
+    if parse_metadata(&report.log).is_some() {
+        return report.log.clone();
+    }
+    let mut text = format!("{}\n", METADATA_HEADER);
+    for (key, value) in &report.summary.metadata {
+        text.push_str(&format!("{}{}{}\n", key, METADATA_SEPARATOR, value));
+    }
+    text.push('\n');
+    text.push_str(&report.log);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = "System information:\nid: 8a1b\nos: Linux\n\n====================\nLog: \
+                          daemon.log\n====================\nstarted\n";
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = parse_metadata(REPORT).unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["os"], "Linux");
+
+        let metadata = parse_metadata(&REPORT.replace('\n', "\r\n")).unwrap();
+        assert_eq!(metadata["id"], "8a1b");
+
+        assert_eq!(parse_metadata("started\n"), None);
+        assert_eq!(parse_metadata("System information:\nbroken line\n"), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut report = ProblemReport {
+            summary: ReportSummary {
+                id: 1,
+                received: Utc::now(),
+                client: "127.0.0.1".to_string(),
+                account: None,
+                email: String::new(),
+                metadata: parse_metadata(REPORT).unwrap(),
+                log_size: 0,
+            },
+            message: String::new(),
+            log: REPORT.to_string(),
+        };
+        assert_eq!(render(&report), REPORT);
+
+        report.log = "started\n".to_string();
+        let rendered = render(&report);
+        assert_eq!(parse_metadata(&rendered), parse_metadata(REPORT));
+        assert!(rendered.ends_with("\n\nstarted\n"));
+    }
+}
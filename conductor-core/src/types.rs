@@ -1,11 +1,12 @@
 use chrono::{offset::Utc, TimeZone, DateTime};
+use std::fmt;
 use std::net::IpAddr;
 use std::str::FromStr;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct AccountInfo {
     pub expiry:     DateTime<Utc>,
-    pub status:     String,
+    pub status:     AccountStatus,
     pub vip:        IpAddr,
 }
 
@@ -16,8 +17,66 @@ impl AccountInfo {
 
         AccountInfo {
             expiry,
-            status: String::new(),
+            status: AccountStatus::Unused,
             vip:    IpAddr::from_str("255.255.255.255").unwrap(),
         }
     }
-}
\ No newline at end of file
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expiry < now
+    }
+}
+
+/// Lifecycle state of an account.
+///
+/// New accounts start out as `Unused` and become `Active` the first time a client registers a
+/// key. `Expired` is entered once the expiry time has passed, and left again when the account is
+/// extended. `Suspended` is only entered and left by an operator.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    Unused,
+    Active,
+    Expired,
+    Suspended,
+}
+
+impl AccountStatus {
+    /// Returns whether the server allows moving an account from `self` to `next`.
+    pub fn can_transition_to(self, next: AccountStatus) -> bool {
+        use self::AccountStatus::*;
+        match (self, next) {
+            (Unused, Active) | (Unused, Expired) | (Unused, Suspended) => true,
+            (Active, Expired) | (Active, Suspended) => true,
+            (Expired, Active) | (Expired, Suspended) => true,
+            (Suspended, Active) | (Suspended, Expired) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            AccountStatus::Unused => "unused",
+            AccountStatus::Active => "active",
+            AccountStatus::Expired => "expired",
+            AccountStatus::Suspended => "suspended",
+        };
+        f.pad(status)
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unused" => Ok(AccountStatus::Unused),
+            "active" => Ok(AccountStatus::Active),
+            "expired" => Ok(AccountStatus::Expired),
+            "suspended" => Ok(AccountStatus::Suspended),
+            _ => Err(format!("Unknown account status \"{}\"", s)),
+        }
+    }
+}
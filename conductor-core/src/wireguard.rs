@@ -0,0 +1,113 @@
+//! WireGuard key registration.
+//!
+//! Every public key registered by a client gets its own pair of tunnel addresses, leased from the
+//! pools in `WireguardConfig`. The addresses stay with a key when it is replaced, and are freed
+//! when the key is revoked or the account is removed.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use chrono::{offset::Utc, DateTime};
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use mullvad_types::wireguard::AssociatedAddresses;
+use talpid_types::net::wireguard::PublicKey;
+
+use crate::account;
+use crate::database::{Database, Result};
+use crate::ipam::AddressPool;
+
+/// Name of the pool WireGuard IPv4 addresses are leased from.
+pub const IPV4_POOL_NAME: &str = "wg4";
+/// Name of the pool WireGuard IPv6 addresses are leased from.
+pub const IPV6_POOL_NAME: &str = "wg6";
+
+/// Number of keys an account may have registered when nothing else is configured.
+pub const DEFAULT_MAX_KEYS_PER_ACCOUNT: usize = 5;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WireguardConfig {
+    /// Registering more keys than this fails until one of them is revoked.
+    pub max_keys_per_account: usize,
+    pub ipv4_pool:            AddressPool,
+    pub ipv6_pool:            AddressPool,
+}
+
+impl Default for WireguardConfig {
+    /// 172.16.0.0/12 and fc00:bbbb:bbbb:bb01::/64, without the first address of each pool,
+    /// which is used by the relays.
+    fn default() -> Self {
+        WireguardConfig {
+            max_keys_per_account: DEFAULT_MAX_KEYS_PER_ACCOUNT,
+            ipv4_pool:            AddressPool::new(
+                IPV4_POOL_NAME,
+                IpNetwork::from_str("172.16.0.0/12").unwrap(),
+                vec![IpNetwork::from_str("172.16.0.1/32").unwrap()],
+            ),
+            ipv6_pool:            AddressPool::new(
+                IPV6_POOL_NAME,
+                IpNetwork::from_str("fc00:bbbb:bbbb:bb01::/64").unwrap(),
+                vec![IpNetwork::from_str("fc00:bbbb:bbbb:bb01::1/128").unwrap()],
+            ),
+        }
+    }
+}
+
+/// A registered key and the addresses leased to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireguardKey {
+    pub public_key: PublicKey,
+    pub addresses:  AssociatedAddresses,
+    pub created:    DateTime<Utc>,
+}
+
+/// Registers `key` for the account. Pushing a key that is already registered to the account
+/// returns its current addresses.
+pub fn push_key(
+    db: &dyn Database,
+    config: &WireguardConfig,
+    account: &str,
+    key: &PublicKey,
+) -> Result<AssociatedAddresses> {
+    account::activate(db, account)?;
+    db.wg_key_add(account, key, None, config)
+}
+
+/// Registers `new` in place of `old`, handing the addresses of `old` over to `new`. If `old`
+/// isn't registered this works like `push_key`.
+pub fn replace_key(
+    db: &dyn Database,
+    config: &WireguardConfig,
+    account: &str,
+    old: &PublicKey,
+    new: &PublicKey,
+) -> Result<AssociatedAddresses> {
+    account::activate(db, account)?;
+    db.wg_key_add(account, new, Some(old), config)
+}
+
+/// Returns whether `key` is registered to the account.
+pub fn check_key(db: &dyn Database, account: &str, key: &PublicKey) -> Result<bool> {
+    db.account_select(account)?;
+    Ok(db
+        .wg_key_list(account)?
+        .iter()
+        .any(|registered| registered.public_key == *key))
+}
+
+/// Revokes `key` and frees its addresses.
+pub fn remove_key(db: &dyn Database, account: &str, key: &PublicKey) -> Result<()> {
+    db.wg_key_delete(account, key)
+}
+
+pub fn list_keys(db: &dyn Database, account: &str) -> Result<Vec<WireguardKey>> {
+    db.account_select(account)?;
+    db.wg_key_list(account)
+}
+
+/// The single-host networks clients configure on their tunnel interface.
+pub fn associated_addresses(ipv4: Ipv4Addr, ipv6: Ipv6Addr) -> AssociatedAddresses {
+    AssociatedAddresses {
+        ipv4_address: Ipv4Network::new(ipv4, 32).unwrap(),
+        ipv6_address: Ipv6Network::new(ipv6, 128).unwrap(),
+    }
+}
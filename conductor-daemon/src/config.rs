@@ -0,0 +1,657 @@
+//! Configuration of conductor-daemon.
+//!
+//! Settings are read from an optional TOML file and then overridden by the command line flags of
+//! the same name, so the file can be reloaded on SIGHUP without losing flags given at startup.
+
+use std::fs;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ipnetwork::IpNetwork;
+use log::LevelFilter;
+use serde_derive::Deserialize;
+
+use conductor::ipam::{AddressPool, IpamConfig, ACCOUNT_POOL_NAME};
+use conductor::report::ReportConfig;
+use conductor::wireguard::{WireguardConfig, IPV4_POOL_NAME, IPV6_POOL_NAME};
+use conductor::DEFAULT_DATABASE_PATH;
+use tinc_plugin::{TincNetwork, DEFAULT_INTERFACE, DEFAULT_NETWORK_NAME, DEFAULT_PORT};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Unable to read config file {}", _0)]
+    Read(String, #[error(cause)] io::Error),
+    #[error(display = "Invalid config file {}: {}", _0, _1)]
+    Parse(String, toml::de::Error),
+    #[error(display = "Invalid {} \"{}\": {}", _0, _1, _2)]
+    Invalid(&'static str, String, String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the client API listens on.
+    pub bind:       String,
+    /// Number of worker threads of each listener.
+    pub workers:    usize,
+    /// One of `off`, `error`, `warn`, `info`, `debug` and `trace`.
+    pub log_level:  String,
+    pub database:   PathBuf,
+    /// Directory holding the directories of the tinc networks.
+    pub tinc_home:      PathBuf,
+    /// The tinc networks served. Clients that don't name a network get the first one.
+    pub tinc_networks:  Vec<NetworkConfig>,
+    pub tls:            TlsConfig,
+    pub admin:          AdminConfig,
+    /// Pool account VIPs are leased from.
+    pub ipam:           PoolConfig,
+    pub wireguard:      WireguardPoolsConfig,
+    /// Limits applied to incoming problem reports.
+    pub reports:        ReportConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind:       "0.0.0.0:50071".to_string(),
+            workers:    1,
+            log_level:  "info".to_string(),
+            database:   PathBuf::from(DEFAULT_DATABASE_PATH),
+            tinc_home:      PathBuf::from("/root/mullvadvpn-app/"),
+            tinc_networks:  vec![NetworkConfig::default()],
+            tls:            TlsConfig::default(),
+            admin:          AdminConfig::default(),
+            ipam:           PoolConfig::from(&IpamConfig::default().account_pool),
+            wireguard:      WireguardPoolsConfig::default(),
+            reports:        ReportConfig::default(),
+        }
+    }
+}
+
+/// A tinc network served by the proxy.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Name of the network, also the name of its directory in `tinc_home`.
+    pub name:       String,
+    pub interface:  String,
+    /// Port tincd listens on for peers and control connections.
+    pub port:       u16,
+    /// Gateway of the clients, the VIP of the proxy. Required unless the host file of the proxy
+    /// has a Subnet of its VIP.
+    pub gateway:    Option<IpAddr>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            name:       DEFAULT_NETWORK_NAME.to_string(),
+            interface:  DEFAULT_INTERFACE.to_string(),
+            port:       DEFAULT_PORT,
+            gateway:    None,
+        }
+    }
+}
+
+/// Certificate served on both listeners.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Certificate chain in PEM format.
+    pub certificate:    PathBuf,
+    /// Private key of the certificate in PEM format.
+    pub private_key:    PathBuf,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            certificate:    PathBuf::from("cert.pem"),
+            private_key:    PathBuf::from("key.pem"),
+        }
+    }
+}
+
+/// Where the admin API is served, and how its callers are authenticated.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub bind:       String,
+    /// CA that admin client certificates must be signed by. Bearer tokens are required instead
+    /// when this isn't set.
+    pub client_ca:  Option<PathBuf>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            bind:       "127.0.0.1:50072".to_string(),
+            client_ca:  None,
+        }
+    }
+}
+
+/// A range of addresses leases are allocated from. Leases already made outside of it are
+/// replaced the next time they are used.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolConfig {
+    pub network:    IpNetwork,
+    /// Addresses inside `network` that are never leased, e.g. gateways.
+    #[serde(default)]
+    pub reserved:   Vec<IpNetwork>,
+}
+
+impl PoolConfig {
+    fn to_pool(&self, name: &str) -> AddressPool {
+        AddressPool::new(name, self.network, self.reserved.clone())
+    }
+}
+
+impl<'a> From<&'a AddressPool> for PoolConfig {
+    fn from(pool: &'a AddressPool) -> Self {
+        PoolConfig {
+            network:    pool.network,
+            reserved:   pool.reserved.clone(),
+        }
+    }
+}
+
+/// Pools the tunnel addresses of WireGuard keys are leased from.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WireguardPoolsConfig {
+    pub max_keys_per_account:   usize,
+    pub ipv4:                   PoolConfig,
+    pub ipv6:                   PoolConfig,
+}
+
+impl Default for WireguardPoolsConfig {
+    fn default() -> Self {
+        let defaults = WireguardConfig::default();
+        WireguardPoolsConfig {
+            max_keys_per_account:   defaults.max_keys_per_account,
+            ipv4:                   PoolConfig::from(&defaults.ipv4_pool),
+            ipv6:                   PoolConfig::from(&defaults.ipv6_pool),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the file given with `--config`, if any, applies the other flags and validates the
+    /// result.
+    pub fn load(matches: &clap::ArgMatches<'_>) -> Result<Self> {
+        let mut config = match matches.value_of("config") {
+            Some(path) => Self::read(Path::new(path))?,
+            None => Config::default(),
+        };
+        config.apply_flags(matches)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let name = path.display().to_string();
+        let content = fs::read_to_string(path).map_err(|e| Error::Read(name.clone(), e))?;
+        toml::from_str(&content).map_err(|e| Error::Parse(name, e))
+    }
+
+    fn apply_flags(&mut self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(bind) = matches.value_of("bind") {
+            self.bind = bind.to_string();
+        }
+        if let Some(workers) = matches.value_of("workers") {
+            self.workers = workers.parse().map_err(|_| {
+                Error::Invalid("worker count", workers.to_string(), "Not a number".to_string())
+            })?;
+        }
+        if let Some(log_level) = matches.value_of("log-level") {
+            self.log_level = log_level.to_string();
+        }
+        if let Some(database) = matches.value_of("database") {
+            self.database = PathBuf::from(database);
+        }
+        if let Some(tinc_home) = matches.value_of("tinc-home") {
+            self.tinc_home = PathBuf::from(tinc_home);
+        }
+        let tinc_flags = ["tinc-network", "tinc-interface", "tinc-port"];
+        if tinc_flags.iter().any(|flag| matches.is_present(flag)) {
+            if self.tinc_networks.is_empty() {
+                self.tinc_networks.push(NetworkConfig::default());
+            }
+            let network = &mut self.tinc_networks[0];
+            if let Some(name) = matches.value_of("tinc-network") {
+                network.name = name.to_string();
+            }
+            if let Some(interface) = matches.value_of("tinc-interface") {
+                network.interface = interface.to_string();
+            }
+            if let Some(port) = matches.value_of("tinc-port") {
+                network.port = port.parse().map_err(|_| {
+                    Error::Invalid("tinc port", port.to_string(), "Not a port number".to_string())
+                })?;
+            }
+        }
+        if let Some(certificate) = matches.value_of("tls-cert") {
+            self.tls.certificate = PathBuf::from(certificate);
+        }
+        if let Some(private_key) = matches.value_of("tls-key") {
+            self.tls.private_key = PathBuf::from(private_key);
+        }
+        if let Some(bind) = matches.value_of("admin-bind") {
+            self.admin.bind = bind.to_string();
+        }
+        if let Some(client_ca) = matches.value_of("admin-client-ca") {
+            self.admin.client_ca = Some(PathBuf::from(client_ca));
+        }
+        if let Some(network) = matches.value_of("vip-pool") {
+            self.ipam.network = network.parse().map_err(|_| {
+                Error::Invalid("VIP pool", network.to_string(), "Not a network".to_string())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Checks everything that can be checked without binding sockets or loading certificates.
+    pub fn validate(&self) -> Result<()> {
+        check_bind("bind address", &self.bind)?;
+        check_bind("admin bind address", &self.admin.bind)?;
+        if self.workers == 0 {
+            return Err(Error::Invalid(
+                "worker count",
+                self.workers.to_string(),
+                "At least one worker is needed".to_string(),
+            ));
+        }
+        self.log_level()?;
+        check_file("TLS certificate", &self.tls.certificate)?;
+        check_file("TLS private key", &self.tls.private_key)?;
+        if let Some(ref client_ca) = self.admin.client_ca {
+            check_file("admin client CA", client_ca)?;
+        }
+        if !self.tinc_home.is_dir() {
+            return Err(invalid_path("tinc home", &self.tinc_home, "Not a directory"));
+        }
+        self.check_tinc_networks()?;
+        let database_dir = match self.database.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+            Some(dir) => dir,
+            None => return Err(invalid_path("database", &self.database, "Not a file path")),
+        };
+        if !database_dir.is_dir() {
+            return Err(invalid_path("database", &self.database, "No such directory"));
+        }
+        // Clients name their tinc node after their VIP, which only works with IPv4 addresses.
+        check_pool("VIP pool", &self.ipam().account_pool, true)?;
+        let wireguard = self.wireguard();
+        check_pool("WireGuard IPv4 pool", &wireguard.ipv4_pool, true)?;
+        check_pool("WireGuard IPv6 pool", &wireguard.ipv6_pool, false)?;
+        if wireguard.max_keys_per_account == 0 {
+            return Err(Error::Invalid(
+                "WireGuard key limit",
+                wireguard.max_keys_per_account.to_string(),
+                "At least one key per account is needed".to_string(),
+            ));
+        }
+        self.check_reports()?;
+        Ok(())
+    }
+
+    fn check_tinc_networks(&self) -> Result<()> {
+        let networks = self.tinc_networks();
+        if networks.is_empty() {
+            return Err(Error::Invalid(
+                "tinc networks",
+                String::new(),
+                "At least one network is needed".to_string(),
+            ));
+        }
+        for (i, network) in networks.iter().enumerate() {
+            let describe = || format!("{} on {}:{}", network.name, network.interface, network.port);
+            if !network.is_valid() {
+                return Err(Error::Invalid(
+                    "tinc network",
+                    describe(),
+                    "Names may only contain letters, digits and underscores".to_string(),
+                ));
+            }
+            // Clients reach the gateway on the link of their VIP.
+            if let Some(gateway) = self.tinc_networks[i].gateway {
+                if !self.ipam.network.contains(gateway) {
+                    return Err(Error::Invalid(
+                        "tinc gateway",
+                        gateway.to_string(),
+                        format!("Not in the VIP pool {}", self.ipam.network),
+                    ));
+                }
+            }
+            for other in &networks[..i] {
+                let clash = if other.name == network.name {
+                    "name"
+                } else if other.interface == network.interface {
+                    "interface"
+                } else if other.port == network.port {
+                    "port"
+                } else {
+                    continue;
+                };
+                return Err(Error::Invalid(
+                    "tinc network",
+                    describe(),
+                    format!("Same {} as network {}", clash, other.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_reports(&self) -> Result<()> {
+        let reports = &self.reports;
+        if reports.window_secs == 0 {
+            return Err(Error::Invalid(
+                "problem report window",
+                reports.window_secs.to_string(),
+                "Must be at least one second".to_string(),
+            ));
+        }
+        // Escaping the log for JSON can make it grow, leave room for that in the request.
+        let largest_report = reports.max_log_size
+            + reports.max_message_size
+            + reports.max_email_size
+            + reports.max_metadata_size;
+        if largest_report.saturating_mul(2) > crate::MAX_RPC_BODY_SIZE {
+            return Err(Error::Invalid(
+                "problem report size limits",
+                format!("{} bytes in total", largest_report),
+                format!(
+                    "Reports this large don't fit in a request of at most {} bytes",
+                    crate::MAX_RPC_BODY_SIZE
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn ipam(&self) -> IpamConfig {
+        IpamConfig {
+            account_pool: self.ipam.to_pool(ACCOUNT_POOL_NAME),
+        }
+    }
+
+    pub fn wireguard(&self) -> WireguardConfig {
+        WireguardConfig {
+            max_keys_per_account:   self.wireguard.max_keys_per_account,
+            ipv4_pool:              self.wireguard.ipv4.to_pool(IPV4_POOL_NAME),
+            ipv6_pool:              self.wireguard.ipv6.to_pool(IPV6_POOL_NAME),
+        }
+    }
+
+    pub fn tinc_networks(&self) -> Vec<TincNetwork> {
+        self.tinc_networks
+            .iter()
+            .map(|network| {
+                TincNetwork::new(&self.tinc_home, &network.name, &network.interface, network.port)
+            })
+            .collect()
+    }
+
+    pub fn log_level(&self) -> Result<LevelFilter> {
+        LevelFilter::from_str(&self.log_level).map_err(|_| {
+            Error::Invalid(
+                "log level",
+                self.log_level.clone(),
+                "Expected off, error, warn, info, debug or trace".to_string(),
+            )
+        })
+    }
+}
+
+/// Command line flags overriding the config file.
+pub fn clap_args() -> Vec<clap::Arg<'static, 'static>> {
+    vec![
+        clap::Arg::with_name("config")
+            .help("TOML config file, overridden by the other flags")
+            .long("config")
+            .short("c")
+            .env("CONDUCTOR_CONFIG")
+            .takes_value(true),
+        clap::Arg::with_name("bind")
+            .help("Address the client API listens on")
+            .long("bind")
+            .takes_value(true),
+        clap::Arg::with_name("workers")
+            .help("Number of worker threads of each listener")
+            .long("workers")
+            .takes_value(true),
+        clap::Arg::with_name("log-level")
+            .help("Log level")
+            .long("log-level")
+            .takes_value(true)
+            .possible_values(&["off", "error", "warn", "info", "debug", "trace"]),
+        clap::Arg::with_name("database")
+            .help("Path of the conductor database")
+            .long("database")
+            .takes_value(true),
+        clap::Arg::with_name("tinc-home")
+            .help("Directory holding the tinc networks")
+            .long("tinc-home")
+            .takes_value(true),
+        clap::Arg::with_name("tinc-network")
+            .help("Name of the first tinc network served")
+            .long("tinc-network")
+            .takes_value(true),
+        clap::Arg::with_name("tinc-interface")
+            .help("Interface of the first tinc network")
+            .long("tinc-interface")
+            .takes_value(true),
+        clap::Arg::with_name("tinc-port")
+            .help("Port tincd of the first tinc network listens on")
+            .long("tinc-port")
+            .takes_value(true),
+        clap::Arg::with_name("tls-cert")
+            .help("TLS certificate chain (PEM)")
+            .long("tls-cert")
+            .takes_value(true),
+        clap::Arg::with_name("tls-key")
+            .help("Private key of the TLS certificate (PEM)")
+            .long("tls-key")
+            .takes_value(true),
+        clap::Arg::with_name("admin-bind")
+            .help("Address the admin API listens on")
+            .long("admin-bind")
+            .takes_value(true),
+        clap::Arg::with_name("admin-client-ca")
+            .help("Require admin clients to present a certificate signed by this CA (PEM) \
+                   instead of a bearer token")
+            .long("admin-client-ca")
+            .takes_value(true),
+        clap::Arg::with_name("vip-pool")
+            .help("Network account VIPs are leased from, e.g. 10.0.0.0/8")
+            .long("vip-pool")
+            .takes_value(true),
+    ]
+}
+
+fn check_bind(what: &'static str, bind: &str) -> Result<()> {
+    match bind.to_socket_addrs() {
+        Ok(mut addrs) => {
+            if addrs.next().is_none() {
+                return Err(Error::Invalid(what, bind.to_string(), "No address".to_string()));
+            }
+            Ok(())
+        }
+        Err(e) => Err(Error::Invalid(what, bind.to_string(), e.to_string())),
+    }
+}
+
+fn check_pool(what: &'static str, pool: &AddressPool, ipv4: bool) -> Result<()> {
+    let network = pool.network.to_string();
+    if pool.is_ipv4() != ipv4 {
+        let family = if ipv4 { "Not an IPv4 network" } else { "Not an IPv6 network" };
+        return Err(Error::Invalid(what, network, family.to_string()));
+    }
+    if pool.first_free(&[]).is_none() {
+        return Err(Error::Invalid(what, network, "No address left to lease".to_string()));
+    }
+    Ok(())
+}
+
+fn check_file(what: &'static str, path: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Err(invalid_path(what, path, "No such file"));
+    }
+    Ok(())
+}
+
+fn invalid_path(what: &'static str, path: &Path, reason: &str) -> Error {
+    Error::Invalid(what, path.display().to_string(), reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config: Config = toml::from_str(
+            r#"
+            bind = "0.0.0.0:443"
+            workers = 4
+
+            [tls]
+            certificate = "/etc/conductor/cert.pem"
+
+            [admin]
+            client_ca = "/etc/conductor/admin-ca.pem"
+
+            [ipam]
+            network = "10.8.0.0/16"
+
+            [[tinc_networks]]
+            name = "customer"
+            interface = "customer"
+            port = 50070
+            gateway = "10.8.255.254"
+
+            [reports]
+            window_secs = 60
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.bind, "0.0.0.0:443");
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.log_level, "info");
+        assert_eq!(config.tls.certificate, PathBuf::from("/etc/conductor/cert.pem"));
+        assert_eq!(config.tls.private_key, PathBuf::from("key.pem"));
+        assert_eq!(config.admin.bind, "127.0.0.1:50072");
+        assert!(config.admin.client_ca.is_some());
+        assert_eq!(config.tinc_networks.len(), 1);
+        assert_eq!(config.tinc_networks[0].gateway, "10.8.255.254".parse().ok());
+        assert_eq!(config.tinc_networks()[0].home, PathBuf::from("/root/mullvadvpn-app/customer"));
+        let pool = config.ipam().account_pool;
+        assert_eq!(pool.network, "10.8.0.0/16".parse::<IpNetwork>().unwrap());
+        assert!(pool.reserved.is_empty());
+        assert_eq!(config.wireguard(), WireguardConfig::default());
+        assert_eq!(config.reports.window_secs, 60);
+        assert_eq!(config.reports.max_email_size, ReportConfig::default().max_email_size);
+
+        let example: Config = toml::from_str(include_str!("../conductor.example.toml")).unwrap();
+        assert_eq!(example.tls.private_key, PathBuf::from("/etc/conductor/key.pem"));
+        assert_eq!(example.tinc_networks, vec![NetworkConfig::default()]);
+        assert_eq!(example.ipam(), IpamConfig::default());
+        assert_eq!(example.wireguard(), WireguardConfig::default());
+        assert_eq!(example.reports, ReportConfig::default());
+
+        assert!(toml::from_str::<Config>("bnd = \"0.0.0.0:443\"").is_err());
+        assert!(toml::from_str::<Config>("workers = \"four\"").is_err());
+        assert!(toml::from_str::<Config>("[ipam]\nnetwork = \"10.0.0.0/33\"").is_err());
+        assert!(toml::from_str::<Config>("[reports]\nmax_logs = 1").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.tinc_home = dir.path().to_path_buf();
+        config.tls.certificate = dir.path().join("cert.pem");
+        config.tls.private_key = dir.path().join("key.pem");
+        fs::write(&config.tls.certificate, "").unwrap();
+        fs::write(&config.tls.private_key, "").unwrap();
+        config.validate().unwrap();
+
+        let mut invalid = config.clone();
+        invalid.bind = "0.0.0.0".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.workers = 0;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.log_level = "verbose".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.tinc_networks[0].name = "../tinc".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.tinc_networks.clear();
+        assert!(invalid.validate().is_err());
+
+        let mut valid = config.clone();
+        valid.tinc_networks.push(NetworkConfig {
+            name:       "customer".to_string(),
+            interface:  "customer".to_string(),
+            port:       50070,
+            gateway:    Some("10.255.255.253".parse().unwrap()),
+        });
+        valid.validate().unwrap();
+        let mut invalid = valid.clone();
+        invalid.tinc_networks[1].gateway = Some("192.168.0.1".parse().unwrap());
+        assert!(invalid.validate().is_err());
+        let mut invalid = valid.clone();
+        invalid.tinc_networks[1].port = DEFAULT_PORT;
+        assert!(invalid.validate().is_err());
+        let mut invalid = valid;
+        invalid.tinc_networks[1].interface = DEFAULT_INTERFACE.to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.database = dir.path().join("missing").join("conductor.db");
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.ipam.network = "fd00::/64".parse().unwrap();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.ipam.network = "10.0.0.0/30".parse().unwrap();
+        invalid.ipam.reserved = vec!["10.0.0.0/30".parse().unwrap()];
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.wireguard.ipv6 = invalid.wireguard.ipv4.clone();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.wireguard.max_keys_per_account = 0;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.reports.window_secs = 0;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.reports.max_log_size = crate::MAX_RPC_BODY_SIZE;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config;
+        invalid.admin.client_ca = Some(dir.path().join("ca.pem"));
+        assert!(invalid.validate().is_err());
+    }
+}
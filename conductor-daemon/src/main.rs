@@ -1,211 +1,158 @@
 use std::error;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, TcpListener};
 use std::sync::Arc;
 use std::sync::RwLock;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use std::str::FromStr;
-use std::fs;
-use std::path;
 
-use chrono::{offset::Utc, TimeZone, Duration};
 use clap::App as ClapApp;
-use actix_web::{middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use actix_web::dev::Server;
+use actix_web::{http::header, middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use futures::{future, Future, Stream};
 use futures_timer::Delay;
-use serde_json;
-use serde_json::Value;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
-use ipnetwork;
-use rand::{thread_rng, Rng};
-
-use mullvad_types::relay_list::RelayList;
-use mullvad_types::version::AppVersionInfo;
-use mullvad_types::wireguard::AssociatedAddresses;
+use log::LevelFilter;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::X509Name;
+#[cfg(unix)]
+use tokio_signal::unix::{Signal, SIGHUP};
+
+use talpid_types::ErrorExt;
 use tinc_plugin::{TincOperator, TincRunMode};
 
 extern crate conductor;
-#[allow(dead_code)]
 use conductor::convention;
-use conductor::Database;
-use conductor::AccountInfo;
+use conductor::{Database, SqliteDatabase};
+use conductor::{auth, relay};
+use conductor::dispatcher::Dispatcher;
+use conductor::ipam::IpamConfig;
+use conductor::report::ReportConfig;
+use conductor::wireguard::WireguardConfig;
+
+mod config;
+mod methods;
+
+use crate::config::{Config, TlsConfig};
 
 pub const COMMIT_ID: &str = include_str!(concat!(env!("OUT_DIR"), "/git-commit-id.txt"));
 
 pub const COMMIT_DATE: &str = include_str!(concat!(env!("OUT_DIR"), "/git-commit-date.txt"));
 
-/// The main handler for JSONRPC server.
+/// Largest request body accepted from the VPN clients and admins. Leaves room for a problem report
+/// with the largest log `ReportConfig` accepts by default, even after JSON escaping. The config is
+/// checked against it when loaded.
+const MAX_RPC_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// The handler for methods called by the VPN clients.
 fn rpc_handler(
     req: HttpRequest,
     payload: web::Payload,
+    methods: web::Data<Dispatcher<AppState>>,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
-    payload.concat2().from_err().and_then(move |body| {
-        let reqjson: convention::Request = match serde_json::from_slice(body.as_ref()) {
-            Ok(ok) => ok,
-            Err(_) => {
-                let r = convention::Response {
-                    jsonrpc: String::from(convention::JSONRPC_VERSION),
-                    result: Value::Null,
-                    error: Some(convention::ErrorData::std(-32700)),
-                    id: Value::Null,
-                };
-                return Ok(HttpResponse::Ok()
-                    .content_type("application/json")
-                    .body(r.dump()));
-            }
-        };
-        let app_state = req.app_data().unwrap();
-        let mut result = convention::Response::default();
-        result.id = reqjson.id.clone();
-
-        match rpc_select(&app_state, reqjson.method.as_str(), reqjson.params) {
-            Ok(ok) => result.result = ok,
-            Err(e) => result.error = Some(e),
-        }
-
-        Ok(HttpResponse::Ok()
-            .content_type("application/json")
-            .body(result.dump()))
+    read_body(payload).and_then(move |body| {
+        let app_state = req
+            .app_data::<AppState>()
+            .unwrap()
+            .with_peer(req.peer_addr().map(|addr| addr.ip()));
+        Ok(rpc_response(methods.handle(&app_state, &body)))
     })
 }
 
-fn rpc_select(
-    _app_state: &AppState,
-    method: &str,
-    params: Vec<Value>,
-) -> Result<Value, convention::ErrorData> {
-    match method {
-        "account_create" => {
-            let days_str = match params[0].as_str() {
-                Some(x) => x,
-                None => return Err(convention::ErrorData::new(500, "Error params")),
-            };
-            let days: i64 = match days_str.parse() {
-                Ok(x) => x,
-                Err(_) => return Err(convention::ErrorData::new(500, "Error params")),
-            };
-            let res = AccountOperator::create_account(days);
-            let r = serde_json::to_value(res).unwrap();
-            return Ok(r);
-        }
-        "account_update" => {
-            let acc = match params[0].as_str() {
-                Some(x) => x,
-                None => return Err(convention::ErrorData::new(400, "Error params")),
-            };
-
-            let days_str = match params[1].as_str() {
-                Some(x) => x,
-                None => return Err(convention::ErrorData::new(400, "Error params")),
-            };
-            let days: i64 = match days_str.parse() {
-                Ok(x) => x,
-                Err(_) => return Err(convention::ErrorData::new(500, "Error params")),
-            };
-            AccountOperator::update_account(acc, days);
-            let r = serde_json::to_value(()).unwrap();
-            return Ok(r);
-        }
-        "account_remove" => {
-            let acc = match params[0].as_str() {
-                Some(x) => x,
-                None => return Err(convention::ErrorData::new(400, "Error params")),
-            };
-
-            AccountOperator::remove_account(acc);
-            let r = serde_json::to_value(()).unwrap();
-            return Ok(r);
+/// The handler for account management methods. Callers must be authorized, see `is_admin`.
+fn admin_handler(
+    req: HttpRequest,
+    payload: web::Payload,
+    methods: web::Data<Dispatcher<AppState>>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    read_body(payload).and_then(move |body| {
+        let app_state = req.app_data::<AppState>().unwrap();
+        if !is_admin(&req, &app_state) {
+            let r = convention::Response::error(convention::ErrorData::new(401, "Unauthorized"));
+            return Ok(HttpResponse::Unauthorized()
+                .header(header::WWW_AUTHENTICATE, "Bearer")
+                .content_type("application/json")
+                .body(r.dump()));
         }
-        "push_tinc_key" => {
-            let acc = match params[0].as_str() {
-                Some(x) => x,
-                None => return Err(convention::ErrorData::new(400, "Error account param")),
-            };
-            if acc.len() < 6 {
-                return Err(convention::ErrorData::new(401, "Account len error."))
-            }
+        Ok(rpc_response(methods.handle(&app_state, &body)))
+    })
+}
 
-            let db = Database::instance();
-            if let Ok(info) = db.account_select(acc) {
-                let acc_time = info.expiry.timestamp();
-                let now_time = Utc::now().timestamp();
-                if acc_time - now_time < 0 {
-                    return Err(convention::ErrorData::new(401, "Account expiry."));
-                }
+/// Reads a request body, failing once it grows past `MAX_RPC_BODY_SIZE`.
+fn read_body(payload: web::Payload) -> impl Future<Item = web::BytesMut, Error = Error> {
+    payload
+        .from_err::<Error>()
+        .fold(web::BytesMut::new(), |mut body, chunk| {
+            if body.len() + chunk.len() > MAX_RPC_BODY_SIZE {
+                return Err(actix_web::error::ErrorPayloadTooLarge("Request too large"));
             }
+            body.extend_from_slice(&chunk);
+            Ok(body)
+        })
+}
 
-            let pubkey = match params[1].as_str() {
-                Some(x) => x,
-                None => return Err(convention::ErrorData::new(400, "Error pubkey param")),
-            };
-
-            let vip_num: u32 = match ("1".to_string() + &acc[4..]).parse() {
-                Ok(x) => x,
-                Err(_) => return Err(convention::ErrorData::new(400, "Error params")),
-            };
-            let vip = IpAddr::from(Ipv4Addr::from(vip_num)).to_string();
-
-            let host_name = TincOperator::get_filename_by_ip(false, &vip);
-            if let Err(_) = TincOperator::instance().add_hosts(&host_name, pubkey) {
-                return Err(convention::ErrorData::new(500, "Set host file failed."));
-            };
-            let local_pubkey = TincOperator::instance().get_local_pub_key().unwrap();
-            let r = serde_json::to_value(&local_pubkey).unwrap();
-            return Ok(r);
+/// Serves the relay list over plain HTTP GET, so that clients can fetch it conditionally with
+/// `If-None-Match`.
+fn relay_list_handler(req: HttpRequest) -> HttpResponse {
+    let app_state = req.app_data::<AppState>().unwrap();
+    let current = match app_state.db.relay_list_versioned() {
+        Ok(current) => current,
+        Err(e) => {
+            log::error!("Unable to load relay list: {}", e);
+            return HttpResponse::InternalServerError().finish();
         }
-        "get_expiry" => {
-            if let Some(acc) = params[0].as_str() {
-                let db = Database::instance();
-                match db.account_select(acc) {
-                    Ok(r) => {
-                        let expiry = r.expiry;
-                        let r = serde_json::to_value(&expiry).unwrap();
-                        return Ok(r);
-                    }
-                    Err(e) => {
-                        return Err(convention::ErrorData::new(404, &e.to_string()));
-                    }
-                }
-            }
+    };
+    let etag = format!("\"{}\"", current.etag);
+    let unchanged = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| relay::etag_matches(value, &current.etag))
+        .unwrap_or(false);
+    if unchanged {
+        return HttpResponse::NotModified().header(header::ETAG, etag).finish();
+    }
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .header(header::ETAG, etag)
+        .body(current.json)
+}
 
-            let r = Utc.ymd(1970, 1, 1)
-                .and_hms_milli(0, 0, 0, 0);
-            let r = serde_json::to_value(&r).unwrap();
-            return Ok(r);
+/// Requests made up only of notifications are answered without a body.
+fn rpc_response(reply: Option<String>) -> HttpResponse {
+    match reply {
+        Some(body) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(body),
+        None => HttpResponse::NoContent().finish(),
+    }
+}
 
+/// Requests on the admin listener are authorized by the TLS handshake when a client CA is
+/// configured, and by a bearer token from the database otherwise.
+fn is_admin(req: &HttpRequest, app_state: &AppState) -> bool {
+    if app_state.admin_mtls {
+        return true;
+    }
+    let token = match req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) if value.starts_with("Bearer ") => value["Bearer ".len()..].trim(),
+        _ => return false,
+    };
+    match auth::verify_token(&*app_state.db, token) {
+        Ok(Some(name)) => {
+            log::debug!("Admin request authorized by token {}", name);
+            true
         }
-        "problem_report" => {
-            Ok(serde_json::to_value(()).unwrap())
+        Ok(None) => {
+            log::warn!("Admin request with unknown token from {:?}", req.peer_addr());
+            false
         }
-        "relay_list_v2" => {
-            let path = path::Path::new("./relay.json");
-            let buf = fs::read(path).unwrap();
-            let res = String::from_utf8(buf).unwrap();
-
-            let relay_list: RelayList = serde_json::from_str(&res).unwrap();
-            let r = serde_json::to_value(&relay_list).unwrap();
-            Ok(r)
+        Err(e) => {
+            log::error!("Unable to verify admin token: {}", e);
+            false
         }
-
-        "app_version_check" => {
-            let version = AppVersionInfo {
-                current_is_supported: true,
-                latest_stable:        "test_latest_stable".to_string(),
-                latest:               "test_latest".to_string(),
-            };
-            let res = serde_json::to_value(&version).unwrap();
-            Ok(res)
-        }
-
-        "push_wg_key" => {
-            let ipv4 = Ipv4Addr::from_str("10.0.0.1").unwrap();
-            let ipv6 = Ipv6Addr::from_str("::0").unwrap();
-            let ip = AssociatedAddresses {
-                ipv4_address: ipnetwork::Ipv4Network::new(ipv4, 0).unwrap(),
-                ipv6_address: ipnetwork::Ipv6Network::new(ipv6, 0).unwrap(),
-            };
-            let r = serde_json::to_value(&ip).unwrap();
-            Ok(r)
-        }
-        _ => Err(convention::ErrorData::std(-32601)),
     }
 }
 
@@ -252,107 +199,318 @@ impl ImplNetwork for ObjNetwork {
 #[derive(Clone)]
 pub struct AppState {
     network: Arc<RwLock<ImplNetwork>>,
+    db: Arc<dyn Database>,
+    ipam: Arc<IpamConfig>,
+    wireguard: Arc<WireguardConfig>,
+    reports: Arc<ReportConfig>,
+    /// The tinc networks, in the order of the config.
+    tinc: Arc<Vec<ProxyNetwork>>,
+    /// Address of the client making the current request.
+    peer: Option<IpAddr>,
+    /// Whether requests were authenticated with a client certificate during the TLS handshake.
+    admin_mtls: bool,
 }
 
 impl AppState {
-    pub fn new(network: Arc<RwLock<ImplNetwork>>) -> Self {
-        Self { network }
+    pub fn new(
+        network: Arc<RwLock<ImplNetwork>>,
+        db: Arc<dyn Database>,
+        ipam: Arc<IpamConfig>,
+        wireguard: Arc<WireguardConfig>,
+        reports: Arc<ReportConfig>,
+        tinc: Arc<Vec<ProxyNetwork>>,
+        admin_mtls: bool,
+    ) -> Self {
+        Self { network, db, ipam, wireguard, reports, tinc, peer: None, admin_mtls }
     }
-}
 
-fn web_server() {
-    std::env::set_var("RUST_LOG", "info");
-    env_logger::init();
-
-    let network = Arc::new(RwLock::new(ObjNetwork::new()));
+    /// The state handed to the methods of a request made from `peer`.
+    fn with_peer(&self, peer: Option<IpAddr>) -> Self {
+        Self { peer, ..self.clone() }
+    }
+}
 
-    // load ssl keys
-    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-    builder
-        .set_private_key_file("key.pem", SslFiletype::PEM)
-        .unwrap();
-    builder.set_certificate_chain_file("cert.pem").unwrap();
+/// A tinc network served by the proxy.
+pub struct ProxyNetwork {
+    operator: TincOperator,
+    /// Gateway of the clients, the VIP of the proxy.
+    gateway: IpAddr,
+}
 
+#[derive(err_derive::Error, Debug)]
+enum StartError {
+    #[error(display = "Unable to load the TLS certificate or private key")]
+    Tls(#[error(cause)] openssl::error::ErrorStack),
+    #[error(display = "Unable to load admin client CA {}", _0)]
+    ClientCa(String, #[error(cause)] openssl::error::ErrorStack),
+    #[error(display = "Unable to bind {}", _0)]
+    Bind(String, #[error(cause)] io::Error),
+    #[error(display = "Unable to open database {}", _0)]
+    Database(String, #[error(cause)] conductor::DbError),
+    #[error(
+        display = "Gateway of tinc network {} is not configured, and not in its host file",
+        _0
+    )]
+    UnknownGateway(String),
+}
 
-    let sys = actix::System::new("actix_jrpc");
-    HttpServer::new(move || {
-        let app_state = AppState::new(network.clone());
-        App::new()
-            .data(app_state)
-            .wrap(middleware::Logger::default())
-            .service(web::resource("/rpc/").route(web::post().to_async(rpc_handler)))
-    })
-        .bind_ssl("0.0.0.0:50071", builder)
-        .unwrap()
-        .workers(1)
-        .start();
+/// State shared by every generation of listeners.
+#[derive(Clone)]
+struct Shared {
+    network: Arc<RwLock<ImplNetwork>>,
+    tinc: Arc<Vec<ProxyNetwork>>,
+    client_methods: web::Data<Dispatcher<AppState>>,
+    admin_methods: web::Data<Dispatcher<AppState>>,
+}
 
-    let _ = sys.run();
+/// The listeners started from one version of the config.
+struct Servers {
+    config: Config,
+    db: Arc<dyn Database>,
+    client: (TcpListener, Server),
+    admin: (TcpListener, Server),
 }
 
-struct AccountOperator{}
-impl AccountOperator {
-    fn create_account(days: i64) -> String {
-        let mut rng = thread_rng();
-        let db = conductor::Database::instance();
-        let mut account;
-        loop {
-            let vip_8 = rng.gen_range(67_837_953, 84_549_373).to_string();
-            let random_head = rng.gen_range(1_000, 9_999).to_string();
-            account = random_head + &vip_8;
-            match db.account_select(&account) {
-                Ok(_) => (),
-                Err(conductor::DbError::NoAccount) => break,
-                Err(e) => {
-                    return e.to_string();
-                },
-            }
+impl Servers {
+    /// Starts listeners for `config`. Sockets of `previous` are reused when their address hasn't
+    /// changed, so that no connection is refused while the listeners are replaced.
+    fn start(
+        config: Config,
+        shared: &Shared,
+        previous: Option<&Servers>,
+    ) -> Result<Self, StartError> {
+        let builder = ssl_acceptor(&config.tls)?;
+        let mut admin_builder = ssl_acceptor(&config.tls)?;
+        if let Some(ref ca) = config.admin.client_ca {
+            let ca_error = |e| StartError::ClientCa(ca.display().to_string(), e);
+            let client_cas = X509Name::load_client_ca_file(ca).map_err(ca_error)?;
+            admin_builder.set_ca_file(ca).map_err(ca_error)?;
+            admin_builder.set_client_ca_list(client_cas);
+            admin_builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
         }
-        let expiry = Utc::now() + Duration::days(days);
-        let mut num: u64 = account.parse().unwrap();
-        num = num % 10_000_0000 + 100_000_000;
-        let vip_v4 = Ipv4Addr::from(num as u32);
-        let vip = IpAddr::from(vip_v4);
-        let info = AccountInfo {
-            expiry,
-            status:     "unused".to_string(),
-            vip,
+        let admin_mtls = config.admin.client_ca.is_some();
+
+        let db: Arc<dyn Database> = match previous {
+            Some(previous) if previous.config.database == config.database => previous.db.clone(),
+            _ => match SqliteDatabase::open(&config.database) {
+                Ok(db) => Arc::new(db),
+                Err(e) => {
+                    return Err(StartError::Database(config.database.display().to_string(), e))
+                }
+            },
+        };
+        let pools = Pools {
+            ipam: Arc::new(config.ipam()),
+            wireguard: Arc::new(config.wireguard()),
+            reports: Arc::new(config.reports.clone()),
         };
-        let res = match db.account_insert(&account, &info) {
-            Ok(_) => account,
-            Err(e) => e.to_string(),
+        let client_listener = listener(
+            &config.bind,
+            previous.map(|previous| (&previous.config.bind[..], &previous.client.0)),
+        )?;
+        let admin_listener = listener(
+            &config.admin.bind,
+            previous.map(|previous| (&previous.config.admin.bind[..], &previous.admin.0)),
+        )?;
+
+        let client = {
+            let shared = shared.clone();
+            let db = db.clone();
+            let pools = pools.clone();
+            HttpServer::new(move || {
+                App::new()
+                    .data(shared.app_state(db.clone(), &pools, false))
+                    .register_data(shared.client_methods.clone())
+                    .wrap(middleware::Logger::default())
+                    .service(web::resource("/rpc/").route(web::post().to_async(rpc_handler)))
+                    .service(web::resource("/relays/").route(web::get().to(relay_list_handler)))
+            })
+                .listen_ssl(clone_listener(&config.bind, &client_listener)?, builder)
+                .map_err(|e| StartError::Bind(config.bind.clone(), e))?
+                .workers(config.workers)
+                .start()
+        };
+
+        let admin = {
+            let shared = shared.clone();
+            let db = db.clone();
+            let pools = pools.clone();
+            HttpServer::new(move || {
+                App::new()
+                    .data(shared.app_state(db.clone(), &pools, admin_mtls))
+                    .register_data(shared.admin_methods.clone())
+                    .wrap(middleware::Logger::default())
+                    .service(web::resource("/admin/").route(web::post().to_async(admin_handler)))
+            })
+                .listen_ssl(clone_listener(&config.admin.bind, &admin_listener)?, admin_builder)
+                .map_err(|e| StartError::Bind(config.admin.bind.clone(), e))?
+                .workers(config.workers)
+                .start()
         };
-        return res;
+
+        Ok(Servers {
+            config,
+            db,
+            client: (client_listener, client),
+            admin: (admin_listener, admin),
+        })
     }
 
-    fn update_account(account:&str, days: i64) -> bool {
-        let db = conductor::Database::instance();
-        if let Ok(mut info) = db.account_select(account) {
-            info.expiry = info.expiry + Duration::days(days);
-            if let Ok(_) = db.account_insert(&account, &info) {
-                return true;
-            };
+    /// Stops accepting connections, and lets requests being handled finish.
+    fn stop(&self) {
+        actix::spawn(self.client.1.stop(true));
+        actix::spawn(self.admin.1.stop(true));
+    }
+}
+
+/// Address pools and limits, which take effect on reload.
+#[derive(Clone)]
+struct Pools {
+    ipam: Arc<IpamConfig>,
+    wireguard: Arc<WireguardConfig>,
+    reports: Arc<ReportConfig>,
+}
+
+impl Shared {
+    fn app_state(&self, db: Arc<dyn Database>, pools: &Pools, admin_mtls: bool) -> AppState {
+        AppState::new(
+            self.network.clone(),
+            db,
+            pools.ipam.clone(),
+            pools.wireguard.clone(),
+            pools.reports.clone(),
+            self.tinc.clone(),
+            admin_mtls,
+        )
+    }
+}
+
+fn ssl_acceptor(tls: &TlsConfig) -> Result<SslAcceptorBuilder, StartError> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(StartError::Tls)?;
+    builder
+        .set_private_key_file(&tls.private_key, SslFiletype::PEM)
+        .map_err(StartError::Tls)?;
+    builder
+        .set_certificate_chain_file(&tls.certificate)
+        .map_err(StartError::Tls)?;
+    builder.check_private_key().map_err(StartError::Tls)?;
+    Ok(builder)
+}
+
+/// Binds `bind`, or shares the socket of the previous listener if it was bound to the same
+/// address.
+fn listener(bind: &str, previous: Option<(&str, &TcpListener)>) -> Result<TcpListener, StartError> {
+    match previous {
+        Some((previous_bind, previous)) if previous_bind == bind => clone_listener(bind, previous),
+        _ => TcpListener::bind(bind).map_err(|e| StartError::Bind(bind.to_string(), e)),
+    }
+}
+
+fn clone_listener(bind: &str, listener: &TcpListener) -> Result<TcpListener, StartError> {
+    listener
+        .try_clone()
+        .map_err(|e| StartError::Bind(bind.to_string(), e))
+}
+
+/// Loads the config again and replaces the listeners. Nothing changes if the new config is
+/// invalid.
+fn reload(matches: &clap::ArgMatches<'_>, shared: &Shared, current: &mut Servers) {
+    let config = match Config::load(matches) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e.display_chain_with_msg("Not reloading invalid config"));
+            return;
+        }
+    };
+    if config.tinc_home != current.config.tinc_home
+        || config.tinc_networks != current.config.tinc_networks
+    {
+        log::warn!("Changing the tinc networks only takes effect after a restart");
+    }
+    let log_level = config.log_level().expect("Log level was validated");
+    match Servers::start(config, shared, Some(current)) {
+        Ok(servers) => {
+            mem::replace(current, servers).stop();
+            log::set_max_level(log_level);
+            log::info!("Reloaded config");
         }
-        return false;
+        Err(e) => log::error!("{}", e.display_chain_with_msg("Unable to reload config")),
     }
+}
 
-    fn remove_account(account:&str) -> bool {
-        let db = conductor::Database::instance();
-        if let Ok(_) = db.account_delete(&account) {
-            return true;
-        };
-        return false;
+/// The tinc networks of `config`, with the gateway of each taken from the config or else from
+/// the Subnet of the host file of the proxy.
+fn proxy_networks(config: &Config) -> Result<Vec<ProxyNetwork>, StartError> {
+    config
+        .tinc_networks()
+        .into_iter()
+        .zip(&config.tinc_networks)
+        .map(|(network, network_config)| {
+            let operator = TincOperator::new(network, TincRunMode::Proxy);
+            let gateway = match network_config.gateway {
+                Some(gateway) => gateway,
+                None => operator
+                    .get_local_vip()
+                    .map_err(|_| StartError::UnknownGateway(network_config.name.clone()))?,
+            };
+            Ok(ProxyNetwork { operator, gateway })
+        })
+        .collect()
+}
+
+fn web_server(matches: clap::ArgMatches<'static>, config: Config) {
+    let tinc = proxy_networks(&config).unwrap_or_else(|e| {
+        log::error!("{}", e.display_chain());
+        std::process::exit(1);
+    });
+    let shared = Shared {
+        network: Arc::new(RwLock::new(ObjNetwork::new())),
+        tinc: Arc::new(tinc),
+        client_methods: web::Data::new(methods::client_methods()),
+        admin_methods: web::Data::new(methods::admin_methods()),
+    };
+
+    let sys = actix::System::new("actix_jrpc");
+    let mut servers = Servers::start(config, &shared, None).unwrap_or_else(|e| {
+        log::error!("{}", e.display_chain());
+        std::process::exit(1);
+    });
+
+    #[cfg(unix)]
+    {
+        let reloads = Signal::new(SIGHUP)
+            .flatten_stream()
+            .map_err(|e| log::error!("Unable to listen for SIGHUP: {}", e))
+            .for_each(move |_| {
+                reload(&matches, &shared, &mut servers);
+                Ok(())
+            });
+        actix::spawn(reloads);
     }
+
+    let _ = sys.run();
+}
+
+/// Logs at the level of the config. The level can be changed later with `log::set_max_level`.
+fn init_logging(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Trace)
+        .init();
+    log::set_max_level(level);
 }
 
 fn main() {
-    ClapApp::new("conductor")
+    let matches = ClapApp::new("conductor")
         .version(&format!("\nCommit date: {}\nCommit id: {}", COMMIT_DATE, COMMIT_ID).to_string()[..])
         .setting(clap::AppSettings::ColorAuto)
+        .args(&config::clap_args())
         .get_matches();
 
-    // TODO mullvad path
-    TincOperator::new("/root/mullvadvpn-app/", TincRunMode::Proxy);
+    let config = Config::load(&matches).unwrap_or_else(|e| {
+        eprintln!("{}", e.display_chain());
+        std::process::exit(1);
+    });
+    init_logging(config.log_level().expect("Log level was validated"));
 
-    web_server();
-}
\ No newline at end of file
+    web_server(matches, config);
+}
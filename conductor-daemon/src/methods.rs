@@ -0,0 +1,510 @@
+//! JSON-RPC methods served by the conductor.
+//!
+//! Parameters may be given by-position, in the order the Mullvad clients send them, or by-name.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use chrono::{offset::Utc, TimeZone};
+use serde_json::Value;
+
+use mullvad_types::relay_list::{Relay, RelayList};
+use talpid_types::net::wireguard::PublicKey;
+use tinc_plugin::{KeyExchange, KeyType, PublicKeys, TincOperator, TincOperatorError};
+
+use conductor::account::{self, AccountFilter};
+use conductor::app_version;
+use conductor::convention::{ErrorData, Params};
+use conductor::dispatcher::{Dispatcher, MethodResult};
+use conductor::report::{self, ReportFilter};
+use conductor::{relay, wireguard, DbError};
+
+use crate::{AppState, ProxyNetwork};
+
+/// Methods called by the VPN clients.
+pub fn client_methods() -> Dispatcher<AppState> {
+    let mut methods = Dispatcher::new();
+    methods
+        .add("push_tinc_key", push_tinc_key)
+        .add("exchange_tinc_keys", exchange_tinc_keys)
+        .add("get_expiry", get_expiry)
+        .add("problem_report", problem_report)
+        .add("relay_list_v2", relay_list_v2)
+        .add("app_version_check", app_version_check)
+        .add("push_wg_key", push_wg_key)
+        .add("replace_wg_key", replace_wg_key)
+        .add("check_wg_key", check_wg_key)
+        .add("remove_wg_key", remove_wg_key);
+    methods
+}
+
+/// Methods managing the conductor, only served on the admin listener.
+pub fn admin_methods() -> Dispatcher<AppState> {
+    let mut methods = Dispatcher::new();
+    methods
+        .add("account_create", account_create)
+        .add("account_update", account_update)
+        .add("account_remove", account_remove)
+        .add("account_list", account_list)
+        .add("account_show", account_show)
+        .add("account_suspend", account_suspend)
+        .add("account_reactivate", account_reactivate)
+        .add("lease_list", lease_list)
+        .add("wg_key_list", wg_key_list)
+        .add("wg_key_revoke", wg_key_revoke)
+        .add("report_list", report_list)
+        .add("report_show", report_show)
+        .add("report_attach", report_attach)
+        .add("version_policy_list", version_policy_list)
+        .add("version_policy_show", version_policy_show)
+        .add("version_policy_set", version_policy_set)
+        .add("version_policy_remove", version_policy_remove)
+        .add("version_block", version_block)
+        .add("version_unblock", version_unblock)
+        .add("relay_list_show", relay_list_show)
+        .add("relay_list_replace", relay_list_replace)
+        .add("relay_country_add", relay_country_add)
+        .add("relay_country_update", relay_country_update)
+        .add("relay_country_remove", relay_country_remove)
+        .add("relay_city_add", relay_city_add)
+        .add("relay_city_update", relay_city_update)
+        .add("relay_city_remove", relay_city_remove)
+        .add("relay_add", relay_add)
+        .add("relay_update", relay_update)
+        .add("relay_remove", relay_remove);
+    methods
+}
+
+fn account_create(app_state: &AppState, params: &Params) -> MethodResult {
+    let days = days_param(params, 0)?;
+    let res = account::create(&*app_state.db, &app_state.ipam.account_pool, days)
+        .map_err(db_error)?;
+    Ok(serde_json::to_value(res).unwrap())
+}
+
+fn account_update(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let days = days_param(params, 1)?;
+    account::extend(&*app_state.db, &acc, days).map_err(db_error)?;
+    Ok(serde_json::to_value(()).unwrap())
+}
+
+fn account_remove(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    account::remove(&*app_state.db, &acc).map_err(db_error)?;
+    Ok(serde_json::to_value(()).unwrap())
+}
+
+fn account_list(app_state: &AppState, params: &Params) -> MethodResult {
+    let filter: AccountFilter = params.get_optional(0, "filter")?.unwrap_or_default();
+    let page = account::list(&*app_state.db, &filter).map_err(db_error)?;
+    Ok(serde_json::to_value(&page).unwrap())
+}
+
+fn account_show(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let info = account::show(&*app_state.db, &acc).map_err(db_error)?;
+    Ok(serde_json::to_value(&info).unwrap())
+}
+
+fn account_suspend(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let info = account::suspend(&*app_state.db, &acc).map_err(db_error)?;
+    Ok(serde_json::to_value(&info).unwrap())
+}
+
+fn account_reactivate(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let info = account::reactivate(&*app_state.db, &acc).map_err(db_error)?;
+    Ok(serde_json::to_value(&info).unwrap())
+}
+
+fn lease_list(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let leases = app_state.db.lease_list(&acc).map_err(db_error)?;
+    Ok(serde_json::to_value(&leases).unwrap())
+}
+
+fn wg_key_list(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let keys = wireguard::list_keys(&*app_state.db, &acc).map_err(db_error)?;
+    Ok(serde_json::to_value(&keys).unwrap())
+}
+
+fn wg_key_revoke(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let key: PublicKey = params.get(1, "public_key")?;
+    wireguard::remove_key(&*app_state.db, &acc, &key).map_err(db_error)?;
+    Ok(serde_json::to_value(()).unwrap())
+}
+
+fn report_list(app_state: &AppState, params: &Params) -> MethodResult {
+    let filter: ReportFilter = params.get_optional(0, "filter")?.unwrap_or_default();
+    let page = report::list(&*app_state.db, &filter).map_err(db_error)?;
+    Ok(serde_json::to_value(&page).unwrap())
+}
+
+fn report_show(app_state: &AppState, params: &Params) -> MethodResult {
+    let id: i64 = params.get(0, "id")?;
+    let problem_report = report::show(&*app_state.db, id).map_err(db_error)?;
+    Ok(serde_json::to_value(&problem_report).unwrap())
+}
+
+/// Attaches a report to an account, or detaches it when no account is given.
+fn report_attach(app_state: &AppState, params: &Params) -> MethodResult {
+    let id: i64 = params.get(0, "id")?;
+    let acc: Option<String> = params.get_optional(1, "account_token")?;
+    report::attach(&*app_state.db, id, acc.as_ref().map(String::as_str)).map_err(db_error)?;
+    Ok(serde_json::to_value(()).unwrap())
+}
+
+fn version_policy_list(app_state: &AppState, _: &Params) -> MethodResult {
+    let policies = app_version::list(&*app_state.db).map_err(db_error)?;
+    Ok(serde_json::to_value(&policies).unwrap())
+}
+
+fn version_policy_show(app_state: &AppState, params: &Params) -> MethodResult {
+    let platform: String = params.get(0, "platform")?;
+    let policy = app_version::show(&*app_state.db, &platform).map_err(db_error)?;
+    Ok(serde_json::to_value(&policy).unwrap())
+}
+
+fn version_policy_set(app_state: &AppState, params: &Params) -> MethodResult {
+    let platform: String = params.get(0, "platform")?;
+    let latest_stable: String = params.get(1, "latest_stable")?;
+    let latest_beta: Option<String> = params.get_optional(2, "latest_beta")?;
+    let min_supported: Option<String> = params.get_optional(3, "min_supported")?;
+    let policy = app_version::set_releases(
+        &*app_state.db,
+        &platform,
+        &latest_stable,
+        latest_beta.as_ref().map(String::as_str),
+        min_supported.as_ref().map(String::as_str),
+    )
+    .map_err(db_error)?;
+    Ok(serde_json::to_value(&policy).unwrap())
+}
+
+fn version_policy_remove(app_state: &AppState, params: &Params) -> MethodResult {
+    let platform: String = params.get(0, "platform")?;
+    app_version::remove(&*app_state.db, &platform).map_err(db_error)?;
+    Ok(serde_json::to_value(()).unwrap())
+}
+
+fn version_block(app_state: &AppState, params: &Params) -> MethodResult {
+    let platform: String = params.get(0, "platform")?;
+    let version: String = params.get(1, "version")?;
+    let policy = app_version::block(&*app_state.db, &platform, &version).map_err(db_error)?;
+    Ok(serde_json::to_value(&policy).unwrap())
+}
+
+fn version_unblock(app_state: &AppState, params: &Params) -> MethodResult {
+    let platform: String = params.get(0, "platform")?;
+    let version: String = params.get(1, "version")?;
+    let policy = app_version::unblock(&*app_state.db, &platform, &version).map_err(db_error)?;
+    Ok(serde_json::to_value(&policy).unwrap())
+}
+
+fn relay_list_show(app_state: &AppState, _: &Params) -> MethodResult {
+    let version = relay::show(&*app_state.db).map_err(db_error)?;
+    Ok(serde_json::to_value(&version).unwrap())
+}
+
+fn relay_list_replace(app_state: &AppState, params: &Params) -> MethodResult {
+    let relay_list: RelayList = params.get(0, "relay_list")?;
+    let etag = relay::replace(&*app_state.db, relay_list).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_country_add(app_state: &AppState, params: &Params) -> MethodResult {
+    let code: String = params.get(0, "code")?;
+    let name: String = params.get(1, "name")?;
+    let etag = relay::add_country(&*app_state.db, &code, &name).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_country_update(app_state: &AppState, params: &Params) -> MethodResult {
+    let code: String = params.get(0, "code")?;
+    let name: String = params.get(1, "name")?;
+    let etag = relay::update_country(&*app_state.db, &code, &name).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_country_remove(app_state: &AppState, params: &Params) -> MethodResult {
+    let code: String = params.get(0, "code")?;
+    let etag = relay::remove_country(&*app_state.db, &code).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_city_add(app_state: &AppState, params: &Params) -> MethodResult {
+    let country: String = params.get(0, "country")?;
+    let code: String = params.get(1, "code")?;
+    let name: String = params.get(2, "name")?;
+    let latitude: f64 = params.get(3, "latitude")?;
+    let longitude: f64 = params.get(4, "longitude")?;
+    let etag = relay::add_city(&*app_state.db, &country, &code, &name, latitude, longitude)
+        .map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_city_update(app_state: &AppState, params: &Params) -> MethodResult {
+    let country: String = params.get(0, "country")?;
+    let code: String = params.get(1, "code")?;
+    let name: Option<String> = params.get_optional(2, "name")?;
+    let latitude: Option<f64> = params.get_optional(3, "latitude")?;
+    let longitude: Option<f64> = params.get_optional(4, "longitude")?;
+    let etag = relay::update_city(
+        &*app_state.db,
+        &country,
+        &code,
+        name.as_ref().map(String::as_str),
+        latitude,
+        longitude,
+    )
+    .map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_city_remove(app_state: &AppState, params: &Params) -> MethodResult {
+    let country: String = params.get(0, "country")?;
+    let code: String = params.get(1, "code")?;
+    let etag = relay::remove_city(&*app_state.db, &country, &code).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_add(app_state: &AppState, params: &Params) -> MethodResult {
+    let country: String = params.get(0, "country")?;
+    let city: String = params.get(1, "city")?;
+    let entry: Relay = params.get(2, "relay")?;
+    let etag = relay::add_relay(&*app_state.db, &country, &city, entry).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_update(app_state: &AppState, params: &Params) -> MethodResult {
+    let entry: Relay = params.get(0, "relay")?;
+    let etag = relay::update_relay(&*app_state.db, entry).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn relay_remove(app_state: &AppState, params: &Params) -> MethodResult {
+    let hostname: String = params.get(0, "hostname")?;
+    let etag = relay::remove_relay(&*app_state.db, &hostname).map_err(db_error)?;
+    Ok(Value::from(etag))
+}
+
+fn push_tinc_key(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let pubkey: String = params.get(1, "public_key")?;
+    let network: Option<String> = params.get_optional(2, "network")?;
+    let tinc = &proxy_network(app_state, network.as_ref().map(String::as_str))?.operator;
+    set_account_host(app_state, tinc, &acc, &pubkey)?;
+
+    let local_pubkey = tinc.get_local_pub_key().map_err(|e| {
+        log::error!("Unable to read the tinc public key: {}", e);
+        ErrorData::new(500, &e.to_string())
+    })?;
+    Ok(serde_json::to_value(&local_pubkey).unwrap())
+}
+
+/// Stores the keys of the account's node and answers with the keys of the proxy, along with the
+/// key type to use: the preferred one if both nodes have a key of it. Exchanging new keys
+/// replaces the old ones and keeps the VIP of the account. The answer also holds the VIP leased to
+/// the account, the gateway to route through and the node name of the proxy. Keys are exchanged
+/// on the tinc network the client names, or on the first one. The other proxies this one connects
+/// to are listed for the client to fail over to. Only the keys themselves are written to the host
+/// file of the account, as read back from the keys the client sent.
+fn exchange_tinc_keys(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let keys: PublicKeys = params.get(1, "keys")?;
+    let keys = keys
+        .validate()
+        .map_err(|e| ErrorData::new(400, &e.to_string()))?;
+    let preferred: KeyType = params.get_optional(2, "key_type")?.unwrap_or_default();
+    let network: Option<String> = params.get_optional(3, "network")?;
+    let network = proxy_network(app_state, network.as_ref().map(String::as_str))?;
+    let tinc = &network.operator;
+
+    let local_keys = tinc.get_local_pub_keys().map_err(|e| {
+        log::error!("Unable to read the tinc public keys: {}", e);
+        ErrorData::new(500, &e.to_string())
+    })?;
+    let key_type = local_keys
+        .negotiate(&keys, preferred)
+        .ok_or_else(|| ErrorData::new(400, "No key type in common."))?;
+    let vip = set_account_host(app_state, tinc, &acc, &keys.to_host_config())?;
+    let node = tinc.get_node_name().map_err(|e| {
+        log::error!("Unable to read the tinc node name: {}", e);
+        ErrorData::new(500, &e.to_string())
+    })?;
+    let proxies = tinc.get_connect_to().unwrap_or_else(|e| {
+        log::error!("Unable to read the other tinc proxies: {}", e);
+        Vec::new()
+    });
+
+    Ok(serde_json::to_value(&KeyExchange {
+        key_type,
+        keys: local_keys,
+        vip,
+        prefix: app_state.ipam.account_pool.network.prefix(),
+        gateway: network.gateway,
+        node,
+        proxies,
+    })
+    .unwrap())
+}
+
+/// The tinc network named `network`, or the first network if no name is given.
+fn proxy_network<'a>(app_state: &'a AppState, network: Option<&str>)
+    -> Result<&'a ProxyNetwork, ErrorData>
+{
+    let found = match network {
+        Some(network) => {
+            app_state.tinc.iter().find(|tinc| tinc.operator.network().name == network)
+        }
+        None => app_state.tinc.first(),
+    };
+    found.ok_or_else(|| ErrorData::new(404, "No such tinc network."))
+}
+
+/// Writes the host file of the account's node, named after its VIP, and returns the VIP.
+fn set_account_host(app_state: &AppState, tinc: &TincOperator, acc: &str, host_config: &str)
+    -> Result<IpAddr, ErrorData>
+{
+    if acc.len() < 6 {
+        return Err(ErrorData::new(401, "Account len error."));
+    }
+    let info = account::authorize(&*app_state.db, &app_state.ipam.account_pool, acc)
+        .map_err(db_error)?;
+    let host_name = TincOperator::get_filename_by_ip(false, &info.vip.to_string());
+    match tinc.add_hosts(&host_name, host_config) {
+        Ok(()) => Ok(info.vip),
+        Err(e @ TincOperatorError::InvalidConf(_))
+        | Err(e @ TincOperatorError::UnexpectedHostVariables)
+        | Err(e @ TincOperatorError::InvalidPublicKey(_)) => {
+            Err(ErrorData::new(400, &e.to_string()))
+        }
+        Err(_) => Err(ErrorData::new(500, "Set host file failed.")),
+    }
+}
+
+fn get_expiry(app_state: &AppState, params: &Params) -> MethodResult {
+    if let Some(acc) = params.get_optional::<String>(0, "account_token")? {
+        return match app_state.db.account_select(&acc) {
+            Ok(r) => Ok(serde_json::to_value(&r.expiry).unwrap()),
+            Err(e) => Err(ErrorData::new(404, &e.to_string())),
+        };
+    }
+
+    let r = Utc.ymd(1970, 1, 1).and_hms_milli(0, 0, 0, 0);
+    Ok(serde_json::to_value(&r).unwrap())
+}
+
+fn problem_report(app_state: &AppState, params: &Params) -> MethodResult {
+    let email: String = params.get(0, "email")?;
+    let message: String = params.get(1, "message")?;
+    let log: String = params.get(2, "log")?;
+    let metadata: BTreeMap<String, String> =
+        params.get_optional(3, "metadata")?.unwrap_or_default();
+
+    let client = app_state
+        .peer
+        .map(|peer| peer.to_string())
+        .unwrap_or_default();
+    let id = report::submit(
+        &*app_state.db,
+        &app_state.reports,
+        &client,
+        &email,
+        &message,
+        &log,
+        metadata,
+    )
+    .map_err(db_error)?;
+    log::info!("Received problem report {} from {}", id, client);
+    Ok(serde_json::to_value(()).unwrap())
+}
+
+fn relay_list_v2(app_state: &AppState, _: &Params) -> MethodResult {
+    let relay_list = match app_state.db.relay_list_select() {
+        Ok(x) => x,
+        Err(e) => {
+            log::error!("Unable to load relay list: {}", e);
+            return Err(ErrorData::std(-32603));
+        }
+    };
+    Ok(serde_json::to_value(&relay_list).unwrap())
+}
+
+fn app_version_check(app_state: &AppState, params: &Params) -> MethodResult {
+    let version: String = params.get(0, "version")?;
+    let platform: String = params.get(1, "platform")?;
+    let info = app_version::check(&*app_state.db, &platform, &version).map_err(db_error)?;
+    Ok(serde_json::to_value(&info).unwrap())
+}
+
+fn push_wg_key(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let key: PublicKey = params.get(1, "public_key")?;
+    let addresses = wireguard::push_key(&*app_state.db, &app_state.wireguard, &acc, &key)
+        .map_err(db_error)?;
+    Ok(serde_json::to_value(&addresses).unwrap())
+}
+
+fn replace_wg_key(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let old: PublicKey = params.get(1, "old")?;
+    let new: PublicKey = params.get(2, "new")?;
+    let addresses =
+        wireguard::replace_key(&*app_state.db, &app_state.wireguard, &acc, &old, &new)
+            .map_err(db_error)?;
+    Ok(serde_json::to_value(&addresses).unwrap())
+}
+
+fn check_wg_key(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let key: PublicKey = params.get(1, "public_key")?;
+    let registered = wireguard::check_key(&*app_state.db, &acc, &key).map_err(db_error)?;
+    Ok(Value::from(registered))
+}
+
+fn remove_wg_key(app_state: &AppState, params: &Params) -> MethodResult {
+    let acc: String = params.get(0, "account_token")?;
+    let key: PublicKey = params.get(1, "public_key")?;
+    wireguard::remove_key(&*app_state.db, &acc, &key).map_err(db_error)?;
+    Ok(serde_json::to_value(()).unwrap())
+}
+
+/// Number of days, sent as a decimal string by the clients.
+fn days_param(params: &Params, index: usize) -> Result<i64, ErrorData> {
+    let days: String = params.get(index, "days")?;
+    days.parse()
+        .map_err(|_| ErrorData::invalid_params(&format!("Invalid number of days \"{}\"", days)))
+}
+
+fn db_error(e: DbError) -> ErrorData {
+    match e {
+        DbError::NoAccount
+        | DbError::NoToken
+        | DbError::NoRelayEntry(_)
+        | DbError::NoWireguardKey
+        | DbError::NoReport
+        | DbError::NoVersionPolicy(_) => ErrorData::new(404, &e.to_string()),
+        DbError::InvalidRelayList(_) | DbError::InvalidVersionPolicy(_) => {
+            ErrorData::new(400, &e.to_string())
+        }
+        DbError::AccountSuspended | DbError::AccountExpired => ErrorData::new(401, &e.to_string()),
+        DbError::InvalidStatusTransition(..)
+        | DbError::AddressInUse(_)
+        | DbError::TokenExists
+        | DbError::RelayEntryExists(_)
+        | DbError::WireguardKeyInUse => ErrorData::new(409, &e.to_string()),
+        // The code the Mullvad clients expect when an account has too many keys.
+        DbError::TooManyWireguardKeys => ErrorData::new(-703, &e.to_string()),
+        DbError::ReportTooLarge(_) => ErrorData::new(413, &e.to_string()),
+        DbError::ReportRateLimited => ErrorData::new(429, &e.to_string()),
+        e => {
+            log::error!("Database error: {}", e);
+            ErrorData::new(500, &e.to_string())
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t;
+
+use std::net::{IpAddr, SocketAddr};
+use talpid_types::net::dns::{
+    self, CustomDnsServer, DnsRoute, DnsUpstream, DnsUpstreamProtocol, DOH_PORT, DOT_PORT,
+};
+
+pub struct Dns;
+
+impl Command for Dns {
+    fn name(&self) -> &'static str {
+        "dns"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage custom DNS servers, used instead of the tunnel gateway while connected")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Add a DNS server, after the ones already added")
+                    .arg(clap::Arg::with_name("address").required(true))
+                    .arg(
+                        clap::Arg::with_name("route")
+                            .help(
+                                "How the server is reached. Servers reached over the LAN must \
+                                 have a local address",
+                            )
+                            .long("route")
+                            .takes_value(true)
+                            .default_value("tunnel")
+                            .possible_values(&["tunnel", "lan"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove a DNS server")
+                    .arg(clap::Arg::with_name("address").required(true)),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("clear")
+                    .about("Remove all DNS servers and go back to the default DNS settings"),
+            )
+            .subcommand(clap::SubCommand::with_name("list").about("List the DNS servers"))
+            .subcommand(create_upstream_subcommand())
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => Self::add(add_matches),
+            ("remove", Some(remove_matches)) => Self::remove(remove_matches),
+            ("clear", Some(_)) => Self::clear(),
+            ("list", Some(_)) => Self::list(),
+            ("upstream", Some(upstream_matches)) => Self::upstream(upstream_matches),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+fn create_upstream_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("upstream")
+        .about(
+            "Manage encrypted DNS resolvers. While any are set, DNS queries are forwarded to them \
+             over HTTPS or TLS instead of going to the custom DNS servers or the tunnel gateway",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Add an encrypted DNS resolver, after the ones already added")
+                .arg(
+                    clap::Arg::with_name("protocol")
+                        .help("The protocol the resolver is reached with")
+                        .required(true)
+                        .possible_values(&["https", "tls"]),
+                )
+                .arg(
+                    clap::Arg::with_name("address")
+                        .help("The IP address of the resolver")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("hostname")
+                        .help("The name the certificate of the resolver is verified against")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help("The port of the resolver. Defaults to 443 for https and 853 for tls")
+                        .long("port")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("path")
+                        .help("The URL path queries are sent to. Only used with https")
+                        .long("path")
+                        .takes_value(true)
+                        .default_value("/dns-query"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Remove an encrypted DNS resolver")
+                .arg(clap::Arg::with_name("address").required(true)),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("clear")
+                .about("Remove all encrypted DNS resolvers and stop forwarding DNS queries"),
+        )
+        .subcommand(clap::SubCommand::with_name("list").about("List the encrypted DNS resolvers"))
+}
+
+impl Dns {
+    fn add(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let address = value_t!(matches.value_of("address"), IpAddr).unwrap_or_else(|e| e.exit());
+        let route = value_t!(matches.value_of("route"), DnsRoute).unwrap_or_else(|e| e.exit());
+        let server = CustomDnsServer { address, route };
+        if let Err(error) = dns::validate_custom_dns_server(&server) {
+            clap::Error::with_description(&error, clap::ErrorKind::InvalidValue).exit();
+        }
+
+        let mut rpc = new_rpc_client()?;
+        let mut servers = rpc.get_settings()?.get_custom_dns().to_vec();
+        servers.retain(|existing| existing.address != address);
+        servers.push(server);
+        rpc.set_custom_dns(servers)?;
+        println!("Added DNS server {}", server);
+        Ok(())
+    }
+
+    fn remove(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let address = value_t!(matches.value_of("address"), IpAddr).unwrap_or_else(|e| e.exit());
+
+        let mut rpc = new_rpc_client()?;
+        let mut servers = rpc.get_settings()?.get_custom_dns().to_vec();
+        let old_len = servers.len();
+        servers.retain(|existing| existing.address != address);
+        if servers.len() == old_len {
+            clap::Error::with_description(
+                &format!("{} is not a custom DNS server", address),
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit();
+        }
+        rpc.set_custom_dns(servers)?;
+        println!("Removed DNS server {}", address);
+        Ok(())
+    }
+
+    fn clear() -> Result<()> {
+        new_rpc_client()?.set_custom_dns(vec![])?;
+        println!("Removed all custom DNS servers");
+        Ok(())
+    }
+
+    fn list() -> Result<()> {
+        let settings = new_rpc_client()?.get_settings()?;
+        let servers = settings.get_custom_dns();
+        if servers.is_empty() {
+            println!("No custom DNS servers");
+        }
+        for server in servers {
+            println!("{}", server);
+        }
+        Ok(())
+    }
+
+    fn upstream(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => Self::add_upstream(add_matches),
+            ("remove", Some(remove_matches)) => Self::remove_upstream(remove_matches),
+            ("clear", Some(_)) => Self::clear_upstreams(),
+            ("list", Some(_)) => Self::list_upstreams(),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    fn add_upstream(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let ip = value_t!(matches.value_of("address"), IpAddr).unwrap_or_else(|e| e.exit());
+        let hostname = matches.value_of("hostname").unwrap().to_owned();
+        let (protocol, default_port) = match matches.value_of("protocol").unwrap() {
+            "https" => {
+                let path = matches.value_of("path").unwrap().to_owned();
+                (DnsUpstreamProtocol::Https { path }, DOH_PORT)
+            }
+            "tls" => (DnsUpstreamProtocol::Tls, DOT_PORT),
+            _ => unreachable!("unhandled protocol"),
+        };
+        let port = if matches.is_present("port") {
+            value_t!(matches.value_of("port"), u16).unwrap_or_else(|e| e.exit())
+        } else {
+            default_port
+        };
+        let upstream = DnsUpstream {
+            address: SocketAddr::new(ip, port),
+            hostname,
+            protocol,
+        };
+        if let Err(error) = dns::validate_dns_upstream(&upstream) {
+            clap::Error::with_description(&error, clap::ErrorKind::InvalidValue).exit();
+        }
+
+        let mut rpc = new_rpc_client()?;
+        let mut upstreams = rpc.get_settings()?.get_dns_upstreams().to_vec();
+        upstreams.retain(|existing| existing.address != upstream.address);
+        upstreams.push(upstream.clone());
+        rpc.set_dns_upstreams(upstreams)?;
+        println!("Added encrypted DNS resolver {}", upstream);
+        Ok(())
+    }
+
+    fn remove_upstream(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let ip = value_t!(matches.value_of("address"), IpAddr).unwrap_or_else(|e| e.exit());
+
+        let mut rpc = new_rpc_client()?;
+        let mut upstreams = rpc.get_settings()?.get_dns_upstreams().to_vec();
+        let old_len = upstreams.len();
+        upstreams.retain(|existing| existing.address.ip() != ip);
+        if upstreams.len() == old_len {
+            clap::Error::with_description(
+                &format!("{} is not an encrypted DNS resolver", ip),
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit();
+        }
+        rpc.set_dns_upstreams(upstreams)?;
+        println!("Removed encrypted DNS resolver {}", ip);
+        Ok(())
+    }
+
+    fn clear_upstreams() -> Result<()> {
+        new_rpc_client()?.set_dns_upstreams(vec![])?;
+        println!("Removed all encrypted DNS resolvers");
+        Ok(())
+    }
+
+    fn list_upstreams() -> Result<()> {
+        let settings = new_rpc_client()?.get_settings()?;
+        let upstreams = settings.get_dns_upstreams();
+        if upstreams.is_empty() {
+            println!("No encrypted DNS resolvers");
+        }
+        for upstream in upstreams {
+            println!("{}", upstream);
+        }
+        Ok(())
+    }
+}
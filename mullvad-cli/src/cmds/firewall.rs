@@ -0,0 +1,163 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::{value_t, values_t};
+
+use ipnetwork::IpNetwork;
+use talpid_types::net::{
+    firewall::{self, AllowRule, Direction, PortRange},
+    TransportProtocol,
+};
+
+pub struct Firewall;
+
+impl Command for Firewall {
+    fn name(&self) -> &'static str {
+        "firewall"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage custom firewall rules")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(create_rule_subcommand())
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("rule", Some(rule_matches)) => Self::handle_rule_cmd(rule_matches),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+fn create_rule_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("rule")
+        .about(
+            "Manage rules that allow traffic through the firewall, whether the tunnel is up, \
+             connecting or blocking",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Allow traffic to or from a network")
+                .arg(
+                    clap::Arg::with_name("network")
+                        .help("The network, such as 192.0.2.10 or 192.0.2.0/24")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("protocol")
+                        .help("Only allow this transport protocol")
+                        .long("protocol")
+                        .takes_value(true)
+                        .possible_values(&["udp", "tcp"]),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help(
+                            "Only allow connections to this port or port range, such as 22 or \
+                             8000-8100. Local ports for incoming connections. Requires \
+                             --protocol",
+                        )
+                        .long("port")
+                        .takes_value(true)
+                        .requires("protocol"),
+                )
+                .arg(
+                    clap::Arg::with_name("direction")
+                        .help("Which end initiates the allowed connections")
+                        .long("direction")
+                        .takes_value(true)
+                        .default_value("out")
+                        .possible_values(&["in", "out", "both"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Remove rules, by the numbers that 'list' shows")
+                .arg(
+                    clap::Arg::with_name("number")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("list").about("List the custom rules"))
+}
+
+impl Firewall {
+    fn handle_rule_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => Self::add_rule(add_matches),
+            ("remove", Some(remove_matches)) => Self::remove_rules(remove_matches),
+            ("list", Some(_)) => Self::list_rules(),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    fn add_rule(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let network = value_t!(matches.value_of("network"), IpNetwork).unwrap_or_else(|e| e.exit());
+        let protocol = match matches.value_of("protocol") {
+            Some(_) => Some(
+                value_t!(matches.value_of("protocol"), TransportProtocol)
+                    .unwrap_or_else(|e| e.exit()),
+            ),
+            None => None,
+        };
+        let ports = match matches.value_of("port") {
+            Some(_) => {
+                Some(value_t!(matches.value_of("port"), PortRange).unwrap_or_else(|e| e.exit()))
+            }
+            None => None,
+        };
+        let direction =
+            value_t!(matches.value_of("direction"), Direction).unwrap_or_else(|e| e.exit());
+
+        let rule = AllowRule {
+            network,
+            protocol,
+            ports,
+            direction,
+        };
+        if let Err(error) = firewall::validate_allow_rule(&rule) {
+            clap::Error::with_description(&error, clap::ErrorKind::InvalidValue).exit();
+        }
+
+        new_rpc_client()?.add_firewall_rule(rule)?;
+        println!("Added rule: {}", rule);
+        Ok(())
+    }
+
+    fn remove_rules(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let numbers = values_t!(matches.values_of("number"), usize).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client()?;
+        let rules = rpc.get_settings()?.get_firewall_rules().to_vec();
+
+        let mut to_remove = Vec::with_capacity(numbers.len());
+        for number in numbers {
+            match number.checked_sub(1).and_then(|index| rules.get(index)) {
+                Some(rule) => to_remove.push(*rule),
+                None => clap::Error::with_description(
+                    &format!("There is no rule number {}", number),
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit(),
+            }
+        }
+        for rule in to_remove {
+            rpc.remove_firewall_rule(rule)?;
+            println!("Removed rule: {}", rule);
+        }
+        Ok(())
+    }
+
+    fn list_rules() -> Result<()> {
+        let settings = new_rpc_client()?.get_settings()?;
+        let rules = settings.get_firewall_rules();
+        if rules.is_empty() {
+            println!("No custom firewall rules");
+        }
+        for (index, rule) in rules.iter().enumerate() {
+            println!("{}: {}", index + 1, rule);
+        }
+        Ok(())
+    }
+}
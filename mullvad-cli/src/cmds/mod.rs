@@ -19,15 +19,24 @@ pub use self::connect::Connect;
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
+mod dns;
+pub use self::dns::Dns;
+
 mod block_when_disconnected;
 pub use self::block_when_disconnected::BlockWhenDisconnected;
 
 mod relay;
 pub use self::relay::Relay;
 
+mod firewall;
+pub use self::firewall::Firewall;
+
 mod lan;
 pub use self::lan::Lan;
 
+mod split_tunnel;
+pub use self::split_tunnel::SplitTunnel;
+
 mod tunnel;
 pub use self::tunnel::Tunnel;
 
@@ -43,8 +52,11 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(Bridge),
         Box::new(Connect),
         Box::new(Disconnect),
+        Box::new(Dns),
+        Box::new(Firewall),
         Box::new(Lan),
         Box::new(Relay),
+        Box::new(SplitTunnel),
         Box::new(Status),
         Box::new(Tunnel),
         Box::new(Version),
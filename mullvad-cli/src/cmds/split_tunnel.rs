@@ -0,0 +1,122 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+
+pub struct SplitTunnel;
+
+impl Command for SplitTunnel {
+    fn name(&self) -> &'static str {
+        "split-tunnel"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage processes that are excluded from the tunnel")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(create_pid_subcommand())
+            .subcommand(create_cgroup_subcommand())
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("pid", Some(pid_matches)) => Self::handle_pid_cmd(pid_matches),
+            ("cgroup", Some(cgroup_matches)) => Self::handle_cgroup_cmd(cgroup_matches),
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+fn create_pid_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("pid")
+        .about("Exclude processes and their children from the tunnel, until they exit")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Exclude a process from the tunnel")
+                .arg(clap::Arg::with_name("pid").required(true)),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("delete")
+                .about("Stop excluding a process from the tunnel")
+                .arg(clap::Arg::with_name("pid").required(true)),
+        )
+        .subcommand(clap::SubCommand::with_name("list").about("List the excluded processes"))
+        .subcommand(clap::SubCommand::with_name("clear").about("Stop excluding all processes"))
+}
+
+fn create_cgroup_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("cgroup")
+        .about(
+            "Exclude the processes of a cgroup from the tunnel. The setting is saved, and \
+             processes that joined the cgroup are excluded each time the tunnel connects",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Exclude the processes of a cgroup from the tunnel")
+                .arg(
+                    clap::Arg::with_name("cgroup")
+                        .help("Path of the cgroup, such as /system.slice/apt-cacher-ng.service")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("delete")
+                .about("Stop excluding the processes of a cgroup from the tunnel")
+                .arg(clap::Arg::with_name("cgroup").required(true)),
+        )
+        .subcommand(clap::SubCommand::with_name("list").about("List the excluded cgroups"))
+}
+
+impl SplitTunnel {
+    fn handle_pid_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(matches)) => {
+                let pid = value_t_or_exit!(matches.value_of("pid"), i32);
+                new_rpc_client()?.add_split_tunnel_process(pid)?;
+                println!("Excluded PID {} from the tunnel", pid);
+            }
+            ("delete", Some(matches)) => {
+                let pid = value_t_or_exit!(matches.value_of("pid"), i32);
+                new_rpc_client()?.remove_split_tunnel_process(pid)?;
+                println!("Stopped excluding PID {} from the tunnel", pid);
+            }
+            ("list", Some(_)) => {
+                let pids = new_rpc_client()?.get_split_tunnel_processes()?;
+                println!("Excluded PIDs:");
+                for pid in pids {
+                    println!("{}", pid);
+                }
+            }
+            ("clear", Some(_)) => {
+                new_rpc_client()?.clear_split_tunnel_processes()?;
+                println!("Stopped excluding all PIDs from the tunnel");
+            }
+            _ => unreachable!("unhandled command"),
+        }
+        Ok(())
+    }
+
+    fn handle_cgroup_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(matches)) => {
+                let cgroup = value_t_or_exit!(matches.value_of("cgroup"), String);
+                new_rpc_client()?.add_split_tunnel_cgroup(cgroup.clone())?;
+                println!("Excluded cgroup {} from the tunnel", cgroup);
+            }
+            ("delete", Some(matches)) => {
+                let cgroup = value_t_or_exit!(matches.value_of("cgroup"), String);
+                new_rpc_client()?.remove_split_tunnel_cgroup(cgroup.clone())?;
+                println!("Stopped excluding cgroup {} from the tunnel", cgroup);
+            }
+            ("list", Some(_)) => {
+                let settings = new_rpc_client()?.get_settings()?;
+                println!("Excluded cgroups:");
+                for cgroup in &settings.get_split_tunnel_settings().excluded_cgroups {
+                    println!("{}", cgroup);
+                }
+            }
+            _ => unreachable!("unhandled command"),
+        }
+        Ok(())
+    }
+}
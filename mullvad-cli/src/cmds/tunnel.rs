@@ -2,6 +2,22 @@ use crate::{new_rpc_client, Command, Result};
 use clap::value_t;
 
 use mullvad_types::settings::TunnelOptions;
+use talpid_types::net::tinc;
+
+/// Options of `mullvad tunnel tinc set` and `unset`, named like the fields of the tinc options.
+const TINC_OPTIONS: &[&str] = &[
+    "mtu",
+    "pmtu_discovery",
+    "compression",
+    "cipher",
+    "digest",
+    "ping_interval",
+    "ping_timeout",
+    "tcp_only",
+    "port",
+    "monitor_interval",
+    "monitor_threshold",
+];
 
 pub struct Tunnel;
 
@@ -16,6 +32,7 @@ impl Command for Tunnel {
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(create_openvpn_subcommand())
             .subcommand(create_wireguard_subcommand())
+            .subcommand(create_tinc_subcommand())
             .subcommand(create_ipv6_subcommand())
     }
 
@@ -23,6 +40,7 @@ impl Command for Tunnel {
         match matches.subcommand() {
             ("openvpn", Some(openvpn_matches)) => Self::handle_openvpn_cmd(openvpn_matches),
             ("wireguard", Some(wg_matches)) => Self::handle_wireguard_cmd(wg_matches),
+            ("tinc", Some(tinc_matches)) => Self::handle_tinc_cmd(tinc_matches),
             ("ipv6", Some(ipv6_matches)) => Self::handle_ipv6_cmd(ipv6_matches),
             _ => {
                 unreachable!("unhandled comand");
@@ -58,6 +76,64 @@ fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
         .subcommand(clap::SubCommand::with_name("generate"))
 }
 
+fn create_tinc_subcommand() -> clap::App<'static, 'static> {
+    let on_off = |name| {
+        clap::Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .possible_values(&["on", "off"])
+    };
+    let number = |name| clap::Arg::with_name(name).long(name).takes_value(true);
+    clap::SubCommand::with_name("tinc")
+        .about("Manage options for tinc tunnels, used from the next time a tunnel starts")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .setting(clap::AppSettings::ArgRequiredElseHelp)
+                .arg(number("mtu").help("Largest packet to send over the tunnel"))
+                .arg(on_off("pmtu_discovery").help("Discover the path MTU to the proxies"))
+                .arg(number("compression").help("Compression level of received packets, 0 to 11"))
+                .arg(number("cipher").help("Cipher of the legacy protocol, e.g. aes-256-cbc"))
+                .arg(number("digest").help("Digest of the legacy protocol, e.g. sha256"))
+                .arg(number("ping_interval").help("Seconds between pings to idle proxies"))
+                .arg(number("ping_timeout").help("Seconds to wait for the answer to a ping"))
+                .arg(on_off("tcp_only").help("Send all packets over TCP"))
+                .arg(number("port").help("Port tincd listens on"))
+                .arg(number("monitor_interval").help("Seconds between pings to the gateway"))
+                .arg(
+                    number("monitor_threshold")
+                        .help("Unanswered pings in a row before the tunnel is considered down"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("unset").arg(
+                clap::Arg::with_name("option")
+                    .required(true)
+                    .multiple(true)
+                    .possible_values(TINC_OPTIONS),
+            ),
+        )
+        .subcommand(create_tinc_key_subcommand())
+}
+
+fn create_tinc_key_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("key")
+        .about("Manage your tinc keys")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("rotate")
+                .about("Replace a key pair and exchange the new keys, keeping the VIP")
+                .arg(
+                    clap::Arg::with_name("type")
+                        .long("type")
+                        .takes_value(true)
+                        .default_value("ed25519")
+                        .possible_values(&["ed25519", "rsa"])
+                        .help("Type of the key to replace, preferred from then on"),
+                ),
+        )
+}
 
 fn create_openvpn_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("openvpn")
@@ -180,6 +256,93 @@ impl Tunnel {
         Ok(())
     }
 
+    fn handle_tinc_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", Some(_)) => Self::process_tinc_get(),
+            ("set", Some(set_matches)) => Self::process_tinc_set(set_matches),
+            ("unset", Some(unset_matches)) => Self::process_tinc_unset(unset_matches),
+            ("key", Some(key_matches)) => match key_matches.subcommand() {
+                ("rotate", Some(matches)) => Self::process_tinc_key_rotate(matches),
+                _ => unreachable!("unhandled command"),
+            },
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    fn process_tinc_key_rotate(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let key_type = match matches.value_of("type").unwrap() {
+            "rsa" => tinc::KeyType::Rsa,
+            _ => tinc::KeyType::Ed25519,
+        };
+        let mut rpc = new_rpc_client()?;
+        rpc.rotate_tinc_key(key_type)?;
+        println!("tinc key has been rotated");
+        Ok(())
+    }
+
+    fn process_tinc_get() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let options = rpc.get_tinc_options()?;
+        let show = |value: Option<String>| value.unwrap_or_else(|| "unset".to_owned());
+        let on_off = |value: Option<bool>| {
+            show(value.map(|on| if on { "on" } else { "off" }.to_owned()))
+        };
+        println!("mtu: {}", show(options.mtu.map(|v| v.to_string())));
+        println!("pmtu_discovery: {}", on_off(options.pmtu_discovery));
+        println!("compression: {}", show(options.compression.map(|v| v.to_string())));
+        println!("cipher: {}", show(options.cipher));
+        println!("digest: {}", show(options.digest));
+        println!("ping_interval: {}", show(options.ping_interval.map(|v| v.to_string())));
+        println!("ping_timeout: {}", show(options.ping_timeout.map(|v| v.to_string())));
+        println!("tcp_only: {}", on_off(options.tcp_only));
+        println!("port: {}", show(options.port.map(|v| v.to_string())));
+        println!(
+            "monitor_interval: {}",
+            show(options.monitor_interval.map(|v| v.to_string()))
+        );
+        println!(
+            "monitor_threshold: {}",
+            show(options.monitor_threshold.map(|v| v.to_string()))
+        );
+        Ok(())
+    }
+
+    fn process_tinc_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut options = rpc.get_tinc_options()?;
+        let on_off = |name| matches.value_of(name).map(|value| value == "on");
+        let string = |name| matches.value_of(name).map(str::to_owned);
+
+        options.mtu = number_of(matches, "mtu").or(options.mtu);
+        options.pmtu_discovery = on_off("pmtu_discovery").or(options.pmtu_discovery);
+        options.compression = number_of(matches, "compression").or(options.compression);
+        options.cipher = string("cipher").or(options.cipher);
+        options.digest = string("digest").or(options.digest);
+        options.ping_interval = number_of(matches, "ping_interval").or(options.ping_interval);
+        options.ping_timeout = number_of(matches, "ping_timeout").or(options.ping_timeout);
+        options.tcp_only = on_off("tcp_only").or(options.tcp_only);
+        options.port = number_of(matches, "port").or(options.port);
+        options.monitor_interval =
+            number_of(matches, "monitor_interval").or(options.monitor_interval);
+        options.monitor_threshold =
+            number_of(matches, "monitor_threshold").or(options.monitor_threshold);
+
+        rpc.set_tinc_options(options)?;
+        println!("tinc options have been updated");
+        Ok(())
+    }
+
+    fn process_tinc_unset(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut options = rpc.get_tinc_options()?;
+        for option in matches.values_of("option").unwrap() {
+            unset_tinc_option(&mut options, option);
+        }
+        rpc.set_tinc_options(options)?;
+        println!("tinc options have been unset");
+        Ok(())
+    }
+
     fn handle_ipv6_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get()
@@ -244,3 +407,29 @@ impl Tunnel {
         Ok(())
     }
 }
+
+/// Value of the option `name`, exiting with a usage error if it isn't a number that fits `T`.
+fn number_of<T: std::str::FromStr>(matches: &clap::ArgMatches<'_>, name: &str) -> Option<T> {
+    if matches.is_present(name) {
+        Some(value_t!(matches.value_of(name), T).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    }
+}
+
+fn unset_tinc_option(options: &mut tinc::TunnelOptions, option: &str) {
+    match option {
+        "mtu" => options.mtu = None,
+        "pmtu_discovery" => options.pmtu_discovery = None,
+        "compression" => options.compression = None,
+        "cipher" => options.cipher = None,
+        "digest" => options.digest = None,
+        "ping_interval" => options.ping_interval = None,
+        "ping_timeout" => options.ping_timeout = None,
+        "tcp_only" => options.tcp_only = None,
+        "port" => options.port = None,
+        "monitor_interval" => options.monitor_interval = None,
+        "monitor_threshold" => options.monitor_threshold = None,
+        _ => unreachable!("unhandled tinc option"),
+    }
+}
@@ -1,4 +1,4 @@
-use mullvad_types::{account::AccountToken, wireguard::WireguardData};
+use mullvad_types::{account::AccountToken, tinc::TincData, wireguard::WireguardData};
 use std::{
     collections::VecDeque,
     fs,
@@ -66,6 +66,7 @@ impl AccountHistory {
                     .map(|account| AccountEntry {
                         account,
                         wireguard: None,
+                        tinc: None,
                     })
                     .collect()
             }
@@ -115,6 +116,7 @@ impl AccountHistory {
             let new_entry = AccountEntry {
                 account: account.to_string(),
                 wireguard: None,
+                tinc: None,
             };
             self.insert(new_entry)?;
         }
@@ -162,4 +164,6 @@ impl AccountHistory {
 pub struct AccountEntry {
     pub account: AccountToken,
     pub wireguard: Option<WireguardData>,
+    #[serde(default)]
+    pub tinc: Option<TincData>,
 }
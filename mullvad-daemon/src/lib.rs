@@ -21,7 +21,6 @@ mod rpc_uniqueness_check;
 mod settings;
 pub mod version;
 // add by YanBowen
-use std::convert::TryFrom;
 mod tinc_key;
 
 pub use crate::management_interface::ManagementCommand;
@@ -35,7 +34,7 @@ use futures::{
 };
 use log::{debug, error, info, warn};
 //add by YanBowen
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
 //add by YanBowen
 use mullvad_rpc::{AccountCreate, AccountUpdate};
 use mullvad_rpc::{AccountsProxy, AppVersionProxy, HttpHandle, WireguardKeyProxy};
@@ -49,24 +48,31 @@ use mullvad_types::{
     },
     relay_list::{Relay, RelayList},
     states::{TargetState, TunnelState},
+    tinc::TincData,
     version::{AppVersion, AppVersionInfo},
     wireguard::KeygenEvent,
 };
 use settings::Settings;
-use std::{io, mem, path::PathBuf, sync::mpsc, thread, time::Duration};
+use std::{io, mem, path::PathBuf, sync::{mpsc, Arc}, thread, time::Duration};
+#[cfg(target_os = "linux")]
+use talpid_core::split_tunnel;
 use talpid_core::{
     mpsc::IntoSender,
     tunnel::tun_provider::{PlatformTunProvider, TunProvider},
     tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
 };
 use talpid_types::{
-    net::{openvpn, TransportProtocol, TunnelParameters},
+    net::{
+        dns::{CustomDnsServer, DnsUpstream},
+        firewall::AllowRule,
+        openvpn, TransportProtocol, TunnelParameters,
+    },
     tunnel::{BlockReason, TunnelStateTransition},
     ErrorExt,
 };
 // add by YanBowen
 use talpid_types::net::tinc;
-use tinc_plugin::{TincOperator, TincRunMode, TincInfo};
+use tinc_plugin::{KeyType, TincOperator, TincRunMode, TincInfo, TincNetwork};
 use talpid_types::net::tinc::ConnectTo;
 
 #[path = "wireguard.rs"]
@@ -111,15 +117,14 @@ pub enum Error {
     #[error(display = "No bridge available")]
     NoBridgeAvailable,
 
+    #[error(display = "Unable to read the tinc keys")]
+    TincKeyError(#[error(cause)] tinc_key::Error),
+
     #[error(display = "Account history problems")]
     AccountHistory(#[error(cause)] account_history::Error),
 
     #[error(display = "Tunnel state machine error")]
     TunnelError(#[error(cause)] tunnel_state_machine::Error),
-
-    // add by YanBowen
-    #[error(display = "Account can not be parse to tinc vip")]
-    Accountparse,
 }
 
 type SyncUnboundedSender<T> = ::futures::sink::Wait<UnboundedSender<T>>;
@@ -143,6 +148,8 @@ pub(crate) enum InternalDaemonEvent {
             ::std::result::Result<mullvad_types::wireguard::WireguardData, wireguard::Error>,
         ),
     ),
+    /// Tinc key exchange event
+    TincKeyEvent((AccountToken, ::std::result::Result<TincData, tinc_key::Error>)),
 }
 
 impl From<TunnelStateTransition> for InternalDaemonEvent {
@@ -249,6 +256,8 @@ pub struct Daemon<L: EventListener = ManagementInterfaceEventBroadcaster> {
     //add by YanBowen
     tinc_key_manager: tinc_key::KeyManager,
     wireguard_key_manager: wireguard::KeyManager,
+    #[cfg(target_os = "linux")]
+    exclude_pids: Option<split_tunnel::PidManager>,
     tokio_remote: tokio_core::reactor::Remote,
     relay_selector: relays::RelaySelector,
     last_generated_relay: Option<Relay>,
@@ -391,12 +400,13 @@ where
         };
 
         // add by YanBowen
-        let resource_dir_str = resource_dir.to_str().unwrap().to_string();
-        if !TincOperator::is_inited() {
-            TincOperator::new(&resource_dir_str, TincRunMode::Client);
-        }
+        let tinc_operator = Arc::new(TincOperator::new(
+            TincNetwork::default_in(&resource_dir),
+            TincRunMode::Client,
+        ));
 
         let tinc_key_manager = tinc_key::KeyManager::new(
+            tinc_operator.clone(),
             internal_event_tx.clone(),
             rpc_handle.clone(),
             tokio_remote.clone(),
@@ -404,11 +414,15 @@ where
 
         let tunnel_command_tx = tunnel_state_machine::spawn(
             settings.get_allow_lan(),
+            Self::active_firewall_rules(&settings),
+            settings.get_custom_dns().to_vec(),
+            settings.get_dns_upstreams().to_vec(),
             settings.get_block_when_disconnected(),
             tunnel_parameters_generator,
             tun_provider,
             log_dir,
             resource_dir,
+            tinc_operator,
             cache_dir.clone(),
             IntoSender::from(internal_event_tx.clone()),
         )
@@ -423,6 +437,16 @@ where
         // Attempt to download a fresh relay list
         relay_selector.update();
 
+        #[cfg(target_os = "linux")]
+        let exclude_pids = split_tunnel::PidManager::new()
+            .map_err(|e| {
+                error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to initialize split tunneling")
+                )
+            })
+            .ok();
+
         let mut daemon = Daemon {
             tunnel_command_tx: Sink::wait(tunnel_command_tx),
             tunnel_state: TunnelState::Disconnected,
@@ -448,8 +472,12 @@ where
             // add by YanBowen
             tinc_key_manager,
             wireguard_key_manager,
+            #[cfg(target_os = "linux")]
+            exclude_pids,
         };
         daemon.ensure_wireguard_keys_for_current_account();
+        #[cfg(target_os = "linux")]
+        daemon.exclude_split_tunnel_cgroups();
 
         Ok(daemon)
     }
@@ -489,6 +517,7 @@ where
             }
             TriggerShutdown => self.handle_trigger_shutdown_event(),
             WgKeyEvent(key_event) => self.handle_wireguard_key_event(key_event),
+            TincKeyEvent(key_event) => self.handle_tinc_key_event(key_event),
         }
         Ok(())
     }
@@ -515,6 +544,9 @@ where
         debug!("New tunnel state: {:?}", tunnel_state);
         match tunnel_state {
             TunnelState::Disconnected => self.state.disconnected(),
+            // Pick up the processes that joined the excluded cgroups since the last tunnel.
+            #[cfg(target_os = "linux")]
+            TunnelState::Connecting { .. } => self.exclude_split_tunnel_cgroups(),
             TunnelState::Blocked(ref reason) => {
                 info!("Blocking all network connections, reason: {}", reason);
 
@@ -656,31 +688,41 @@ where
                     Err(Error::UnsupportedTunnel)
                 }
                 else {
-                    let vpnserver_vip = IpAddr::from(Ipv4Addr::new(10, 255, 255, 254));
-
-                    let connect_to = ConnectTo::new(
+                    let tinc_data = self
+                        .account_history
+                        .get(&account_token)
+                        .map_err(Error::AccountHistory)?
+                        .and_then(|entry| entry.tinc)
+                        .ok_or(Error::NoKeyAvailable)?;
+
+                    let mut connect_to = ConnectTo::new(
                         endpoint.address.ip(),
-                        vpnserver_vip,
-                        self.tinc_key_manager.get_remote_pubkey(),
+                        IpAddr::from(tinc_data.gateway),
+                        tinc_data.proxy_keys,
                     );
+                    connect_to.node = tinc_data.proxy_node;
 
                     let mut tinc_info = TincInfo::new();
+                    // The proxy of the relay comes first, the others are for failover.
                     tinc_info.connect_to = vec![connect_to];
-
-                    let local_vip_num: u32 = ("1".to_string() + &account_token[4..])
-                        .parse()
-                        .map_err(|_|Error::Accountparse)?;
-                    let local_vip = IpAddr::try_from(
-                        Ipv4Addr::try_from(local_vip_num)
-                            .map_err(|_|Error::Accountparse)?
-                    ).map_err(|_|Error::Accountparse)?;
-                    tinc_info.vip = local_vip;
-                    
-                    tinc_info.pub_key = self.tinc_key_manager.get_local_pubkey();
+                    tinc_info.connect_to.extend(
+                        tinc_data.proxies
+                            .into_iter()
+                            .filter(|proxy| proxy.ip != endpoint.address.ip()),
+                    );
+                    tinc_info.vip = IpAddr::from(tinc_data.vip);
+                    tinc_info.pub_key = self
+                        .tinc_key_manager
+                        .get_local_pubkey()
+                        .map_err(Error::TincKeyError)?;
+                    tinc_info.key_type = tinc_data.key_type;
+                    let mut endpoint = endpoint;
+                    if tunnel_options.tinc.tcp_only == Some(true) {
+                        endpoint.protocol = TransportProtocol::Tcp;
+                    }
                     Ok(
                         tinc::TunnelParameters {
                             config: tinc::ConnectionConfig::new(endpoint, tinc_info),
-                            // Empty.
                             options: tunnel_options.tinc,
                             // enable ipv6?
                             generic_options: tunnel_options.generic,
@@ -768,6 +810,10 @@ where
             }
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update),
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan),
+            AddFirewallRule(tx, rule) => self.on_add_firewall_rule(tx, rule),
+            RemoveFirewallRule(tx, rule) => self.on_remove_firewall_rule(tx, rule),
+            SetCustomDns(tx, servers) => self.on_set_custom_dns(tx, servers),
+            SetDnsUpstreams(tx, upstreams) => self.on_set_dns_upstreams(tx, upstreams),
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
             }
@@ -779,6 +825,14 @@ where
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state),
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6),
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu),
+            SetTincOptions(tx, options) => self.on_set_tinc_options(tx, options),
+            RotateTincKey(tx, key_type) => self.on_rotate_tinc_key(tx, key_type),
+            GetSplitTunnelProcesses(tx) => self.on_get_split_tunnel_processes(tx),
+            AddSplitTunnelProcess(tx, pid) => self.on_add_split_tunnel_process(tx, pid),
+            RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
+            ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            AddSplitTunnelCgroup(tx, cgroup) => self.on_add_split_tunnel_cgroup(tx, cgroup),
+            RemoveSplitTunnelCgroup(tx, cgroup) => self.on_remove_split_tunnel_cgroup(tx, cgroup),
             GetSettings(tx) => self.on_get_settings(tx),
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx),
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx),
@@ -820,6 +874,7 @@ where
                     .unwrap_or_else(|| account_history::AccountEntry {
                         account: account.clone(),
                         wireguard: None,
+                        tinc: None,
                     });
                 account_entry.wireguard = Some(data.clone());
                 match self.account_history.insert(account_entry) {
@@ -853,6 +908,33 @@ where
         }
     }
 
+    fn handle_tinc_key_event(
+        &mut self,
+        event: (AccountToken, ::std::result::Result<TincData, tinc_key::Error>),
+    ) {
+        let (account, result) = event;
+        if self
+            .settings
+            .get_account_token()
+            .map(|current_account| current_account != account)
+            .unwrap_or(true)
+        {
+            log::info!("Dropping tinc key event since account has been changed");
+            return;
+        }
+
+        match result {
+            Ok(data) => {
+                self.set_tinc_data(&account, data);
+                info!("Initiating tunnel restart because tinc keys were exchanged");
+                self.reconnect_tunnel();
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Failed to exchange tinc keys"))
+            }
+        }
+    }
+
     fn on_create_account(
         &mut self,
         tx: oneshot::Sender<BoxFuture<AccountToken, mullvad_rpc::Error>>,
@@ -1069,6 +1151,117 @@ where
         }
     }
 
+    #[cfg(not(windows))]
+    fn on_add_firewall_rule(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        rule: AllowRule,
+    ) {
+        let save_result = self.settings.add_firewall_rule(rule);
+        self.on_firewall_rules_saved(tx, save_result, "add_firewall_rule response");
+    }
+
+    #[cfg(windows)]
+    fn on_add_firewall_rule(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        _rule: AllowRule,
+    ) {
+        let error = settings::Error::InvalidFirewallRule(
+            "Allow rules are not supported on Windows".to_owned(),
+        );
+        Self::oneshot_send(tx, Err(error), "add_firewall_rule response");
+    }
+
+    fn on_remove_firewall_rule(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        rule: AllowRule,
+    ) {
+        let save_result = self.settings.remove_firewall_rule(&rule);
+        self.on_firewall_rules_saved(tx, save_result, "remove_firewall_rule response");
+    }
+
+    /// The allow rules to apply. Windows doesn't support them, so rules saved there before they
+    /// were rejected are left out instead of failing every policy.
+    fn active_firewall_rules(settings: &Settings) -> Vec<AllowRule> {
+        if cfg!(windows) {
+            Vec::new()
+        } else {
+            settings.get_firewall_rules().to_vec()
+        }
+    }
+
+    fn on_firewall_rules_saved(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        save_result: settings::Result<bool>,
+        msg: &'static str,
+    ) {
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), msg);
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    let rules = Self::active_firewall_rules(&self.settings);
+                    self.send_tunnel_command(TunnelCommand::AllowedRules(rules));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to change firewall rules"));
+                Self::oneshot_send(tx, Err(e), msg);
+            }
+        }
+    }
+
+    fn on_set_custom_dns(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        servers: Vec<CustomDnsServer>,
+    ) {
+        match self.settings.set_custom_dns(servers) {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_custom_dns response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    let servers = self.settings.get_custom_dns().to_vec();
+                    self.send_tunnel_command(TunnelCommand::CustomDns(servers));
+                }
+            }
+            Err(e) => {
+                error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to set custom DNS servers")
+                );
+                Self::oneshot_send(tx, Err(e), "set_custom_dns response");
+            }
+        }
+    }
+
+    fn on_set_dns_upstreams(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        upstreams: Vec<DnsUpstream>,
+    ) {
+        match self.settings.set_dns_upstreams(upstreams) {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_dns_upstreams response");
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                    let upstreams = self.settings.get_dns_upstreams().to_vec();
+                    self.send_tunnel_command(TunnelCommand::DnsUpstreams(upstreams));
+                }
+            }
+            Err(e) => {
+                error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to set DNS upstreams")
+                );
+                Self::oneshot_send(tx, Err(e), "set_dns_upstreams response");
+            }
+        }
+    }
+
     fn on_set_block_when_disconnected(
         &mut self,
         tx: oneshot::Sender<()>,
@@ -1226,18 +1419,258 @@ where
         }
     }
 
+    fn on_set_tinc_options(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        options: tinc::TunnelOptions,
+    ) {
+        match self.settings.set_tinc_options(options) {
+            Ok(settings_changed) => {
+                // Running tunnels keep their options, the next tunnel started uses the new ones.
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                }
+                Self::oneshot_send(tx, Ok(()), "set_tinc_options response");
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Failed to set tinc options"));
+                Self::oneshot_send(tx, Err(e), "set_tinc_options response");
+            }
+        }
+    }
+
+    fn on_rotate_tinc_key(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), ()>>,
+        key_type: KeyType,
+    ) {
+        let account = match self.settings.get_account_token() {
+            Some(account) => account,
+            None => {
+                log::error!("Can't rotate tinc key without an account");
+                Self::oneshot_send(tx, Err(()), "rotate_tinc_key response");
+                return;
+            }
+        };
+        match self.tinc_key_manager.rotate_key_sync(&account, key_type) {
+            Ok(data) => {
+                self.set_tinc_data(&account, data);
+                Self::oneshot_send(tx, Ok(()), "rotate_tinc_key response");
+                info!("Initiating tunnel restart because the tinc key was rotated");
+                self.reconnect_tunnel();
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Failed to rotate tinc key"));
+                Self::oneshot_send(tx, Err(()), "rotate_tinc_key response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_split_tunnel_processes(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<Vec<i32>, ()>>,
+    ) {
+        let result = self.with_pid_manager(|pids| pids.list(), "Unable to list excluded PIDs");
+        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_add_split_tunnel_process(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), ()>>,
+        pid: i32,
+    ) {
+        let result = self.with_pid_manager(|pids| pids.add(pid), "Unable to exclude PID");
+        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_remove_split_tunnel_process(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), ()>>,
+        pid: i32,
+    ) {
+        let result =
+            self.with_pid_manager(|pids| pids.remove(pid), "Unable to stop excluding PID");
+        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_clear_split_tunnel_processes(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), ()>>,
+    ) {
+        let result = self.with_pid_manager(|pids| pids.clear(), "Unable to clear excluded PIDs");
+        Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_add_split_tunnel_cgroup(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        cgroup: String,
+    ) {
+        let exclude_result = match self.exclude_pids {
+            Some(ref pids) => pids.add_cgroup(&cgroup),
+            None => split_tunnel::find_cgroup(&cgroup).map(|_| ()),
+        };
+        if let Err(e) = exclude_result {
+            log::error!("{}", e.display_chain_with_msg("Unable to exclude cgroup"));
+            let error = settings::Error::InvalidCgroup(e.to_string());
+            Self::oneshot_send(tx, Err(error), "add_split_tunnel_cgroup response");
+            return;
+        }
+
+        match self.settings.add_split_tunnel_cgroup(cgroup) {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                }
+                Self::oneshot_send(tx, Ok(()), "add_split_tunnel_cgroup response");
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "add_split_tunnel_cgroup response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_remove_split_tunnel_cgroup(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        cgroup: String,
+    ) {
+        if let Some(ref pids) = self.exclude_pids {
+            // The cgroup may be gone by now, it should still be removable from the settings.
+            if let Err(e) = pids.remove_cgroup(&cgroup) {
+                log::warn!("{}", e.display_chain_with_msg("Unable to stop excluding cgroup"));
+            }
+        }
+
+        match self.settings.remove_split_tunnel_cgroup(&cgroup) {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener.notify_settings(self.settings.clone());
+                }
+                Self::oneshot_send(tx, Ok(()), "remove_split_tunnel_cgroup response");
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "remove_split_tunnel_cgroup response");
+            }
+        }
+    }
+
+    /// Runs `f` on the PID manager, logging any error with `error_msg`.
+    #[cfg(target_os = "linux")]
+    fn with_pid_manager<T>(
+        &self,
+        f: impl FnOnce(&split_tunnel::PidManager) -> split_tunnel::Result<T>,
+        error_msg: &str,
+    ) -> ::std::result::Result<T, ()> {
+        let pids = self.exclude_pids.as_ref().ok_or_else(|| {
+            log::error!("{}: split tunneling is not initialized", error_msg);
+        })?;
+        f(pids).map_err(|e| log::error!("{}", e.display_chain_with_msg(error_msg)))
+    }
+
+    /// Excludes the processes currently in the cgroups saved in the settings from the tunnel.
+    #[cfg(target_os = "linux")]
+    fn exclude_split_tunnel_cgroups(&self) {
+        if let Some(ref pids) = self.exclude_pids {
+            for cgroup in &self.settings.get_split_tunnel_settings().excluded_cgroups {
+                if let Err(e) = pids.add_cgroup(cgroup) {
+                    log::warn!(
+                        "{}",
+                        e.display_chain_with_msg(&format!("Unable to exclude cgroup {}", cgroup))
+                    );
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn on_get_split_tunnel_processes(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<Vec<i32>, ()>>,
+    ) {
+        Self::oneshot_send(tx, Err(()), "get_split_tunnel_processes response");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn on_add_split_tunnel_process(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), ()>>,
+        _pid: i32,
+    ) {
+        Self::oneshot_send(tx, Err(()), "add_split_tunnel_process response");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn on_remove_split_tunnel_process(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), ()>>,
+        _pid: i32,
+    ) {
+        Self::oneshot_send(tx, Err(()), "remove_split_tunnel_process response");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn on_clear_split_tunnel_processes(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), ()>>,
+    ) {
+        Self::oneshot_send(tx, Err(()), "clear_split_tunnel_processes response");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn on_add_split_tunnel_cgroup(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        _cgroup: String,
+    ) {
+        let error = settings::Error::InvalidCgroup(
+            "Split tunneling is only supported on Linux".to_owned(),
+        );
+        Self::oneshot_send(tx, Err(error), "add_split_tunnel_cgroup response");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn on_remove_split_tunnel_cgroup(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        _cgroup: String,
+    ) {
+        let error = settings::Error::InvalidCgroup(
+            "Split tunneling is only supported on Linux".to_owned(),
+        );
+        Self::oneshot_send(tx, Err(error), "remove_split_tunnel_cgroup response");
+    }
+
     fn ensure_wireguard_keys_for_current_account(&mut self) {
         if let Some(account) = self.settings.get_account_token() {
 
             // add by YanBowen
-            log::info!("Update tinc key for account");
-            if let Err(e) = self
-                .tinc_key_manager
-                .generate_key_sync(&account) {
-                log::error!(
-                        "{:?}",
-                        e
+            if self
+                .account_history
+                .get(&account)
+                .map(|entry| entry.map(|e| e.tinc.is_none()).unwrap_or(true))
+                .unwrap_or(true)
+            {
+                log::info!("Exchanging tinc keys for account");
+                if let Err(e) = self
+                    .tinc_key_manager
+                    .generate_key_async(account.clone(), KeyType::Ed25519)
+                {
+                    log::error!(
+                        "{}",
+                        e.display_chain_with_msg("Failed to start exchanging tinc keys")
                     );
+                }
+            } else {
+                log::info!("Account already has tinc data");
             }
 
 
@@ -1263,6 +1696,22 @@ where
         }
     }
 
+    /// Caches what the proxy assigned to `account`, for the tunnels started later.
+    fn set_tinc_data(&mut self, account: &AccountToken, data: TincData) {
+        let result = self.account_history.get(account).and_then(|entry| {
+            let mut entry = entry.unwrap_or_else(|| account_history::AccountEntry {
+                account: account.clone(),
+                wireguard: None,
+                tinc: None,
+            });
+            entry.tinc = Some(data);
+            self.account_history.insert(entry)
+        });
+        if let Err(e) = result {
+            log::error!("{}", e.display_chain_with_msg("Failed to add tinc data to account data"));
+        }
+    }
+
     fn on_generate_wireguard_key(&mut self, tx: oneshot::Sender<KeygenEvent>) {
         let mut result = || -> ::std::result::Result<KeygenEvent, String> {
             let account_token = self
@@ -1280,6 +1729,7 @@ where
                         account_history::AccountEntry {
                             account: account_token.clone(),
                             wireguard: None,
+                            tinc: None,
                         }
                     })
                 })?;
@@ -28,7 +28,14 @@ use std::{
 };
 use talpid_core::mpsc::IntoSender;
 use talpid_ipc;
-use talpid_types::{net::wireguard, ErrorExt};
+use talpid_types::{
+    net::{
+        dns::{CustomDnsServer, DnsUpstream},
+        firewall::AllowRule,
+        tinc, wireguard,
+    },
+    ErrorExt,
+};
 use uuid;
 
 /// FIXME(linus): This is here just because the futures crate has deprecated it and jsonrpc_core
@@ -78,6 +85,26 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_allow_lan")]
         fn set_allow_lan(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Adds a rule allowing traffic through the firewall, whatever the tunnel state. Not
+        /// supported on Windows.
+        #[rpc(meta, name = "add_firewall_rule")]
+        fn add_firewall_rule(&self, Self::Metadata, AllowRule) -> BoxFuture<(), Error>;
+
+        /// Removes a rule added with `add_firewall_rule`.
+        #[rpc(meta, name = "remove_firewall_rule")]
+        fn remove_firewall_rule(&self, Self::Metadata, AllowRule) -> BoxFuture<(), Error>;
+
+        /// Sets the DNS resolvers used instead of the tunnel gateway. An empty list restores the
+        /// default.
+        #[rpc(meta, name = "set_custom_dns")]
+        fn set_custom_dns(&self, Self::Metadata, Vec<CustomDnsServer>) -> BoxFuture<(), Error>;
+
+        /// Sets the encrypted resolvers that a local DNS forwarder sends DNS queries to. An empty
+        /// list stops the forwarder. Outside the tunnel the forwarder is only used where the DNS
+        /// backend can set system-wide DNS servers, otherwise connecting blocks.
+        #[rpc(meta, name = "set_dns_upstreams")]
+        fn set_dns_upstreams(&self, Self::Metadata, Vec<DnsUpstream>) -> BoxFuture<(), Error>;
+
         /// Set if the client should allow network communication when in the disconnected state.
         #[rpc(meta, name = "set_block_when_disconnected")]
         fn set_block_when_disconnected(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
@@ -137,6 +164,43 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_wireguard_mtu")]
         fn set_wireguard_mtu(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
 
+        /// Returns the options of tinc tunnels
+        #[rpc(meta, name = "get_tinc_options")]
+        fn get_tinc_options(&self, Self::Metadata) -> BoxFuture<tinc::TunnelOptions, Error>;
+
+        /// Sets the options of tinc tunnels, used from the next time a tunnel starts
+        #[rpc(meta, name = "set_tinc_options")]
+        fn set_tinc_options(&self, Self::Metadata, tinc::TunnelOptions) -> BoxFuture<(), Error>;
+
+        /// Replaces the tinc key pair of a type and exchanges the new keys for the current
+        /// account, which keeps its VIP. The type is preferred in later exchanges.
+        #[rpc(meta, name = "rotate_tinc_key")]
+        fn rotate_tinc_key(&self, Self::Metadata, tinc::KeyType) -> BoxFuture<(), Error>;
+
+        /// Returns the PIDs of the processes excluded from the tunnel
+        #[rpc(meta, name = "get_split_tunnel_processes")]
+        fn get_split_tunnel_processes(&self, Self::Metadata) -> BoxFuture<Vec<i32>, Error>;
+
+        /// Excludes a process and its future children from the tunnel
+        #[rpc(meta, name = "add_split_tunnel_process")]
+        fn add_split_tunnel_process(&self, Self::Metadata, i32) -> BoxFuture<(), Error>;
+
+        /// Stops excluding a process from the tunnel
+        #[rpc(meta, name = "remove_split_tunnel_process")]
+        fn remove_split_tunnel_process(&self, Self::Metadata, i32) -> BoxFuture<(), Error>;
+
+        /// Stops excluding any process from the tunnel
+        #[rpc(meta, name = "clear_split_tunnel_processes")]
+        fn clear_split_tunnel_processes(&self, Self::Metadata) -> BoxFuture<(), Error>;
+
+        /// Excludes the processes of a cgroup from the tunnel, and saves it in the settings
+        #[rpc(meta, name = "add_split_tunnel_cgroup")]
+        fn add_split_tunnel_cgroup(&self, Self::Metadata, String) -> BoxFuture<(), Error>;
+
+        /// Stops excluding the processes of a cgroup from the tunnel
+        #[rpc(meta, name = "remove_split_tunnel_cgroup")]
+        fn remove_split_tunnel_cgroup(&self, Self::Metadata, String) -> BoxFuture<(), Error>;
+
         /// Returns the current daemon settings
         #[rpc(meta, name = "get_settings")]
         fn get_settings(&self, Self::Metadata) -> BoxFuture<Settings, Error>;
@@ -218,6 +282,17 @@ pub enum ManagementCommand {
     UpdateRelaySettings(OneshotSender<()>, RelaySettingsUpdate),
     /// Set the allow LAN setting.
     SetAllowLan(OneshotSender<()>, bool),
+    /// Add a user-defined firewall rule
+    AddFirewallRule(OneshotSender<Result<(), settings::Error>>, AllowRule),
+    /// Remove a user-defined firewall rule
+    RemoveFirewallRule(OneshotSender<Result<(), settings::Error>>, AllowRule),
+    /// Set the custom DNS resolvers
+    SetCustomDns(
+        OneshotSender<Result<(), settings::Error>>,
+        Vec<CustomDnsServer>,
+    ),
+    /// Set the resolvers of the DNS forwarder
+    SetDnsUpstreams(OneshotSender<Result<(), settings::Error>>, Vec<DnsUpstream>),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(OneshotSender<()>, bool),
     /// Set the auto-connect setting.
@@ -232,6 +307,22 @@ pub enum ManagementCommand {
     SetEnableIpv6(OneshotSender<()>, bool),
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(OneshotSender<()>, Option<u16>),
+    /// Set the options of tinc tunnels
+    SetTincOptions(OneshotSender<Result<(), settings::Error>>, tinc::TunnelOptions),
+    /// Replace the tinc key pair of a type and exchange it for the current account
+    RotateTincKey(OneshotSender<Result<(), ()>>, tinc::KeyType),
+    /// Get the PIDs of the processes excluded from the tunnel
+    GetSplitTunnelProcesses(OneshotSender<Result<Vec<i32>, ()>>),
+    /// Exclude a process from the tunnel
+    AddSplitTunnelProcess(OneshotSender<Result<(), ()>>, i32),
+    /// Stop excluding a process from the tunnel
+    RemoveSplitTunnelProcess(OneshotSender<Result<(), ()>>, i32),
+    /// Stop excluding any process from the tunnel
+    ClearSplitTunnelProcesses(OneshotSender<Result<(), ()>>),
+    /// Exclude the processes of a cgroup from the tunnel
+    AddSplitTunnelCgroup(OneshotSender<Result<(), settings::Error>>, String),
+    /// Stop excluding the processes of a cgroup from the tunnel
+    RemoveSplitTunnelCgroup(OneshotSender<Result<(), settings::Error>>, String),
     /// Get the daemon settings
     GetSettings(OneshotSender<Settings>),
     /// Generate new wireguard key
@@ -381,6 +472,14 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterface<T> {
             _ => Error::internal_error(),
         }
     }
+
+    /// Lets the caller know if the cgroup it passed was rejected.
+    fn map_cgroup_error(error: settings::Error) -> Error {
+        match error {
+            settings::Error::InvalidCgroup(reason) => Error::invalid_params(reason),
+            _ => Error::internal_error(),
+        }
+    }
 }
 
 impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
@@ -501,6 +600,69 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn add_firewall_rule(&self, _: Self::Metadata, rule: AllowRule) -> BoxFuture<(), Error> {
+        log::debug!("add_firewall_rule({:?})", rule);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::AddFirewallRule(tx, rule))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error {
+                    settings::Error::InvalidFirewallRule(reason) => Error::invalid_params(reason),
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
+    fn remove_firewall_rule(&self, _: Self::Metadata, rule: AllowRule) -> BoxFuture<(), Error> {
+        log::debug!("remove_firewall_rule({:?})", rule);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RemoveFirewallRule(tx, rule))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| settings_result.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_custom_dns(
+        &self,
+        _: Self::Metadata,
+        servers: Vec<CustomDnsServer>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_custom_dns({:?})", servers);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetCustomDns(tx, servers))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error {
+                    settings::Error::InvalidDnsServer(reason) => Error::invalid_params(reason),
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_dns_upstreams(
+        &self,
+        _: Self::Metadata,
+        upstreams: Vec<DnsUpstream>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_dns_upstreams({:?})", upstreams);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetDnsUpstreams(tx, upstreams))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error {
+                    settings::Error::InvalidDnsUpstream(reason) => Error::invalid_params(reason),
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
     fn set_block_when_disconnected(
         &self,
         _: Self::Metadata,
@@ -670,6 +832,109 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn get_tinc_options(&self, _: Self::Metadata) -> BoxFuture<tinc::TunnelOptions, Error> {
+        log::debug!("get_tinc_options");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetSettings(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .map(|settings| settings.get_tunnel_options().tinc.clone());
+        Box::new(future)
+    }
+
+    fn set_tinc_options(
+        &self,
+        _: Self::Metadata,
+        options: tinc::TunnelOptions,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_tinc_options({:?})", options);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetTincOptions(tx, options))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error {
+                    settings::Error::InvalidTincOptions(reason) => Error::invalid_params(reason),
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
+    fn rotate_tinc_key(&self, _: Self::Metadata, key_type: tinc::KeyType) -> BoxFuture<(), Error> {
+        log::debug!("rotate_tinc_key({:?})", key_type);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RotateTincKey(tx, key_type))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_split_tunnel_processes(&self, _: Self::Metadata) -> BoxFuture<Vec<i32>, Error> {
+        log::debug!("get_split_tunnel_processes");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetSplitTunnelProcesses(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn add_split_tunnel_process(&self, _: Self::Metadata, pid: i32) -> BoxFuture<(), Error> {
+        log::debug!("add_split_tunnel_process({})", pid);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::AddSplitTunnelProcess(tx, pid))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn remove_split_tunnel_process(&self, _: Self::Metadata, pid: i32) -> BoxFuture<(), Error> {
+        log::debug!("remove_split_tunnel_process({})", pid);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RemoveSplitTunnelProcess(tx, pid))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn clear_split_tunnel_processes(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
+        log::debug!("clear_split_tunnel_processes");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ClearSplitTunnelProcesses(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn add_split_tunnel_cgroup(&self, _: Self::Metadata, cgroup: String) -> BoxFuture<(), Error> {
+        log::debug!("add_split_tunnel_cgroup({})", cgroup);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::AddSplitTunnelCgroup(tx, cgroup))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| settings_result.map_err(Self::map_cgroup_error));
+        Box::new(future)
+    }
+
+    fn remove_split_tunnel_cgroup(
+        &self,
+        _: Self::Metadata,
+        cgroup: String,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("remove_split_tunnel_cgroup({})", cgroup);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RemoveSplitTunnelCgroup(tx, cgroup))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| settings_result.map_err(Self::map_cgroup_error));
+        Box::new(future)
+    }
+
     fn get_settings(&self, _: Self::Metadata) -> BoxFuture<Settings, Error> {
         log::debug!("get_settings");
         let (tx, rx) = sync::oneshot::channel();
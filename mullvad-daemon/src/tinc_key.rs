@@ -1,77 +1,187 @@
 use crate::InternalDaemonEvent;
 
-use std::sync::mpsc;
+use std::{
+    net::IpAddr,
+    sync::{mpsc, Arc},
+    time::Duration,
+};
 
 use futures::{future::Executor, sync::oneshot, Future};
 use jsonrpc_client_core::Error as JsonRpcError;
 use tokio_core::reactor::Remote;
+use tokio_retry::{
+    strategy::{jitter, ExponentialBackoff},
+    RetryIf,
+};
 
-use mullvad_types::account::AccountToken;
-use tinc_plugin::TincOperator;
+use mullvad_types::{account::AccountToken, tinc::TincData};
+use tinc_plugin::{KeyExchange, KeyType, PublicKeys, TincOperator};
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
     #[error(display = "Failed to generate pubkey")]
     GenerationError,
+    #[error(display = "Failed to read the keys of this node")]
+    ReadKeysError(#[error(cause)] tinc_plugin::TincOperatorError),
     #[error(display = "Failed to spawn future")]
     ExectuionError,
     #[error(display = "Unexpected RPC error")]
     RpcError(#[error(cause)] jsonrpc_client_core::Error),
     #[error(display = "Account already has maximum number of keys")]
     TooManyKeys,
+    #[error(display = "The proxy has no key of a type this node has")]
+    NoCommonKeyType,
+    #[error(display = "The proxy assigned an invalid address")]
+    InvalidAssignment,
+    #[error(display = "The proxy does not support key exchange")]
+    KeyExchangeNotSupported,
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 pub struct KeyManager {
+    tinc:           Arc<TincOperator>,
     tokio_remote:   Remote,
     daemon_tx:      mpsc::Sender<InternalDaemonEvent>,
     http_handle:    mullvad_rpc::HttpHandle,
-    remote_pubkey:  String,
-    local_pubkey:   String,
 }
 
 impl KeyManager {
     pub(crate) fn new(
+        tinc:           Arc<TincOperator>,
         daemon_tx:      mpsc::Sender<InternalDaemonEvent>,
         http_handle:    mullvad_rpc::HttpHandle,
         tokio_remote:   Remote,
     ) -> Self {
         Self {
+            tinc,
             daemon_tx,
             http_handle,
             tokio_remote,
-            remote_pubkey: String::new(),
-            local_pubkey: String::new(),
         }
     }
 
-    pub fn get_local_pubkey(&self) -> String {
-        self.local_pubkey.clone()
+    /// Keys of this node, as written in its host file.
+    pub fn get_local_pubkey(&self) -> Result<String> {
+        self.tinc
+            .get_local_pub_keys()
+            .map(|keys| keys.to_host_config())
+            .map_err(Error::ReadKeysError)
     }
 
-    pub fn get_remote_pubkey(&self) -> String {
-        self.remote_pubkey.clone()
+    /// Makes sure there is a key pair of every type, and exchanges the public keys with the
+    /// proxy, preferring `preferred` as the key type to use. Returns what the proxy assigned to
+    /// the account.
+    pub fn generate_key_sync(&mut self, account: &AccountToken, preferred: KeyType)
+        -> Result<TincData>
+    {
+        let local_keys = self.ensure_local_keys()?;
+        let exchange = self.exchange(account.to_string(), local_keys.clone(), preferred)?;
+        Self::tinc_data(&local_keys, exchange)
     }
 
-    /// Generate a new private key
-    pub fn generate_key_sync(&mut self, account: &AccountToken) -> Result<()> {
-        let account = account.to_string();
+    /// Like `generate_key_sync`, but exchanges the keys on the reactor of the daemon, retrying
+    /// until the proxy answers. What the proxy assigned is sent to the daemon channel.
+    pub fn generate_key_async(&mut self, account: AccountToken, preferred: KeyType) -> Result<()> {
+        let local_keys = self.ensure_local_keys()?;
+
+        let mut rpc = mullvad_rpc::TincKeyProxy::new(self.http_handle.clone());
+        let request_account = account.clone();
+        let request_keys = local_keys.clone();
+        let request = move || {
+            rpc.exchange_tinc_keys(request_account.clone(), request_keys.clone(), preferred)
+        };
+
+        let retry_strategy = ExponentialBackoff::from_millis(300)
+            .max_delay(Duration::from_secs(60 * 60))
+            .map(jitter);
+        let should_retry =
+            |err: &JsonRpcError| -> bool { !is_too_many_keys(err) && !is_method_not_found(err) };
+
+        let daemon_tx = self.daemon_tx.clone();
+        let fut = RetryIf::spawn(retry_strategy, request, should_retry).then(move |result| {
+            let result = match result {
+                Ok(exchange) => Self::tinc_data(&local_keys, exchange),
+                Err(tokio_retry::Error::OperationError(e)) => Err(Self::map_rpc_error(e)),
+                Err(tokio_retry::Error::TimerError(timer_error)) => {
+                    log::error!("Tokio timer error {}", timer_error);
+                    Err(Error::ExectuionError)
+                }
+            };
+            let _ = daemon_tx.send(InternalDaemonEvent::TincKeyEvent((account, result)));
+            Ok(())
+        });
+        self.tokio_remote
+            .execute(fut)
+            .map_err(|_| Error::ExectuionError)
+    }
 
-        let local_pubkey = match TincOperator::instance().get_local_pub_key() {
-            Ok(x) => x,
-            Err(_) => {
-                TincOperator::instance().create_pub_key()
+    /// Creates the key pairs this node is missing, and returns the public keys of all of them.
+    fn ensure_local_keys(&self) -> Result<PublicKeys> {
+        let mut local_keys = self.tinc.get_local_pub_keys().unwrap_or_default();
+        for key_type in &[KeyType::Rsa, KeyType::Ed25519] {
+            if !local_keys.has(*key_type) {
+                self.tinc.create_key(*key_type)
+                    .map_err(|_|Error::GenerationError)?;
+                local_keys = self.tinc.get_local_pub_keys()
                     .map_err(|_|Error::GenerationError)?;
-                TincOperator::instance().get_local_pub_key()
-                    .map_err(|_|Error::GenerationError)?
             }
+        }
+        Ok(local_keys)
+    }
+
+    /// What the proxy assigned in `exchange`, checked against the keys of this node.
+    fn tinc_data(local_keys: &PublicKeys, exchange: KeyExchange) -> Result<TincData> {
+        if !local_keys.has(exchange.key_type) {
+            return Err(Error::NoCommonKeyType);
+        }
+
+        let (vip, gateway) = match (exchange.vip, exchange.gateway) {
+            (IpAddr::V4(vip), IpAddr::V4(gateway)) if exchange.prefix <= 32 => (vip, gateway),
+            _ => return Err(Error::InvalidAssignment),
         };
 
-        self.local_pubkey = local_pubkey.clone();
+        Ok(TincData {
+            vip,
+            prefix: exchange.prefix,
+            gateway,
+            proxy_keys: exchange.keys.to_host_config(),
+            proxy_node: Some(exchange.node).filter(|node| !node.is_empty()),
+            key_type: exchange.key_type,
+            proxies: exchange.proxies,
+        })
+    }
+
+    /// Replaces the key pair of `key_type` and exchanges the new public keys with the proxy. The
+    /// account keeps the VIP leased to it.
+    pub fn rotate_key_sync(&mut self, account: &AccountToken, key_type: KeyType)
+        -> Result<TincData>
+    {
+        self.tinc.create_key(key_type)
+            .map_err(|_|Error::GenerationError)?;
+        self.generate_key_sync(account, key_type)
+    }
 
+    /// Exchanges keys with the proxy. Proxies that only take an RSA key don't assign addresses,
+    /// so there is no tunnel to set up with them.
+    fn exchange(
+        &self,
+        account: AccountToken,
+        local_keys: PublicKeys,
+        preferred: KeyType,
+    ) -> Result<KeyExchange> {
+        let mut rpc = mullvad_rpc::TincKeyProxy::new(self.http_handle.clone());
+        self.execute(rpc.exchange_tinc_keys(account, local_keys, preferred))
+    }
+
+    /// Runs `request` on the reactor of the daemon, waiting for the answer.
+    fn execute<T, F>(&self, request: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Future<Item = T, Error = JsonRpcError> + Send + 'static,
+    {
         let (tx, rx) = oneshot::channel();
-        let fut = self.push_future_generator(account, local_pubkey)().then(|result| {
+        let fut = request.then(|result| {
             let _ = tx.send(result);
             Ok(())
         });
@@ -79,36 +189,33 @@ impl KeyManager {
             .execute(fut)
             .map_err(|_e| Error::ExectuionError)?;
 
-        let server_pubkey = rx.wait()
+        rx.wait()
             .map_err(|_| Error::ExectuionError)?
-            .map_err(Self::map_rpc_error)?;
-
-        self.remote_pubkey = server_pubkey;
-        Ok(())
+            .map_err(Self::map_rpc_error)
     }
 
-    fn push_future_generator(
-        &self,
-        account: AccountToken,
-        local_pubkey: String,
-    ) -> Box<dyn FnMut() -> Box<dyn Future<Item = String, Error = JsonRpcError> + Send> + Send>
-    {
-        let mut rpc = mullvad_rpc::TincKeyProxy::new(self.http_handle.clone());
+    // TODO: Consider handling the invalid account case too.
+    fn map_rpc_error(err: jsonrpc_client_core::Error) -> Error {
+        if is_too_many_keys(&err) {
+            Error::TooManyKeys
+        } else if is_method_not_found(&err) {
+            Error::KeyExchangeNotSupported
+        } else {
+            Error::RpcError(err)
+        }
+    }
+}
 
-        let push_future =
-            move || -> Box<dyn Future<Item = String, Error = JsonRpcError> + Send> {
-                Box::new(rpc.push_tinc_key(account.clone(), local_pubkey.clone()))
-            };
-        Box::new(push_future)
+fn is_too_many_keys(err: &jsonrpc_client_core::Error) -> bool {
+    match err.kind() {
+        jsonrpc_client_core::ErrorKind::JsonRpcError(err) => err.code.code() == -703,
+        _ => false,
     }
+}
 
-    fn map_rpc_error(err: jsonrpc_client_core::Error) -> Error {
-        match err.kind() {
-            // TODO: Consider handling the invalid account case too.
-            jsonrpc_client_core::ErrorKind::JsonRpcError(err) if err.code.code() == -703 => {
-                Error::TooManyKeys
-            }
-            _ => Error::RpcError(err),
-        }
+fn is_method_not_found(err: &jsonrpc_client_core::Error) -> bool {
+    match err.kind() {
+        jsonrpc_client_core::ErrorKind::JsonRpcError(err) => err.code.code() == -32601,
+        _ => false,
     }
 }
@@ -15,7 +15,11 @@ use mullvad_types::{
 };
 use serde::{Deserialize, Serialize};
 use std::{io, path::Path, thread};
-use talpid_types::net::wireguard;
+use talpid_types::net::{
+    dns::{CustomDnsServer, DnsUpstream},
+    firewall::AllowRule,
+    tinc, wireguard,
+};
 
 static NO_ARGS: [u8; 0] = [];
 
@@ -120,6 +124,22 @@ impl DaemonRpcClient {
         self.call("set_allow_lan", &[allow_lan])
     }
 
+    pub fn add_firewall_rule(&mut self, rule: AllowRule) -> Result<()> {
+        self.call("add_firewall_rule", &[rule])
+    }
+
+    pub fn remove_firewall_rule(&mut self, rule: AllowRule) -> Result<()> {
+        self.call("remove_firewall_rule", &[rule])
+    }
+
+    pub fn set_custom_dns(&mut self, servers: Vec<CustomDnsServer>) -> Result<()> {
+        self.call("set_custom_dns", &[servers])
+    }
+
+    pub fn set_dns_upstreams(&mut self, upstreams: Vec<DnsUpstream>) -> Result<()> {
+        self.call("set_dns_upstreams", &[upstreams])
+    }
+
     pub fn set_block_when_disconnected(&mut self, block_when_disconnected: bool) -> Result<()> {
         self.call("set_block_when_disconnected", &[block_when_disconnected])
     }
@@ -196,6 +216,42 @@ impl DaemonRpcClient {
         self.call("set_wireguard_mtu", &[mtu])
     }
 
+    pub fn get_tinc_options(&mut self) -> Result<tinc::TunnelOptions> {
+        self.call("get_tinc_options", &NO_ARGS)
+    }
+
+    pub fn set_tinc_options(&mut self, options: tinc::TunnelOptions) -> Result<()> {
+        self.call("set_tinc_options", &[options])
+    }
+
+    pub fn rotate_tinc_key(&mut self, key_type: tinc::KeyType) -> Result<()> {
+        self.call("rotate_tinc_key", &[key_type])
+    }
+
+    pub fn get_split_tunnel_processes(&mut self) -> Result<Vec<i32>> {
+        self.call("get_split_tunnel_processes", &NO_ARGS)
+    }
+
+    pub fn add_split_tunnel_process(&mut self, pid: i32) -> Result<()> {
+        self.call("add_split_tunnel_process", &[pid])
+    }
+
+    pub fn remove_split_tunnel_process(&mut self, pid: i32) -> Result<()> {
+        self.call("remove_split_tunnel_process", &[pid])
+    }
+
+    pub fn clear_split_tunnel_processes(&mut self) -> Result<()> {
+        self.call("clear_split_tunnel_processes", &NO_ARGS)
+    }
+
+    pub fn add_split_tunnel_cgroup(&mut self, cgroup: String) -> Result<()> {
+        self.call("add_split_tunnel_cgroup", &[cgroup])
+    }
+
+    pub fn remove_split_tunnel_cgroup(&mut self, cgroup: String) -> Result<()> {
+        self.call("remove_split_tunnel_cgroup", &[cgroup])
+    }
+
     pub fn set_openvpn_mssfix(&mut self, mssfix: Option<u16>) -> Result<()> {
         self.call("set_openvpn_mssfix", &[mssfix])
     }
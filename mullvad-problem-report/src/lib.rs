@@ -245,6 +245,12 @@ fn write_logcat_to_file() -> Result<PathBuf, io::Error> {
         .map(|_| logcat_path)
 }
 
+/// Tries to parse out the metadata map from a string that is supposed to be a report written by
+/// `collect_report`.
+pub fn parse_metadata(report: &str) -> Option<BTreeMap<String, String>> {
+    ProblemReport::parse_metadata(report)
+}
+
 pub fn send_problem_report(
     user_email: &str,
     user_message: &str,
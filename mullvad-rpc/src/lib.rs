@@ -18,7 +18,7 @@ use std::{
     path::{Path, PathBuf},
     time::Duration,
 };
-use talpid_types::net::wireguard;
+use talpid_types::net::{tinc, wireguard};
 use tokio_core::reactor::Handle;
 
 pub use jsonrpc_client_core::{Error, ErrorKind};
@@ -122,6 +122,12 @@ jsonrpc_client!(pub struct TincKeyProxy {
         account_token: AccountToken,
         public_key: String
     ) -> RpcRequest<String>;
+    pub fn exchange_tinc_keys(
+        &mut self,
+        account_token: AccountToken,
+        keys: tinc::PublicKeys,
+        key_type: tinc::KeyType
+    ) -> RpcRequest<tinc::KeyExchange>;
 });
 //
 
@@ -158,4 +164,15 @@ jsonrpc_client!(pub struct WireguardKeyProxy {
         account_token: AccountToken,
         public_key: wireguard::PublicKey
     ) -> RpcRequest<bool>;
+    pub fn replace_wg_key(
+        &mut self,
+        account_token: AccountToken,
+        old: wireguard::PublicKey,
+        new: wireguard::PublicKey
+    ) -> RpcRequest<mullvad_types::wireguard::AssociatedAddresses>;
+    pub fn remove_wg_key(
+        &mut self,
+        account_token: AccountToken,
+        public_key: wireguard::PublicKey
+    ) -> RpcRequest<()>;
 });
@@ -16,6 +16,7 @@ pub mod relay_constraints;
 pub mod relay_list;
 pub mod settings;
 pub mod states;
+pub mod tinc;
 pub mod version;
 pub mod wireguard;
 
@@ -47,6 +47,8 @@ pub struct RelayListCity {
 pub struct Relay {
     pub hostname: String,
     pub ipv4_addr_in: Ipv4Addr,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ipv6_addr_in: Option<Ipv6Addr>,
     pub include_in_country: bool,
     pub weight: u64,
     #[serde(skip_serializing_if = "RelayTunnels::is_empty", default)]
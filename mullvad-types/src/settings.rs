@@ -6,7 +6,11 @@ use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use std::{fs::File, io, path::PathBuf};
-use talpid_types::net::{openvpn, wireguard, GenericTunnelOptions};
+use talpid_types::net::{
+    dns::{self, CustomDnsServer, DnsUpstream},
+    firewall::{self, AllowRule},
+    openvpn, wireguard, GenericTunnelOptions,
+};
 // add by YanBowen
 use talpid_types::net::tinc;
 
@@ -32,6 +36,21 @@ pub enum Error {
 
     #[error(display = "Invalid OpenVPN proxy configuration: {}", _0)]
     InvalidProxyData(String),
+
+    #[error(display = "Invalid tinc options: {}", _0)]
+    InvalidTincOptions(String),
+
+    #[error(display = "Invalid cgroup: {}", _0)]
+    InvalidCgroup(String),
+
+    #[error(display = "Invalid firewall rule: {}", _0)]
+    InvalidFirewallRule(String),
+
+    #[error(display = "Invalid DNS server: {}", _0)]
+    InvalidDnsServer(String),
+
+    #[error(display = "Invalid DNS upstream: {}", _0)]
+    InvalidDnsUpstream(String),
 }
 
 static SETTINGS_FILE: &str = "settings.json";
@@ -47,6 +66,13 @@ pub struct Settings {
     bridge_state: BridgeState,
     /// If the daemon should allow communication with private (LAN) networks.
     allow_lan: bool,
+    /// User-defined rules allowing traffic through the firewall, whatever the tunnel state.
+    firewall_rules: Vec<AllowRule>,
+    /// DNS resolvers used instead of the tunnel gateway while connected, in order of preference.
+    custom_dns: Vec<CustomDnsServer>,
+    /// Encrypted resolvers that DNS queries are forwarded to, in order of preference. While there
+    /// are any, they take precedence over `custom_dns`.
+    dns_upstreams: Vec<DnsUpstream>,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     block_when_disconnected: bool,
@@ -55,6 +81,8 @@ pub struct Settings {
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
     tunnel_options: TunnelOptions,
+    /// What bypasses the tunnel. Only used on Linux.
+    split_tunnel: SplitTunnelSettings,
 }
 
 impl Default for Settings {
@@ -70,9 +98,13 @@ impl Default for Settings {
             }),
             bridge_state: BridgeState::Auto,
             allow_lan: false,
+            firewall_rules: vec![],
+            custom_dns: vec![],
+            dns_upstreams: vec![],
             block_when_disconnected: false,
             auto_connect: false,
             tunnel_options: TunnelOptions::default(),
+            split_tunnel: SplitTunnelSettings::default(),
         }
     }
 }
@@ -183,6 +215,62 @@ impl Settings {
         }
     }
 
+    pub fn get_firewall_rules(&self) -> &[AllowRule] {
+        &self.firewall_rules
+    }
+
+    pub fn add_firewall_rule(&mut self, rule: AllowRule) -> Result<bool> {
+        firewall::validate_allow_rule(&rule).map_err(Error::InvalidFirewallRule)?;
+        if !self.firewall_rules.contains(&rule) {
+            self.firewall_rules.push(rule);
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn remove_firewall_rule(&mut self, rule: &AllowRule) -> Result<bool> {
+        let old_len = self.firewall_rules.len();
+        self.firewall_rules.retain(|existing| existing != rule);
+        if self.firewall_rules.len() != old_len {
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_custom_dns(&self) -> &[CustomDnsServer] {
+        &self.custom_dns
+    }
+
+    pub fn set_custom_dns(&mut self, custom_dns: Vec<CustomDnsServer>) -> Result<bool> {
+        for server in &custom_dns {
+            dns::validate_custom_dns_server(server).map_err(Error::InvalidDnsServer)?;
+        }
+        if self.custom_dns != custom_dns {
+            self.custom_dns = custom_dns;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_dns_upstreams(&self) -> &[DnsUpstream] {
+        &self.dns_upstreams
+    }
+
+    pub fn set_dns_upstreams(&mut self, dns_upstreams: Vec<DnsUpstream>) -> Result<bool> {
+        for upstream in &dns_upstreams {
+            dns::validate_dns_upstream(upstream).map_err(Error::InvalidDnsUpstream)?;
+        }
+        if self.dns_upstreams != dns_upstreams {
+            self.dns_upstreams = dns_upstreams;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn get_auto_connect(&self) -> bool {
         self.auto_connect
     }
@@ -223,10 +311,50 @@ impl Settings {
         }
     }
 
+    /// Replaces the tinc options, which tunnels started from then on use.
+    pub fn set_tinc_options(&mut self, options: tinc::TunnelOptions) -> Result<bool> {
+        options
+            .validate()
+            .map_err(|e| Error::InvalidTincOptions(e.to_string()))?;
+        if self.tunnel_options.tinc != options {
+            self.tunnel_options.tinc = options;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn get_tunnel_options(&self) -> &TunnelOptions {
         &self.tunnel_options
     }
 
+    pub fn get_split_tunnel_settings(&self) -> &SplitTunnelSettings {
+        &self.split_tunnel
+    }
+
+    /// Excludes the processes of `cgroup` from the tunnel.
+    pub fn add_split_tunnel_cgroup(&mut self, cgroup: String) -> Result<bool> {
+        if !self.split_tunnel.excluded_cgroups.contains(&cgroup) {
+            self.split_tunnel.excluded_cgroups.push(cgroup);
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Stops excluding the processes of `cgroup` from the tunnel.
+    pub fn remove_split_tunnel_cgroup(&mut self, cgroup: &str) -> Result<bool> {
+        let old_len = self.split_tunnel.excluded_cgroups.len();
+        self.split_tunnel
+            .excluded_cgroups
+            .retain(|excluded| excluded != cgroup);
+        if self.split_tunnel.excluded_cgroups.len() != old_len {
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn get_bridge_settings(&self) -> &BridgeSettings {
         &self.bridge_settings
     }
@@ -282,3 +410,13 @@ impl Default for TunnelOptions {
         }
     }
 }
+
+/// What bypasses the tunnel. Single processes can be excluded as well, but aren't part of the
+/// settings since their PIDs don't outlive a reboot.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitTunnelSettings {
+    /// Cgroups whose processes are excluded from the tunnel, as absolute paths in the cgroup
+    /// hierarchy, such as `/system.slice/apt-cacher-ng.service`.
+    pub excluded_cgroups: Vec<String>,
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use talpid_types::net::tinc::{ConnectTo, KeyType};
+
+/// Contains account specific tinc data, as assigned by the proxy on key exchange.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TincData {
+    /// Virtual address of this node.
+    pub vip: Ipv4Addr,
+    /// Prefix length of the network the virtual address is in.
+    pub prefix: u8,
+    /// Virtual address of the proxy, which routes the traffic of this node.
+    pub gateway: Ipv4Addr,
+    /// Public keys of the proxy, as written in its host file.
+    pub proxy_keys: String,
+    /// Node name of the proxy. Proxies that don't tell are named after their address.
+    #[serde(default)]
+    pub proxy_node: Option<String>,
+    /// Key type agreed on with the proxy.
+    pub key_type: KeyType,
+    /// Other proxies of the network, to fail over to.
+    #[serde(default)]
+    pub proxies: Vec<ConnectTo>,
+}
@@ -18,6 +18,10 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(())
     }
 
+    fn set_global(&mut self, _servers: &[IpAddr]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     fn reset(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -0,0 +1,859 @@
+//! A local stub resolver that forwards plaintext DNS queries to encrypted upstream resolvers,
+//! using DNS over HTTPS (RFC 8484) or DNS over TLS (RFC 7858).
+//!
+//! Every query opens a new connection to the upstream, so upstream traffic follows the routes of
+//! the moment it is sent, e.g. through the tunnel once it is up.
+
+use openssl::ssl::{SslConnector, SslMethod};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    str,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use talpid_types::{
+    net::dns::{DnsUpstream, DnsUpstreamProtocol},
+    ErrorExt,
+};
+
+/// The address the forwarder listens on, and that the system resolver is pointed at.
+pub const LISTEN_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const LISTEN_PORT: u16 = 53;
+
+/// How long to wait for an upstream resolver before trying the next one.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long TCP clients may stay idle between queries.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many UDP queries are resolved at once. Queries received beyond that are dropped, and
+/// clients retry them.
+const MAX_UDP_WORKERS: usize = 64;
+
+const DNS_HEADER_LEN: usize = 12;
+/// The largest DNS message, limited by the length prefix of DNS over TCP.
+const MAX_MESSAGE_LEN: usize = 65535;
+/// The largest UDP response for clients that don't advertise a size with EDNS.
+const DEFAULT_UDP_RESPONSE_LEN: usize = 512;
+const OPT_RECORD_TYPE: u16 = 41;
+const TRUNCATED_FLAG: u8 = 0x02;
+const DNS_MESSAGE_TYPE: &str = "application/dns-message";
+
+/// Errors that can happen when starting the DNS forwarder.
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    /// Failed to listen for DNS queries.
+    #[error(display = "Failed to listen for DNS queries on {}", _0)]
+    ListenError(SocketAddr, #[error(cause)] io::Error),
+
+    /// Failed to set up TLS for the upstream connections.
+    #[error(display = "Failed to set up TLS for the DNS upstreams")]
+    TlsSetupError(#[error(cause)] openssl::error::ErrorStack),
+}
+
+#[derive(err_derive::Error, Debug)]
+enum UpstreamError {
+    #[error(display = "Failed to connect")]
+    Connect(#[error(cause)] io::Error),
+
+    #[error(display = "TLS handshake failed: {}", _0)]
+    Handshake(String),
+
+    #[error(display = "Failed to exchange messages")]
+    Io(#[error(cause)] io::Error),
+
+    #[error(display = "Invalid response: {}", _0)]
+    InvalidResponse(String),
+}
+
+/// Answers DNS queries on UDP and TCP by forwarding them to encrypted upstreams. Stops when
+/// dropped.
+pub struct DnsForwarder {
+    local_addr: SocketAddr,
+    closed: Arc<AtomicBool>,
+    udp_thread: Option<thread::JoinHandle<()>>,
+    tcp_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DnsForwarder {
+    /// Starts forwarding the queries received on port 53 of `LISTEN_IP` to `upstreams`, trying
+    /// them in order until one answers.
+    pub fn start(upstreams: Vec<DnsUpstream>) -> Result<Self, Error> {
+        let connector = SslConnector::builder(SslMethod::tls())
+            .map_err(Error::TlsSetupError)?
+            .build();
+        Self::start_with_connector(
+            SocketAddr::new(LISTEN_IP, LISTEN_PORT),
+            upstreams,
+            connector,
+        )
+    }
+
+    fn start_with_connector(
+        listen_addr: SocketAddr,
+        upstreams: Vec<DnsUpstream>,
+        connector: SslConnector,
+    ) -> Result<Self, Error> {
+        let udp_socket =
+            UdpSocket::bind(listen_addr).map_err(|e| Error::ListenError(listen_addr, e))?;
+        let local_addr = udp_socket
+            .local_addr()
+            .map_err(|e| Error::ListenError(listen_addr, e))?;
+        // Bound to the address of the UDP socket, which differs from `listen_addr` for port 0.
+        let tcp_listener =
+            TcpListener::bind(local_addr).map_err(|e| Error::ListenError(local_addr, e))?;
+
+        let resolver = Arc::new(Resolver {
+            upstreams,
+            connector,
+        });
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let udp_resolver = resolver.clone();
+        let udp_closed = closed.clone();
+        let udp_thread = thread::spawn(move || serve_udp(udp_socket, udp_resolver, udp_closed));
+        let tcp_closed = closed.clone();
+        let tcp_thread = thread::spawn(move || serve_tcp(tcp_listener, resolver, tcp_closed));
+
+        log::info!("Forwarding DNS queries received on {}", local_addr);
+        Ok(DnsForwarder {
+            local_addr,
+            closed,
+            udp_thread: Some(udp_thread),
+            tcp_thread: Some(tcp_thread),
+        })
+    }
+
+    /// Returns the address the forwarder listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for DnsForwarder {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        // Wake the servers up, so that they notice they are closed and release the port. They are
+        // only waited for if the wake up worked.
+        let udp_woken = UdpSocket::bind(SocketAddr::new(self.local_addr.ip(), 0))
+            .and_then(|socket| socket.send_to(&[], self.local_addr))
+            .is_ok();
+        let tcp_woken = TcpStream::connect(self.local_addr).is_ok();
+
+        if let (true, Some(thread)) = (udp_woken, self.udp_thread.take()) {
+            let _ = thread.join();
+        }
+        if let (true, Some(thread)) = (tcp_woken, self.tcp_thread.take()) {
+            let _ = thread.join();
+        }
+        log::debug!(
+            "Stopped forwarding DNS queries received on {}",
+            self.local_addr
+        );
+    }
+}
+
+fn serve_udp(socket: UdpSocket, resolver: Arc<Resolver>, closed: Arc<AtomicBool>) {
+    let socket = Arc::new(socket);
+    let workers = Arc::new(AtomicUsize::new(0));
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    loop {
+        let received = socket.recv_from(&mut buffer);
+        if closed.load(Ordering::SeqCst) {
+            break;
+        }
+        let (length, client) = match received {
+            Ok(received) => received,
+            Err(error) => {
+                log::debug!("Failed to receive DNS query: {}", error);
+                continue;
+            }
+        };
+        if length < DNS_HEADER_LEN {
+            continue;
+        }
+        let worker = match WorkerSlot::acquire(&workers, MAX_UDP_WORKERS) {
+            Some(worker) => worker,
+            None => {
+                log::debug!(
+                    "Too many pending DNS queries, dropping query from {}",
+                    client
+                );
+                continue;
+            }
+        };
+
+        let query = buffer[..length].to_vec();
+        let resolver = resolver.clone();
+        // Only the server keeps the socket alive, so that the port is released when it stops.
+        let socket = Arc::downgrade(&socket);
+        thread::spawn(move || {
+            let _worker = worker;
+            if let Some(response) = resolver.resolve(&query) {
+                let response = fit_udp_response(&query, response);
+                if let Some(socket) = socket.upgrade() {
+                    if let Err(error) = socket.send_to(&response, client) {
+                        log::debug!("Failed to send DNS response to {}: {}", client, error);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// A slot in a bounded set of workers, freed when dropped.
+struct WorkerSlot {
+    workers: Arc<AtomicUsize>,
+}
+
+impl WorkerSlot {
+    fn acquire(workers: &Arc<AtomicUsize>, max_workers: usize) -> Option<Self> {
+        let mut current = workers.load(Ordering::SeqCst);
+        loop {
+            if current >= max_workers {
+                return None;
+            }
+            match workers.compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    return Some(WorkerSlot {
+                        workers: workers.clone(),
+                    })
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+impl Drop for WorkerSlot {
+    fn drop(&mut self) {
+        self.workers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn serve_tcp(listener: TcpListener, resolver: Arc<Resolver>, closed: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if closed.load(Ordering::SeqCst) {
+            break;
+        }
+        match stream {
+            Ok(stream) => {
+                let resolver = resolver.clone();
+                thread::spawn(move || {
+                    if let Err(error) = serve_tcp_client(stream, &resolver) {
+                        log::debug!("DNS over TCP connection failed: {}", error);
+                    }
+                });
+            }
+            Err(error) => log::debug!("Failed to accept DNS over TCP connection: {}", error),
+        }
+    }
+}
+
+fn serve_tcp_client(mut stream: TcpStream, resolver: &Resolver) -> io::Result<()> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    // Clients may send several queries over the same connection.
+    loop {
+        let query = match read_tcp_message(&mut stream) {
+            Ok(query) => query,
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        if query.len() < DNS_HEADER_LEN {
+            return Ok(());
+        }
+        match resolver.resolve(&query) {
+            Some(response) => write_tcp_message(&mut stream, &response)?,
+            None => return Ok(()),
+        }
+    }
+}
+
+struct Resolver {
+    upstreams: Vec<DnsUpstream>,
+    connector: SslConnector,
+}
+
+impl Resolver {
+    /// Sends `query` to the upstreams in order until one of them answers.
+    fn resolve(&self, query: &[u8]) -> Option<Vec<u8>> {
+        for upstream in &self.upstreams {
+            match self.query_upstream(upstream, query) {
+                Ok(response) => return Some(response),
+                Err(error) => log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("DNS query to {} failed", upstream))
+                ),
+            }
+        }
+        log::error!("No DNS upstream answered the query");
+        None
+    }
+
+    fn query_upstream(
+        &self,
+        upstream: &DnsUpstream,
+        query: &[u8],
+    ) -> Result<Vec<u8>, UpstreamError> {
+        let tcp = TcpStream::connect_timeout(&upstream.address, UPSTREAM_TIMEOUT)
+            .map_err(UpstreamError::Connect)?;
+        tcp.set_read_timeout(Some(UPSTREAM_TIMEOUT))
+            .and_then(|_| tcp.set_write_timeout(Some(UPSTREAM_TIMEOUT)))
+            .map_err(UpstreamError::Io)?;
+        let mut stream = self
+            .connector
+            .connect(&upstream.hostname, tcp)
+            .map_err(|e| UpstreamError::Handshake(e.to_string()))?;
+
+        let response = match upstream.protocol {
+            DnsUpstreamProtocol::Https { ref path } => {
+                post_query(&mut stream, &upstream.hostname, path, query)?
+            }
+            DnsUpstreamProtocol::Tls => {
+                write_tcp_message(&mut stream, query).map_err(UpstreamError::Io)?;
+                read_tcp_message(&mut stream).map_err(UpstreamError::Io)?
+            }
+        };
+        if response.len() < DNS_HEADER_LEN || response[..2] != query[..2] {
+            return Err(UpstreamError::InvalidResponse(
+                "Not a response to the query".to_string(),
+            ));
+        }
+        Ok(response)
+    }
+}
+
+/// Sends `query` in a DNS over HTTPS POST request and returns the DNS message in the response.
+fn post_query<S: Read + Write>(
+    stream: &mut S,
+    hostname: &str,
+    path: &str,
+    query: &[u8],
+) -> Result<Vec<u8>, UpstreamError> {
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nAccept: {}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        hostname,
+        DNS_MESSAGE_TYPE,
+        DNS_MESSAGE_TYPE,
+        query.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(query);
+    stream.write_all(&request).map_err(UpstreamError::Io)?;
+
+    let mut response = vec![];
+    let mut buf = [0u8; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            // The server may close the connection without a TLS close_notify once the response
+            // has been sent.
+            Err(_) if !response.is_empty() => break,
+            Err(e) => return Err(UpstreamError::Io(e)),
+        }
+        if response.len() > 2 * MAX_MESSAGE_LEN {
+            return Err(UpstreamError::InvalidResponse("Too large".to_string()));
+        }
+    }
+
+    let (status, body) = parse_response(&response)?;
+    if status != 200 {
+        return Err(UpstreamError::InvalidResponse(format!(
+            "HTTP status {}",
+            status
+        )));
+    }
+    Ok(body)
+}
+
+/// Parses an HTTP/1.1 response whose body is delimited by `Content-Length`, by chunked transfer
+/// encoding or by the end of the connection.
+fn parse_response(response: &[u8]) -> Result<(u16, Vec<u8>), UpstreamError> {
+    let invalid = |message: &str| UpstreamError::InvalidResponse(message.to_string());
+    let header_end = find(response, b"\r\n\r\n").ok_or_else(|| invalid("Truncated header"))?;
+    let head = str::from_utf8(&response[..header_end]).map_err(|_| invalid("Invalid header"))?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| invalid("Invalid status line"))?;
+
+    let mut body = &response[header_end + 4..];
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().unwrap_or("").trim();
+        if name == "transfer-encoding" {
+            if value.eq_ignore_ascii_case("chunked") {
+                return Ok((status, decode_chunked(body)?));
+            } else if value != "identity" {
+                return Err(invalid("Unsupported transfer encoding"));
+            }
+        }
+        if name == "content-length" {
+            let length: usize = value
+                .parse()
+                .map_err(|_| invalid("Invalid content length"))?;
+            if body.len() < length {
+                return Err(invalid("Truncated body"));
+            }
+            body = &body[..length];
+        }
+    }
+    Ok((status, body.to_vec()))
+}
+
+fn decode_chunked(mut chunks: &[u8]) -> Result<Vec<u8>, UpstreamError> {
+    let invalid = || UpstreamError::InvalidResponse("Invalid chunked body".to_string());
+    let mut body = vec![];
+    loop {
+        let line_end = find(chunks, b"\r\n").ok_or_else(invalid)?;
+        let size_line = str::from_utf8(&chunks[..line_end]).map_err(|_| invalid())?;
+        // Chunk extensions follow a semicolon.
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+        chunks = &chunks[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if size > MAX_MESSAGE_LEN - body.len() {
+            return Err(UpstreamError::InvalidResponse(
+                "Response body too large".to_string(),
+            ));
+        }
+        match size.checked_add(2) {
+            Some(chunk_len) if chunks.len() >= chunk_len => (),
+            _ => return Err(invalid()),
+        }
+        body.extend_from_slice(&chunks[..size]);
+        chunks = &chunks[size + 2..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Reads a DNS message prefixed by its length, as sent over TCP and TLS.
+fn read_tcp_message(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0u8; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn write_tcp_message(stream: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    if message.len() > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "DNS message too large",
+        ));
+    }
+    let mut buffer = (message.len() as u16).to_be_bytes().to_vec();
+    buffer.extend_from_slice(message);
+    stream.write_all(&buffer)
+}
+
+/// Makes `response` fit in a UDP datagram to the client that sent `query`. Responses that are too
+/// large are replaced by a truncated one without records, so that the client retries over TCP.
+fn fit_udp_response(query: &[u8], response: Vec<u8>) -> Vec<u8> {
+    if response.len() <= max_udp_response_len(query) {
+        return response;
+    }
+    let question_count = read_u16(&response, 4).unwrap_or(0);
+    let question_end = skip_questions(&response, DNS_HEADER_LEN, question_count);
+
+    let mut truncated = response[..question_end.unwrap_or(DNS_HEADER_LEN)].to_vec();
+    truncated[2] |= TRUNCATED_FLAG;
+    if question_end.is_none() {
+        truncated[4..6].copy_from_slice(&[0, 0]);
+    }
+    truncated[6..12].copy_from_slice(&[0; 6]);
+    truncated
+}
+
+/// Returns the largest UDP response the client accepts, as advertised in the EDNS OPT record of
+/// its query.
+fn max_udp_response_len(query: &[u8]) -> usize {
+    advertised_udp_size(query)
+        .map(|size| size.max(DEFAULT_UDP_RESPONSE_LEN))
+        .unwrap_or(DEFAULT_UDP_RESPONSE_LEN)
+}
+
+fn advertised_udp_size(query: &[u8]) -> Option<usize> {
+    let question_count = read_u16(query, 4)?;
+    let record_count = usize::from(read_u16(query, 6)?) + usize::from(read_u16(query, 8)?);
+    let additional_count = read_u16(query, 10)?;
+
+    let mut offset = skip_questions(query, DNS_HEADER_LEN, question_count)?;
+    for _ in 0..record_count {
+        offset = skip_record(query, offset)?;
+    }
+    for _ in 0..additional_count {
+        let name_end = skip_name(query, offset)?;
+        if read_u16(query, name_end)? == OPT_RECORD_TYPE {
+            // The class of the OPT record holds the size.
+            return read_u16(query, name_end + 2).map(usize::from);
+        }
+        offset = skip_record(query, offset)?;
+    }
+    None
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Returns the offset right after the domain name starting at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(offset)?;
+        match length & 0xc0 {
+            // A pointer to a name elsewhere in the message ends the name.
+            0xc0 => return message.get(offset + 1).map(|_| offset + 2),
+            0x00 if length == 0 => return Some(offset + 1),
+            0x00 => offset += 1 + usize::from(length),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_questions(message: &[u8], mut offset: usize, count: u16) -> Option<usize> {
+    for _ in 0..count {
+        // The name is followed by the type and class.
+        offset = skip_name(message, offset)? + 4;
+    }
+    if offset <= message.len() {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+fn skip_record(message: &[u8], offset: usize) -> Option<usize> {
+    let offset = skip_name(message, offset)?;
+    // The name is followed by the type, class, TTL and the length of the data.
+    let data_len = read_u16(message, offset + 8)?;
+    let end = offset + 10 + usize::from(data_len);
+    if end <= message.len() {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        ssl::SslAcceptor,
+        x509::{extension::SubjectAlternativeName, X509Builder, X509NameBuilder, X509},
+    };
+
+    const UPSTREAM_HOSTNAME: &str = "dns.example";
+    const ANSWER_RECORD: [u8; 16] = [0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 1];
+
+    /// A query for the A record of example.com, optionally with an EDNS OPT record.
+    fn query(id: u16, udp_size: Option<u16>) -> Vec<u8> {
+        let mut query = id.to_be_bytes().to_vec();
+        let additional_count = if udp_size.is_some() { 1 } else { 0 };
+        query.extend_from_slice(&[0x01, 0, 0, 1, 0, 0, 0, 0, 0, additional_count]);
+        query.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        if let Some(size) = udp_size {
+            query.push(0);
+            query.extend_from_slice(&OPT_RECORD_TYPE.to_be_bytes());
+            query.extend_from_slice(&size.to_be_bytes());
+            query.extend_from_slice(&[0; 6]);
+        }
+        query
+    }
+
+    /// Answers `query` with `answer_count` copies of the same A record.
+    fn answer(query: &[u8], answer_count: u16) -> Vec<u8> {
+        let question_end = skip_questions(query, DNS_HEADER_LEN, 1).unwrap();
+        let mut answer = query[..question_end].to_vec();
+        answer[2] |= 0x80;
+        answer[6..8].copy_from_slice(&answer_count.to_be_bytes());
+        answer[8..12].copy_from_slice(&[0; 4]);
+        for _ in 0..answer_count {
+            answer.extend_from_slice(&ANSWER_RECORD);
+        }
+        answer
+    }
+
+    fn self_signed_certificate() -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", UPSTREAM_HOSTNAME).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial_number = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial_number).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let alt_name = SubjectAlternativeName::new()
+            .dns(UPSTREAM_HOSTNAME)
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(alt_name).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    fn read_http_request(stream: &mut impl Read, path: &str) -> Vec<u8> {
+        let mut request = vec![];
+        let mut buf = [0u8; 1024];
+        let header_end = loop {
+            if let Some(end) = find(&request, b"\r\n\r\n") {
+                break end;
+            }
+            let n = stream.read(&mut buf).unwrap();
+            assert_ne!(n, 0, "Truncated request");
+            request.extend_from_slice(&buf[..n]);
+        };
+        let head = str::from_utf8(&request[..header_end])
+            .unwrap()
+            .to_lowercase();
+        assert!(head.starts_with(&format!("post {} http/1.1\r\n", path)));
+        assert!(head.contains(&format!("content-type: {}", DNS_MESSAGE_TYPE)));
+        let length: usize = head
+            .lines()
+            .find_map(|line| {
+                line.trim_start_matches("content-length:")
+                    .trim()
+                    .parse()
+                    .ok()
+            })
+            .unwrap();
+
+        let mut body = request[header_end + 4..].to_vec();
+        while body.len() < length {
+            let n = stream.read(&mut buf).unwrap();
+            assert_ne!(n, 0, "Truncated request body");
+            body.extend_from_slice(&buf[..n]);
+        }
+        body
+    }
+
+    /// Starts a stand-in DNS over HTTPS or TLS server that answers every query with
+    /// `answer_count` records. Returns it along with a connector trusting its certificate.
+    fn spawn_upstream(
+        protocol: DnsUpstreamProtocol,
+        answer_count: u16,
+    ) -> (DnsUpstream, SslConnector) {
+        let (certificate, key) = self_signed_certificate();
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&certificate).unwrap();
+        let acceptor = acceptor.build();
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.cert_store_mut().add_cert(certificate).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = DnsUpstream {
+            address: listener.local_addr().unwrap(),
+            hostname: UPSTREAM_HOSTNAME.to_string(),
+            protocol: protocol.clone(),
+        };
+        thread::spawn(move || {
+            for tcp in listener.incoming() {
+                let mut stream = acceptor.accept(tcp.unwrap()).unwrap();
+                match protocol {
+                    DnsUpstreamProtocol::Https { ref path } => {
+                        let query = read_http_request(&mut stream, path);
+                        let answer = answer(&query, answer_count);
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\n\
+                             Transfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+                            DNS_MESSAGE_TYPE,
+                            answer.len()
+                        )
+                        .unwrap();
+                        stream.write_all(&answer).unwrap();
+                        stream.write_all(b"\r\n0\r\n\r\n").unwrap();
+                    }
+                    DnsUpstreamProtocol::Tls => {
+                        let query = read_tcp_message(&mut stream).unwrap();
+                        write_tcp_message(&mut stream, &answer(&query, answer_count)).unwrap();
+                    }
+                }
+                let _ = stream.shutdown();
+            }
+        });
+        (upstream, connector.build())
+    }
+
+    fn start_forwarder(upstreams: Vec<DnsUpstream>, connector: SslConnector) -> DnsForwarder {
+        DnsForwarder::start_with_connector("127.0.0.1:0".parse().unwrap(), upstreams, connector)
+            .unwrap()
+    }
+
+    fn query_udp(forwarder: &DnsForwarder, query: &[u8]) -> Vec<u8> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        socket.send_to(query, forwarder.local_addr()).unwrap();
+        let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+        let length = socket.recv(&mut buffer).unwrap();
+        buffer.truncate(length);
+        buffer
+    }
+
+    #[test]
+    fn test_doh_upstream() {
+        let protocol = DnsUpstreamProtocol::Https {
+            path: "/dns-query".to_string(),
+        };
+        let (upstream, connector) = spawn_upstream(protocol, 1);
+        let forwarder = start_forwarder(vec![upstream], connector);
+
+        let query = query(0x1234, None);
+        assert_eq!(query_udp(&forwarder, &query), answer(&query, 1));
+    }
+
+    #[test]
+    fn test_dot_upstream_over_tcp() {
+        let (upstream, connector) = spawn_upstream(DnsUpstreamProtocol::Tls, 1);
+        let forwarder = start_forwarder(vec![upstream], connector);
+
+        let mut stream = TcpStream::connect(forwarder.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        for id in 1..3 {
+            let query = query(id, None);
+            write_tcp_message(&mut stream, &query).unwrap();
+            assert_eq!(read_tcp_message(&mut stream).unwrap(), answer(&query, 1));
+        }
+    }
+
+    #[test]
+    fn test_falls_back_to_next_upstream() {
+        let (upstream, connector) = spawn_upstream(DnsUpstreamProtocol::Tls, 1);
+        let unreachable = DnsUpstream {
+            address: TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap(),
+            ..upstream.clone()
+        };
+        let forwarder = start_forwarder(vec![unreachable, upstream], connector);
+
+        let query = query(0x4321, None);
+        assert_eq!(query_udp(&forwarder, &query), answer(&query, 1));
+    }
+
+    #[test]
+    fn test_truncates_large_udp_responses() {
+        let (upstream, connector) = spawn_upstream(DnsUpstreamProtocol::Tls, 40);
+        let forwarder = start_forwarder(vec![upstream], connector);
+
+        let query = query(0x5678, None);
+        let response = query_udp(&forwarder, &query);
+        assert_ne!(response[2] & TRUNCATED_FLAG, 0);
+        assert_eq!(read_u16(&response, 6), Some(0));
+        assert_eq!(
+            response.len(),
+            skip_questions(&query, DNS_HEADER_LEN, 1).unwrap()
+        );
+
+        let query = self::query(0x5679, Some(4096));
+        assert_eq!(query_udp(&forwarder, &query), answer(&query, 40));
+    }
+
+    #[test]
+    fn test_releases_port_when_dropped() {
+        let (upstream, connector) = spawn_upstream(DnsUpstreamProtocol::Tls, 1);
+        let forwarder = start_forwarder(vec![upstream], connector.clone());
+        let address = forwarder.local_addr();
+        drop(forwarder);
+        DnsForwarder::start_with_connector(address, vec![], connector).unwrap();
+    }
+
+    #[test]
+    fn test_max_udp_response_len() {
+        assert_eq!(max_udp_response_len(&query(1, None)), 512);
+        assert_eq!(max_udp_response_len(&query(1, Some(4096))), 4096);
+        assert_eq!(max_udp_response_len(&query(1, Some(100))), 512);
+        assert_eq!(max_udp_response_len(&[0; DNS_HEADER_LEN]), 512);
+        assert_eq!(max_udp_response_len(&query(1, Some(4096))[..20]), 512);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nabtrailing";
+        assert_eq!(parse_response(response).unwrap(), (200, b"ab".to_vec()));
+
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         2;ext=1\r\nab\r\na\r\n0123456789\r\n0\r\n\r\n";
+        assert_eq!(
+            parse_response(response).unwrap().1,
+            b"ab0123456789".to_vec()
+        );
+
+        let response = b"HTTP/1.1 415 Unsupported Media Type\r\nconnection: close\r\n\r\n";
+        assert_eq!(parse_response(response).unwrap().0, 415);
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nab").is_err());
+        assert!(
+            parse_response(b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nab")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_decode_chunked_rejects_oversized_chunks() {
+        assert!(decode_chunked(b"ffffffffffffffff\r\nab\r\n0\r\n\r\n").is_err());
+        assert!(decode_chunked(b"10000\r\nab\r\n0\r\n\r\n").is_err());
+
+        let mut chunks = b"ffff\r\n".to_vec();
+        chunks.extend_from_slice(&[0; 0xffff]);
+        chunks.extend_from_slice(b"\r\n1\r\na\r\n0\r\n\r\n");
+        assert!(decode_chunked(&chunks).is_err());
+    }
+
+    #[test]
+    fn test_worker_slots_are_bounded() {
+        let workers = Arc::new(AtomicUsize::new(0));
+        let first = WorkerSlot::acquire(&workers, 2).unwrap();
+        let _second = WorkerSlot::acquire(&workers, 2).unwrap();
+        assert!(WorkerSlot::acquire(&workers, 2).is_none());
+        drop(first);
+        assert!(WorkerSlot::acquire(&workers, 2).is_some());
+        assert_eq!(workers.load(Ordering::SeqCst), 1);
+    }
+}
@@ -11,6 +11,7 @@ use std::{env, fmt, net::IpAddr, path::Path};
 
 
 const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+const LOOPBACK_INTERFACE: &str = "lo";
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -36,6 +37,10 @@ pub enum Error {
     /// No suitable DNS monitor implementation detected
     #[error(display = "No suitable DNS monitor implementation detected")]
     NoDnsMonitor,
+
+    /// systemd-resolved only sets the DNS servers of a link, and refuses the loopback link
+    #[error(display = "systemd-resolved can't set DNS servers system-wide")]
+    NoGlobalDns,
 }
 
 pub struct DnsMonitor {
@@ -58,6 +63,14 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(())
     }
 
+    fn set_global(&mut self, servers: &[IpAddr]) -> Result<()> {
+        self.reset()?;
+        let mut inner = DnsMonitorHolder::new()?;
+        inner.set_global(servers)?;
+        self.inner = Some(inner);
+        Ok(())
+    }
+
     fn reset(&mut self) -> Result<()> {
         if let Some(mut inner) = self.inner.take() {
             inner.reset()?;
@@ -125,6 +138,18 @@ impl DnsMonitorHolder {
         Ok(())
     }
 
+    /// NetworkManager and a static resolv.conf set DNS servers system-wide anyway. resolvconf
+    /// records of the loopback interface are listed first.
+    fn set_global(&mut self, servers: &[IpAddr]) -> Result<()> {
+        use self::DnsMonitorHolder::*;
+        match self {
+            Resolvconf(ref mut resolvconf) => resolvconf.set_dns(LOOPBACK_INTERFACE, servers)?,
+            SystemdResolved(..) => return Err(Error::NoGlobalDns),
+            StaticResolvConf(..) | NetworkManager(..) => self.set(LOOPBACK_INTERFACE, servers)?,
+        }
+        Ok(())
+    }
+
     fn reset(&mut self) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
@@ -183,6 +183,11 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(())
     }
 
+    /// DNS is set on every network service anyway.
+    fn set_global(&mut self, servers: &[IpAddr]) -> Result<()> {
+        self.set("lo0", servers)
+    }
+
     fn reset(&mut self) -> Result<()> {
         let mut state_lock = self.state.lock();
         if let Some(state) = state_lock.take() {
@@ -1,5 +1,7 @@
 use std::{net::IpAddr, path::Path};
 
+pub mod forwarder;
+
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
 mod imp;
@@ -44,6 +46,20 @@ impl DnsMonitor {
         self.inner.set(interface, servers)
     }
 
+    /// Set DNS to the given servers system-wide, rather than for an interface, for when there is
+    /// no tunnel interface. Fails on backends that can only set the DNS servers of an interface.
+    pub fn set_global(&mut self, servers: &[IpAddr]) -> Result<(), Error> {
+        log::info!(
+            "Setting system-wide DNS servers to {}",
+            servers
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        self.inner.set_global(servers)
+    }
+
     /// Reset system DNS settings to what it was before being set by this instance.
     pub fn reset(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
@@ -58,5 +74,7 @@ trait DnsMonitorT: Sized {
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
 
+    fn set_global(&mut self, servers: &[IpAddr]) -> Result<(), Self::Error>;
+
     fn reset(&mut self) -> Result<(), Self::Error>;
 }
@@ -36,6 +36,11 @@ pub enum Error {
     /// Failure to reset DNS settings from backup.
     #[error(display = "Failed to recover to backed up system state")]
     Recovery,
+
+    /// DNS can't point at a local resolver outside the tunnel, as winfw can't let the resolver
+    /// reach its upstreams.
+    #[error(display = "Setting DNS servers outside the tunnel is not supported on Windows")]
+    NoGlobalDns,
 }
 
 pub struct DnsMonitor {
@@ -97,6 +102,10 @@ impl super::DnsMonitorT for DnsMonitor {
         }
     }
 
+    fn set_global(&mut self, _servers: &[IpAddr]) -> Result<(), Error> {
+        Err(Error::NoGlobalDns)
+    }
+
     fn reset(&mut self) -> Result<(), Error> {
         unsafe { WinDns_Reset().into_result()? };
 
@@ -1,5 +1,5 @@
 use super::{FirewallArguments, FirewallPolicy, FirewallT};
-use crate::tunnel;
+use crate::{split_tunnel, tunnel};
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
 use libc;
@@ -14,7 +14,11 @@ use std::{
     io,
     net::{IpAddr, Ipv4Addr},
 };
-use talpid_types::net::{Endpoint, TransportProtocol};
+use talpid_types::net::{
+    dns::{CustomDnsServer, DnsRoute},
+    firewall::{AllowRule, PortRange},
+    Endpoint, TransportProtocol,
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -56,6 +60,9 @@ lazy_static! {
     static ref TABLE_NAME: CString = CString::new("mullvad").unwrap();
     static ref IN_CHAIN_NAME: CString = CString::new("in").unwrap();
     static ref OUT_CHAIN_NAME: CString = CString::new("out").unwrap();
+    static ref MANGLE_CHAIN_NAME: CString = CString::new("mangle").unwrap();
+    static ref PREROUTING_CHAIN_NAME: CString = CString::new("prerouting").unwrap();
+    static ref NAT_CHAIN_NAME: CString = CString::new("nat").unwrap();
 
     /// Allows controlling whether firewall rules should have packet counters or not from an env
     /// variable. Useful for debugging the rules.
@@ -186,6 +193,9 @@ struct PolicyBatch<'a> {
     batch: Batch,
     in_chain: Chain<'a>,
     out_chain: Chain<'a>,
+    mangle_chain: Chain<'a>,
+    prerouting_chain: Chain<'a>,
+    nat_chain: Chain<'a>,
 }
 
 impl<'a> PolicyBatch<'a> {
@@ -200,17 +210,34 @@ impl<'a> PolicyBatch<'a> {
         out_chain.set_policy(nftnl::Policy::Drop);
         in_chain.set_policy(nftnl::Policy::Drop);
 
+        // Chains of split tunneling. A route chain makes the kernel route packets again once
+        // they're marked.
+        let mut mangle_chain = Chain::new(&*MANGLE_CHAIN_NAME, table);
+        mangle_chain.set_type(nftnl::ChainType::Route);
+        mangle_chain.set_hook(nftnl::Hook::Out, libc::NF_IP_PRI_MANGLE);
+        let mut prerouting_chain = Chain::new(&*PREROUTING_CHAIN_NAME, table);
+        prerouting_chain.set_hook(nftnl::Hook::PreRouting, libc::NF_IP_PRI_MANGLE);
+        let mut nat_chain = Chain::new(&*NAT_CHAIN_NAME, table);
+        nat_chain.set_type(nftnl::ChainType::Nat);
+        nat_chain.set_hook(nftnl::Hook::PostRouting, libc::NF_IP_PRI_NAT_SRC);
+
         // A little dance that will make sure the table exists, but is cleared.
         batch.add(table, nftnl::MsgType::Add);
         batch.add(table, nftnl::MsgType::Del);
         batch.add(table, nftnl::MsgType::Add);
         batch.add(&out_chain, nftnl::MsgType::Add);
         batch.add(&in_chain, nftnl::MsgType::Add);
+        batch.add(&mangle_chain, nftnl::MsgType::Add);
+        batch.add(&prerouting_chain, nftnl::MsgType::Add);
+        batch.add(&nat_chain, nftnl::MsgType::Add);
 
         PolicyBatch {
             batch,
             in_chain,
             out_chain,
+            mangle_chain,
+            prerouting_chain,
+            nat_chain,
         }
     }
 
@@ -342,36 +369,124 @@ impl<'a> PolicyBatch<'a> {
     }
 
     fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
-        let allow_lan = match policy {
+        let (allow_lan, allowed_rules) = match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 pingable_hosts,
                 allow_lan,
+                allowed_rules,
+                dns_forwarder_upstreams,
             } => {
+                self.add_split_tunnel_rules();
                 self.add_allow_icmp_pingable_hosts(&pingable_hosts);
                 self.add_allow_endpoint_rules(peer_endpoint);
-                *allow_lan
+                for endpoint in dns_forwarder_upstreams {
+                    self.add_allow_endpoint_rules(endpoint);
+                }
+                (*allow_lan, allowed_rules)
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
+                proxy_endpoints,
                 tunnel,
                 allow_lan,
+                allowed_rules,
+                dns_servers,
             } => {
+                self.add_split_tunnel_rules();
                 self.add_allow_endpoint_rules(peer_endpoint);
-                self.add_dns_rule(tunnel, TransportProtocol::Udp)?;
-                self.add_dns_rule(tunnel, TransportProtocol::Tcp)?;
+                for endpoint in proxy_endpoints {
+                    self.add_allow_endpoint_rules(endpoint);
+                }
+                self.add_dns_rule(tunnel, dns_servers, TransportProtocol::Udp)?;
+                self.add_dns_rule(tunnel, dns_servers, TransportProtocol::Tcp)?;
                 self.add_allow_tunnel_rules(tunnel)?;
-                *allow_lan
+                (*allow_lan, allowed_rules)
+            }
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_rules,
+                dns_forwarder_upstreams,
+            } => {
+                for endpoint in dns_forwarder_upstreams {
+                    self.add_allow_endpoint_rules(endpoint);
+                }
+                (*allow_lan, allowed_rules)
             }
-            FirewallPolicy::Blocked { allow_lan } => *allow_lan,
         };
 
         if allow_lan {
             self.add_allow_lan_rules();
         }
+        for rule in allowed_rules {
+            self.add_custom_allow_rules(rule);
+        }
         Ok(())
     }
 
+    /// Allows the connections described by a user-defined rule, and the replies to them. Ports
+    /// are the destination ports of the connections, so replies are matched on source ports.
+    fn add_custom_allow_rules(&mut self, rule: &AllowRule) {
+        if rule.direction.allows_out() {
+            let mut out_rule = Rule::new(&self.out_chain);
+            check_custom_rule(&mut out_rule, rule, End::Dst, End::Dst);
+            add_verdict(&mut out_rule, &Verdict::Accept);
+            self.batch.add(&out_rule, nftnl::MsgType::Add);
+
+            let mut reply_rule = Rule::new(&self.in_chain);
+            check_custom_rule(&mut reply_rule, rule, End::Src, End::Src);
+            check_established(&mut reply_rule);
+            add_verdict(&mut reply_rule, &Verdict::Accept);
+            self.batch.add(&reply_rule, nftnl::MsgType::Add);
+        }
+        if rule.direction.allows_in() {
+            let mut in_rule = Rule::new(&self.in_chain);
+            check_custom_rule(&mut in_rule, rule, End::Src, End::Dst);
+            add_verdict(&mut in_rule, &Verdict::Accept);
+            self.batch.add(&in_rule, nftnl::MsgType::Add);
+
+            let mut reply_rule = Rule::new(&self.out_chain);
+            check_custom_rule(&mut reply_rule, rule, End::Dst, End::Src);
+            check_established(&mut reply_rule);
+            add_verdict(&mut reply_rule, &Verdict::Accept);
+            self.batch.add(&reply_rule, nftnl::MsgType::Add);
+        }
+    }
+
+    /// Lets the traffic of excluded processes bypass the tunnel, see `crate::split_tunnel`. Their
+    /// connections are marked, so that they're routed through the routing table of excluded
+    /// traffic, and given the address of the interface they leave through.
+    fn add_split_tunnel_rules(&mut self) {
+        let mut mark_rule = Rule::new(&self.mangle_chain);
+        mark_rule.add_expr(&nft_expr!(meta cgroup));
+        mark_rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
+        mark_rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+        mark_rule.add_expr(&nft_expr!(ct mark set));
+        mark_rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+        mark_rule.add_expr(&nft_expr!(meta mark set));
+        self.batch.add(&mark_rule, nftnl::MsgType::Add);
+
+        // Incoming packets need the mark too, for the reverse path filter to look up the right
+        // routing table.
+        let mut prerouting_rule = Rule::new(&self.prerouting_chain);
+        check_split_tunnel_mark(&mut prerouting_rule);
+        prerouting_rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+        prerouting_rule.add_expr(&nft_expr!(meta mark set));
+        self.batch.add(&prerouting_rule, nftnl::MsgType::Add);
+
+        let mut nat_rule = Rule::new(&self.nat_chain);
+        check_split_tunnel_mark(&mut nat_rule);
+        nat_rule.add_expr(&nft_expr!(masquerade));
+        self.batch.add(&nat_rule, nftnl::MsgType::Add);
+
+        for chain in &[&self.out_chain, &self.in_chain] {
+            let mut rule = Rule::new(chain);
+            check_split_tunnel_mark(&mut rule);
+            add_verdict(&mut rule, &Verdict::Accept);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+    }
+
     fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
         let mut in_rule = Rule::new(&self.in_chain);
         check_endpoint(&mut in_rule, End::Src, endpoint);
@@ -418,6 +533,7 @@ impl<'a> PolicyBatch<'a> {
     fn add_dns_rule(
         &mut self,
         tunnel: &tunnel::TunnelMetadata,
+        dns_servers: &[CustomDnsServer],
         protocol: TransportProtocol,
     ) -> Result<()> {
         // allow DNS traffic to the tunnel gateway
@@ -425,6 +541,15 @@ impl<'a> PolicyBatch<'a> {
         if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
             self.add_allow_dns_rule(&tunnel.interface, protocol, ipv6_gateway.into())?;
         };
+        // allow DNS traffic to the custom resolvers
+        for server in dns_servers {
+            match server.route {
+                DnsRoute::Tunnel => {
+                    self.add_allow_dns_rule(&tunnel.interface, protocol, server.address)?
+                }
+                DnsRoute::Lan => self.add_allow_lan_dns_rule(protocol, server.address),
+            }
+        }
         let mut block_rule = Rule::new(&self.out_chain);
         check_port(&mut block_rule, protocol, End::Dst, 53);
         add_verdict(&mut block_rule, &Verdict::Drop);
@@ -457,6 +582,21 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    fn add_allow_lan_dns_rule(&mut self, protocol: TransportProtocol, host: IpAddr) {
+        let mut allow_rule = Rule::new(&self.out_chain);
+        check_ip(&mut allow_rule, End::Dst, host);
+        check_port(&mut allow_rule, protocol, End::Dst, 53);
+        add_verdict(&mut allow_rule, &Verdict::Accept);
+        self.batch.add(&allow_rule, nftnl::MsgType::Add);
+
+        let mut reply_rule = Rule::new(&self.in_chain);
+        check_ip(&mut reply_rule, End::Src, host);
+        check_port(&mut reply_rule, protocol, End::Src, 53);
+        check_established(&mut reply_rule);
+        add_verdict(&mut reply_rule, &Verdict::Accept);
+        self.batch.add(&reply_rule, nftnl::MsgType::Add);
+    }
+
     fn add_allow_tunnel_rules(&mut self, tunnel: &tunnel::TunnelMetadata) -> Result<()> {
         self.batch.add(
             &allow_interface_rule(&self.out_chain, Direction::Out, &tunnel.interface[..])?,
@@ -540,6 +680,11 @@ fn check_iface(rule: &mut Rule<'_>, direction: Direction, iface: &str) -> Result
     Ok(())
 }
 
+fn check_split_tunnel_mark(rule: &mut Rule<'_>) {
+    rule.add_expr(&nft_expr!(ct mark));
+    rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
+}
+
 fn check_net(rule: &mut Rule<'_>, end: End, net: impl Into<IpNetwork>) {
     let net = net.into();
     // Must check network layer protocol before loading network layer payload
@@ -558,6 +703,25 @@ fn check_net(rule: &mut Rule<'_>, end: End, net: impl Into<IpNetwork>) {
     rule.add_expr(&nft_expr!(cmp == net.ip()));
 }
 
+/// Matches the packets of a user-defined rule with the network of the rule at `net_end` and its
+/// ports at `port_end`.
+fn check_custom_rule(rule: &mut Rule<'_>, allow_rule: &AllowRule, net_end: End, port_end: End) {
+    check_net(rule, net_end, allow_rule.network);
+    if let Some(protocol) = allow_rule.protocol {
+        check_l4proto(rule, protocol);
+        if let Some(ports) = allow_rule.ports {
+            check_port_range(rule, protocol, port_end, ports);
+        }
+    }
+}
+
+fn check_established(rule: &mut Rule<'_>) {
+    rule.add_expr(&nft_expr!(ct state));
+    let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
+    rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
+    rule.add_expr(&nft_expr!(cmp != 0u32));
+}
+
 fn check_endpoint(rule: &mut Rule<'_>, end: End, endpoint: &Endpoint) {
     check_ip(rule, end, endpoint.address.ip());
     check_port(rule, endpoint.protocol, end, endpoint.address.port());
@@ -593,6 +757,18 @@ fn check_port(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, port:
     rule.add_expr(&nft_expr!(cmp == port.to_be()));
 }
 
+fn check_port_range(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, ports: PortRange) {
+    rule.add_expr(&match (protocol, end) {
+        (TransportProtocol::Udp, End::Src) => nft_expr!(payload udp sport),
+        (TransportProtocol::Udp, End::Dst) => nft_expr!(payload udp dport),
+        (TransportProtocol::Tcp, End::Src) => nft_expr!(payload tcp sport),
+        (TransportProtocol::Tcp, End::Dst) => nft_expr!(payload tcp dport),
+    });
+    // Ports are compared in network byte order, which sorts like the numbers they encode.
+    rule.add_expr(&nft_expr!(cmp >= ports.start.to_be()));
+    rule.add_expr(&nft_expr!(cmp <= ports.end.to_be()));
+}
+
 fn check_l3proto(rule: &mut Rule<'_>, ip: IpAddr) {
     rule.add_expr(&nft_expr!(meta nfproto));
     rule.add_expr(&nft_expr!(cmp == l3proto(ip)));
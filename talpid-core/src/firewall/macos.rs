@@ -5,7 +5,11 @@ use std::{
     env,
     net::{IpAddr, Ipv4Addr},
 };
-use talpid_types::net;
+use talpid_types::net::{
+    self,
+    dns::{CustomDnsServer, DnsRoute},
+    firewall::AllowRule,
+};
 
 pub use pfctl::Error;
 
@@ -90,18 +94,27 @@ impl Firewall {
                 peer_endpoint,
                 allow_lan,
                 pingable_hosts,
+                allowed_rules,
+                dns_forwarder_upstreams,
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(peer_endpoint)?];
+                for endpoint in dns_forwarder_upstreams {
+                    rules.push(self.get_allow_relay_rule(endpoint)?);
+                }
                 rules.extend(self.get_allow_pingable_hosts(&pingable_hosts)?);
                 if allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
                 }
+                rules.append(&mut self.get_allow_custom_rules(&allowed_rules)?);
                 Ok(rules)
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
+                proxy_endpoints,
                 tunnel,
                 allow_lan,
+                allowed_rules,
+                dns_servers,
             } => {
                 let mut rules = vec![];
                 let allow_tcp_dns_to_relay_rule = self
@@ -143,6 +156,8 @@ impl Firewall {
                         .build()?;
                     rules.push(v6_dns_rule_udp);
                 }
+                rules
+                    .append(&mut self.get_allow_custom_dns_rules(&tunnel.interface, &dns_servers)?);
 
                 let block_tcp_dns_rule = self
                     .create_rule_builder(FilterRuleAction::Drop)
@@ -162,24 +177,113 @@ impl Firewall {
 
                 rules.push(block_udp_dns_rule);
                 rules.push(self.get_allow_relay_rule(peer_endpoint)?);
+                for endpoint in proxy_endpoints {
+                    rules.push(self.get_allow_relay_rule(endpoint)?);
+                }
                 rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str())?);
 
                 if allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
                 }
+                rules.append(&mut self.get_allow_custom_rules(&allowed_rules)?);
 
                 Ok(rules)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_rules,
+                dns_forwarder_upstreams,
+            } => {
                 let mut rules = Vec::new();
+                for endpoint in dns_forwarder_upstreams {
+                    rules.push(self.get_allow_relay_rule(endpoint)?);
+                }
                 if allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
                 }
+                rules.append(&mut self.get_allow_custom_rules(&allowed_rules)?);
                 Ok(rules)
             }
         }
     }
 
+    /// Allows the connections described by user-defined rules. Replies pass thanks to the state
+    /// the rules keep.
+    fn get_allow_custom_rules(
+        &self,
+        allowed_rules: &[AllowRule],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for allowed_rule in allowed_rules {
+            let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+            rule_builder
+                .quick(true)
+                .keep_state(pfctl::StatePolicy::Keep)
+                .tcp_flags(Self::get_tcp_flags());
+            if let Some(protocol) = allowed_rule.protocol {
+                rule_builder.proto(as_pfctl_proto(protocol));
+            }
+            let ports = match allowed_rule.ports {
+                Some(ports) => pfctl::Port::Range(
+                    ports.start,
+                    ports.end,
+                    pfctl::PortRangeModifier::Inclusive,
+                ),
+                None => pfctl::Port::Any,
+            };
+
+            if allowed_rule.direction.allows_out() {
+                let allow_out = rule_builder
+                    .direction(pfctl::Direction::Out)
+                    .from(pfctl::Ip::Any)
+                    .to(pfctl::Endpoint::new(
+                        pfctl::Ip::from(allowed_rule.network),
+                        ports.clone(),
+                    ))
+                    .build()?;
+                rules.push(allow_out);
+            }
+            if allowed_rule.direction.allows_in() {
+                let allow_in = rule_builder
+                    .direction(pfctl::Direction::In)
+                    .from(pfctl::Ip::from(allowed_rule.network))
+                    .to(ports.clone())
+                    .build()?;
+                rules.push(allow_in);
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Allows DNS requests to custom resolvers, over the tunnel or directly to the LAN.
+    fn get_allow_custom_dns_rules(
+        &self,
+        tunnel_interface: &str,
+        dns_servers: &[CustomDnsServer],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for server in dns_servers {
+            for protocol in vec![pfctl::Proto::Tcp, pfctl::Proto::Udp] {
+                let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+                rule_builder
+                    .direction(pfctl::Direction::Out)
+                    .quick(true)
+                    .proto(protocol)
+                    .to(pfctl::Endpoint::new(server.address, 53));
+                match server.route {
+                    DnsRoute::Tunnel => {
+                        rule_builder.interface(tunnel_interface);
+                    }
+                    DnsRoute::Lan => {
+                        rule_builder.keep_state(pfctl::StatePolicy::Keep);
+                    }
+                }
+                rules.push(rule_builder.build()?);
+            }
+        }
+        Ok(rules)
+    }
+
     fn get_allow_relay_rule(&self, relay_endpoint: net::Endpoint) -> Result<pfctl::FilterRule> {
         let pfctl_proto = as_pfctl_proto(relay_endpoint.protocol);
 
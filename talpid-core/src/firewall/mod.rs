@@ -7,7 +7,7 @@ use std::fmt;
 use std::net::IpAddr;
 #[cfg(unix)]
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use talpid_types::net::Endpoint;
+use talpid_types::net::{dns::CustomDnsServer, firewall::AllowRule, Endpoint};
 
 
 #[cfg(target_os = "macos")]
@@ -92,6 +92,10 @@ const DHCPV6_CLIENT_PORT: u16 = 546;
 ///    * Incoming DHCPv4 requests and outgoing responses (be a DHCPv4 server):
 ///      * Incoming from *:DHCPV4_CLIENT_PORT to 255.255.255.255:DHCPV4_SERVER_PORT
 ///      * Outgoing from *:DHCPV4_SERVER_PORT to *:DHCPV4_CLIENT_PORT
+/// 5. The connections described by each rule in `allowed_rules`, in the directions the rule
+///    allows, and the replies to them. Not supported on Windows yet, where the rules are ignored.
+/// 6. In the `Connecting` and `Blocked` policies, connections to the endpoints in
+///    `dns_forwarder_upstreams` and the replies to them.
 ///
 /// ## Policy specific rules
 ///
@@ -102,9 +106,12 @@ const DHCPV6_CLIENT_PORT: u16 = 546;
 /// 3. In the `Connected` policy, DNS requests (destination port 53 on both UDP and TCP) should be
 ///    allowed over the tunnel interface in `tunnel.interface` and to the IPs `tunnel.ipv4_gateway`
 ///    and `tunnel.ipv6_gateway`. But blocked to all other destinations and over all other
-///    interfaces.
+///    interfaces. DNS requests should also be allowed to the resolvers in `dns_servers`, over the
+///    tunnel interface or to a LAN address, depending on how each resolver is reached.
 /// 4. In the `Connected` policy, all traffic should be allowed over the tunnel interface in
 ///    `tunnel.interface`, minus the DNS packets described above.
+/// 5. On Linux, in the `Connecting` and `Connected` policies, all traffic of the processes
+///    excluded from the tunnel should be allowed, see `crate::split_tunnel`.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum FirewallPolicy {
     /// Allow traffic only to server
@@ -115,22 +122,36 @@ pub enum FirewallPolicy {
         pingable_hosts: Vec<IpAddr>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// User-defined rules allowing traffic through the firewall.
+        allowed_rules: Vec<AllowRule>,
+        /// Upstreams of the DNS forwarder, reached outside the tunnel.
+        dns_forwarder_upstreams: Vec<Endpoint>,
     },
 
     /// Allow traffic only to server and over tunnel interface
     Connected {
         /// The peer endpoint that should be allowed.
         peer_endpoint: Endpoint,
+        /// Other endpoints of the tunnel, which it may switch to without reconnecting.
+        proxy_endpoints: Vec<Endpoint>,
         /// Metadata about the tunnel and tunnel interface.
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// User-defined rules allowing traffic through the firewall.
+        allowed_rules: Vec<AllowRule>,
+        /// Custom DNS resolvers that should be reachable besides the tunnel gateway.
+        dns_servers: Vec<CustomDnsServer>,
     },
 
     /// Block all network traffic in and out from the computer.
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// User-defined rules allowing traffic through the firewall.
+        allowed_rules: Vec<AllowRule>,
+        /// Upstreams of the DNS forwarder, reached outside the tunnel.
+        dns_forwarder_upstreams: Vec<Endpoint>,
     },
 }
 
@@ -141,25 +162,35 @@ impl fmt::Display for FirewallPolicy {
                 peer_endpoint,
                 pingable_hosts,
                 allow_lan,
+                allowed_rules,
+                dns_forwarder_upstreams,
             } => write!(
                 f,
-                "Connecting to {} with gateways {}, {} LAN",
+                "Connecting to {} with gateways {}, {} LAN, {} custom rules, {} DNS forwarder \
+                 upstreams",
                 peer_endpoint,
                 pingable_hosts
                     .iter()
                     .map(ToString::to_string)
                     .collect::<Vec<String>>()
                     .join(","),
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                if *allow_lan { "Allowing" } else { "Blocking" },
+                allowed_rules.len(),
+                dns_forwarder_upstreams.len()
             ),
             FirewallPolicy::Connected {
                 peer_endpoint,
+                proxy_endpoints,
                 tunnel,
                 allow_lan,
+                allowed_rules,
+                dns_servers,
             } => write!(
                 f,
-                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                "Connected to {} ({} other proxies) over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), \
+                 {} LAN, {} custom rules, {} custom DNS servers",
                 peer_endpoint,
+                proxy_endpoints.len(),
                 tunnel.interface,
                 tunnel
                     .ips
@@ -169,12 +200,20 @@ impl fmt::Display for FirewallPolicy {
                     .join(","),
                 tunnel.ipv4_gateway,
                 tunnel.ipv6_gateway,
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                if *allow_lan { "Allowing" } else { "Blocking" },
+                allowed_rules.len(),
+                dns_servers.len()
             ),
-            FirewallPolicy::Blocked { allow_lan } => write!(
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_rules,
+                dns_forwarder_upstreams,
+            } => write!(
                 f,
-                "Blocked, {} LAN",
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                "Blocked, {} LAN, {} custom rules, {} DNS forwarder upstreams",
+                if *allow_lan { "Allowing" } else { "Blocking" },
+                allowed_rules.len(),
+                dns_forwarder_upstreams.len()
             ),
         }
     }
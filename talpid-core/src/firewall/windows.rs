@@ -3,8 +3,12 @@ use std::{net::IpAddr, ptr};
 use self::winfw::*;
 use super::{FirewallArguments, FirewallPolicy, FirewallT};
 use crate::winnet;
-use log::{debug, error, trace};
-use talpid_types::net::Endpoint;
+use log::{debug, error, trace, warn};
+use talpid_types::net::{
+    dns::{CustomDnsServer, DnsRoute},
+    firewall::AllowRule,
+    Endpoint,
+};
 use widestring::WideCString;
 
 
@@ -38,6 +42,10 @@ pub enum Error {
     /// Failure to set TAP adapter metric
     #[error(display = "Unable to set TAP adapter metric")]
     SetTapMetric(#[error(cause)] crate::winnet::Error),
+
+    /// The policy holds user-defined allow rules, which winfw can't apply
+    #[error(display = "Custom firewall rules are not supported on Windows")]
+    UnsupportedAllowRules,
 }
 
 const WINFW_TIMEOUT_SECONDS: u32 = 2;
@@ -82,19 +90,37 @@ impl FirewallT for Firewall {
                 // TODO: Allow ICMP traffic to a list of hosts for wireguard
                 pingable_hosts: _,
                 allow_lan,
+                allowed_rules,
+                // The DNS forwarder is never used outside the tunnel on Windows, since system-wide
+                // DNS servers can't be set there, so there are no upstreams to allow.
+                dns_forwarder_upstreams: _,
             } => {
+                Self::check_allowed_rules(&allowed_rules)?;
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_connecting_state(&peer_endpoint, &cfg)
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
+                // winfw allows a single relay. Tunnels don't switch proxies on Windows, see
+                // `crate::tunnel::tinc`, so the other proxies need not be reachable.
+                proxy_endpoints: _,
                 tunnel,
                 allow_lan,
+                allowed_rules,
+                dns_servers,
             } => {
+                Self::check_allowed_rules(&allowed_rules)?;
                 let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connected_state(&peer_endpoint, &cfg, &tunnel)
+                self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &dns_servers)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_rules,
+                // The DNS forwarder is never used outside the tunnel on Windows, since system-wide
+                // DNS servers can't be set there, so there are no upstreams to allow.
+                dns_forwarder_upstreams: _,
+            } => {
+                Self::check_allowed_rules(&allowed_rules)?;
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_blocked_state(&cfg)
             }
@@ -118,6 +144,51 @@ impl Drop for Firewall {
 }
 
 impl Firewall {
+    // TODO: Every WFP filter of winfw needs a registered GUID, so user-defined rules need
+    // support in winfw before they can be applied. Until then a policy holding any is refused
+    // rather than applied without them.
+    fn check_allowed_rules(allowed_rules: &[AllowRule]) -> Result<(), Error> {
+        if allowed_rules.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedAllowRules)
+        }
+    }
+
+    /// Returns the IPv4 and IPv6 hosts DNS requests are allowed to. winfw only allows one host
+    /// per address family, so the first custom resolver of each family replaces the gateway. The
+    /// state machine applies the policy without custom resolvers when it falls back to the
+    /// gateway for DNS.
+    fn dns_hosts(
+        tunnel_metadata: &crate::tunnel::TunnelMetadata,
+        dns_servers: &[CustomDnsServer],
+    ) -> (IpAddr, Option<IpAddr>) {
+        let mut v4_host = None;
+        let mut v6_host = None;
+        for server in dns_servers {
+            let host = match (server.route, server.address) {
+                (DnsRoute::Tunnel, IpAddr::V4(_)) => &mut v4_host,
+                (DnsRoute::Tunnel, IpAddr::V6(_)) => &mut v6_host,
+                (DnsRoute::Lan, _) => {
+                    warn!("Ignoring DNS server {}, not supported on Windows", server);
+                    continue;
+                }
+            };
+            if host.is_none() {
+                *host = Some(server.address);
+            } else {
+                warn!(
+                    "Ignoring DNS server {}, only one per IP version is supported",
+                    server
+                );
+            }
+        }
+        (
+            v4_host.unwrap_or_else(|| tunnel_metadata.ipv4_gateway.into()),
+            v6_host.or_else(|| tunnel_metadata.ipv6_gateway.map(IpAddr::from)),
+        )
+    }
+
     fn set_connecting_state(
         &mut self,
         endpoint: &Endpoint,
@@ -146,13 +217,13 @@ impl Firewall {
         endpoint: &Endpoint,
         winfw_settings: &WinFwSettings,
         tunnel_metadata: &crate::tunnel::TunnelMetadata,
+        dns_servers: &[CustomDnsServer],
     ) -> Result<(), Error> {
         trace!("Applying 'connected' firewall policy");
         let ip_str = Self::widestring_ip(endpoint.address.ip());
-        let v4_gateway = Self::widestring_ip(tunnel_metadata.ipv4_gateway.into());
-        let v6_gateway = tunnel_metadata
-            .ipv6_gateway
-            .map(|v6_ip| Self::widestring_ip(v6_ip.into()));
+        let (v4_dns_host, v6_dns_host) = Self::dns_hosts(tunnel_metadata, dns_servers);
+        let v4_gateway = Self::widestring_ip(v4_dns_host);
+        let v6_gateway = v6_dns_host.map(Self::widestring_ip);
 
         let tunnel_alias =
             WideCString::new(tunnel_metadata.interface.encode_utf16().collect::<Vec<_>>()).unwrap();
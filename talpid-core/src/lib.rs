@@ -50,6 +50,12 @@ pub mod dns;
 /// State machine to handle tunnel configuration.
 pub mod tunnel_state_machine;
 
+#[cfg(target_os = "linux")]
+/// Excludes processes from the tunnel. Their traffic is classified by a net_cls cgroup, marked by
+/// the firewall and routed through a routing table of its own. The table is kept by the route
+/// managers of tunnels, so only tunnels whose routes are set by a route manager can be bypassed.
+pub mod split_tunnel;
+
 #[cfg(not(target_os = "android"))]
 /// Internal code for managing bundled proxy software.
 mod proxy;
@@ -4,8 +4,10 @@
 #![allow(unreachable_patterns)]
 
 use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
 
-use tinc_plugin::TincInfo;
+use tinc_plugin::{ConfChange, TincInfo, TincNetwork, TincOptions};
 use tinc_plugin::{TincOperator as PluginTincOperator, TincOperatorError};
 
 /// Errors that can happen when using the Tinc tunnel
@@ -48,20 +50,28 @@ const TINC_AUTH_FILENAME: &str = "auth.txt";
 
 /// Tinc operator
 pub struct TincOperator {
-    tinc_home:              String,
+    operator:               Arc<PluginTincOperator>,
 }
 impl TincOperator {
-    /// 获取tinc home dir 创建tinc操作。
-    pub fn new(tinc_home: String) -> Self {
-        TincOperator {
-            tinc_home,
-        }
+    /// Wraps the operator of the client side of a network, shared with the rest of the daemon.
+    pub fn new(operator: Arc<PluginTincOperator>) -> Self {
+        TincOperator { operator }
+    }
+
+    /// The network operated on.
+    pub fn network(&self) -> &TincNetwork {
+        self.operator.network()
+    }
+
+    /// Pid file of tincd.
+    pub fn pid_file(&self) -> String {
+        self.operator.pid_file()
     }
 
     /// 启动tinc 返回duct::handle
-    pub fn start_tinc(&mut self) -> Result<duct::Handle> {
-        PluginTincOperator::instance().start_tinc()?;
-        if let Some(handle) = PluginTincOperator::instance().get_tinc_handle() {
+    pub fn start_tinc(&mut self, log_path: Option<&Path>) -> Result<duct::Handle> {
+        self.operator.start_tinc(log_path)?;
+        if let Some(handle) = self.operator.get_tinc_handle() {
             return Ok(handle);
         }
         Err(TincOperatorError::StartTincError)
@@ -69,7 +79,7 @@ impl TincOperator {
 
     /// 添加子设备
     pub fn add_hosts(&self, host_name: &str, pub_key: &str) -> Result<()> {
-        PluginTincOperator::instance().add_hosts(host_name, pub_key)
+        self.operator.add_hosts(host_name, pub_key)
     }
 
     /// 获取子设备公钥
@@ -81,17 +91,22 @@ impl TincOperator {
 
     /// 从pub_key文件读取pub_key
     pub fn get_local_pub_key(&self) -> Result<String> {
-        PluginTincOperator::instance().get_local_pub_key()
+        self.operator.get_local_pub_key()
     }
 
     /// 修改本地公钥
     pub fn set_local_pub_key(&mut self, pub_key: &str) -> Result<()> {
-        PluginTincOperator::instance().set_local_pub_key(pub_key)
+        self.operator.set_local_pub_key(pub_key)
     }
 
     /// 获取本地tinc虚拟ip
     pub fn get_local_vip(&self) -> Result<IpAddr> {
-        PluginTincOperator::instance().get_local_vip()
+        self.operator.get_local_vip()
+    }
+
+    /// Info the tinc configuration was last written from, if any.
+    pub fn get_local_info(&self) -> Option<TincInfo> {
+        self.operator.get_local_info()
     }
 
     /// 添加hosts文件
@@ -100,12 +115,14 @@ impl TincOperator {
     fn set_hosts(&self,
                  is_proxy: bool,
                  ip: &str,
-                 pubkey: &str) -> Result<()> {
-        PluginTincOperator::instance().set_hosts(is_proxy, ip, pubkey)
+                 pubkey: &str) -> Result<ConfChange> {
+        self.operator.set_hosts(is_proxy, ip, pubkey)
     }
 
     /// set_tinc_conf_file
-    pub fn set_info_to_local(&self, tinc_info: &TincInfo) -> Result<()> {
-        PluginTincOperator::instance().set_info_to_local(tinc_info)
+    pub fn set_info_to_local(&self, tinc_info: &TincInfo, options: &TincOptions)
+        -> Result<ConfChange>
+    {
+        self.operator.set_info_to_local(tinc_info, options)
     }
 }
@@ -11,6 +11,7 @@ use netlink_packet::{
 use netlink_sys::SocketAddr;
 use rtnetlink::constants::{
     AF_INET, AF_INET6, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_ROUTE, RTMGRP_LINK, RTMGRP_NOTIFY,
+    RT_TABLE_MAIN,
 };
 
 #[derive(err_derive::Error, Debug)]
@@ -79,10 +80,16 @@ impl RouteChangeListener {
                 Ok(None)
             }
 
-            NetlinkPayload::Rtnl(RtnlMessage::NewRoute(new_route)) => {
+            // Only the main table is tracked, other tables such as the one of split tunneling are
+            // derived from it.
+            NetlinkPayload::Rtnl(RtnlMessage::NewRoute(new_route))
+                if new_route.header.table == RT_TABLE_MAIN =>
+            {
                 self.get_route(new_route).map(RouteChange::Add).map(Some)
             }
-            NetlinkPayload::Rtnl(RtnlMessage::DelRoute(old_route)) => {
+            NetlinkPayload::Rtnl(RtnlMessage::DelRoute(old_route))
+                if old_route.header.table == RT_TABLE_MAIN =>
+            {
                 self.get_route(old_route).map(RouteChange::Remove).map(Some)
             }
             _ => Ok(None),
@@ -159,6 +166,7 @@ impl RouteChangeListener {
             node,
             prefix: prefix.unwrap(),
             metric,
+            table_id: None,
         })
     }
 
@@ -1,9 +1,10 @@
 use super::{NetNode, Node, Route};
+use crate::split_tunnel;
 
 use ipnetwork::IpNetwork;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
-    io,
+    fs, io,
     process::{Command, Stdio},
 };
 
@@ -15,6 +16,8 @@ use tokio_process::CommandExt;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+const SRC_VALID_MARK_PATH: &str = "/proc/sys/net/ipv4/conf/all/src_valid_mark";
+
 /// Errors that can happen in the Linux routing integration
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -26,6 +29,10 @@ pub enum Error {
     #[error(display = "Failed to remove route")]
     FailedToRemoveRoute,
 
+    /// Failed to add the routing rule of split tunneling.
+    #[error(display = "Failed to add routing rule")]
+    FailedToAddRule,
+
     /// Error while running "ip route".
     #[error(display = "Error while running \"ip route\"")]
     FailedToRunIp(#[error(cause)] io::Error),
@@ -126,7 +133,16 @@ impl RouteManagerImpl {
                     _ => continue,
                 }
             }
-            Ok(())
+
+            let default_nodes = [(true, &best_default_node_v4), (false, &best_default_node_v6)];
+            for (v4, default_node) in default_nodes.iter() {
+                if let Some(default_node) = default_node {
+                    let route = split_tunnel_route(default_node.clone(), *v4);
+                    Self::add_route(&route).wait()?;
+                    added_routes.insert(route);
+                }
+            }
+            Self::add_split_tunnel_rules()
         };
 
         if let Err(e) = establish_baseline_fn() {
@@ -139,6 +155,7 @@ of route monitor -{}",
                     );
                 }
             }
+            Self::remove_split_tunnel_routing();
             return Err(e);
         }
 
@@ -225,6 +242,8 @@ of route monitor -{}",
                     destination,
                 )));
             }
+            // Replaces the default route of the split tunneling table.
+            self.enque_route_change(RouteChange::Add(split_tunnel_route(new_node.clone(), true)));
             self.best_default_node_v4 = Some(new_node);
         }
 
@@ -251,6 +270,7 @@ of route monitor -{}",
                     destination,
                 )));
             }
+            self.enque_route_change(RouteChange::Add(split_tunnel_route(new_node.clone(), false)));
             self.best_default_node_v6 = Some(new_node);
         }
     }
@@ -350,10 +370,74 @@ of route monitor -{}",
         if let Some(metric) = route.metric {
             cmd.arg("metric").arg(metric.to_string());
         };
+        if let Some(table_id) = route.table_id {
+            cmd.arg("table").arg(table_id.to_string());
+        };
 
         cmd
     }
 
+    /// Routes the traffic marked as excluded from the tunnel by the firewall through the routing
+    /// table of split tunneling, which holds the best default routes.
+    fn add_split_tunnel_rules() -> Result<()> {
+        // Replies to excluded traffic are marked as well, and are only accepted by the reverse
+        // path filter if it takes the mark into account.
+        if let Err(e) = fs::write(SRC_VALID_MARK_PATH, b"1") {
+            log::warn!("Failed to enable src_valid_mark - {}", e);
+        }
+
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            // Rules left behind by a crash would otherwise be duplicated.
+            let _ = Self::split_tunnel_rule_cmd("delete", *ip_version)
+                .stderr(Stdio::null())
+                .status();
+            let status = Self::split_tunnel_rule_cmd("add", *ip_version)
+                .status()
+                .map_err(Error::FailedToRunIp)?;
+            if !status.success() {
+                match ip_version {
+                    IpVersion::V4 => return Err(Error::FailedToAddRule),
+                    // IPv6 may be disabled.
+                    IpVersion::V6 => log::warn!("Failed to add IPv6 split tunneling rule"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the routing rules and the routing table of split tunneling.
+    fn remove_split_tunnel_routing() {
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            let mut flush_cmd = Command::new("ip");
+            flush_cmd
+                .arg(ip_version.to_route_arg())
+                .arg("route")
+                .arg("flush")
+                .arg("table")
+                .arg(split_tunnel::ROUTING_TABLE_ID.to_string());
+
+            for mut cmd in vec![Self::split_tunnel_rule_cmd("delete", *ip_version), flush_cmd] {
+                log::trace!("running cmd - {:?}", &cmd);
+                match cmd.stderr(Stdio::null()).status() {
+                    Ok(status) if status.success() => (),
+                    _ => log::debug!("Failed to run {:?}", cmd),
+                }
+            }
+        }
+    }
+
+    fn split_tunnel_rule_cmd(action: &str, ip_version: IpVersion) -> Command {
+        let mut cmd = Command::new("ip");
+        cmd.arg(ip_version.to_route_arg())
+            .arg("rule")
+            .arg(action)
+            .arg("fwmark")
+            .arg(format!("{:#x}", split_tunnel::MARK))
+            .arg("table")
+            .arg(split_tunnel::ROUTING_TABLE_ID.to_string());
+        cmd
+    }
+
     fn run_cmd(mut cmd: Command, err: Error) -> Box<dyn Future<Item = (), Error = Error> + Send> {
         log::trace!("running cmd - {:?}", &cmd);
         Box::new(
@@ -459,6 +543,7 @@ impl Future for RouteManagerImpl {
         }
         let all_changes_applied = self.apply_route_table_changes()?;
         if all_changes_applied && self.should_shut_down {
+            Self::remove_split_tunnel_routing();
             if let Some(tx) = self.shutdown_finished_tx.take() {
                 if tx.send(()).is_err() {
                     log::error!("RouteManagerHandle already stopped");
@@ -521,6 +606,14 @@ fn parse_ip_route_show_line(line: &str, ip_version: IpVersion) -> Option<Route>
     }
 }
 
+/// Default route of the routing table of split tunneling.
+fn split_tunnel_route(node: Node, v4: bool) -> Route {
+    let prefix = if v4 { "0.0.0.0/0" } else { "::/0" };
+    let mut route = Route::new(node, prefix.parse().unwrap());
+    route.table_id = Some(split_tunnel::ROUTING_TABLE_ID);
+    route
+}
+
 fn ip_vers(route: &Route) -> &'static str {
     if route.prefix.is_ipv4() {
         "-4"
@@ -87,6 +87,9 @@ struct Route {
     node: Node,
     prefix: IpNetwork,
     metric: Option<u32>,
+    /// Routing table of the route, the main one if unset.
+    #[cfg(target_os = "linux")]
+    table_id: Option<u32>,
 }
 
 impl Route {
@@ -95,6 +98,8 @@ impl Route {
             node,
             prefix,
             metric: None,
+            #[cfg(target_os = "linux")]
+            table_id: None,
         }
     }
 }
@@ -0,0 +1,222 @@
+use nix::mount::{mount, MsFlags};
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Identifies packets coming from the cgroup of excluded processes.
+pub const NET_CLS_CLASSID: u32 = 0x4d9f41;
+/// Value used to mark the packets and connections of excluded processes.
+pub const MARK: u32 = 0xf41;
+/// Routing table of the traffic of excluded processes. It only holds the default routes of the
+/// host, so that excluded traffic leaves through the interface it would without a tunnel.
+pub const ROUTING_TABLE_ID: u32 = 19;
+
+/// Where the net_cls controller is usually mounted.
+const NET_CLS_DIR: &str = "/sys/fs/cgroup/net_cls";
+/// Where the net_cls controller is mounted if the system doesn't mount it, as on systems that
+/// only use cgroup v2.
+const NET_CLS_MOUNT_DIR: &str = "/run/net_cls";
+const EXCLUSIONS_CGROUP_NAME: &str = "mullvad-exclusions";
+
+/// Roots of the hierarchies that cgroups named by path are looked up in: cgroup v2 on hybrid and
+/// unified setups, then the hierarchy of systemd on legacy setups.
+const CGROUP_ROOTS: [&str; 3] = [
+    "/sys/fs/cgroup/unified",
+    "/sys/fs/cgroup",
+    "/sys/fs/cgroup/systemd",
+];
+
+/// Errors related to split tunneling.
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    /// Unable to create the cgroup of excluded processes.
+    #[error(display = "Unable to create cgroup for excluded processes")]
+    CreateCGroup(#[error(cause)] io::Error),
+
+    /// Unable to mount the net_cls controller.
+    #[error(display = "Unable to mount the net_cls controller")]
+    MountNetCls(#[error(cause)] nix::Error),
+
+    /// Unable to set the class ID of the cgroup of excluded processes.
+    #[error(display = "Unable to set class ID for net_cls cgroup")]
+    SetClassId(#[error(cause)] io::Error),
+
+    /// Unable to add a PID to the cgroup of excluded processes.
+    #[error(display = "Unable to add PID to cgroup.procs")]
+    AddPid(#[error(cause)] io::Error),
+
+    /// Unable to remove a PID from the cgroup of excluded processes.
+    #[error(display = "Unable to remove PID from cgroup.procs")]
+    RemovePid(#[error(cause)] io::Error),
+
+    /// Unable to read the PIDs of a cgroup.
+    #[error(display = "Unable to read cgroup.procs")]
+    ListPids(#[error(cause)] io::Error),
+
+    /// The cgroup is not an absolute path inside the cgroup hierarchy.
+    #[error(display = "Invalid cgroup path: {}", _0)]
+    InvalidCGroup(String),
+
+    /// No cgroup exists at the path.
+    #[error(display = "No cgroup found at {}", _0)]
+    CGroupNotFound(String),
+}
+
+/// Result type for split tunneling operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Manages the PIDs in the net_cls cgroup of excluded processes. Children of excluded processes
+/// are excluded as well. The cgroup outlives the daemon, so processes stay excluded across
+/// restarts.
+pub struct PidManager {
+    net_cls_path: PathBuf,
+}
+
+impl PidManager {
+    /// Creates the cgroup of excluded processes, mounting the net_cls controller if needed.
+    pub fn new() -> Result<PidManager> {
+        let net_cls_path = Self::net_cls_root()?.join(EXCLUSIONS_CGROUP_NAME);
+        if !net_cls_path.exists() {
+            fs::create_dir(&net_cls_path).map_err(Error::CreateCGroup)?;
+        }
+        fs::write(
+            net_cls_path.join("net_cls.classid"),
+            NET_CLS_CLASSID.to_string().as_bytes(),
+        )
+        .map_err(Error::SetClassId)?;
+
+        Ok(PidManager { net_cls_path })
+    }
+
+    fn net_cls_root() -> Result<PathBuf> {
+        let system_dir = Path::new(NET_CLS_DIR);
+        if system_dir.join("net_cls.classid").exists() {
+            return Ok(system_dir.to_path_buf());
+        }
+
+        let own_dir = Path::new(NET_CLS_MOUNT_DIR);
+        if !own_dir.join("net_cls.classid").exists() {
+            log::debug!("Mounting the net_cls controller at {}", own_dir.display());
+            fs::create_dir_all(own_dir).map_err(Error::CreateCGroup)?;
+            mount(
+                Some("net_cls"),
+                own_dir,
+                Some("cgroup"),
+                MsFlags::empty(),
+                Some("net_cls"),
+            )
+            .map_err(Error::MountNetCls)?;
+        }
+        Ok(own_dir.to_path_buf())
+    }
+
+    /// Excludes a process from the tunnel.
+    pub fn add(&self, pid: i32) -> Result<()> {
+        write_pid(&self.net_cls_path, pid).map_err(Error::AddPid)
+    }
+
+    /// Stops excluding a process from the tunnel.
+    pub fn remove(&self, pid: i32) -> Result<()> {
+        // Moving a process to the root cgroup removes it from ours.
+        let root = self.net_cls_path.parent().expect("cgroup has no parent");
+        write_pid(root, pid).map_err(Error::RemovePid)
+    }
+
+    /// Returns the PIDs of the excluded processes.
+    pub fn list(&self) -> Result<Vec<i32>> {
+        read_pids(&self.net_cls_path).map_err(Error::ListPids)
+    }
+
+    /// Stops excluding any process from the tunnel.
+    pub fn clear(&self) -> Result<()> {
+        for pid in self.list()? {
+            self.remove(pid)?;
+        }
+        Ok(())
+    }
+
+    /// Excludes the processes currently in `cgroup` from the tunnel. Processes that join the
+    /// cgroup later are only excluded if they are children of excluded processes.
+    pub fn add_cgroup(&self, cgroup: &str) -> Result<()> {
+        for pid in cgroup_pids(cgroup)? {
+            match self.add(pid) {
+                // The process exited in the meantime.
+                Err(Error::AddPid(ref e)) if e.raw_os_error() == Some(libc::ESRCH) => (),
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops excluding the processes currently in `cgroup` from the tunnel.
+    pub fn remove_cgroup(&self, cgroup: &str) -> Result<()> {
+        let excluded = self.list()?;
+        for pid in cgroup_pids(cgroup)? {
+            if excluded.contains(&pid) {
+                match self.remove(pid) {
+                    Err(Error::RemovePid(ref e)) if e.raw_os_error() == Some(libc::ESRCH) => (),
+                    result => result?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the directory of `cgroup`, an absolute path inside the cgroup hierarchy such as
+/// `/system.slice/apt-cacher-ng.service`.
+pub fn find_cgroup(cgroup: &str) -> Result<PathBuf> {
+    let relative_path =
+        relative_cgroup_path(cgroup).ok_or_else(|| Error::InvalidCGroup(cgroup.to_owned()))?;
+    CGROUP_ROOTS
+        .iter()
+        .map(|root| Path::new(root).join(relative_path))
+        .find(|path| path.join("cgroup.procs").exists())
+        .ok_or_else(|| Error::CGroupNotFound(cgroup.to_owned()))
+}
+
+fn cgroup_pids(cgroup: &str) -> Result<Vec<i32>> {
+    read_pids(&find_cgroup(cgroup)?).map_err(Error::ListPids)
+}
+
+/// Strips the leading `/` of `cgroup`, which must name a cgroup below the root one.
+fn relative_cgroup_path(cgroup: &str) -> Option<&Path> {
+    let relative_path = Path::new(cgroup).strip_prefix("/").ok()?;
+    let mut components = relative_path.components().peekable();
+    if components.peek().is_some()
+        && components.all(|component| match component {
+            Component::Normal(_) => true,
+            _ => false,
+        })
+    {
+        Some(relative_path)
+    } else {
+        None
+    }
+}
+
+fn write_pid(cgroup_dir: &Path, pid: i32) -> io::Result<()> {
+    fs::write(cgroup_dir.join("cgroup.procs"), pid.to_string().as_bytes())
+}
+
+fn read_pids(cgroup_dir: &Path) -> io::Result<Vec<i32>> {
+    let procs = fs::read_to_string(cgroup_dir.join("cgroup.procs"))?;
+    Ok(procs.lines().filter_map(|line| line.trim().parse().ok()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_cgroup_path() {
+        assert_eq!(
+            relative_cgroup_path("/system.slice/apt-cacher-ng.service"),
+            Some(Path::new("system.slice/apt-cacher-ng.service"))
+        );
+        assert_eq!(relative_cgroup_path("/"), None);
+        assert_eq!(relative_cgroup_path("system.slice"), None);
+        assert_eq!(relative_cgroup_path("/system.slice/../.."), None);
+    }
+}
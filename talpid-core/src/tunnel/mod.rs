@@ -6,12 +6,13 @@ use std::{
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
 #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
 use talpid_types::net::wireguard as wireguard_types;
-use talpid_types::net::{GenericTunnelOptions, TunnelParameters};
+use talpid_types::net::{Endpoint, GenericTunnelOptions, TunnelParameters};
 
 // add by YanBowen
 use talpid_types::net::{tinc as tinc_types};
@@ -85,6 +86,8 @@ pub enum TunnelEvent {
     Up(TunnelMetadata),
     /// Sent when the tunnel goes down.
     Down,
+    /// Sent when the tunnel switches over to another endpoint without going down.
+    Reconnected(TunnelMetadata, Endpoint),
 }
 
 /// Information about a VPN tunnel.
@@ -199,6 +202,7 @@ impl TunnelMonitor {
         tunnel_parameters: &TunnelParameters,
         log_dir: &Option<PathBuf>,
         resource_dir: &Path,
+        tinc: &Arc<tinc_plugin::TincOperator>,
         on_event: L,
         tun_provider: &dyn TunProvider,
     ) -> Result<Self>
@@ -220,7 +224,7 @@ impl TunnelMonitor {
             // add by YanBowen
             #[cfg(not(target_os = "android"))]
             TunnelParameters::Tinc(config) => {
-                Self::start_tinc_tunnel(&config, log_file, resource_dir, on_event)
+                Self::start_tinc_tunnel(&config, log_file, tinc.clone(), on_event)
             }
             #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
             TunnelParameters::Wireguard(config) => {
@@ -273,14 +277,14 @@ impl TunnelMonitor {
     fn start_tinc_tunnel<L>(
         config: &tinc_types::TunnelParameters,
         log: Option<PathBuf>,
-        resource_dir: &Path,
+        operator: Arc<tinc_plugin::TincOperator>,
         on_event: L,
     ) -> Result<Self>
         where
             L: Fn(TunnelEvent) + Send + Sync + Clone + 'static,
     {
         let monitor =
-            tinc::TincMonitor::start(on_event, config, log, resource_dir)
+            tinc::TincMonitor::start(on_event, config, log, operator)
                 .map_err(|e|{log::error!("{:?}", e);e})?;
         Ok(TunnelMonitor {
             monitor: InternalTunnelMonitor::Tinc(monitor),
@@ -2,29 +2,53 @@ use super::{TunnelEvent, TunnelMetadata};
 use crate::process::tinc::TincOperator;
 use std::{
     io,
+    net::{IpAddr, Ipv4Addr},
     path::{Path, PathBuf},
     sync::{
         mpsc,
         Arc,
+        Mutex,
         atomic::{AtomicBool, Ordering},
     },
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
+use talpid_types::{
+    net::{tinc, Endpoint},
+    ErrorExt,
 };
-use talpid_types::net::tinc;
-
-use std::net::{TcpStream, SocketAddr};
-use std::io::Write;
 
-use tinc_plugin;
+use tinc_plugin::{self, ConnectTo, EventType, TincEvent, TincInfo};
+use tinc_plugin::TincOperator as PluginTincOperator;
 
 #[cfg(target_os = "linux")]
 use which;
-use std::net::IpAddr;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use self::routes::ProxyRoutes;
 
 mod ping_monitor;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod routes;
 
 // amount of seconds to run `ping` until it returns.
 const PING_TIMEOUT: u16 = 7;
 
+/// Time between the pings monitoring the tunnel, unless the tunnel options say otherwise.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Unanswered pings in a row that make the tunnel down, unless the tunnel options say otherwise.
+const MONITOR_THRESHOLD: u32 = 3;
+
+/// Time tincd has to create its interface and run `tinc-up`.
+const TINC_UP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time between checks of whether tincd is still running.
+const CHILD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Times tincd is restarted after dying unexpectedly, before the tunnel is given up on.
+const MAX_TINC_RESTARTS: u32 = 3;
+
 /// Results from fallible operations on the Tinc tunnel.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -71,137 +95,376 @@ pub enum Error {
     #[error(display = "Failure in Windows syscall")]
     WinnetError(#[error(cause)] crate::winnet::Error),
 
+    /// The tinc info has no proxy to connect to.
+    #[error(display = "No Tinc proxy to connect to")]
+    NoProxy,
+
+    /// Tinc didn't report its interface up in time.
+    #[error(display = "Tinc didn't report up within {} seconds", _0)]
+    TincUpTimeout(u64),
+
+    /// Unable to set the address of the Tinc interface.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Unable to set the address of the Tinc interface")]
+    SetInterfaceAddressError(#[error(cause)] io::Error),
+
+    /// Unable to start the reactor the routes are managed on.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Unable to start the routing reactor")]
+    RoutingReactorError(#[error(cause)] io::Error),
+
+    /// Failed to set up routing.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Failed to setup routing")]
+    SetupRoutingError(#[error(cause)] crate::routing::Error),
+
+    /// Unable to listen for the events reported by the tinc scripts.
+    #[error(display = "Unable to listen for Tinc events")]
+    EventChannelError(#[error(cause)] io::Error),
 
     /// process::tinc::Error
     #[error(display = "Tinc Operator Error")]
     TincOperatorError(#[error(cause)] crate::process::tinc::Error),
+
+    /// The gateway stopped answering pings through the tunnel.
+    #[error(display = "Gateway of the Tinc tunnel stopped answering pings")]
+    PingTimeoutError,
+}
+
+/// A proxy of the tinc info, as seen by tincd.
+struct Proxy {
+    connect_to:         ConnectTo,
+    /// Name of the tinc node of the proxy.
+    node:               String,
+    reachable:          bool,
 }
 
 /// Struct for monitoring an Tinc process.
 pub struct TincMonitor {
     tinc:               TincOperator,
     on_event:           Box<dyn Fn(TunnelEvent) + Send + Sync + 'static>,
-    _log_path:           Option<PathBuf>,
-    resource_dir:       PathBuf,
-    event_rx:           mpsc::Receiver<tinc_plugin::EventType>,
-    child:              Arc<duct::Handle>,
+    log_path:           Option<PathBuf>,
+    event_rx:           mpsc::Receiver<TincEvent>,
+    /// The running tincd, replaced when it's restarted.
+    child:              Arc<Mutex<duct::Handle>>,
+    /// Times tincd has been restarted.
+    restarts:           u32,
     closed:             Arc<AtomicBool>,
+    interface_name:     String,
+    vip:                Ipv4Addr,
+    /// Endpoint of the tunnel parameters, the proxies are reached on its port and protocol.
+    endpoint:           Endpoint,
+    proxies:            Vec<Proxy>,
+    /// Index of the proxy the traffic is routed through.
+    active:             usize,
+    /// Gateway of the active proxy, pinged to tell whether the tunnel works.
+    gateway:            Arc<Mutex<IpAddr>>,
+    /// Set when the gateway stopped answering pings.
+    unreachable:        Arc<AtomicBool>,
+    /// Routes through the active proxy, removed when tinc goes down.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    routes:             ProxyRoutes,
 }
 
 impl TincMonitor {
-    /// Creates a new `TincMonitor` with the given listener and using the plugin at the given
-    /// path.
+    /// Creates a new `TincMonitor` with the given listener, running tincd of the network of
+    /// `operator`.
+    ///
+    /// Returns once tinc has reported its interface up and the interface address and routes are
+    /// in place.
     pub fn start<L>(
         on_event:       L,
         params:         &tinc::TunnelParameters,
         log_file:       Option<PathBuf>,
-        resource_dir:   &Path,
+        operator:       Arc<PluginTincOperator>,
     ) -> Result<Self>
         where
             L: Fn(TunnelEvent) + Send + Sync + Clone + 'static,
     {
-        let resource_dir_str = resource_dir.to_str().unwrap();
-
-        let mut tinc_operator = TincOperator::new(resource_dir_str.to_string() + "/tinc/");
+        let mut tinc_operator = TincOperator::new(operator);
+        let tinc_info = &params.config.tinc_info;
+        let vip = match tinc_info.vip {
+            IpAddr::V4(vip) => vip,
+            IpAddr::V6(_) => return Err(Error::StartTincError),
+        };
+        let proxies = Self::get_proxies(tinc_info)?;
 
-        tinc_operator.set_info_to_local(&params.config.tinc_info).map_err(Error::TincOperatorError)?;
+        tinc_operator
+            .set_info_to_local(tinc_info, &params.options)
+            .map_err(Error::TincOperatorError)?;
 
-        let child = tinc_operator.start_tinc().map_err(|_|Error::StartTincError)?;
+        // Listening before tincd starts, so that `tinc-up` can't report before anyone listens.
+        let event_rx = tinc_plugin::spawn(&tinc_operator.network().home)
+            .map_err(Error::EventChannelError)?;
 
-        let event_rx = tinc_plugin::spawn();
+        let child = tinc_operator
+            .start_tinc(log_file.as_ref().map(PathBuf::as_path))
+            .map_err(|_|Error::StartTincError)?;
 
-        let pinger_event = on_event.clone();
+        let interface_name;
+        #[cfg(not(target_os = "macos"))]
         {
-            let vip = tinc_operator.get_local_vip().map_err(Error::TincOperatorError)?;
-            let vip_ipv4 = match vip {
-                IpAddr::V4(x) => x,
-                IpAddr::V6(_) => return Err(Error::StartTincError),
-            };
-            let ips = vec![vip.clone()];
-
-            let interface_name;
-
-            #[cfg(not(target_os = "macos"))]
-            {
-                interface_name = "dnet".to_string();
-            }
-
-            #[cfg(target_os = "macos")]
-            {
-                interface_name = "tap0".to_string();
-            }
-            let metadata = TunnelMetadata {
-                interface: interface_name.clone(),
-                ips,
-                ipv4_gateway: vip_ipv4,
-                ipv6_gateway: None
-            };
-
-            ::std::thread::spawn(move || {
-                if let Ok(()) = ping_monitor::ping(
-                    IpAddr::from(vip.clone()),
-                    PING_TIMEOUT,
-                    &interface_name,
-                    true) {
-                    (pinger_event)(TunnelEvent::Up(metadata));
-                };
-            });
+            interface_name = tinc_operator.network().interface.clone();
+        }
+        #[cfg(target_os = "macos")]
+        {
+            interface_name = "tap0".to_string();
         }
 
-        let on_event = Box::new(on_event.clone());
+        // `tinc-up` sets the address and routes on Windows.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let setup = || -> Result<ProxyRoutes> {
+            wait_for_up(&event_rx)?;
+            set_interface_address(&interface_name, vip).map_err(Error::SetInterfaceAddressError)?;
+            let mut routes = ProxyRoutes::new(
+                &interface_name,
+                proxies.iter().map(|proxy| proxy.connect_to.ip).collect(),
+            )
+            .map_err(Error::RoutingReactorError)?;
+            routes
+                .route_through(proxies[0].connect_to.vip)
+                .map_err(Error::SetupRoutingError)?;
+            Ok(routes)
+        };
+        #[cfg(windows)]
+        let setup = || wait_for_up(&event_rx);
+        #[cfg_attr(windows, allow(unused_variables))]
+        let routes = match setup() {
+            Ok(routes) => routes,
+            Err(e) => {
+                kill(&child, &tinc_operator.network().home);
+                return Err(e);
+            }
+        };
 
-        return Ok(TincMonitor {
+        let monitor = TincMonitor {
             tinc: tinc_operator,
-            on_event,
-            _log_path: log_file,
-            resource_dir: resource_dir.to_owned(),
+            on_event: Box::new(on_event.clone()),
+            log_path: log_file,
             event_rx,
-            child: Arc::new(child),
+            child: Arc::new(Mutex::new(child)),
+            restarts: 0,
             closed: Arc::new(AtomicBool::new(false)),
+            interface_name,
+            vip,
+            endpoint: params.config.endpoint,
+            gateway: Arc::new(Mutex::new(proxies[0].connect_to.vip)),
+            unreachable: Arc::new(AtomicBool::new(false)),
+            proxies,
+            active: 0,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            routes,
+        };
+
+        monitor.spawn_ping_monitor(on_event, &params.options);
+        Ok(monitor)
+    }
+
+    /// Pings the gateway until it answers, which makes the tunnel up, and then keeps pinging it
+    /// until the tunnel closes. Closes the tunnel when the gateway stops answering.
+    fn spawn_ping_monitor<L>(&self, on_event: L, options: &tinc::TunnelOptions)
+    where
+        L: Fn(TunnelEvent) + Send + Sync + 'static,
+    {
+        let metadata = self.tunnel_metadata();
+        let interface_name = self.interface_name.clone();
+        let gateway = self.gateway.clone();
+        let unreachable = self.unreachable.clone();
+        let closed = self.closed.clone();
+        let close_handle = self.close_handle();
+        let interval = options
+            .monitor_interval
+            .map(|secs| Duration::from_secs(secs.into()))
+            .unwrap_or(MONITOR_INTERVAL);
+        let threshold = options.monitor_threshold.unwrap_or(MONITOR_THRESHOLD);
+
+        ::std::thread::spawn(move || {
+            let first_gateway = *gateway.lock().unwrap();
+            let result = ping_monitor::ping(first_gateway, PING_TIMEOUT, &interface_name, true)
+                .map(|()| (on_event)(TunnelEvent::Up(metadata)))
+                .and_then(|()| {
+                    ping_monitor::monitor_ping(
+                        || *gateway.lock().unwrap(),
+                        interval,
+                        threshold,
+                        &interface_name,
+                        &closed,
+                    )
+                });
+            if let Err(e) = result {
+                if !closed.load(Ordering::SeqCst) {
+                    log::error!("{}", e.display_chain_with_msg("Tinc tunnel is unreachable"));
+                    unreachable.store(true, Ordering::SeqCst);
+                    let _ = close_handle.close();
+                }
+            }
         });
     }
 
-    fn tunnel_up(&self) -> Result<()> {
-        let vip = self.tinc.get_local_vip().map_err(Error::TincOperatorError)?;
-        let ips = vec![
-            vip.clone(),
-        ];
+    /// The proxies to fail over between, in order of preference. They're assumed reachable until
+    /// tinc reports otherwise.
+    fn get_proxies(tinc_info: &TincInfo) -> Result<Vec<Proxy>> {
+        if tinc_info.connect_to.is_empty() {
+            return Err(Error::NoProxy);
+        }
+        tinc_info.connect_to
+            .iter()
+            .map(|connect_to| match (connect_to.ip, connect_to.vip) {
+                (IpAddr::V4(_), IpAddr::V4(_)) => Ok(Proxy {
+                    node: connect_to.node_name(),
+                    connect_to: connect_to.clone(),
+                    reachable: true,
+                }),
+                _ => Err(Error::StartTincError),
+            })
+            .collect()
+    }
 
-        let vip_ipv4 = match vip {
-            IpAddr::V4(x) => x,
-            IpAddr::V6(_) => return Err(Error::StartTincError),
+    fn tunnel_metadata(&self) -> TunnelMetadata {
+        let ipv4_gateway = match self.proxies[self.active].connect_to.vip {
+            IpAddr::V4(vip) => vip,
+            IpAddr::V6(_) => unreachable!("Only proxies with IPv4 addresses are accepted"),
         };
+        TunnelMetadata {
+            interface: self.interface_name.clone(),
+            ips: vec![IpAddr::V4(self.vip)],
+            ipv4_gateway,
+            ipv6_gateway: None,
+        }
+    }
 
-        let interface_name = "dnet";
-        let metadata = TunnelMetadata {
-            interface: interface_name.to_string(),
-            ips,
-            ipv4_gateway: vip_ipv4,
-            ipv6_gateway: None
-        };
-        (self.on_event)(TunnelEvent::Up(metadata));
+    fn tunnel_endpoint(&self) -> Endpoint {
+        Endpoint::new(
+            self.proxies[self.active].connect_to.ip,
+            self.endpoint.address.port(),
+            self.endpoint.protocol,
+        )
+    }
+
+    /// Consumes the monitor and waits for tinc to go down, removing the routes when it does.
+    pub fn wait(mut self) -> Result<()> {
+        let mut result = Ok(());
+        loop {
+            let event = self.event_rx.recv_timeout(CHILD_POLL_INTERVAL).map(|event| event.event);
+            let handled = match event {
+                Ok(EventType::Down) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Ok(EventType::HostUp(node)) => self.set_reachable(&node, true),
+                Ok(EventType::HostDown(node)) => self.set_reachable(&node, false),
+                Ok(EventType::Up) => {
+                    log::debug!("Tinc reported up again");
+                    Ok(())
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => match self.child_exit_status() {
+                    Ok(Some(_)) if self.closed.load(Ordering::SeqCst) => break,
+                    Ok(Some(status)) => {
+                        log::error!("Tinc died unexpectedly with status: {}", status);
+                        self.restart()
+                    }
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                },
+            };
+            if let Err(e) = handled {
+                // Either no routes are left to any proxy, or tincd is gone for good.
+                log::error!("{}", e.display_chain_with_msg("Tinc tunnel failed"));
+                let _ = self.close_handle().close();
+                result = Err(e);
+                break;
+            }
+        }
+        // Stops the ping monitor, tincd is gone.
+        self.closed.store(true, Ordering::SeqCst);
+        if self.unreachable.load(Ordering::SeqCst) && result.is_ok() {
+            result = Err(Error::PingTimeoutError);
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.routes.stop();
+        (self.on_event)(TunnelEvent::Down);
+        result
+    }
+
+    /// How tincd exited, if it has.
+    fn child_exit_status(&self) -> Result<Option<ExitStatus>> {
+        match self.child.lock().unwrap().try_wait() {
+            Ok(output) => Ok(output.map(|output| output.status)),
+            Err(e) => Err(Error::ChildProcessError("Error when waiting", e)),
+        }
+    }
+
+    /// Starts tincd again after it died, and sets up the interface it creates like the first
+    /// time. Fails with `ChildProcessDied` once tincd has been restarted too many times.
+    fn restart(&mut self) -> Result<()> {
+        if self.restarts >= MAX_TINC_RESTARTS {
+            return Err(Error::ChildProcessDied);
+        }
+        self.restarts += 1;
+        log::warn!("Restarting Tinc, {} of {} times", self.restarts, MAX_TINC_RESTARTS);
+
+        let child = self.tinc
+            .start_tinc(self.log_path.as_ref().map(PathBuf::as_path))
+            .map_err(|_| Error::StartTincError)?;
+        *self.child.lock().unwrap() = child;
+        wait_for_up(&self.event_rx)?;
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            set_interface_address(&self.interface_name, self.vip)
+                .map_err(Error::SetInterfaceAddressError)?;
+            self.routes
+                .route_through(self.proxies[self.active].connect_to.vip)
+                .map_err(Error::SetupRoutingError)?;
+        }
+        (self.on_event)(TunnelEvent::Reconnected(self.tunnel_metadata(), self.tunnel_endpoint()));
         Ok(())
     }
 
-    /// Consumes the monitor and waits for both proxy and tunnel, as applicable.
-    pub fn wait(self) -> Result<()> {
-        let wait_result = match self.event_rx.recv() {
-            Ok(tinc_plugin::EventType::Up) => {
-                self.tunnel_up()?;
-                return Ok(());
-            },
-            Ok(tinc_plugin::EventType::Down) => {
-                (self.on_event)(TunnelEvent::Down);
-                return Ok(());
-            },
-            Ok(_) => {
+    /// Records whether the tinc node `node` is reachable, and switches to another proxy when the
+    /// active one no longer is, except on Windows.
+    fn set_reachable(&mut self, node: &str, reachable: bool) -> Result<()> {
+        log::info!("Tinc host {} is {}", node, if reachable { "up" } else { "down" });
+        match self.proxies.iter_mut().find(|proxy| proxy.node == node) {
+            Some(proxy) => proxy.reachable = reachable,
+            None => return Ok(()),
+        }
+        if self.proxies[self.active].reachable {
+            return Ok(());
+        }
+        match self.proxies.iter().position(|proxy| proxy.reachable) {
+            #[cfg(not(windows))]
+            Some(index) => self.switch_to(index),
+            // `tinc-up` routes through the first proxy, and there are no routes to switch.
+            #[cfg(windows)]
+            Some(_) => {
+                log::warn!(
+                    "Tinc proxy {} is down, switching proxies is not supported on Windows",
+                    self.proxies[self.active].node
+                );
                 Ok(())
-            },
-            Err(_) => {
+            }
+            None => {
+                log::warn!(
+                    "No tinc proxy is reachable, staying on {}",
+                    self.proxies[self.active].node
+                );
                 Ok(())
-            },
-        };
-        wait_result
+            }
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn switch_to(&mut self, index: usize) -> Result<()> {
+        log::info!(
+            "Switching from tinc proxy {} to {}",
+            self.proxies[self.active].node,
+            self.proxies[index].node
+        );
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.routes
+            .route_through(self.proxies[index].connect_to.vip)
+            .map_err(Error::SetupRoutingError)?;
+        self.active = index;
+        *self.gateway.lock().unwrap() = self.proxies[index].connect_to.vip;
+        (self.on_event)(TunnelEvent::Reconnected(self.tunnel_metadata(), self.tunnel_endpoint()));
+        Ok(())
     }
 
     /// Creates a handle to this monitor, allowing the tunnel to be closed while some other
@@ -210,16 +473,85 @@ impl TincMonitor {
         TincCloseHandle {
             child: self.child.clone(),
             closed: self.closed.clone(),
-            pid_file: self.resource_dir.clone().join("/tinc/tinc.pid"),
+            tinc_home: self.tinc.network().home.clone(),
+            pid_file: self.tinc.pid_file(),
         }
     }
 }
 
+/// Waits for `tinc-up` to report that the interface of tincd exists.
+fn wait_for_up(event_rx: &mpsc::Receiver<TincEvent>) -> Result<()> {
+    let deadline = Instant::now() + TINC_UP_TIMEOUT;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::TincUpTimeout(TINC_UP_TIMEOUT.as_secs()));
+        }
+        match event_rx.recv_timeout(deadline - now).map(|event| event.event) {
+            Ok(EventType::Up) => return Ok(()),
+            Ok(EventType::Down) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(Error::ChildProcessDied);
+            }
+            Ok(_) => (),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                return Err(Error::TincUpTimeout(TINC_UP_TIMEOUT.as_secs()));
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_interface_address(interface_name: &str, vip: Ipv4Addr) -> io::Result<()> {
+    let address = format!("{}/32", vip);
+    run_checked(
+        "ip addr replace",
+        duct::cmd!("ip", "addr", "replace", address, "dev", interface_name),
+    )?;
+    run_checked("ip link set", duct::cmd!("ip", "link", "set", "dev", interface_name, "up"))
+}
+
+#[cfg(target_os = "macos")]
+fn set_interface_address(interface_name: &str, vip: Ipv4Addr) -> io::Result<()> {
+    run_checked(
+        "ifconfig",
+        duct::cmd!("ifconfig", interface_name, vip.to_string(), "netmask", "255.255.255.255", "up"),
+    )
+}
+
+/// Runs `command`, failing with what it wrote to stderr unless it exits successfully.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run_checked(name: &str, command: duct::Expression) -> io::Result<()> {
+    let output = command.stdout_null().stderr_capture().unchecked().run()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!(
+            "{} failed with {}: {}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    ))
+}
+
+/// Kills tincd and wakes up whoever waits for its events, as `tinc-down` doesn't run when tincd
+/// is killed.
+fn kill(child: &duct::Handle, tinc_home: &Path) {
+    let _ = child.kill();
+    let event = TincEvent::new(EventType::Down);
+    if let Err(e) = tinc_plugin::listener::send(tinc_home, &event) {
+        log::error!("Unable to report that Tinc was stopped: {}", e);
+    }
+}
+
 /// 用于关闭tinc进程
 pub struct TincCloseHandle {
-    child:              Arc<duct::Handle>,
+    child:              Arc<Mutex<duct::Handle>>,
     closed:             Arc<AtomicBool>,
-    pid_file:           PathBuf
+    tinc_home:          PathBuf,
+    pid_file:           String,
 }
 
 impl TincCloseHandle {
@@ -227,11 +559,9 @@ impl TincCloseHandle {
     /// making the `TincMonitor::wait` method return.
     pub fn close(self) -> io::Result<()> {
         if !self.closed.swap(true, Ordering::SeqCst) {
-            if let Err(e) = tinc_plugin::control::stop(
-                self.pid_file.to_str().unwrap()){
+            if let Err(e) = tinc_plugin::control::stop(&self.pid_file) {
                 log::warn!("{}", e);
-                let _ = self.child.kill();
-                sender_tinc_close();
+                kill(&self.child.lock().unwrap(), &self.tinc_home);
             };
             Ok(())
         } else {
@@ -239,9 +569,3 @@ impl TincCloseHandle {
         }
     }
 }
-
-fn sender_tinc_close() {
-    let addr = SocketAddr::from(([127, 0, 0, 1], 50070));
-    let mut stream = TcpStream::connect(&addr).expect("Tinc Monitor not exist");
-    stream.write("Down".as_bytes()).expect("Tinc Monitor not exist");
-}
@@ -1,6 +1,7 @@
 use std::{
     io,
     net::IpAddr,
+    sync::atomic::{AtomicBool, Ordering},
     thread,
     time::{Duration, Instant},
 };
@@ -14,16 +15,35 @@ pub enum Error {
     TimeoutError,
 }
 
-pub fn monitor_ping(ip: IpAddr, timeout_secs: u16, interface: &str) -> Result<(), Error> {
-    loop {
+/// Pings the address `gateway` returns once every `interval` until `stop` is set, or until
+/// `threshold` pings in a row go unanswered.
+pub fn monitor_ping(
+    gateway: impl Fn() -> IpAddr,
+    interval: Duration,
+    threshold: u32,
+    interface: &str,
+    stop: &AtomicBool,
+) -> Result<(), Error> {
+    let timeout_secs = interval.as_secs().max(1).min(u64::from(u16::max_value())) as u16;
+    let mut unanswered = 0;
+    while !stop.load(Ordering::SeqCst) {
         let start = Instant::now();
-        ping(ip, timeout_secs, &interface, false)?;
-        if let Some(remaining) =
-            Duration::from_secs(timeout_secs.into()).checked_sub(start.elapsed())
-        {
+        match ping(gateway(), timeout_secs, interface, true) {
+            Ok(()) => unanswered = 0,
+            Err(Error::TimeoutError) => {
+                unanswered += 1;
+                log::debug!("{} unanswered pings in a row through {}", unanswered, interface);
+                if unanswered >= threshold {
+                    return Err(Error::TimeoutError);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        if let Some(remaining) = interval.checked_sub(start.elapsed()) {
             thread::sleep(remaining);
         }
     }
+    Ok(())
 }
 
 pub fn ping(
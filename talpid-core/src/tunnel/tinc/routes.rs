@@ -0,0 +1,104 @@
+use crate::routing::{self, NetNode, Node, RouteManager};
+use futures::{future::Future, sync::oneshot};
+use ipnetwork::IpNetwork;
+use std::{collections::HashMap, io, net::IpAddr, sync::mpsc, thread};
+use tokio_core::reactor::{Core, Remote};
+use tokio_executor::{Executor, SpawnError};
+
+/// Routes of a tinc client. The real addresses of all proxies are routed through the default
+/// route, and everything else through the active proxy on the tinc interface.
+///
+/// The route managers run on a reactor of their own, so that the active proxy can be switched
+/// from the thread handling the tinc events.
+pub struct ProxyRoutes {
+    interface_name:     String,
+    proxies:            Vec<IpAddr>,
+    remote:             Remote,
+    route_manager:      Option<RouteManager>,
+    // Stops the reactor when dropped.
+    _stop_tx:           oneshot::Sender<()>,
+}
+
+impl ProxyRoutes {
+    /// Starts the reactor of the route managers, without applying any routes.
+    pub fn new(interface_name: &str, proxies: Vec<IpAddr>) -> io::Result<Self> {
+        let (remote_tx, remote_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        thread::spawn(move || {
+            let mut core = match Core::new() {
+                Ok(core) => core,
+                Err(e) => {
+                    let _ = remote_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = remote_tx.send(Ok(core.remote()));
+            let _ = core.run(stop_rx);
+        });
+        let remote = remote_rx.recv().map_err(|_| {
+            io::Error::new(io::ErrorKind::Other, "Routing reactor stopped unexpectedly")
+        })??;
+
+        Ok(ProxyRoutes {
+            interface_name: interface_name.to_string(),
+            proxies,
+            remote,
+            route_manager: None,
+            _stop_tx: stop_tx,
+        })
+    }
+
+    /// Replaces the applied routes with routes through the proxy at `gateway`.
+    pub fn route_through(&mut self, gateway: IpAddr) -> Result<(), routing::Error> {
+        self.stop();
+        let route_manager = RouteManager::new(
+            self.get_routes(gateway),
+            &mut RemoteExecutor(self.remote.clone()),
+        )?;
+        self.route_manager = Some(route_manager);
+        Ok(())
+    }
+
+    /// Removes the applied routes.
+    pub fn stop(&mut self) {
+        if let Some(mut route_manager) = self.route_manager.take() {
+            route_manager.stop();
+        }
+    }
+
+    fn get_routes(&self, gateway: IpAddr) -> HashMap<IpNetwork, NetNode> {
+        let mut routes: HashMap<_, _> = self.proxies
+            .iter()
+            .map(|proxy| (IpNetwork::from(*proxy), NetNode::DefaultNode))
+            .collect();
+        routes.insert(
+            IpNetwork::from(gateway),
+            Node::device(self.interface_name.clone()).into(),
+        );
+        let node = Node::new(gateway, self.interface_name.clone());
+        for network in &["0.0.0.0/1", "128.0.0.0/1"] {
+            routes.insert(network.parse().unwrap(), node.clone().into());
+        }
+        routes
+    }
+}
+
+impl Drop for ProxyRoutes {
+    fn drop(&mut self) {
+        // The reactor has to outlive the route manager, which needs it to remove its routes.
+        self.stop();
+    }
+}
+
+/// Spawns futures on a reactor running on another thread.
+struct RemoteExecutor(Remote);
+
+impl Executor for RemoteExecutor {
+    fn spawn(
+        &mut self,
+        future: Box<dyn Future<Item = (), Error = ()> + Send>,
+    ) -> Result<(), SpawnError> {
+        self.0.spawn(move |_| future);
+        Ok(())
+    }
+}
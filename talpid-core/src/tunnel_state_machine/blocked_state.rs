@@ -15,6 +15,8 @@ impl BlockedState {
     fn set_firewall_policy(shared_values: &mut SharedTunnelStateValues) -> Option<BlockReason> {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
+            allowed_rules: shared_values.allowed_rules.clone(),
+            dns_forwarder_upstreams: shared_values.dns_forwarder_upstreams(),
         };
 //      modify by YanBowen
 //        match shared_values.firewall.apply_policy(policy) {
@@ -63,6 +65,25 @@ impl TunnelState for BlockedState {
                 Self::set_firewall_policy(shared_values);
                 SameState(self)
             }
+            Ok(TunnelCommand::AllowedRules(allowed_rules)) => {
+                shared_values.allowed_rules = allowed_rules;
+                Self::set_firewall_policy(shared_values);
+                SameState(self)
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                SameState(self)
+            }
+            Ok(TunnelCommand::DnsUpstreams(dns_upstreams)) => {
+                if let Err(error) = shared_values.set_dns_upstreams(dns_upstreams) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Unable to point DNS at the DNS forwarder")
+                    );
+                }
+                Self::set_firewall_policy(shared_values);
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
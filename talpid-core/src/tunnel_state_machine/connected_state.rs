@@ -8,6 +8,7 @@ use super::{
     SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
+    dns::forwarder,
     firewall::FirewallPolicy,
     tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
 };
@@ -16,7 +17,10 @@ use futures::{
     Async, Future, Stream,
 };
 use talpid_types::{
-    net::{Endpoint, TunnelParameters},
+    net::{
+        dns::{self, CustomDnsServer, DnsRoute},
+        Endpoint, TunnelParameters,
+    },
     tunnel::BlockReason,
     ErrorExt,
 };
@@ -49,21 +53,70 @@ impl ConnectedState {
         }
     }
 
+    fn into_bootstrap(self) -> ConnectedStateBootstrap {
+        ConnectedStateBootstrap {
+            metadata: self.metadata,
+            tunnel_events: self.tunnel_events,
+            tunnel_parameters: self.tunnel_parameters,
+            tunnel_close_event: self.tunnel_close_event,
+            close_handle: self.close_handle,
+        }
+    }
+
     fn set_firewall_policy(
         &self,
         shared_values: &mut SharedTunnelStateValues,
+    ) -> Result<(), crate::firewall::Error> {
+        let dns_servers = self.custom_dns_servers(shared_values);
+        self.apply_firewall_policy(shared_values, dns_servers)
+    }
+
+    fn apply_firewall_policy(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+        dns_servers: Vec<CustomDnsServer>,
     ) -> Result<(), crate::firewall::Error> {
         // If a proxy is specified we need to pass it on as the peer endpoint.
         let peer_endpoint = self.get_endpoint_from_params();
 
         let policy = FirewallPolicy::Connected {
             peer_endpoint,
+            proxy_endpoints: self.proxy_endpoints(peer_endpoint),
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            allowed_rules: shared_values.allowed_rules.clone(),
+            dns_servers,
         };
-        // modify by YanBowen
-//        shared_values.firewall.apply_policy(policy)
-        Ok(())
+        shared_values.firewall.apply_policy(policy)
+    }
+
+    /// Endpoints of the proxies other than `peer_endpoint`, which tincd keeps its connections to
+    /// so that it can switch over to them.
+    fn proxy_endpoints(&self, peer_endpoint: Endpoint) -> Vec<Endpoint> {
+        match self.tunnel_parameters {
+            TunnelParameters::Tinc(ref params) => params
+                .config
+                .tinc_info
+                .connect_to
+                .iter()
+                .filter(|proxy| proxy.ip != peer_endpoint.address.ip())
+                .map(|proxy| {
+                    Endpoint::new(proxy.ip, peer_endpoint.address.port(), peer_endpoint.protocol)
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The connecting and disconnected states don't apply firewall policies of their own, so
+    /// the policy of this state is lifted as it is left for them.
+    fn reset_firewall_policy(shared_values: &mut SharedTunnelStateValues) {
+        if let Err(error) = shared_values.firewall.reset_policy() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to reset firewall policy")
+            );
+        }
     }
 
     fn get_endpoint_from_params(&self) -> Endpoint {
@@ -79,27 +132,103 @@ impl ConnectedState {
         }
     }
 
+    /// Returns the configured custom DNS servers that can be used with this tunnel.
+    fn custom_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<CustomDnsServer> {
+        shared_values
+            .custom_dns
+            .iter()
+            .filter(|server| dns::validate_custom_dns_server(server).is_ok())
+            .filter(|server| {
+                server.route == DnsRoute::Lan
+                    || server.address.is_ipv4()
+                    || self.metadata.ipv6_gateway.is_some()
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Points the system DNS at the local DNS forwarder if it is running, or else at the custom
+    /// resolvers, falling back to the tunnel gateway when none of them can be used. DNS is left
+    /// untouched if neither is configured.
     fn set_dns(
         &self,
         shared_values: &mut SharedTunnelStateValues,
     ) -> Result<(), crate::dns::Error> {
-//        modify by YanBowen
-//        let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
-//        if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-//            dns_ips.push(ipv6_gateway.into());
-//        };
-//
-//        shared_values
-//            .dns_monitor
-//            .set(&self.metadata.interface, &dns_ips)
-        Ok(())
+        if shared_values.dns_forwarder.is_some() {
+            match shared_values
+                .dns_monitor
+                .set(&self.metadata.interface, &[forwarder::LISTEN_IP])
+            {
+                Ok(()) => return Ok(()),
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to point DNS at the DNS forwarder")
+                ),
+            }
+            return self.set_gateway_dns(shared_values);
+        }
+        if shared_values.custom_dns.is_empty() {
+            return Ok(());
+        }
+
+        let custom_dns_ips = self
+            .custom_dns_servers(shared_values)
+            .iter()
+            .map(|server| server.address)
+            .collect::<Vec<_>>();
+        if custom_dns_ips.len() < shared_values.custom_dns.len() {
+            log::warn!(
+                "Ignoring {} custom DNS servers that can't be used with this tunnel",
+                shared_values.custom_dns.len() - custom_dns_ips.len()
+            );
+        }
+        if !custom_dns_ips.is_empty() {
+            match shared_values
+                .dns_monitor
+                .set(&self.metadata.interface, &custom_dns_ips)
+            {
+                Ok(()) => return Ok(()),
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set custom DNS servers")
+                ),
+            }
+        }
+
+        self.set_gateway_dns(shared_values)
+    }
+
+    fn set_gateway_dns(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> Result<(), crate::dns::Error> {
+        log::warn!("Falling back to the tunnel gateway for DNS");
+        // The firewall may only let DNS through to the custom resolvers, which replace the
+        // gateway on some platforms.
+        if !self.custom_dns_servers(shared_values).is_empty() {
+            if let Err(error) = self.apply_firewall_policy(shared_values, Vec::new()) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to allow DNS to the tunnel gateway")
+                );
+            }
+        }
+        let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
+        if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
+            dns_ips.push(ipv6_gateway.into());
+        };
+
+        shared_values
+            .dns_monitor
+            .set(&self.metadata.interface, &dns_ips)
     }
 
+    /// Failing to point DNS at the DNS forwarder outside the tunnel blocks the connecting state
+    /// that follows.
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
-//        modify by YanBowen
-//        if let Err(error) = shared_values.dns_monitor.reset() {
-//            log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
-//        }
+        if let Err(error) = shared_values.reset_dns() {
+            log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
+        }
     }
 
     fn disconnect(
@@ -108,12 +237,69 @@ impl ConnectedState {
         after_disconnect: AfterDisconnect,
     ) -> EventConsequence<Self> {
         Self::reset_dns(shared_values);
+        if let AfterDisconnect::Block(_) = after_disconnect {
+            // Keep traffic confined to the tunnel until the blocked state is left.
+        } else {
+            Self::reset_firewall_policy(shared_values);
+        }
         EventConsequence::NewState(DisconnectingState::enter(
             shared_values,
             (self.close_handle, self.tunnel_close_event, after_disconnect),
         ))
     }
 
+    fn update_firewall_policy(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        use self::EventConsequence::*;
+
+        match self.set_firewall_policy(shared_values) {
+            Ok(()) => SameState(self),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to apply firewall policy for connected state"
+                    )
+                );
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                )
+            }
+        }
+    }
+
+    fn update_dns(self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence<Self> {
+        use self::EventConsequence::*;
+
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to apply firewall policy for connected state")
+            );
+            self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+            )
+        } else if shared_values.custom_dns.is_empty() && shared_values.dns_forwarder.is_none() {
+            Self::reset_dns(shared_values);
+            SameState(self)
+        } else if let Err(error) = self.set_dns(shared_values) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set system DNS settings")
+            );
+            self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(BlockReason::SetDnsError),
+            )
+        } else {
+            SameState(self)
+        }
+    }
+
     fn handle_commands(
         self,
         commands: &mut mpsc::UnboundedReceiver<TunnelCommand>,
@@ -124,22 +310,19 @@ impl ConnectedState {
         match try_handle_event!(self, commands.poll()) {
             Ok(TunnelCommand::AllowLan(allow_lan)) => {
                 shared_values.allow_lan = allow_lan;
-
-                match self.set_firewall_policy(shared_values) {
-                    Ok(()) => SameState(self),
-                    Err(error) => {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg(
-                                "Failed to apply firewall policy for connected state"
-                            )
-                        );
-                        self.disconnect(
-                            shared_values,
-                            AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
-                        )
-                    }
-                }
+                self.update_firewall_policy(shared_values)
+            }
+            Ok(TunnelCommand::AllowedRules(allowed_rules)) => {
+                shared_values.allowed_rules = allowed_rules;
+                self.update_firewall_policy(shared_values)
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                self.update_dns(shared_values)
+            }
+            Ok(TunnelCommand::DnsUpstreams(dns_upstreams)) => {
+                shared_values.restart_dns_forwarder(dns_upstreams);
+                self.update_dns(shared_values)
             }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
@@ -178,6 +361,15 @@ impl ConnectedState {
             Ok(TunnelEvent::Down) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Ok(TunnelEvent::Reconnected(metadata, endpoint)) => {
+                log::info!("Tunnel switched over to {}", endpoint);
+                self.metadata = metadata;
+                if let TunnelParameters::Tinc(ref mut params) = self.tunnel_parameters {
+                    params.config.endpoint = endpoint;
+                }
+                // Entering again applies the firewall policy of the new endpoint.
+                NewState(ConnectedState::enter(shared_values, self.into_bootstrap()))
+            }
             Err(_) => {
 //                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
                 SameState(self)
@@ -204,6 +396,7 @@ impl ConnectedState {
 
         log::info!("Tunnel closed. Reconnecting.");
         Self::reset_dns(shared_values);
+        Self::reset_firewall_policy(shared_values);
         NewState(ConnectingState::enter(shared_values, 0))
     }
 }
@@ -219,35 +412,33 @@ impl TunnelState for ConnectedState {
         let connected_state = ConnectedState::from(bootstrap);
         let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
 
-        // modify by YanBowen
-//        if let Err(error) = connected_state.set_firewall_policy(shared_values) {
-//            log::error!(
-//                "{}",
-//                error.display_chain_with_msg("Failed to apply firewall policy for connected state")
-//            );
-//            DisconnectingState::enter(
-//                shared_values,
-//                (
-//                    connected_state.close_handle,
-//                    connected_state.tunnel_close_event,
-//                    AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
-//                ),
-//            )
-//        } else if let Err(error) = connected_state.set_dns(shared_values) {
-//            log::error!(
-//                "{}",
-//                error.display_chain_with_msg("Failed to set system DNS settings")
-//            );
-//            DisconnectingState::enter(
-//                shared_values,
-//                (
-//                    connected_state.close_handle,
-//                    connected_state.tunnel_close_event,
-//                    AfterDisconnect::Block(BlockReason::SetDnsError),
-//                ),
-//            )
-//        } else {
-        {
+        if let Err(error) = connected_state.set_firewall_policy(shared_values) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to apply firewall policy for connected state")
+            );
+            DisconnectingState::enter(
+                shared_values,
+                (
+                    connected_state.close_handle,
+                    connected_state.tunnel_close_event,
+                    AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                ),
+            )
+        } else if let Err(error) = connected_state.set_dns(shared_values) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set system DNS settings")
+            );
+            DisconnectingState::enter(
+                shared_values,
+                (
+                    connected_state.close_handle,
+                    connected_state.tunnel_close_event,
+                    AfterDisconnect::Block(BlockReason::SetDnsError),
+                ),
+            )
+        } else {
             (
                 TunnelStateWrapper::from(connected_state),
                 TunnelStateTransition::Connected(tunnel_endpoint),
@@ -18,6 +18,7 @@ use std::{
     borrow::Borrow,
     net::IpAddr,
     path::{Path, PathBuf},
+    sync::Arc,
     thread,
     time::{Duration, Instant},
 };
@@ -26,6 +27,7 @@ use talpid_types::{
     tunnel::BlockReason,
     ErrorExt,
 };
+use tinc_plugin::TincOperator;
 
 
 const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
@@ -56,6 +58,8 @@ impl ConnectingState {
             peer_endpoint,
             pingable_hosts: gateway_list_from_params(params),
             allow_lan: shared_values.allow_lan,
+            allowed_rules: shared_values.allowed_rules.clone(),
+            dns_forwarder_upstreams: shared_values.dns_forwarder_upstreams(),
         };
 //        modify by YanBowen
 //        shared_values.firewall.apply_policy(policy)
@@ -67,6 +71,7 @@ impl ConnectingState {
         parameters: TunnelParameters,
         log_dir: &Option<PathBuf>,
         resource_dir: &Path,
+        tinc: &Arc<TincOperator>,
         tun_provider: &dyn TunProvider,
         retry_attempt: u32,
     ) -> crate::tunnel::Result<Self> {
@@ -78,6 +83,7 @@ impl ConnectingState {
             &parameters,
             log_dir,
             resource_dir,
+            tinc,
             on_tunnel_event,
             tun_provider,
         )?;
@@ -157,6 +163,34 @@ impl ConnectingState {
         }
     }
 
+    fn update_firewall_policy(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        use self::EventConsequence::*;
+
+        match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
+            Ok(()) => SameState(self),
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to apply firewall policy for connecting state"
+                    )
+                );
+
+                NewState(DisconnectingState::enter(
+                    shared_values,
+                    (
+                        self.close_handle,
+                        self.tunnel_close_event,
+                        AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                    ),
+                ))
+            }
+        }
+    }
+
     fn handle_commands(
         self,
         commands: &mut mpsc::UnboundedReceiver<TunnelCommand>,
@@ -167,22 +201,30 @@ impl ConnectingState {
         match try_handle_event!(self, commands.poll()) {
             Ok(TunnelCommand::AllowLan(allow_lan)) => {
                 shared_values.allow_lan = allow_lan;
-                match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
-                    Ok(()) => SameState(self),
+                self.update_firewall_policy(shared_values)
+            }
+            Ok(TunnelCommand::AllowedRules(allowed_rules)) => {
+                shared_values.allowed_rules = allowed_rules;
+                self.update_firewall_policy(shared_values)
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                SameState(self)
+            }
+            Ok(TunnelCommand::DnsUpstreams(dns_upstreams)) => {
+                match shared_values.set_dns_upstreams(dns_upstreams) {
+                    Ok(()) => self.update_firewall_policy(shared_values),
                     Err(error) => {
                         error!(
                             "{}",
-                            error.display_chain_with_msg(
-                                "Failed to apply firewall policy for connecting state"
-                            )
+                            error.display_chain_with_msg("Unable to point DNS at the DNS forwarder")
                         );
-
                         NewState(DisconnectingState::enter(
                             shared_values,
                             (
                                 self.close_handle,
                                 self.tunnel_close_event,
-                                AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                                AfterDisconnect::Block(BlockReason::SetDnsError),
                             ),
                         ))
                     }
@@ -254,6 +296,30 @@ impl ConnectingState {
                 shared_values,
                 self.into_connected_state_bootstrap(metadata),
             )),
+            Ok(TunnelEvent::Reconnected(_, endpoint)) => {
+                if let TunnelParameters::Tinc(ref mut params) = self.tunnel_parameters {
+                    params.config.endpoint = endpoint;
+                }
+                match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
+                    Ok(()) => SameState(self),
+                    Err(error) => {
+                        error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to apply firewall policy for connecting state"
+                            )
+                        );
+                        NewState(DisconnectingState::enter(
+                            shared_values,
+                            (
+                                self.close_handle,
+                                self.tunnel_close_event,
+                                AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                            ),
+                        ))
+                    }
+                }
+            }
             Ok(_) => SameState(self),
             Err(_) => {
                 debug!("The OpenVPN tunnel event plugin disconnected");
@@ -313,6 +379,10 @@ impl TunnelState for ConnectingState {
         if shared_values.is_offline {
             return BlockedState::enter(shared_values, BlockReason::IsOffline);
         }
+        if shared_values.dns_forwarder.is_some() && !shared_values.dns_forwarded {
+            // DNS queries outside the tunnel would bypass the DNS forwarder.
+            return BlockedState::enter(shared_values, BlockReason::SetDnsError);
+        }
         match shared_values
             .tunnel_parameters_generator
             .generate(retry_attempt)
@@ -332,6 +402,7 @@ impl TunnelState for ConnectingState {
                         tunnel_parameters,
                         &shared_values.log_dir,
                         &shared_values.resource_dir,
+                        &shared_values.tinc,
                         shared_values.tun_provider.borrow(),
                         retry_attempt,
                     ) {
@@ -16,6 +16,8 @@ impl DisconnectedState {
         let result = if false {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                allowed_rules: shared_values.allowed_rules.clone(),
+                dns_forwarder_upstreams: shared_values.dns_forwarder_upstreams(),
             };
             shared_values.firewall.apply_policy(policy).map_err(|e| {
                 e.display_chain_with_msg(
@@ -63,6 +65,31 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self)
             }
+            Ok(TunnelCommand::AllowedRules(allowed_rules)) => {
+                if shared_values.allowed_rules != allowed_rules {
+                    shared_values.allowed_rules = allowed_rules;
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                SameState(self)
+            }
+            Ok(TunnelCommand::DnsUpstreams(dns_upstreams)) => {
+                if shared_values.dns_upstreams != dns_upstreams {
+                    if let Err(error) = shared_values.set_dns_upstreams(dns_upstreams) {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Unable to point DNS at the DNS forwarder, connecting will block"
+                            )
+                        );
+                    }
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 if shared_values.block_when_disconnected != block_when_disconnected {
                     shared_values.block_when_disconnected = block_when_disconnected;
@@ -79,7 +106,13 @@ impl TunnelState for DisconnectedState {
                 NewState(BlockedState::enter(shared_values, reason))
             }
             Ok(_) => SameState(self),
-            Err(_) => Finished,
+            Err(_) => {
+                // Stop the DNS forwarder, and point the system DNS away from it.
+                if let Err(error) = shared_values.set_dns_upstreams(Vec::new()) {
+                    log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
+                }
+                Finished
+            }
         }
     }
 }
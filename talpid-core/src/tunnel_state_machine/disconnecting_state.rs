@@ -9,6 +9,7 @@ use futures::{
 };
 use std::thread;
 use talpid_types::{
+    net::dns::DnsUpstream,
     tunnel::{ActionAfterDisconnect, BlockReason},
     ErrorExt,
 };
@@ -35,6 +36,18 @@ impl DisconnectingState {
                     shared_values.allow_lan = allow_lan;
                     AfterDisconnect::Nothing
                 }
+                Ok(TunnelCommand::AllowedRules(allowed_rules)) => {
+                    shared_values.allowed_rules = allowed_rules;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::CustomDns(servers)) => {
+                    shared_values.custom_dns = servers;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::DnsUpstreams(dns_upstreams)) => {
+                    Self::set_dns_upstreams(shared_values, dns_upstreams);
+                    AfterDisconnect::Nothing
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
@@ -52,6 +65,18 @@ impl DisconnectingState {
                     shared_values.allow_lan = allow_lan;
                     AfterDisconnect::Block(reason)
                 }
+                Ok(TunnelCommand::AllowedRules(allowed_rules)) => {
+                    shared_values.allowed_rules = allowed_rules;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::CustomDns(servers)) => {
+                    shared_values.custom_dns = servers;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::DnsUpstreams(dns_upstreams)) => {
+                    Self::set_dns_upstreams(shared_values, dns_upstreams);
+                    AfterDisconnect::Block(reason)
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
@@ -74,6 +99,18 @@ impl DisconnectingState {
                     shared_values.allow_lan = allow_lan;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Ok(TunnelCommand::AllowedRules(allowed_rules)) => {
+                    shared_values.allowed_rules = allowed_rules;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::CustomDns(servers)) => {
+                    shared_values.custom_dns = servers;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::DnsUpstreams(dns_upstreams)) => {
+                    Self::set_dns_upstreams(shared_values, dns_upstreams);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Reconnect(retry_attempt)
@@ -95,6 +132,20 @@ impl DisconnectingState {
         EventConsequence::SameState(self)
     }
 
+    /// A failure to point DNS at the DNS forwarder blocks the connecting state, if that is what
+    /// follows.
+    fn set_dns_upstreams(
+        shared_values: &mut SharedTunnelStateValues,
+        dns_upstreams: Vec<DnsUpstream>,
+    ) {
+        if let Err(error) = shared_values.set_dns_upstreams(dns_upstreams) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Unable to point DNS at the DNS forwarder")
+            );
+        }
+    }
+
     fn handle_exit_event(
         mut self,
         shared_values: &mut SharedTunnelStateValues,
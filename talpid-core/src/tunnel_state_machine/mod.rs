@@ -15,7 +15,11 @@ use self::{
     disconnecting_state::{AfterDisconnect, DisconnectingState},
 };
 use crate::{
-    dns::DnsMonitor,
+    dns::{
+        self,
+        forwarder::{self, DnsForwarder},
+        DnsMonitor,
+    },
     firewall::{Firewall, FirewallArguments},
     mpsc::IntoSender,
     offline,
@@ -25,14 +29,19 @@ use futures::{sync::mpsc, Async, Future, Poll, Stream};
 use std::{
     io,
     path::{Path, PathBuf},
-    sync::mpsc as sync_mpsc,
+    sync::{mpsc as sync_mpsc, Arc},
     thread,
 };
 use talpid_types::{
-    net::TunnelParameters,
+    net::{
+        dns::{CustomDnsServer, DnsUpstream},
+        firewall::AllowRule,
+        Endpoint, TransportProtocol, TunnelParameters,
+    },
     tunnel::{BlockReason, TunnelStateTransition},
     ErrorExt,
 };
+use tinc_plugin::TincOperator;
 use tokio_core::reactor::Core;
 
 /// Errors that can happen when setting up or using the state machine.
@@ -62,11 +71,15 @@ pub enum Error {
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
 pub fn spawn<P, T>(
     allow_lan: bool,
+    allowed_rules: Vec<AllowRule>,
+    custom_dns: Vec<CustomDnsServer>,
+    dns_upstreams: Vec<DnsUpstream>,
     block_when_disconnected: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     tun_provider: impl TunProvider,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
+    tinc: Arc<TincOperator>,
     cache_dir: P,
     state_change_listener: IntoSender<TunnelStateTransition, T>,
 ) -> Result<mpsc::UnboundedSender<TunnelCommand>, Error>
@@ -83,12 +96,16 @@ where
     thread::spawn(move || {
         match create_event_loop(
             allow_lan,
+            allowed_rules,
+            custom_dns,
+            dns_upstreams,
             block_when_disconnected,
             is_offline,
             tunnel_parameters_generator,
             tun_provider,
             log_dir,
             resource_dir,
+            tinc,
             cache_dir,
             command_rx,
             state_change_listener,
@@ -122,12 +139,16 @@ where
 
 fn create_event_loop<T>(
     allow_lan: bool,
+    allowed_rules: Vec<AllowRule>,
+    custom_dns: Vec<CustomDnsServer>,
+    dns_upstreams: Vec<DnsUpstream>,
     block_when_disconnected: bool,
     is_offline: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     tun_provider: impl TunProvider,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
+    tinc: Arc<TincOperator>,
     cache_dir: impl AsRef<Path>,
     commands: mpsc::UnboundedReceiver<TunnelCommand>,
     state_change_listener: IntoSender<TunnelStateTransition, T>,
@@ -138,12 +159,16 @@ where
     let reactor = Core::new().map_err(Error::ReactorError)?;
     let state_machine = TunnelStateMachine::new(
         allow_lan,
+        allowed_rules,
+        custom_dns,
+        dns_upstreams,
         block_when_disconnected,
         is_offline,
         tunnel_parameters_generator,
         tun_provider,
         log_dir,
         resource_dir,
+        tinc,
         cache_dir,
         commands,
     )?;
@@ -161,6 +186,12 @@ where
 pub enum TunnelCommand {
     /// Enable or disable LAN access in the firewall.
     AllowLan(bool),
+    /// Replace the user-defined rules allowing traffic through the firewall.
+    AllowedRules(Vec<AllowRule>),
+    /// Replace the DNS resolvers used instead of the tunnel gateway.
+    CustomDns(Vec<CustomDnsServer>),
+    /// Replace the encrypted resolvers of the local DNS forwarder.
+    DnsUpstreams(Vec<DnsUpstream>),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
     /// Notify the state machine of the connectivity of the device.
@@ -188,12 +219,16 @@ struct TunnelStateMachine {
 impl TunnelStateMachine {
     fn new(
         allow_lan: bool,
+        allowed_rules: Vec<AllowRule>,
+        custom_dns: Vec<CustomDnsServer>,
+        dns_upstreams: Vec<DnsUpstream>,
         block_when_disconnected: bool,
         is_offline: bool,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
         tun_provider: impl TunProvider,
         log_dir: Option<PathBuf>,
         resource_dir: PathBuf,
+        tinc: Arc<TincOperator>,
         cache_dir: impl AsRef<Path>,
         commands: mpsc::UnboundedReceiver<TunnelCommand>,
     ) -> Result<Self, Error> {
@@ -210,18 +245,32 @@ impl TunnelStateMachine {
         };
         let firewall = Firewall::new(args).map_err(Error::InitFirewallError)?;
         let dns_monitor = DnsMonitor::new(cache_dir).map_err(Error::InitDnsMonitorError)?;
+        let dns_forwarder = start_dns_forwarder(&dns_upstreams);
         let mut shared_values = SharedTunnelStateValues {
             firewall,
             dns_monitor,
             allow_lan,
+            allowed_rules,
+            custom_dns,
+            dns_upstreams,
+            dns_forwarder,
+            dns_forwarded: false,
             block_when_disconnected,
             is_offline,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
             tun_provider: Box::new(tun_provider),
             log_dir,
             resource_dir,
+            tinc,
         };
 
+        if let Err(error) = shared_values.reset_dns() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Unable to point DNS at the DNS forwarder")
+            );
+        }
+
         let (initial_state, _) = DisconnectedState::enter(&mut shared_values, ());
         Ok(TunnelStateMachine {
             current_state: Some(initial_state),
@@ -294,6 +343,16 @@ struct SharedTunnelStateValues {
     dns_monitor: DnsMonitor,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
+    /// User-defined rules allowing traffic outside the tunnel.
+    allowed_rules: Vec<AllowRule>,
+    /// DNS resolvers used instead of the tunnel gateway while connected.
+    custom_dns: Vec<CustomDnsServer>,
+    /// Encrypted resolvers the local DNS forwarder sends queries to.
+    dns_upstreams: Vec<DnsUpstream>,
+    /// Forwards DNS queries to `dns_upstreams`. Running as long as there are any.
+    dns_forwarder: Option<DnsForwarder>,
+    /// True when the system DNS points at the DNS forwarder outside the tunnel.
+    dns_forwarded: bool,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
     /// True when the computer is known to be offline.
@@ -306,6 +365,78 @@ struct SharedTunnelStateValues {
     log_dir: Option<PathBuf>,
     /// Resource directory path.
     resource_dir: PathBuf,
+    /// Operator of the tinc network, shared with the daemon.
+    tinc: Arc<TincOperator>,
+}
+
+impl SharedTunnelStateValues {
+    /// Restarts the DNS forwarder with new upstreams, and resets DNS with `reset_dns`.
+    fn set_dns_upstreams(&mut self, dns_upstreams: Vec<DnsUpstream>) -> Result<(), dns::Error> {
+        self.restart_dns_forwarder(dns_upstreams);
+        self.reset_dns()
+    }
+
+    /// Restarts the DNS forwarder with new upstreams, leaving DNS as it is.
+    fn restart_dns_forwarder(&mut self, dns_upstreams: Vec<DnsUpstream>) {
+        // The running forwarder has to release its port before another one can listen on it.
+        self.dns_forwarder = None;
+        self.dns_forwarder = start_dns_forwarder(&dns_upstreams);
+        self.dns_upstreams = dns_upstreams;
+    }
+
+    /// Points the system DNS at the DNS forwarder if it is running, or else restores the DNS
+    /// settings of the system. The connected state points DNS at its own resolvers afterwards.
+    /// Fails where the DNS backend can't set DNS system-wide, leaving DNS to the system: the
+    /// forwarder is then only used inside the tunnel.
+    fn reset_dns(&mut self) -> Result<(), dns::Error> {
+        self.dns_forwarded = false;
+        if self.dns_forwarder.is_none() {
+            return self.dns_monitor.reset();
+        }
+        match self.dns_monitor.set_global(&[forwarder::LISTEN_IP]) {
+            Ok(()) => {
+                self.dns_forwarded = true;
+                Ok(())
+            }
+            Err(error) => {
+                if let Err(reset_error) = self.dns_monitor.reset() {
+                    log::error!("{}", reset_error.display_chain_with_msg("Unable to reset DNS"));
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the endpoints the DNS forwarder reaches outside the tunnel. Empty unless the
+    /// system DNS points at it.
+    fn dns_forwarder_upstreams(&self) -> Vec<Endpoint> {
+        if !self.dns_forwarded {
+            return vec![];
+        }
+        self.dns_upstreams
+            .iter()
+            .map(|upstream| Endpoint {
+                address: upstream.address,
+                protocol: TransportProtocol::Tcp,
+            })
+            .collect()
+    }
+}
+
+fn start_dns_forwarder(dns_upstreams: &[DnsUpstream]) -> Option<DnsForwarder> {
+    if dns_upstreams.is_empty() {
+        return None;
+    }
+    match DnsForwarder::start(dns_upstreams.to_vec()) {
+        Ok(forwarder) => Some(forwarder),
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to start the DNS forwarder")
+            );
+            None
+        }
+    }
 }
 
 /// Asynchronous result of an attempt to progress a state.
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+/// A DNS resolver used instead of the tunnel gateway while connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CustomDnsServer {
+    /// The address of the resolver.
+    pub address: IpAddr,
+    /// How the resolver is reached.
+    pub route: DnsRoute,
+}
+
+impl fmt::Display for CustomDnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} over the {}", self.address, self.route)
+    }
+}
+
+/// How a custom DNS resolver is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsRoute {
+    /// Through the tunnel, like any other traffic.
+    Tunnel,
+    /// Directly on the local network, outside the tunnel.
+    Lan,
+}
+
+impl FromStr for DnsRoute {
+    type Err = DnsRouteParseError;
+
+    fn from_str(s: &str) -> Result<DnsRoute, Self::Err> {
+        match s {
+            "tunnel" => Ok(DnsRoute::Tunnel),
+            "lan" => Ok(DnsRoute::Lan),
+            _ => Err(DnsRouteParseError),
+        }
+    }
+}
+
+impl fmt::Display for DnsRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DnsRoute::Tunnel => "tunnel".fmt(f),
+            DnsRoute::Lan => "LAN".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRouteParseError;
+
+impl fmt::Display for DnsRouteParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.description())
+    }
+}
+
+impl Error for DnsRouteParseError {
+    fn description(&self) -> &str {
+        "Not a valid DNS route, expected \"tunnel\" or \"lan\""
+    }
+}
+
+/// The default port of DNS over HTTPS resolvers.
+pub const DOH_PORT: u16 = 443;
+/// The default port of DNS over TLS resolvers.
+pub const DOT_PORT: u16 = 853;
+
+/// An encrypted DNS resolver that the local DNS forwarder sends queries to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DnsUpstream {
+    /// The address of the resolver. An IP address, so that no plaintext lookup is needed to reach
+    /// the resolver.
+    pub address: SocketAddr,
+    /// The name the certificate of the resolver is verified against.
+    pub hostname: String,
+    /// How queries are sent to the resolver.
+    pub protocol: DnsUpstreamProtocol,
+}
+
+impl fmt::Display for DnsUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.protocol {
+            DnsUpstreamProtocol::Https { ref path } => {
+                write!(f, "https://{}{} at {}", self.hostname, path, self.address)
+            }
+            DnsUpstreamProtocol::Tls => write!(f, "tls://{} at {}", self.hostname, self.address),
+        }
+    }
+}
+
+/// The protocol used to send queries to a `DnsUpstream`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsUpstreamProtocol {
+    /// DNS over HTTPS, as specified in RFC 8484. Queries are POSTed to `path`.
+    Https { path: String },
+    /// DNS over TLS, as specified in RFC 7858.
+    Tls,
+}
+
+pub fn validate_custom_dns_server(server: &CustomDnsServer) -> Result<(), String> {
+    let address = server.address;
+    if address.is_unspecified() || address.is_loopback() || address.is_multicast() {
+        return Err(format!("{} is not a valid DNS server address", address));
+    }
+    if server.route == DnsRoute::Lan && !is_local_address(address) {
+        return Err(format!(
+            "{} is not on a local network, it can only be reached over the tunnel",
+            address
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_dns_upstream(upstream: &DnsUpstream) -> Result<(), String> {
+    let address = upstream.address.ip();
+    if address.is_unspecified() || address.is_loopback() || address.is_multicast() {
+        return Err(format!("{} is not a valid DNS server address", address));
+    }
+    if upstream.address.port() == 0 {
+        return Err(String::from("Invalid port number"));
+    }
+    if upstream.hostname.is_empty() || upstream.hostname.contains(char::is_whitespace) {
+        return Err(format!("\"{}\" is not a valid hostname", upstream.hostname));
+    }
+    if let DnsUpstreamProtocol::Https { ref path } = upstream.protocol {
+        if !path.starts_with('/') || path.contains(char::is_whitespace) {
+            return Err(format!("\"{}\" is not a valid URL path", path));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if `address` is in one of the networks the firewall treats as local.
+fn is_local_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_local_ipv4(address),
+        IpAddr::V6(address) => is_link_local_ipv6(address),
+    }
+}
+
+fn is_local_ipv4(address: Ipv4Addr) -> bool {
+    address.is_private() || address.is_link_local()
+}
+
+fn is_link_local_ipv6(address: Ipv6Addr) -> bool {
+    address.segments()[0] & 0xffc0 == 0xfe80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(address: &str, route: DnsRoute) -> CustomDnsServer {
+        CustomDnsServer {
+            address: address.parse().unwrap(),
+            route,
+        }
+    }
+
+    #[test]
+    fn test_validate_custom_dns_server() {
+        for address in &["127.0.0.1", "::1", "0.0.0.0", "::", "224.0.0.251", "ff02::fb"] {
+            assert!(validate_custom_dns_server(&server(address, DnsRoute::Tunnel)).is_err());
+            assert!(validate_custom_dns_server(&server(address, DnsRoute::Lan)).is_err());
+        }
+
+        assert!(validate_custom_dns_server(&server("8.8.8.8", DnsRoute::Tunnel)).is_ok());
+        assert!(validate_custom_dns_server(&server("2001:4860::8888", DnsRoute::Tunnel)).is_ok());
+        assert!(validate_custom_dns_server(&server("192.168.1.1", DnsRoute::Tunnel)).is_ok());
+
+        assert!(validate_custom_dns_server(&server("192.168.1.1", DnsRoute::Lan)).is_ok());
+        assert!(validate_custom_dns_server(&server("fe80::1", DnsRoute::Lan)).is_ok());
+        assert!(validate_custom_dns_server(&server("8.8.8.8", DnsRoute::Lan)).is_err());
+        assert!(validate_custom_dns_server(&server("2001:4860::8888", DnsRoute::Lan)).is_err());
+    }
+
+    #[test]
+    fn test_is_local_address() {
+        for address in &["10.1.2.3", "172.16.0.1", "172.31.255.254", "192.168.0.1", "169.254.1.1"] {
+            assert!(is_local_address(address.parse().unwrap()), "{}", address);
+        }
+        for address in &["fe80::1", "febf::1"] {
+            assert!(is_local_address(address.parse().unwrap()), "{}", address);
+        }
+        for address in &["8.8.8.8", "172.32.0.1", "100.64.0.1", "fec0::1", "fd00::1", "2001::1"] {
+            assert!(!is_local_address(address.parse().unwrap()), "{}", address);
+        }
+    }
+}
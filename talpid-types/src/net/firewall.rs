@@ -0,0 +1,226 @@
+use crate::net::TransportProtocol;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, str::FromStr};
+
+/// A user-defined rule that allows traffic through the firewall, whatever the tunnel state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowRule {
+    /// The network of the hosts on the other end of the traffic.
+    pub network: IpNetwork,
+    /// The transport protocol of the traffic. `None` allows any protocol.
+    pub protocol: Option<TransportProtocol>,
+    /// The destination ports of the connections, i.e. ports of `network` for outgoing
+    /// connections and local ports for incoming ones. `None` allows any port. Requires a
+    /// `protocol`.
+    pub ports: Option<PortRange>,
+    /// The direction the allowed connections are initiated in. Replies are always allowed.
+    pub direction: Direction,
+}
+
+impl fmt::Display for AllowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let preposition = match self.direction {
+            Direction::In => "from",
+            Direction::Out => "to",
+            Direction::Both => "to and from",
+        };
+        write!(f, "{} {} {}", self.direction, preposition, self.network)?;
+        if let Some(protocol) = self.protocol {
+            write!(f, " over {}", protocol)?;
+        }
+        if let Some(ports) = self.ports {
+            write!(f, " on port {}", ports)?;
+        }
+        Ok(())
+    }
+}
+
+/// An inclusive range of ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl FromStr for PortRange {
+    type Err = PortRangeParseError;
+
+    /// Parses a single port, like `22`, or a range, like `8000-8100`.
+    fn from_str(s: &str) -> Result<PortRange, Self::Err> {
+        let mut parts = s.splitn(2, '-');
+        let start = parts
+            .next()
+            .and_then(|start| start.trim().parse().ok())
+            .ok_or(PortRangeParseError)?;
+        let end = match parts.next() {
+            Some(end) => end.trim().parse().map_err(|_| PortRangeParseError)?,
+            None => start,
+        };
+        Ok(PortRange { start, end })
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRangeParseError;
+
+impl fmt::Display for PortRangeParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.description())
+    }
+}
+
+impl Error for PortRangeParseError {
+    fn description(&self) -> &str {
+        "Not a valid port or port range"
+    }
+}
+
+/// The direction connections allowed by an `AllowRule` are initiated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Connections initiated by hosts in the network of the rule.
+    In,
+    /// Connections initiated by this host.
+    Out,
+    /// Connections initiated by either end.
+    Both,
+}
+
+impl Direction {
+    /// Returns true if the rule allows incoming connections.
+    pub fn allows_in(self) -> bool {
+        self != Direction::Out
+    }
+
+    /// Returns true if the rule allows outgoing connections.
+    pub fn allows_out(self) -> bool {
+        self != Direction::In
+    }
+}
+
+impl FromStr for Direction {
+    type Err = DirectionParseError;
+
+    fn from_str(s: &str) -> Result<Direction, Self::Err> {
+        match s {
+            "in" => Ok(Direction::In),
+            "out" => Ok(Direction::Out),
+            "both" => Ok(Direction::Both),
+            _ => Err(DirectionParseError),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Direction::In => "Incoming".fmt(f),
+            Direction::Out => "Outgoing".fmt(f),
+            Direction::Both => "Incoming and outgoing".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectionParseError;
+
+impl fmt::Display for DirectionParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.description())
+    }
+}
+
+impl Error for DirectionParseError {
+    fn description(&self) -> &str {
+        "Not a valid direction"
+    }
+}
+
+pub fn validate_allow_rule(rule: &AllowRule) -> Result<(), String> {
+    if let Some(ports) = rule.ports {
+        if rule.protocol.is_none() {
+            return Err(String::from("A port range requires a protocol"));
+        }
+        if ports.start == 0 {
+            return Err(String::from("Invalid port number"));
+        }
+        if ports.start > ports.end {
+            return Err(String::from("The port range ends before it starts"));
+        }
+    }
+    if rule.network.ip() != rule.network.network() {
+        return Err(format!(
+            "{} has host bits set, did you mean {}/{}?",
+            rule.network,
+            rule.network.network(),
+            rule.network.prefix()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(network: &str, ports: Option<&str>, direction: Direction) -> AllowRule {
+        AllowRule {
+            network: network.parse().unwrap(),
+            protocol: Some(TransportProtocol::Tcp),
+            ports: ports.map(|ports| ports.parse().unwrap()),
+            direction,
+        }
+    }
+
+    #[test]
+    fn test_validate_allow_rule() {
+        assert!(validate_allow_rule(&rule("10.0.0.0/8", None, Direction::Both)).is_ok());
+        assert!(validate_allow_rule(&rule("10.0.0.1/32", Some("22"), Direction::In)).is_ok());
+        assert!(validate_allow_rule(&rule("fd00::/64", Some("8000-8100"), Direction::Out)).is_ok());
+        assert!(validate_allow_rule(&rule("10.0.0.0/8", Some("1-65535"), Direction::Out)).is_ok());
+
+        let backwards = rule("10.0.0.0/8", Some("8100-8000"), Direction::Out);
+        assert!(validate_allow_rule(&backwards).is_err());
+        assert!(validate_allow_rule(&rule("10.0.0.0/8", Some("0-22"), Direction::Out)).is_err());
+        assert!(validate_allow_rule(&rule("10.0.0.1/8", None, Direction::In)).is_err());
+
+        let mut any_protocol = rule("10.0.0.0/8", Some("22"), Direction::In);
+        any_protocol.protocol = None;
+        assert!(validate_allow_rule(&any_protocol).is_err());
+        any_protocol.ports = None;
+        assert!(validate_allow_rule(&any_protocol).is_ok());
+    }
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!("22".parse(), Ok(PortRange { start: 22, end: 22 }));
+        assert_eq!(" 8000 - 8100 ".parse(), Ok(PortRange { start: 8000, end: 8100 }));
+        assert_eq!("".parse::<PortRange>(), Err(PortRangeParseError));
+        assert_eq!("22-".parse::<PortRange>(), Err(PortRangeParseError));
+        assert_eq!("65536".parse::<PortRange>(), Err(PortRangeParseError));
+        assert_eq!(PortRange { start: 8000, end: 8100 }.to_string(), "8000-8100");
+    }
+
+    #[test]
+    fn test_direction() {
+        assert_eq!("in".parse(), Ok(Direction::In));
+        assert_eq!("out".parse(), Ok(Direction::Out));
+        assert_eq!("both".parse(), Ok(Direction::Both));
+        assert_eq!("In".parse::<Direction>(), Err(DirectionParseError));
+        assert!(Direction::In.allows_in() && !Direction::In.allows_out());
+        assert!(!Direction::Out.allows_in() && Direction::Out.allows_out());
+        assert!(Direction::Both.allows_in() && Direction::Both.allows_out());
+    }
+}
@@ -6,6 +6,8 @@ use std::{
     str::FromStr,
 };
 
+pub mod dns;
+pub mod firewall;
 pub mod openvpn;
 pub mod proxy;
 pub mod wireguard;
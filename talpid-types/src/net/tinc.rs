@@ -1,12 +1,14 @@
 use crate::net::{Endpoint, GenericTunnelOptions, TunnelEndpoint, TunnelType};
 use serde::{Deserialize, Serialize};
 
-pub use tinc_plugin::{TincInfo, ConnectTo};
+pub use tinc_plugin::{TincInfo, ConnectTo, KeyExchange, KeyType, PublicKeys};
+
+/// Tunables of the tinc client, written to its `tinc.conf` and host file.
+pub use tinc_plugin::TincOptions as TunnelOptions;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct TunnelParameters {
     pub config: ConnectionConfig,
-    // Empty. Reserved to tunnel command.
     pub options: TunnelOptions,
     // pub enable_ipv6: bool,
     pub generic_options: GenericTunnelOptions,
@@ -38,9 +40,6 @@ impl ConnectionConfig {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
-pub struct TunnelOptions {}
-
 //#[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
 //pub struct TincNode {
 //    pub ip:         String,
@@ -1,8 +1,10 @@
-use std::net::IpAddr;
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::io::{Error, ErrorKind, Result, Read};
-use std::io::Write;
+use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use tinc_plugin::listener::send;
+use tinc_plugin::{EventType, TincEvent};
 
 fn help() {
     let buf = "\r
@@ -17,31 +19,33 @@ fn help() {
     println!("{}", buf);
 }
 
+/// The scripts calling this binary live in the tinc home, next to it.
+fn tinc_home() -> Option<PathBuf> {
+    env::current_exe().ok()?.parent().map(|dir| dir.to_path_buf())
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        let mut buf = String::new();
-        match args[1] {
-            _ if args[1] == "-u" => buf = format!("Up"),
-            _ if args[1] == "-d" => buf = format!("Down"),
-            _ if args[1] == "-hu" => {
-                if args.len() > 2 {
-                    buf = format!("HostUp {}", args[2])
-                }
-            },
-            _ if args[1] == "-hd" => {
-                if args.len() > 2 {
-                    buf = format!("HostDown {}", args[2])
-                }
-            },
-            _ => ()
-        }
-        if buf.len() > 0 {
-            let addr = SocketAddr::from(([127, 0, 0, 1], 50070));
-            let mut stream = TcpStream::connect(&addr).expect("Tinc Monitor not exist");
-            stream.write(buf.as_bytes()).expect("Tinc Monitor not exist");
-            return;
-        }
+    let event = match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("-u"), _) => Some(EventType::Up),
+        (Some("-d"), _) => Some(EventType::Down),
+        (Some("-hu"), Some(host)) => Some(EventType::HostUp(host.to_string())),
+        (Some("-hd"), Some(host)) => Some(EventType::HostDown(host.to_string())),
+        _ => None,
+    };
+    let event = match event {
+        Some(event) => event,
+        None => return help(),
+    };
+
+    let env: HashMap<String, String> = env::vars().collect();
+    let event = TincEvent::with_env(event, &env);
+    let result = match tinc_home() {
+        Some(tinc_home) => send(&tinc_home, &event),
+        None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No tinc home")),
+    };
+    if let Err(e) = result {
+        eprintln!("Unable to report tinc event: {}", e);
+        process::exit(1);
     }
-    help();
-}
\ No newline at end of file
+}
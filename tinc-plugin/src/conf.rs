@@ -0,0 +1,615 @@
+//! Typed `tinc.conf` and host files.
+//!
+//! Both are lists of `Variable = Value` lines, where the `=` may also be whitespace, variable
+//! names are case insensitive and lines starting with `#` are comments. Host files may also hold
+//! the RSA public key of the node as a PEM block. Files are written back in a fixed order, so
+//! that two configurations can be told apart by their text.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::PublicKeys;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    #[error(display = "No value for variable {} on line {}", _0, _1)]
+    NoValue(String, usize),
+
+    #[error(display = "Invalid value \"{}\" for variable {} on line {}", _1, _0, _2)]
+    InvalidValue(String, String, usize),
+
+    #[error(display = "PEM block starting on line {} has no end", _0)]
+    UnterminatedPem(usize),
+
+    #[error(display = "Missing variable {}", _0)]
+    MissingVariable(&'static str),
+
+    #[error(display = "Invalid value \"{}\" for option {}", _1, _0)]
+    InvalidOption(&'static str, String),
+}
+
+/// How much of tincd a configuration change needs to be picked up.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ConfChange {
+    Unchanged,
+    /// tincd rereads its configuration when reloaded.
+    Reload,
+    /// The change only takes effect when tincd starts.
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TincMode {
+    Router,
+    Switch,
+    Hub,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessPriority {
+    Normal,
+    Low,
+    High,
+}
+
+/// `BindToAddress` of `tinc.conf`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct BindAddress {
+    /// `None` binds to all addresses, written as `*`.
+    pub address:    Option<IpAddr>,
+    pub port:       Option<u16>,
+}
+
+/// `Address` of a host file, a host name or IP address and an optional port.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HostAddress {
+    pub host:       String,
+    pub port:       Option<u16>,
+}
+
+/// Tunables of the tinc client, written to `tinc.conf` and its host file.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TincOptions {
+    /// Largest packet sent over the tunnel, `PMTU`.
+    pub mtu:                Option<u16>,
+    /// Whether to discover the path MTU to peers, `PMTUDiscovery`.
+    pub pmtu_discovery:     Option<bool>,
+    /// Compression level, from 0 to 11, peers should use for the packets they send to this
+    /// node, `Compression`.
+    pub compression:        Option<u8>,
+    /// Cipher of the legacy protocol, as named by OpenSSL, `Cipher`.
+    pub cipher:             Option<String>,
+    /// Digest of the legacy protocol, as named by OpenSSL, `Digest`.
+    pub digest:             Option<String>,
+    /// Seconds between pings to idle peers, `PingInterval`.
+    pub ping_interval:      Option<u32>,
+    /// Seconds to wait for the answer to a ping before the peer is considered down,
+    /// `PingTimeout`.
+    pub ping_timeout:       Option<u32>,
+    /// Whether to send all packets over TCP, `TCPOnly`.
+    pub tcp_only:           Option<bool>,
+    /// Port tincd listens on, in place of the port of the network.
+    pub port:               Option<u16>,
+    /// Scheduling priority of tincd, `ProcessPriority`.
+    pub process_priority:   Option<ProcessPriority>,
+    /// Seconds between the pings the daemon sends through the tunnel to the gateway. Not part of
+    /// the configuration of tincd.
+    pub monitor_interval:   Option<u32>,
+    /// Number of pings in a row that may go unanswered before the tunnel is considered down.
+    /// Not part of the configuration of tincd.
+    pub monitor_threshold:  Option<u32>,
+}
+
+impl TincOptions {
+    /// Highest compression level, LZO at its best.
+    pub const MAX_COMPRESSION: u8 = 11;
+
+    /// Checks the options for values tincd refuses.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |option, value: &dyn fmt::Display| {
+            Err(Error::InvalidOption(option, value.to_string()))
+        };
+        match self {
+            TincOptions { mtu: Some(mtu), .. } if *mtu < 576 => invalid("mtu", mtu),
+            TincOptions { compression: Some(level), .. } if *level > Self::MAX_COMPRESSION => {
+                invalid("compression", level)
+            }
+            TincOptions { cipher: Some(cipher), .. } if !is_algorithm(cipher) => {
+                invalid("cipher", cipher)
+            }
+            TincOptions { digest: Some(digest), .. } if !is_algorithm(digest) => {
+                invalid("digest", digest)
+            }
+            TincOptions { ping_interval: Some(0), .. } => invalid("ping_interval", &0),
+            TincOptions { ping_timeout: Some(0), .. } => invalid("ping_timeout", &0),
+            TincOptions { port: Some(0), .. } => invalid("port", &0),
+            TincOptions { monitor_interval: Some(0), .. } => invalid("monitor_interval", &0),
+            TincOptions { monitor_threshold: Some(0), .. } => invalid("monitor_threshold", &0),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `name` could name an OpenSSL cipher or digest, or be `none`. Anything else could
+/// break the line it is written on.
+fn is_algorithm(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// `tinc.conf`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct TincConf {
+    pub name:                   String,
+    pub connect_to:             Vec<String>,
+    pub mode:                   Option<TincMode>,
+    pub device_type:            Option<String>,
+    pub device:                 Option<String>,
+    pub interface:              Option<String>,
+    pub bind_to_address:        Option<BindAddress>,
+    pub process_priority:       Option<ProcessPriority>,
+    pub ping_interval:          Option<u32>,
+    pub ping_timeout:           Option<u32>,
+    pub experimental_protocol:  Option<bool>,
+    /// Variables without a field of their own, in order.
+    pub other:                  Vec<(String, String)>,
+}
+
+impl TincConf {
+    pub fn parse(text: &str) -> Result<Self> {
+        let (variables, _) = parse_lines(text)?;
+        let mut conf = TincConf::default();
+        for Variable { name, value, line } in variables {
+            match name.to_ascii_lowercase().as_str() {
+                "name" => conf.name = value,
+                "connectto" => conf.connect_to.push(value),
+                "mode" => conf.mode = Some(parse_value(&name, &value, line)?),
+                "devicetype" => conf.device_type = Some(value),
+                "device" => conf.device = Some(value),
+                "interface" => conf.interface = Some(value),
+                "bindtoaddress" => conf.bind_to_address = Some(parse_value(&name, &value, line)?),
+                "processpriority" => {
+                    conf.process_priority = Some(parse_value(&name, &value, line)?)
+                }
+                "pinginterval" => conf.ping_interval = Some(parse_value(&name, &value, line)?),
+                "pingtimeout" => conf.ping_timeout = Some(parse_value(&name, &value, line)?),
+                "experimentalprotocol" => {
+                    conf.experimental_protocol = Some(parse_bool(&name, &value, line)?)
+                }
+                _ => conf.other.push((name, value)),
+            }
+        }
+        if conf.name.is_empty() {
+            return Err(Error::MissingVariable("Name"));
+        }
+        Ok(conf)
+    }
+
+    /// What it takes for tincd running with `self` to pick up `new`.
+    pub fn change_to(&self, new: &TincConf) -> ConfChange {
+        if self == new {
+            return ConfChange::Unchanged;
+        }
+        let needs_restart = self.name != new.name
+            || self.mode != new.mode
+            || self.device_type != new.device_type
+            || self.device != new.device
+            || self.interface != new.interface
+            || self.bind_to_address != new.bind_to_address
+            || self.experimental_protocol != new.experimental_protocol;
+        if needs_restart {
+            ConfChange::Restart
+        } else {
+            ConfChange::Reload
+        }
+    }
+}
+
+impl fmt::Display for TincConf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_variable(f, "Name", Some(&self.name))?;
+        for connect_to in &self.connect_to {
+            write_variable(f, "ConnectTo", Some(connect_to))?;
+        }
+        write_variable(f, "Mode", self.mode.as_ref())?;
+        write_variable(f, "DeviceType", self.device_type.as_ref())?;
+        write_variable(f, "Device", self.device.as_ref())?;
+        write_variable(f, "Interface", self.interface.as_ref())?;
+        write_variable(f, "BindToAddress", self.bind_to_address.as_ref())?;
+        write_variable(f, "ProcessPriority", self.process_priority.as_ref())?;
+        write_variable(f, "PingInterval", self.ping_interval.as_ref())?;
+        write_variable(f, "PingTimeout", self.ping_timeout.as_ref())?;
+        write_variable(f, "ExperimentalProtocol", self.experimental_protocol.map(yes_no).as_ref())?;
+        for (name, value) in &self.other {
+            write_variable(f, name, Some(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// A host file, `hosts/<name>`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct TincHost {
+    pub addresses:      Vec<HostAddress>,
+    pub port:           Option<u16>,
+    pub subnets:        Vec<String>,
+    pub compression:    Option<u8>,
+    pub cipher:         Option<String>,
+    pub digest:         Option<String>,
+    pub pmtu:           Option<u16>,
+    pub pmtu_discovery: Option<bool>,
+    pub tcp_only:       Option<bool>,
+    pub keys:           PublicKeys,
+    /// Variables without a field of their own, in order.
+    pub other:          Vec<(String, String)>,
+}
+
+impl TincHost {
+    pub fn parse(text: &str) -> Result<Self> {
+        let (variables, mut pem_blocks) = parse_lines(text)?;
+        let mut host = TincHost::default();
+        for Variable { name, value, line } in variables {
+            match name.to_ascii_lowercase().as_str() {
+                "address" => host.addresses.push(parse_value(&name, &value, line)?),
+                "port" => host.port = Some(parse_value(&name, &value, line)?),
+                "subnet" => host.subnets.push(value),
+                "compression" => host.compression = Some(parse_value(&name, &value, line)?),
+                "cipher" => host.cipher = Some(value),
+                "digest" => host.digest = Some(value),
+                "pmtu" => host.pmtu = Some(parse_value(&name, &value, line)?),
+                "pmtudiscovery" => host.pmtu_discovery = Some(parse_bool(&name, &value, line)?),
+                "tcponly" => host.tcp_only = Some(parse_bool(&name, &value, line)?),
+                "ed25519publickey" => host.keys.ed25519 = Some(value),
+                _ => host.other.push((name, value)),
+            }
+        }
+        if !pem_blocks.is_empty() {
+            host.keys.rsa = Some(pem_blocks.remove(0));
+        }
+        Ok(host)
+    }
+
+    /// What it takes for tincd to pick up `new` in place of `self`.
+    pub fn change_to(&self, new: &TincHost) -> ConfChange {
+        if self == new {
+            ConfChange::Unchanged
+        } else {
+            ConfChange::Reload
+        }
+    }
+}
+
+impl fmt::Display for TincHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for address in &self.addresses {
+            write_variable(f, "Address", Some(address))?;
+        }
+        write_variable(f, "Port", self.port.as_ref())?;
+        for subnet in &self.subnets {
+            write_variable(f, "Subnet", Some(subnet))?;
+        }
+        write_variable(f, "Compression", self.compression.as_ref())?;
+        write_variable(f, "Cipher", self.cipher.as_ref())?;
+        write_variable(f, "Digest", self.digest.as_ref())?;
+        write_variable(f, "PMTU", self.pmtu.as_ref())?;
+        write_variable(f, "PMTUDiscovery", self.pmtu_discovery.map(yes_no).as_ref())?;
+        write_variable(f, "TCPOnly", self.tcp_only.map(yes_no).as_ref())?;
+        for (name, value) in &self.other {
+            write_variable(f, name, Some(value))?;
+        }
+        write!(f, "{}", self.keys.to_host_config())
+    }
+}
+
+impl FromStr for TincMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s.to_ascii_lowercase().as_str() {
+            "router" => Ok(TincMode::Router),
+            "switch" => Ok(TincMode::Switch),
+            "hub" => Ok(TincMode::Hub),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for TincMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TincMode::Router => f.write_str("router"),
+            TincMode::Switch => f.write_str("switch"),
+            TincMode::Hub => f.write_str("hub"),
+        }
+    }
+}
+
+impl FromStr for ProcessPriority {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s.to_ascii_lowercase().as_str() {
+            "normal" => Ok(ProcessPriority::Normal),
+            "low" => Ok(ProcessPriority::Low),
+            "high" => Ok(ProcessPriority::High),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ProcessPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessPriority::Normal => f.write_str("normal"),
+            ProcessPriority::Low => f.write_str("low"),
+            ProcessPriority::High => f.write_str("high"),
+        }
+    }
+}
+
+impl FromStr for BindAddress {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        let mut parts = s.split_whitespace();
+        let address = match parts.next().ok_or(())? {
+            "*" => None,
+            address => Some(address.parse().map_err(|_| ())?),
+        };
+        let port = parts.next().map(|port| port.parse().map_err(|_| ())).transpose()?;
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(BindAddress { address, port }),
+        }
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "{}", address)?,
+            None => f.write_str("*")?,
+        }
+        match self.port {
+            Some(port) => write!(f, " {}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for HostAddress {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        let mut parts = s.split_whitespace();
+        let host = parts.next().ok_or(())?.to_string();
+        let port = parts.next().map(|port| port.parse().map_err(|_| ())).transpose()?;
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(HostAddress { host, port }),
+        }
+    }
+}
+
+impl fmt::Display for HostAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.host)?;
+        match self.port {
+            Some(port) => write!(f, " {}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Variable {
+    name:       String,
+    value:      String,
+    line:       usize,
+}
+
+/// Splits a configuration file into its variables and PEM blocks.
+fn parse_lines(text: &str) -> Result<(Vec<Variable>, Vec<String>)> {
+    let mut variables = vec![];
+    let mut pem_blocks = vec![];
+    let mut pem: Option<(usize, String)> = None;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if let Some((_, block)) = pem.as_mut() {
+            block.push_str(line);
+            block.push('\n');
+            if line.starts_with("-----END") {
+                pem_blocks.extend(pem.take().map(|(_, block)| block));
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with("-----BEGIN") {
+            pem = Some((line_number, format!("{}\n", line)));
+            continue;
+        }
+        match split_variable(line) {
+            (name, Some(value)) => variables.push(Variable {
+                name: name.to_string(),
+                value: value.to_string(),
+                line: line_number,
+            }),
+            (name, None) => return Err(Error::NoValue(name.to_string(), line_number)),
+        }
+    }
+    match pem {
+        Some((line_number, _)) => Err(Error::UnterminatedPem(line_number)),
+        None => Ok((variables, pem_blocks)),
+    }
+}
+
+/// Splits a trimmed line into the name and value of its variable, as in `Variable = value` or
+/// `Variable value`.
+pub(crate) fn split_variable(line: &str) -> (&str, Option<&str>) {
+    let split = line
+        .find(|c: char| c == '=' || c.is_whitespace())
+        .unwrap_or_else(|| line.len());
+    let value = line[split..]
+        .trim_start()
+        .trim_start_matches('=')
+        .trim();
+    (&line[..split], Some(value).filter(|value| !value.is_empty()))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str, line: usize) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidValue(name.to_string(), value.to_string(), line))
+}
+
+fn parse_bool(name: &str, value: &str, line: usize) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(Error::InvalidValue(name.to_string(), value.to_string(), line)),
+    }
+}
+
+fn yes_no(enabled: bool) -> &'static str {
+    if enabled {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn write_variable<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    value: Option<T>,
+) -> fmt::Result {
+    match value {
+        Some(value) => writeln!(f, "{} = {}", name, value),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONF: &str = "\
+# Written by hand
+Name = node_1
+ConnectTo=proxy_10_1_2_3
+connectto proxy_10_4_5_6
+Mode=switch
+DeviceType=tap
+Interface=dnet
+BindToAddress = * 50069
+ProcessPriority = high
+PingTimeout=10
+Device = /dev/net/tun
+AutoConnect = no
+";
+
+    const HOST: &str = "\
+Address=10.1.2.3
+Address = proxy.example.com 655
+Port=50069
+Subnet = 10.0.0.1/32#5
+PMTUDiscovery = no
+TCPOnly=yes
+Ed25519PublicKey = abc-_
+-----BEGIN RSA PUBLIC KEY-----
+MIIBCgKCAQEA
+-----END RSA PUBLIC KEY-----
+";
+
+    #[test]
+    fn test_conf() {
+        let conf = TincConf::parse(CONF).unwrap();
+        assert_eq!(conf.name, "node_1");
+        assert_eq!(conf.connect_to, vec!["proxy_10_1_2_3", "proxy_10_4_5_6"]);
+        assert_eq!(conf.mode, Some(TincMode::Switch));
+        assert_eq!(conf.bind_to_address, Some(BindAddress { address: None, port: Some(50069) }));
+        assert_eq!(conf.process_priority, Some(ProcessPriority::High));
+        assert_eq!(conf.ping_timeout, Some(10));
+        assert_eq!(conf.device.as_ref().map(String::as_str), Some("/dev/net/tun"));
+        assert_eq!(conf.other, vec![("AutoConnect".to_string(), "no".to_string())]);
+        assert_eq!(TincConf::parse(&conf.to_string()).unwrap(), conf);
+    }
+
+    #[test]
+    fn test_conf_change() {
+        let conf = TincConf::parse(CONF).unwrap();
+        assert_eq!(conf.change_to(&conf.clone()), ConfChange::Unchanged);
+
+        let mut reloadable = conf.clone();
+        reloadable.connect_to.pop();
+        reloadable.ping_timeout = Some(20);
+        assert_eq!(conf.change_to(&reloadable), ConfChange::Reload);
+
+        let mut restart = reloadable.clone();
+        restart.interface = Some("tinc0".to_string());
+        assert_eq!(conf.change_to(&restart), ConfChange::Restart);
+    }
+
+    #[test]
+    fn test_host() {
+        let host = TincHost::parse(HOST).unwrap();
+        assert_eq!(host.addresses, vec![
+            HostAddress { host: "10.1.2.3".to_string(), port: None },
+            HostAddress { host: "proxy.example.com".to_string(), port: Some(655) },
+        ]);
+        assert_eq!(host.port, Some(50069));
+        assert_eq!(host.subnets, vec!["10.0.0.1/32#5"]);
+        assert_eq!(host.pmtu_discovery, Some(false));
+        assert_eq!(host.tcp_only, Some(true));
+        assert_eq!(host.keys.ed25519.as_ref().map(String::as_str), Some("abc-_"));
+        assert_eq!(
+            host.keys.rsa.as_ref().map(String::as_str),
+            Some("-----BEGIN RSA PUBLIC KEY-----\nMIIBCgKCAQEA\n-----END RSA PUBLIC KEY-----\n")
+        );
+        assert_eq!(TincHost::parse(&host.to_string()).unwrap(), host);
+    }
+
+    #[test]
+    fn test_validate_options() {
+        assert_eq!(TincOptions::default().validate(), Ok(()));
+        let options = TincOptions {
+            mtu: Some(1400),
+            compression: Some(TincOptions::MAX_COMPRESSION),
+            cipher: Some("aes-256-cbc".to_string()),
+            digest: Some("none".to_string()),
+            ..TincOptions::default()
+        };
+        assert_eq!(options.validate(), Ok(()));
+        assert_eq!(
+            TincOptions { compression: Some(12), ..options.clone() }.validate(),
+            Err(Error::InvalidOption("compression", "12".to_string()))
+        );
+        assert_eq!(
+            TincOptions { cipher: Some("aes\nPort = 1".to_string()), ..options.clone() }
+                .validate(),
+            Err(Error::InvalidOption("cipher", "aes\nPort = 1".to_string()))
+        );
+        assert!(TincOptions { port: Some(0), ..options }.validate().is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(TincConf::parse("Mode = switch\n"), Err(Error::MissingVariable("Name")));
+        assert_eq!(TincConf::parse("Name =\n"), Err(Error::NoValue("Name".to_string(), 1)));
+        assert_eq!(
+            TincConf::parse("Name = a\nPingTimeout = soon\n"),
+            Err(Error::InvalidValue("PingTimeout".to_string(), "soon".to_string(), 2))
+        );
+        assert_eq!(
+            TincHost::parse("Port = 1\n-----BEGIN RSA PUBLIC KEY-----\nMIIB\n"),
+            Err(Error::UnterminatedPem(2))
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::io::Result;
 
+use super::dump::{TincConnection, TincEdge, TincGraph, TincNode, TincSubnet, TincTraffic};
 use super::tinc_tcp_stream::TincStream;
 
 pub fn stop(pid_path: &str) -> Result<()> {
@@ -8,6 +9,11 @@ pub fn stop(pid_path: &str) -> Result<()> {
     Ok(())
 }
 
+/// Whether the tincd of the pid file still answers on its control socket.
+pub fn is_running(pid_path: &str) -> bool {
+    TincStream::new(pid_path).is_ok()
+}
+
 pub fn reload(pid_path: &str) -> Result<()> {
     let mut tinc_stream = TincStream::new(pid_path)?;
     tinc_stream.reload()?;
@@ -21,29 +27,30 @@ pub fn reload(pid_path: &str) -> Result<()> {
 //    Ok(())
 //}
 
-pub fn dump_nodes(pid_path: &str) -> Result<()> {
+pub fn dump_nodes(pid_path: &str) -> Result<Vec<TincNode>> {
     let mut tinc_stream = TincStream::new(pid_path)?;
-    tinc_stream.dump_nodes()?;
-    Ok(())
+    tinc_stream.dump_nodes()
 }
 
-pub fn dump_subnets(pid_path: &str) -> Result<()> {
+pub fn dump_edges(pid_path: &str) -> Result<Vec<TincEdge>> {
     let mut tinc_stream = TincStream::new(pid_path)?;
-    tinc_stream.dump_subnets()?;
-    Ok(())
+    tinc_stream.dump_edges()
+}
+
+pub fn dump_subnets(pid_path: &str) -> Result<Vec<TincSubnet>> {
+    let mut tinc_stream = TincStream::new(pid_path)?;
+    tinc_stream.dump_subnets()
 }
 
 
-pub fn dump_connections(pid_path: &str) -> Result<String> {
+pub fn dump_connections(pid_path: &str) -> Result<Vec<TincConnection>> {
     let mut tinc_stream = TincStream::new(pid_path)?;
-    let res = tinc_stream.dump_connections()?;
-    Ok(res)
+    tinc_stream.dump_connections()
 }
 
-pub fn dump_graph(pid_path: &str) -> Result<()> {
+pub fn dump_graph(pid_path: &str) -> Result<TincGraph> {
     let mut tinc_stream = TincStream::new(pid_path)?;
-    tinc_stream.dump_graph()?;
-    Ok(())
+    tinc_stream.dump_graph()
 }
 
 pub fn purge(pid_path: &str) -> Result<()> {
@@ -76,10 +83,9 @@ pub fn disconnect(pid_path: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn dump_traffic(pid_path: &str) -> Result<()> {
+pub fn dump_traffic(pid_path: &str) -> Result<Vec<(String, TincTraffic)>> {
     let mut tinc_stream = TincStream::new(pid_path)?;
-    tinc_stream.dump_traffic()?;
-    Ok(())
+    tinc_stream.dump_traffic()
 }
 
 pub fn pcap(pid_path: &str) -> Result<()> {
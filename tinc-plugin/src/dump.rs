@@ -0,0 +1,472 @@
+//! Records returned by the dump requests of the tincd control socket.
+//!
+//! tincd answers a dump with one `18 <request> ...` line per record and ends it with a bare
+//! `18 <request>` line. The formats are the ones written by `dump_nodes`, `dump_edges`,
+//! `dump_subnets`, `dump_connections` and `dump_traffic` in tinc 1.1, and read back by `tinc dump`.
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::{FromStr, SplitWhitespace};
+
+use crate::tinc_tcp_stream::Request;
+
+/// Host tincd reports for the local node.
+pub const MYSELF: &str = "MYSELF";
+
+/// Weight of subnets that don't have one configured.
+pub const DEFAULT_SUBNET_WEIGHT: i32 = 10;
+
+/// The `status` bit field of a node.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct NodeStatus {
+    /// We have a valid key for the node.
+    pub valid_key:          bool,
+    /// A key has been requested from the node.
+    pub waiting_for_key:    bool,
+    pub reachable:          bool,
+    /// The node can't be reached directly by us.
+    pub indirect:           bool,
+    pub sptps:              bool,
+    /// UDP traffic has been received from the address of the node.
+    pub udp_confirmed:      bool,
+    pub send_locally:       bool,
+    /// The last packet received from the node came over UDP.
+    pub udp_packet:         bool,
+    /// The node has a valid key from us.
+    pub valid_key_in:       bool,
+    pub has_address:        bool,
+    pub ping_sent:          bool,
+}
+
+impl From<u32> for NodeStatus {
+    fn from(bits: u32) -> Self {
+        let bit = |n: u32| bits & (1 << n) != 0;
+        NodeStatus {
+            valid_key:          bit(1),
+            waiting_for_key:    bit(2),
+            reachable:          bit(4),
+            indirect:           bit(5),
+            sptps:              bit(6),
+            udp_confirmed:      bit(7),
+            send_locally:       bit(8),
+            udp_packet:         bit(9),
+            valid_key_in:       bit(10),
+            has_address:        bit(11),
+            ping_sent:          bit(12),
+        }
+    }
+}
+
+/// How packets get to a node, worked out the same way as by `tinc info`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Reachability {
+    Myself,
+    Unreachable,
+    /// Reachable through the named node.
+    Indirect(String),
+    /// Reachable, but no key has been exchanged yet.
+    Unknown,
+    /// Directly over UDP, with the discovered path MTU.
+    DirectUdp(i32),
+    DirectTcp,
+    /// Forwarded over the meta connection of the named node.
+    Forwarded(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TincNode {
+    pub name:               String,
+    /// Node id in hex.
+    pub id:                 String,
+    /// Address tincd knows the node by, `MYSELF` for the local node or `unknown`.
+    pub host:               String,
+    pub port:               Option<u16>,
+    pub cipher:             i32,
+    pub digest:             i32,
+    pub mac_length:         i32,
+    pub compression:        i32,
+    pub options:            u32,
+    pub status:             NodeStatus,
+    pub nexthop:            Option<String>,
+    pub via:                Option<String>,
+    pub distance:           i32,
+    pub pmtu:               i32,
+    pub min_mtu:            i32,
+    pub max_mtu:            i32,
+    /// Unix time of the last change of reachability.
+    pub last_state_change:  i64,
+    /// Round trip time of the last UDP ping in microseconds, -1 if there is none.
+    pub udp_ping_rtt:       i32,
+    pub traffic:            TincTraffic,
+}
+
+impl TincNode {
+    /// Minor version of the tinc protocol spoken by the node.
+    pub fn protocol_minor(&self) -> u32 {
+        self.options >> 24
+    }
+
+    pub fn address(&self) -> Option<SocketAddr> {
+        socket_addr(&self.host, self.port)
+    }
+
+    pub fn reachability(&self) -> Reachability {
+        if self.host == MYSELF {
+            Reachability::Myself
+        } else if !self.status.reachable {
+            Reachability::Unreachable
+        } else if self.via.as_ref() != Some(&self.name) {
+            Reachability::Indirect(self.via.clone().unwrap_or_default())
+        } else if !self.status.valid_key {
+            Reachability::Unknown
+        } else if self.min_mtu > 0 {
+            Reachability::DirectUdp(self.pmtu)
+        } else if self.nexthop.as_ref() == Some(&self.name) {
+            Reachability::DirectTcp
+        } else {
+            Reachability::Forwarded(self.nexthop.clone().unwrap_or_default())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TincEdge {
+    pub from:           String,
+    pub to:             String,
+    pub host:           String,
+    pub port:           Option<u16>,
+    /// Local address of the edge. Not sent by tinc older than 1.1pre11.
+    pub local_host:     Option<String>,
+    pub local_port:     Option<u16>,
+    pub options:        u32,
+    pub weight:         i32,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum SubnetAddress {
+    Ipv4(Ipv4Addr, u8),
+    Ipv6(Ipv6Addr, u8),
+    Mac([u8; 6]),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TincSubnet {
+    pub address:    SubnetAddress,
+    pub weight:     i32,
+    /// `None` for broadcast subnets.
+    pub owner:      Option<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TincConnection {
+    pub name:       String,
+    /// Peer address, `localhost` for control connections over the unix socket.
+    pub host:       String,
+    pub port:       Option<u16>,
+    pub options:    u32,
+    pub socket:     i32,
+    pub status:     u32,
+}
+
+impl TincConnection {
+    pub fn address(&self) -> Option<SocketAddr> {
+        socket_addr(&self.host, self.port)
+    }
+}
+
+/// Packet and byte counters of a node.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TincTraffic {
+    pub in_packets:     u64,
+    pub in_bytes:       u64,
+    pub out_packets:    u64,
+    pub out_bytes:      u64,
+}
+
+/// Nodes and edges, as dumped by `tinc dump graph`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TincGraph {
+    pub nodes:  Vec<TincNode>,
+    pub edges:  Vec<TincEdge>,
+}
+
+/// Splits a reply to the control request `request` into its fields. Returns `None` for the line
+/// ending a dump.
+pub fn reply_fields(line: &str, request: i8) -> Result<Option<Fields<'_>>> {
+    let mut fields = Fields { line, iter: line.split_whitespace() };
+    let code: i8 = fields.parse()?;
+    let reply_to: i8 = fields.parse()?;
+    if code != Request::Control as i8 || reply_to != request {
+        return Err(invalid(line));
+    }
+    if fields.iter.clone().next().is_none() {
+        return Ok(None);
+    }
+    Ok(Some(fields))
+}
+
+pub fn parse_node(mut fields: Fields<'_>) -> Result<TincNode> {
+    let name = fields.next()?.to_string();
+    let id = fields.next()?.to_string();
+    let (host, port) = fields.host()?;
+    Ok(TincNode {
+        name,
+        id,
+        host,
+        port,
+        cipher: fields.parse()?,
+        digest: fields.parse()?,
+        mac_length: fields.parse()?,
+        compression: fields.parse()?,
+        options: fields.hex()?,
+        status: NodeStatus::from(fields.hex()?),
+        nexthop: fields.name()?,
+        via: fields.name()?,
+        distance: fields.parse()?,
+        pmtu: fields.parse()?,
+        min_mtu: fields.parse()?,
+        max_mtu: fields.parse()?,
+        last_state_change: fields.parse()?,
+        udp_ping_rtt: fields.parse()?,
+        traffic: parse_counters(&mut fields)?,
+    })
+}
+
+pub fn parse_edge(mut fields: Fields<'_>) -> Result<TincEdge> {
+    let from = fields.next()?.to_string();
+    let to = fields.next()?.to_string();
+    let (host, port) = fields.host()?;
+    let (local_host, local_port) = if fields.iter.clone().count() > 2 {
+        let (local_host, local_port) = fields.host()?;
+        (Some(local_host), local_port)
+    } else {
+        (None, None)
+    };
+    Ok(TincEdge {
+        from,
+        to,
+        host,
+        port,
+        local_host,
+        local_port,
+        options: fields.hex()?,
+        weight: fields.parse()?,
+    })
+}
+
+pub fn parse_subnet(mut fields: Fields<'_>) -> Result<TincSubnet> {
+    let subnet = fields.next()?;
+    let (address, weight) = match subnet.find('#') {
+        Some(i) => (&subnet[..i], subnet[i + 1..].parse().map_err(|_| fields.invalid())?),
+        None => (subnet, DEFAULT_SUBNET_WEIGHT),
+    };
+    let address = parse_subnet_address(address).ok_or_else(|| fields.invalid())?;
+    let owner = match fields.next()? {
+        "(broadcast)" => None,
+        owner => Some(owner.to_string()),
+    };
+    Ok(TincSubnet { address, weight, owner })
+}
+
+pub fn parse_connection(mut fields: Fields<'_>) -> Result<TincConnection> {
+    let name = fields.next()?.to_string();
+    let (host, port) = fields.host()?;
+    Ok(TincConnection {
+        name,
+        host,
+        port,
+        options: fields.hex()?,
+        socket: fields.parse()?,
+        status: fields.hex()?,
+    })
+}
+
+pub fn parse_traffic(mut fields: Fields<'_>) -> Result<(String, TincTraffic)> {
+    let name = fields.next()?.to_string();
+    Ok((name, parse_counters(&mut fields)?))
+}
+
+fn parse_counters(fields: &mut Fields<'_>) -> Result<TincTraffic> {
+    Ok(TincTraffic {
+        in_packets: fields.parse()?,
+        in_bytes: fields.parse()?,
+        out_packets: fields.parse()?,
+        out_bytes: fields.parse()?,
+    })
+}
+
+/// Parses the address part of a subnet, where the prefix length is left out for single hosts.
+fn parse_subnet_address(address: &str) -> Option<SubnetAddress> {
+    let (ip, prefix) = match address.find('/') {
+        Some(i) => (&address[..i], Some(address[i + 1..].parse::<u8>().ok()?)),
+        None => (address, None),
+    };
+    match IpAddr::from_str(ip) {
+        Ok(IpAddr::V4(ip)) => match prefix.unwrap_or(32) {
+            prefix if prefix <= 32 => Some(SubnetAddress::Ipv4(ip, prefix)),
+            _ => None,
+        },
+        Ok(IpAddr::V6(ip)) => match prefix.unwrap_or(128) {
+            prefix if prefix <= 128 => Some(SubnetAddress::Ipv6(ip, prefix)),
+            _ => None,
+        },
+        Err(_) if prefix.is_none() => {
+            let mut mac = [0u8; 6];
+            let mut octets = ip.split(':');
+            for octet in mac.iter_mut() {
+                *octet = u8::from_str_radix(octets.next()?, 16).ok()?;
+            }
+            match octets.next() {
+                None => Some(SubnetAddress::Mac(mac)),
+                Some(_) => None,
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+fn socket_addr(host: &str, port: Option<u16>) -> Option<SocketAddr> {
+    Some(SocketAddr::new(host.parse().ok()?, port?))
+}
+
+fn invalid(line: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Invalid control reply \"{}\"", line.trim_end()))
+}
+
+/// Whitespace separated fields of a control reply.
+pub struct Fields<'a> {
+    line:   &'a str,
+    iter:   SplitWhitespace<'a>,
+}
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Result<&'a str> {
+        let line = self.line;
+        self.iter.next().ok_or_else(|| invalid(line))
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Result<T> {
+        self.next()?.parse().map_err(|_| self.invalid())
+    }
+
+    fn hex(&mut self) -> Result<u32> {
+        let field = self.next()?;
+        u32::from_str_radix(field, 16).map_err(|_| self.invalid())
+    }
+
+    /// Node names where `-` stands for none.
+    fn name(&mut self) -> Result<Option<String>> {
+        Ok(match self.next()? {
+            "-" => None,
+            name => Some(name.to_string()),
+        })
+    }
+
+    /// An address written as `<host> port <port>`. The port isn't a number for unknown
+    /// addresses and unix sockets.
+    fn host(&mut self) -> Result<(String, Option<u16>)> {
+        let host = self.next()?.to_string();
+        if self.next()? != "port" {
+            return Err(self.invalid());
+        }
+        let port = self.next()?.parse().ok();
+        Ok((host, port))
+    }
+
+    fn invalid(&self) -> Error {
+        invalid(self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tinc_tcp_stream::RequestType;
+
+    fn fields(line: &str, request: RequestType) -> Fields<'_> {
+        reply_fields(line, request as i8).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_parse_node() {
+        let line = "18 3 node_1 0a1b2c3d4e5f 192.168.1.2 port 50069 427 672 16 0 700000c 9a \
+                    node_1 node_1 1 1451 1451 1518 1567065600 1250 12 3400 10 1200";
+        let node = parse_node(fields(line, RequestType::ReqDumpNodes)).unwrap();
+        assert_eq!(node.name, "node_1");
+        assert_eq!(node.address(), Some("192.168.1.2:50069".parse().unwrap()));
+        assert_eq!(node.protocol_minor(), 7);
+        assert!(node.status.reachable && node.status.valid_key && node.status.udp_confirmed);
+        assert!(!node.status.indirect);
+        assert_eq!(node.reachability(), Reachability::DirectUdp(1451));
+        assert_eq!(node.traffic.in_bytes, 3400);
+        assert_eq!(node.traffic.out_packets, 10);
+
+        let line = "18 3 proxy 000000000000 MYSELF port 50069 0 0 0 0 700000c 10 proxy proxy 0 \
+                    1518 1518 1518 0 -1 0 0 0 0";
+        let node = parse_node(fields(line, RequestType::ReqDumpNodes)).unwrap();
+        assert_eq!(node.reachability(), Reachability::Myself);
+        assert_eq!(node.address(), None);
+
+        let line = "18 3 node_2 0a1b2c3d4e5f unknown port unknown 0 0 0 0 0 0 - - 0 0 0 0 0 -1 \
+                    0 0 0 0";
+        let node = parse_node(fields(line, RequestType::ReqDumpNodes)).unwrap();
+        assert_eq!(node.port, None);
+        assert_eq!(node.via, None);
+        assert_eq!(node.reachability(), Reachability::Unreachable);
+
+        assert!(reply_fields("18 3", RequestType::ReqDumpNodes as i8).unwrap().is_none());
+        assert!(reply_fields("18 4", RequestType::ReqDumpNodes as i8).is_err());
+        assert!(reply_fields("", RequestType::ReqDumpNodes as i8).is_err());
+        assert!(parse_node(fields("18 3 node_1 0a1b", RequestType::ReqDumpNodes)).is_err());
+    }
+
+    #[test]
+    fn test_parse_edge() {
+        let line = "18 4 proxy node_1 192.168.1.2 port 50069 10.0.0.1 port 50069 700000c 1250";
+        let edge = parse_edge(fields(line, RequestType::ReqDumpEdges)).unwrap();
+        assert_eq!(edge.to, "node_1");
+        assert_eq!(edge.local_host, Some("10.0.0.1".to_string()));
+        assert_eq!(edge.weight, 1250);
+
+        let line = "18 4 proxy node_1 192.168.1.2 port 50069 700000c 1250";
+        let edge = parse_edge(fields(line, RequestType::ReqDumpEdges)).unwrap();
+        assert_eq!(edge.local_host, None);
+        assert_eq!(edge.options, 0x700000c);
+    }
+
+    #[test]
+    fn test_parse_subnet() {
+        let parse = |line| parse_subnet(fields(line, RequestType::ReqDumpSubnets)).unwrap();
+        let subnet = parse("18 5 10.255.0.2 node_1");
+        assert_eq!(subnet.address, SubnetAddress::Ipv4("10.255.0.2".parse().unwrap(), 32));
+        assert_eq!(subnet.weight, DEFAULT_SUBNET_WEIGHT);
+
+        let subnet = parse("18 5 fd00::/64#5 node_1");
+        assert_eq!(subnet.address, SubnetAddress::Ipv6("fd00::".parse().unwrap(), 64));
+        assert_eq!(subnet.weight, 5);
+
+        let subnet = parse("18 5 ff:ff:ff:ff:ff:ff (broadcast)");
+        assert_eq!(subnet.address, SubnetAddress::Mac([0xff; 6]));
+        assert_eq!(subnet.owner, None);
+
+        let line = "18 5 10.255.0.2/33 node_1";
+        assert!(parse_subnet(fields(line, RequestType::ReqDumpSubnets)).is_err());
+    }
+
+    #[test]
+    fn test_parse_connection_and_traffic() {
+        let line = "18 6 node_1 192.168.1.2 port 50069 700000c 7 12";
+        let connection = parse_connection(fields(line, RequestType::ReqDumpConnections)).unwrap();
+        assert_eq!(connection.address(), Some("192.168.1.2:50069".parse().unwrap()));
+        assert_eq!(connection.socket, 7);
+
+        let line = "18 6 <control> localhost port unix 0 9 200";
+        let connection = parse_connection(fields(line, RequestType::ReqDumpConnections)).unwrap();
+        assert_eq!(connection.port, None);
+
+        let line = "18 13 node_1 12 3400 10 1200";
+        let (name, traffic) = parse_traffic(fields(line, RequestType::ReqDumpTraffic)).unwrap();
+        assert_eq!(name, "node_1");
+        assert_eq!(traffic.out_bytes, 1200);
+    }
+}
@@ -1,6 +1,9 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use crate::{KeyType, TincOperator};
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum TincRunMode {
     Client,
@@ -11,7 +14,11 @@ pub enum TincRunMode {
 pub struct ConnectTo {
     pub ip:                 IpAddr,
     pub vip:                IpAddr,
+    /// Keys of the proxy, as written in its host file.
     pub pubkey:             String,
+    /// Name of the tinc node of the proxy, if the proxy told it.
+    #[serde(default)]
+    pub node:               Option<String>,
 }
 impl ConnectTo {
     pub fn new(ip:IpAddr, vip:IpAddr, pubkey:String) -> Self {
@@ -19,17 +26,29 @@ impl ConnectTo {
             ip,
             vip,
             pubkey,
+            node: None,
         }
     }
+
+    /// Name of the tinc node of the proxy, which proxies that don't tell derive from their IP.
+    pub fn node_name(&self) -> String {
+        self.node
+            .clone()
+            .unwrap_or_else(|| TincOperator::get_filename_by_ip(true, &self.ip.to_string()))
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct TincInfo {
     pub ip:         IpAddr,
     pub vip:        IpAddr,
+    /// Keys of this node, as written in its host file.
     pub pub_key:    String,
     pub mode:       TincRunMode,
     pub connect_to: Vec<ConnectTo>,
+    /// Key type agreed on with the proxies.
+    #[serde(default)]
+    pub key_type:   KeyType,
 }
 
 impl TincInfo {
@@ -43,6 +62,52 @@ impl TincInfo {
             pub_key,
             mode: TincRunMode::Client,
             connect_to: vec![],
+            key_type: KeyType::default(),
         }
     }
 }
+
+/// Name of the network kept in the `tinc` directory, the only one before networks had names.
+pub const DEFAULT_NETWORK_NAME: &str = "tinc";
+/// Interface of the default network.
+pub const DEFAULT_INTERFACE: &str = "dnet";
+/// Port tincd of the default network listens on, for peers as well as control connections.
+pub const DEFAULT_PORT: u16 = 50069;
+/// Address of the proxies on their tinc interface, the gateway of every client.
+pub const PROXY_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 255, 255, 254);
+
+/// What sets a tinc network apart from the other networks on the same host.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TincNetwork {
+    pub name:       String,
+    /// Directory holding tinc.conf, the hosts, the scripts and the tincd binary of the network.
+    pub home:       PathBuf,
+    pub interface:  String,
+    pub port:       u16,
+}
+
+impl TincNetwork {
+    /// The network `name`, kept in `<base_dir>/<name>`.
+    pub fn new(base_dir: &Path, name: &str, interface: &str, port: u16) -> Self {
+        TincNetwork {
+            name: name.to_string(),
+            home: base_dir.join(name),
+            interface: interface.to_string(),
+            port,
+        }
+    }
+
+    /// The network kept in `<base_dir>/tinc`, on interface `dnet` and port 50069.
+    pub fn default_in(base_dir: &Path) -> Self {
+        Self::new(base_dir, DEFAULT_NETWORK_NAME, DEFAULT_INTERFACE, DEFAULT_PORT)
+    }
+
+    /// Network and interface names end up in paths and scripts, so tinc's rules for node names
+    /// are applied to them.
+    pub fn is_valid(&self) -> bool {
+        let is_name = |name: &str| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        is_name(&self.name) && is_name(&self.interface) && self.port != 0
+    }
+}
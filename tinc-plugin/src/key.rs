@@ -0,0 +1,329 @@
+//! Keys of tinc nodes.
+//!
+//! tinc 1.1 authenticates peers with RSA keys on the legacy protocol and with Ed25519 keys on
+//! SPTPS. A host file may hold both: the RSA key as a PEM block and the Ed25519 key as an
+//! `Ed25519PublicKey` line. tinc encodes Ed25519 keys in its own base64, which puts the least
+//! significant bits first.
+
+use std::net::IpAddr;
+
+use openssl::error::ErrorStack;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+
+use crate::info::ConnectTo;
+
+/// Prefix of an Ed25519 public key in DER, as written by openssl.
+const ED25519_PUBLIC_DER_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+/// Prefix of an Ed25519 private key in DER, as written by openssl.
+const ED25519_PRIVATE_DER_PREFIX: &[u8] = &[
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04,
+    0x20,
+];
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URLSAFE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+const ED25519_HOST_VARIABLE: &str = "Ed25519PublicKey";
+/// Length of an Ed25519 public key in the base64 of tinc.
+const ED25519_PUBLIC_KEY_LEN: usize = 43;
+
+/// A public key received from another node is invalid.
+#[derive(err_derive::Error, Debug, Clone, Eq, PartialEq)]
+pub enum KeyError {
+    #[error(display = "Invalid Ed25519 public key")]
+    InvalidEd25519,
+
+    #[error(display = "Invalid RSA public key")]
+    InvalidRsa,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+    /// 2048 bit RSA, used by the legacy protocol.
+    Rsa,
+    /// Ed25519, used by SPTPS.
+    Ed25519,
+}
+
+impl Default for KeyType {
+    /// Every node has had an RSA key, from before Ed25519 keys were supported.
+    fn default() -> Self {
+        KeyType::Rsa
+    }
+}
+
+/// Public keys of a node, in the form they take in host files.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PublicKeys {
+    /// RSA public key in PEM.
+    #[serde(default)]
+    pub rsa:        Option<String>,
+    /// Ed25519 public key in the base64 of tinc.
+    #[serde(default)]
+    pub ed25519:    Option<String>,
+}
+
+impl PublicKeys {
+    pub fn has(&self, key_type: KeyType) -> bool {
+        match key_type {
+            KeyType::Rsa => self.rsa.is_some(),
+            KeyType::Ed25519 => self.ed25519.is_some(),
+        }
+    }
+
+    /// Picks the key type to use with a node holding `other`: `preferred` if both nodes have a
+    /// key of that type, otherwise any type both have.
+    pub fn negotiate(&self, other: &PublicKeys, preferred: KeyType) -> Option<KeyType> {
+        let common = |key_type| self.has(key_type) && other.has(key_type);
+        if common(preferred) {
+            return Some(preferred);
+        }
+        [KeyType::Ed25519, KeyType::Rsa].iter().cloned().find(|key_type| common(*key_type))
+    }
+
+    /// The keys as written in a host file.
+    pub fn to_host_config(&self) -> String {
+        let mut config = String::new();
+        if let Some(ed25519) = &self.ed25519 {
+            config += &format!("{} = {}\n", ED25519_HOST_VARIABLE, ed25519);
+        }
+        if let Some(rsa) = &self.rsa {
+            config += rsa.trim_end();
+            config.push('\n');
+        }
+        config
+    }
+
+    /// Checks keys received from another node, and returns them as written from the parsed keys,
+    /// so that nothing but the keys ends up in a host file.
+    pub fn validate(&self) -> std::result::Result<PublicKeys, KeyError> {
+        let ed25519 = match &self.ed25519 {
+            Some(key) => Some(validate_ed25519(key)?),
+            None => None,
+        };
+        let rsa = match &self.rsa {
+            Some(key) => Some(validate_rsa(key)?),
+            None => None,
+        };
+        Ok(PublicKeys { rsa, ed25519 })
+    }
+
+    /// Reads the keys of a host file, ignoring everything else in it.
+    pub fn from_host_config(config: &str) -> Self {
+        let mut keys = PublicKeys::default();
+        let mut pem: Option<String> = None;
+        for line in config.lines().map(str::trim) {
+            if let Some(block) = pem.as_mut() {
+                block.push_str(line);
+                block.push('\n');
+                if line.starts_with("-----END") {
+                    keys.rsa = pem.take();
+                }
+            } else if line.starts_with("-----BEGIN") {
+                pem = Some(format!("{}\n", line));
+            } else if let Some(value) = host_variable(line, ED25519_HOST_VARIABLE) {
+                keys.ed25519 = Some(value.to_string());
+            }
+        }
+        keys
+    }
+}
+
+/// Answer of a proxy to a key exchange, along with the addresses it assigned to the node.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct KeyExchange {
+    /// The key type the nodes agreed on.
+    pub key_type:   KeyType,
+    /// Public keys of the answering node.
+    pub keys:       PublicKeys,
+    /// VIP assigned to the node that started the exchange.
+    pub vip:        IpAddr,
+    /// Prefix length of the network the VIP is assigned from.
+    pub prefix:     u8,
+    /// VIP of the proxy, the gateway of the node.
+    pub gateway:    IpAddr,
+    /// Name of the tinc node of the proxy, and of its host file.
+    pub node:       String,
+    /// Other proxies of the network, to fail over to when the answering one is down.
+    #[serde(default)]
+    pub proxies:    Vec<ConnectTo>,
+}
+
+/// Ed25519 key pair in the form tinc keeps it.
+pub struct Ed25519Key {
+    /// The expanded private key, the clamped SHA-512 hash of the seed.
+    private:        [u8; 64],
+    public:         [u8; 32],
+}
+
+impl Ed25519Key {
+    pub fn generate() -> std::result::Result<Self, ErrorStack> {
+        let key = PKey::generate_ed25519()?;
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&key.private_key_to_der()?[ED25519_PRIVATE_DER_PREFIX.len()..]);
+        let mut public = [0u8; 32];
+        public.copy_from_slice(&key.public_key_to_der()?[ED25519_PUBLIC_DER_PREFIX.len()..]);
+        Ok(Self::from_seed(&seed, public))
+    }
+
+    fn from_seed(seed: &[u8; 32], public: [u8; 32]) -> Self {
+        let mut private = openssl::sha::sha512(seed);
+        private[0] &= 248;
+        private[31] &= 63;
+        private[31] |= 64;
+        Ed25519Key { private, public }
+    }
+
+    /// The public key as written in host files.
+    pub fn public_key(&self) -> String {
+        encode(&self.public, BASE64_URLSAFE)
+    }
+
+    /// The key pair as written in `ed25519_key.priv`.
+    pub fn to_pem(&self) -> String {
+        let mut key = self.private.to_vec();
+        key.extend_from_slice(&self.public);
+        let mut pem = "-----BEGIN ED25519 PRIVATE KEY-----\n".to_string();
+        for line in key.chunks(48) {
+            pem += &encode(line, BASE64);
+            pem.push('\n');
+        }
+        pem + "-----END ED25519 PRIVATE KEY-----\n"
+    }
+}
+
+/// An Ed25519 public key in the base64 of tinc, which takes both alphabets.
+fn validate_ed25519(key: &str) -> std::result::Result<String, KeyError> {
+    let is_base64 = |c: u8| BASE64.contains(&c) || BASE64_URLSAFE.contains(&c);
+    if key.len() != ED25519_PUBLIC_KEY_LEN || !key.bytes().all(is_base64) {
+        return Err(KeyError::InvalidEd25519);
+    }
+    Ok(key.to_string())
+}
+
+/// A single PEM block holding an RSA public key, in either form tinc reads.
+fn validate_rsa(key: &str) -> std::result::Result<String, KeyError> {
+    let key = key.trim();
+    if !key.starts_with("-----BEGIN ")
+        || !key.ends_with("-----")
+        || key.matches("-----BEGIN ").count() != 1
+        || key.matches("-----END ").count() != 1
+    {
+        return Err(KeyError::InvalidRsa);
+    }
+    let rsa = Rsa::public_key_from_pem_pkcs1(key.as_bytes())
+        .or_else(|_| Rsa::public_key_from_pem(key.as_bytes()))
+        .map_err(|_| KeyError::InvalidRsa)?;
+    let pem = rsa.public_key_to_pem_pkcs1().map_err(|_| KeyError::InvalidRsa)?;
+    String::from_utf8(pem).map_err(|_| KeyError::InvalidRsa)
+}
+
+/// Value of `variable` if `line` sets it.
+fn host_variable<'a>(line: &'a str, variable: &str) -> Option<&'a str> {
+    match crate::conf::split_variable(line) {
+        (name, value) if name.eq_ignore_ascii_case(variable) => value,
+        _ => None,
+    }
+}
+
+/// Base64 as tinc encodes it, least significant bits first and without padding.
+fn encode(data: &[u8], alphabet: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 4 + 2) / 3);
+    for chunk in data.chunks(3) {
+        let triplet = chunk
+            .iter()
+            .rev()
+            .fold(0u32, |triplet, byte| triplet << 8 | u32::from(*byte));
+        for i in 0..=chunk.len() {
+            encoded.push(alphabet[(triplet >> (6 * i) & 63) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(&[], BASE64), "");
+        assert_eq!(encode(&[1], BASE64), "BA");
+        assert_eq!(encode(&[1, 0, 0], BASE64), "BAAA");
+        assert_eq!(encode(&[0, 0, 0x80], BASE64), "AAAg");
+        assert_eq!(encode(&[0xff, 0xff], BASE64_URLSAFE), "__P");
+        assert_eq!(encode(&[0xfb; 32], BASE64_URLSAFE).len(), 43);
+    }
+
+    #[test]
+    fn test_ed25519_pem() {
+        let key = Ed25519Key::from_seed(&[7; 32], [9; 32]);
+        assert_eq!(key.private[0] & 7, 0);
+        assert_eq!(key.private[31] & 0xc0, 0x40);
+        let pem = key.to_pem();
+        let lines: Vec<&str> = pem.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "-----BEGIN ED25519 PRIVATE KEY-----");
+        assert_eq!(lines[1].len(), 64);
+        assert!(lines[2].ends_with(&encode(&[9; 30], BASE64)));
+    }
+
+    #[test]
+    fn test_host_config() {
+        let keys = PublicKeys {
+            rsa: Some(
+                "-----BEGIN RSA PUBLIC KEY-----\nMIIB\n-----END RSA PUBLIC KEY-----\n".into(),
+            ),
+            ed25519: Some("abc-_".into()),
+        };
+        let config = "Address=1.2.3.4\nPort=50069\n".to_string() + &keys.to_host_config();
+        assert_eq!(PublicKeys::from_host_config(&config), keys);
+        assert_eq!(
+            PublicKeys::from_host_config("ed25519publickey abc-_\nSubnet = 10.0.0.1"),
+            PublicKeys { rsa: None, ed25519: Some("abc-_".into()) }
+        );
+        assert_eq!(PublicKeys::from_host_config("Ed25519PublicKey =\n"), PublicKeys::default());
+    }
+
+    #[test]
+    fn test_validate() {
+        let ed25519 = encode(&[0xfb; 32], BASE64_URLSAFE);
+        let rsa = Rsa::generate(1024).unwrap();
+        let pkcs1 = String::from_utf8(rsa.public_key_to_pem_pkcs1().unwrap()).unwrap();
+        let spki = String::from_utf8(rsa.public_key_to_pem().unwrap()).unwrap();
+        let keys = |rsa: Option<&str>, ed25519: Option<&str>| PublicKeys {
+            rsa: rsa.map(str::to_string),
+            ed25519: ed25519.map(str::to_string),
+        };
+
+        let valid = keys(Some(&pkcs1), Some(&ed25519));
+        assert_eq!(valid.validate(), Ok(valid.clone()));
+        assert_eq!(keys(Some(&spki), None).validate(), Ok(keys(Some(&pkcs1), None)));
+        assert!(keys(None, Some(&encode(&[0xfb; 32], BASE64))).validate().is_ok());
+
+        let injected = format!("{}\nSubnet = 0.0.0.0/0", ed25519);
+        assert_eq!(keys(None, Some(&injected)).validate(), Err(KeyError::InvalidEd25519));
+        assert_eq!(keys(None, Some(&ed25519[1..])).validate(), Err(KeyError::InvalidEd25519));
+        let injected = format!("{}Subnet = 0.0.0.0/0\n", pkcs1);
+        assert_eq!(keys(Some(&injected), None).validate(), Err(KeyError::InvalidRsa));
+        let two_blocks = format!("{}{}", pkcs1, pkcs1);
+        assert_eq!(keys(Some(&two_blocks), None).validate(), Err(KeyError::InvalidRsa));
+        assert_eq!(keys(Some("-----BEGIN x-----"), None).validate(), Err(KeyError::InvalidRsa));
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let both = PublicKeys { rsa: Some("rsa".into()), ed25519: Some("ed".into()) };
+        let rsa = PublicKeys { rsa: Some("rsa".into()), ed25519: None };
+        let ed25519 = PublicKeys { rsa: None, ed25519: Some("ed".into()) };
+        assert_eq!(both.negotiate(&both, KeyType::Rsa), Some(KeyType::Rsa));
+        assert_eq!(both.negotiate(&both, KeyType::Ed25519), Some(KeyType::Ed25519));
+        assert_eq!(both.negotiate(&rsa, KeyType::Ed25519), Some(KeyType::Rsa));
+        assert_eq!(ed25519.negotiate(&both, KeyType::Rsa), Some(KeyType::Ed25519));
+        assert_eq!(ed25519.negotiate(&rsa, KeyType::Rsa), None);
+    }
+}
@@ -10,9 +10,21 @@ extern crate derive_try_from_primitive;
 mod operator;
 pub use operator::{TincOperator, Error as TincOperatorError};
 mod info;
-pub use info::{TincInfo, TincRunMode, ConnectTo};
+pub use info::{
+    TincInfo, TincRunMode, ConnectTo, TincNetwork, DEFAULT_INTERFACE, DEFAULT_NETWORK_NAME,
+    DEFAULT_PORT, PROXY_GATEWAY,
+};
 pub mod tinc_tcp_stream;
+pub mod dump;
+pub use dump::{
+    NodeStatus, Reachability, SubnetAddress, TincConnection, TincEdge, TincGraph, TincNode,
+    TincSubnet, TincTraffic,
+};
+pub mod conf;
+pub use conf::{ConfChange, TincConf, TincHost, TincOptions};
 pub mod control;
+pub mod key;
+pub use key::{KeyError, KeyExchange, KeyType, PublicKeys};
 pub mod listener;
-pub use self::listener::EventType;
+pub use self::listener::{EventType, TincEvent};
 pub use self::listener::spawn;
@@ -1,9 +1,31 @@
-use std::net::{SocketAddr, TcpListener, TcpStream, Shutdown};
-use std::io::{Error, ErrorKind, Result, Read};
+//! Channel carrying the events reported by the tinc scripts to the tunnel monitor.
+//!
+//! tincd runs `tinc-up`, `tinc-down`, `host-up` and `host-down`, which call `tinc-report` with the
+//! event. Each event is sent as one line of JSON over its own connection. On unix the channel is
+//! a socket in a directory of the tinc home that only its owner may enter. On Windows it is a
+//! loopback TCP socket, and connections must start with a line holding a token that is generated
+//! for every listener and stored in the tinc home next to the address of the socket.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::IpAddr;
+use std::path::Path;
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Largest accepted connection, token included.
+const MAX_FRAME_SIZE: u64 = 4096;
+/// Time a script has to send its event once connected.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
 
-extern crate talpid_ipc;
+#[cfg(unix)]
+const SOCKET_DIRNAME: &str = "event";
+#[cfg(unix)]
+const SOCKET_FILENAME: &str = "event.sock";
+#[cfg(windows)]
+const TOKEN_FILENAME: &str = "event.token";
 
 //use serde::{de::DeserializeOwned, Serialize};
 //#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -16,50 +38,218 @@ pub enum EventType {
     HostDown(String),
 }
 
-fn handle_client(stream: &mut TcpStream) -> Result<EventType> {
-    let res = &mut[0; 128];
-    stream.read(res)?;
-    let event_str = String::from_utf8_lossy(res).to_string().replace("\u{0}", "");
-    let event_vec: Vec<&str> = event_str.split_whitespace().collect();
-
-    let mut out = EventType::Down;
-    match event_vec[0] {
-        _ if event_vec[0] == "Up" => out = EventType::Up,
-        _ if event_vec[0] == "Down" => out = EventType::Down,
-        _ if event_vec[0] == "HostUp" => {
-            if event_vec.len() > 1 {
-                out = EventType::HostUp(event_vec[1].to_string())
-            }
-        },
-        _ if event_vec[0] == "HostDown" => {
-            if event_vec.len() > 1 {
-                out = EventType::HostDown(event_vec[1].to_string())
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TincEvent {
+    pub event:          EventType,
+    /// Seconds since the Unix epoch when the event was reported.
+    pub timestamp:      u64,
+    /// `NODE` of the script environment, the node the event is about.
+    pub node:           Option<String>,
+    /// `REMOTEADDRESS` of the script environment, the real address of the node.
+    pub remote_address: Option<IpAddr>,
+    /// `REMOTEPORT` of the script environment.
+    pub remote_port:    Option<u16>,
+}
+
+impl TincEvent {
+    pub fn new(event: EventType) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0);
+        TincEvent {
+            event,
+            timestamp,
+            node: None,
+            remote_address: None,
+            remote_port: None,
+        }
+    }
+
+    /// Creates an event carrying the variables tincd passes to its scripts. Variables that
+    /// aren't set or can't be parsed are left out.
+    pub fn with_env(event: EventType, env: &HashMap<String, String>) -> Self {
+        TincEvent {
+            node: env.get("NODE").cloned(),
+            remote_address: env.get("REMOTEADDRESS").and_then(|address| address.parse().ok()),
+            remote_port: env.get("REMOTEPORT").and_then(|port| port.parse().ok()),
+            ..Self::new(event)
+        }
+    }
+}
+
+/// Listens for events on the channel of `tinc_home`. Listening stops after a `Down` event.
+#[cfg(unix)]
+pub fn spawn(tinc_home: &Path) -> Result<mpsc::Receiver<TincEvent>> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+
+    // The socket is reachable as soon as it is bound, so it is locked down by its directory
+    // rather than by its own mode.
+    let dir = tinc_home.join(SOCKET_DIRNAME);
+    if let Err(e) = fs::DirBuilder::new().mode(0o700).create(&dir) {
+        if e.kind() != ErrorKind::AlreadyExists {
+            return Err(e);
+        }
+    }
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+    let path = dir.join(SOCKET_FILENAME);
+    // Left behind by a listener that didn't get to clean up.
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+
+    let (tinc_event_tx, tinc_event_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let event = stream.and_then(|stream| {
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                read_event(stream, None)
+            });
+            if !forward(event, &tinc_event_tx) {
+                break;
             }
-        },
-        _ => (return Err(Error::new(ErrorKind::InvalidData, "")))
-    };
-    return Ok(out);
+        }
+        let _ = fs::remove_file(&path);
+    });
+    Ok(tinc_event_rx)
 }
 
-fn listen(addr: SocketAddr, tinc_event_tx: mpsc::Sender<EventType>) {
-    let listener = TcpListener::bind(&addr).expect("");
-    for res_stream in listener.incoming() {
-        if let Ok(mut stream) = res_stream {
-            if let Ok(event) = handle_client(&mut stream) {
-                log::info!("Tinc event {:?}", event);
-                let _ = tinc_event_tx.send(event.clone());
-                if event == EventType::Down {
-                    let _ = stream.shutdown(Shutdown::Both);
-                    break;
-                }
+/// Listens for events on the channel of `tinc_home`. Listening stops after a `Down` event.
+#[cfg(windows)]
+pub fn spawn(tinc_home: &Path) -> Result<mpsc::Receiver<TincEvent>> {
+    use std::net::{SocketAddr, TcpListener};
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+    let token = new_token()?;
+    let path = tinc_home.join(TOKEN_FILENAME);
+    fs::write(&path, format!("{} {}", listener.local_addr()?, token))?;
+
+    let (tinc_event_tx, tinc_event_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let event = stream.and_then(|stream| {
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                read_event(stream, Some(&token))
+            });
+            if !forward(event, &tinc_event_tx) {
+                break;
             }
         }
+        let _ = fs::remove_file(&path);
+    });
+    Ok(tinc_event_rx)
+}
+
+/// Sends `event` to the listener of `tinc_home`.
+#[cfg(unix)]
+pub fn send(tinc_home: &Path, event: &TincEvent) -> Result<()> {
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(tinc_home.join(SOCKET_DIRNAME).join(SOCKET_FILENAME))?;
+    stream.write_all(frame(event)?.as_bytes())
+}
+
+/// Sends `event` to the listener of `tinc_home`.
+#[cfg(windows)]
+pub fn send(tinc_home: &Path, event: &TincEvent) -> Result<()> {
+    use std::net::{SocketAddr, TcpStream};
+
+    let channel = fs::read_to_string(tinc_home.join(TOKEN_FILENAME))?;
+    let mut channel = channel.split_whitespace();
+    let addr: SocketAddr = channel
+        .next()
+        .and_then(|addr| addr.parse().ok())
+        .ok_or_else(|| invalid("No address in the event token file"))?;
+    let token = channel.next().ok_or_else(|| invalid("No token in the event token file"))?;
+    let mut stream = TcpStream::connect(&addr)?;
+    stream.write_all(format!("{}\n{}", token, frame(event)?).as_bytes())
+}
+
+fn frame(event: &TincEvent) -> Result<String> {
+    let mut frame = serde_json::to_string(event)?;
+    frame.push('\n');
+    Ok(frame)
+}
+
+/// Hands an event over to the monitor, or logs why it was dropped. Returns `false` when the
+/// listener should stop.
+fn forward(event: Result<TincEvent>, tinc_event_tx: &mpsc::Sender<TincEvent>) -> bool {
+    match event {
+        Ok(event) => {
+            log::info!("Tinc event {:?}", event);
+            let down = event.event == EventType::Down;
+            tinc_event_tx.send(event).is_ok() && !down
+        }
+        Err(e) => {
+            log::warn!("Dropping invalid tinc event: {}", e);
+            true
+        }
     }
 }
 
-pub fn spawn() -> mpsc::Receiver<EventType> {
-    let (tinc_event_tx, tinc_event_rx) = mpsc::channel();
-    let addr = SocketAddr::from(([127, 0, 0, 1], 50070));
-    thread::spawn(move ||listen(addr, tinc_event_tx));
-    tinc_event_rx
+/// Reads the event sent over a connection. When `token` is given, the event must be preceded by
+/// a line holding it.
+fn read_event<R: Read>(stream: R, token: Option<&str>) -> Result<TincEvent> {
+    let mut reader = BufReader::new(stream.take(MAX_FRAME_SIZE));
+    if let Some(token) = token {
+        let received = read_frame(&mut reader)?;
+        if received.len() != token.len()
+            || !openssl::memcmp::eq(received.as_bytes(), token.as_bytes())
+        {
+            return Err(invalid("Wrong token"));
+        }
+    }
+    let frame = read_frame(&mut reader)?;
+    serde_json::from_str(&frame).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn read_frame<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut frame = String::new();
+    reader.read_line(&mut frame)?;
+    if !frame.ends_with('\n') {
+        return Err(invalid("Incomplete or oversized event"));
+    }
+    frame.pop();
+    Ok(frame)
+}
+
+#[cfg(windows)]
+fn new_token() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    openssl::rand::rand_bytes(&mut bytes).map_err(|e| Error::new(ErrorKind::Other, e))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_event() {
+        let mut env = HashMap::new();
+        env.insert("NODE".to_string(), "node_1".to_string());
+        env.insert("REMOTEADDRESS".to_string(), "192.168.1.2".to_string());
+        env.insert("REMOTEPORT".to_string(), "unknown".to_string());
+        let event = TincEvent::with_env(EventType::HostUp("node_1".to_string()), &env);
+        assert_eq!(event.remote_address, Some("192.168.1.2".parse().unwrap()));
+        assert_eq!(event.remote_port, None);
+
+        let frame = serde_json::to_string(&event).unwrap() + "\n";
+        assert_eq!(read_event(frame.as_bytes(), None).unwrap(), event);
+
+        let with_token = format!("secret\n{}", frame);
+        assert_eq!(read_event(with_token.as_bytes(), Some("secret")).unwrap(), event);
+        assert!(read_event(with_token.as_bytes(), Some("public")).is_err());
+        assert!(read_event(frame.as_bytes(), Some("secret")).is_err());
+
+        assert!(read_event(&b""[..], None).is_err());
+        assert!(read_event(&b"Up\n"[..], None).is_err());
+        assert!(read_event(frame.trim_end().as_bytes(), None).is_err());
+        let oversized = " ".repeat(MAX_FRAME_SIZE as usize) + &frame;
+        assert!(read_event(oversized.as_bytes(), None).is_err());
+    }
 }
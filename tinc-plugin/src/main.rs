@@ -2,13 +2,16 @@ extern crate tinc_plugin;
 use tinc_plugin::control::dump_connections;
 use tinc_plugin::listener::spawn;
 
+use std::env;
+use std::path::Path;
 use std::thread;
 use std::sync::mpsc::Receiver;
-use tinc_plugin::EventType;
+use tinc_plugin::TincEvent;
 use std::time::Duration;
 
-fn test() -> Receiver<EventType> {
-    let a = spawn();
+fn test() -> Receiver<TincEvent> {
+    let tinc_home = env::args().nth(1).unwrap_or_else(|| ".".to_string());
+    let a = spawn(Path::new(&tinc_home)).expect("Unable to listen for tinc events");
     a
 }
 
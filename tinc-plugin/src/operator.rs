@@ -6,20 +6,27 @@
 use std::ffi::OsString;
 use std::fs;
 use std::io::{self, Write, Read};
+use std::path::Path;
 use std::sync::Mutex;
 use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use duct;
 use openssl::rsa::Rsa;
 
-use crate::{TincInfo, TincRunMode};
+use crate::{
+    ConnectTo, KeyType, PublicKeys, TincInfo, TincNetwork, TincRunMode, DEFAULT_PORT,
+};
+use crate::conf::{
+    self, BindAddress, ConfChange, ProcessPriority, TincConf, TincHost, TincMode, TincOptions,
+};
+use crate::key::{Ed25519Key, KeyError};
 
 /// Results from fallible operations on the Tinc tunnel.
 pub type Result<T> = std::result::Result<T, Error>;
 
-static mut EL: *mut TincOperator = 0 as *mut _;
-
 #[cfg(unix)]
 const TINC_BIN_FILENAME: &str = "tincd";
 #[cfg(windows)]
@@ -29,6 +36,10 @@ const PRIV_KEY_FILENAME: &str = "rsa_key.priv";
 
 const PUB_KEY_FILENAME: &str = "rsa_key.pub";
 
+const ED25519_PRIV_KEY_FILENAME: &str = "ed25519_key.priv";
+
+const ED25519_PUB_KEY_FILENAME: &str = "ed25519_key.pub";
+
 #[cfg(unix)]
 const TINC_UP_FILENAME: &str = "tinc-up";
 #[cfg(windows)]
@@ -51,6 +62,10 @@ const HOST_DOWN_FILENAME: &str = "host-down.bat";
 
 const PID_FILENAME: &str = "tinc.pid";
 
+/// Time tincd left running by a previous run has to exit once asked to stop.
+const STALE_TINC_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+const STALE_TINC_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 const TINC_AUTH_PATH: &str = "auth/";
 
 const TINC_AUTH_FILENAME: &str = "auth.txt";
@@ -66,7 +81,7 @@ pub enum Error {
     #[error(display = "duct can not start tinc")]
     StartTincError,
 
-    #[error(display = "duct can not start tinc")]
+    #[error(display = "tincd of a previous run is still running")]
     AnotherTincRunning,
 
     /// Unable to stop
@@ -125,84 +140,139 @@ pub enum Error {
     ///
     #[error(display = "Permissions error")]
     VnicNotFind(String),
+
+    /// A configuration or host file is invalid
+    #[error(display = "Invalid tinc configuration")]
+    InvalidConf(#[error(cause)] conf::Error),
+
+    /// A host file from another node sets more than its keys
+    #[error(display = "Host file sets variables other than the public keys")]
+    UnexpectedHostVariables,
+
+    /// A public key from another node is invalid
+    #[error(display = "Invalid public key")]
+    InvalidPublicKey(#[error(cause)] KeyError),
 }
 
-/// Tinc operator
+/// Tinc operator of one network. Operators of different networks don't share any state, and an
+/// operator can be shared between threads.
 pub struct TincOperator {
-    tinc_home:              String,
-    tinc_handle:            Option<duct::Handle>,
+    network:                TincNetwork,
+    tinc_handle:            Mutex<Option<duct::Handle>>,
+    /// Info last written by `set_info_to_local`.
+    info:                   Mutex<Option<TincInfo>>,
     mutex:                  Mutex<i32>,
     mode:                   TincRunMode,
 }
 
 impl TincOperator {
     /// 获取tinc home dir 创建tinc操作。
-    pub fn new(tinc_home: &str, mode: TincRunMode) {
-        let operator = TincOperator {
-            tinc_home:      tinc_home.to_string() + "/tinc/",
-            tinc_handle:    None,
+    pub fn new(network: TincNetwork, mode: TincRunMode) -> Self {
+        TincOperator {
+            network,
+            tinc_handle:    Mutex::new(None),
+            info:           Mutex::new(None),
             mutex:          Mutex::new(0),
             mode,
-        };
-
-        unsafe {
-            EL = Box::into_raw(Box::new(operator));
         }
     }
 
-    pub fn instance() ->  &'static mut Self {
-        unsafe {
-            if EL == 0 as *mut _ {
-                panic!("Get tinc Operator instance, before init");
-            }
-            &mut *EL
-        }
+    pub fn network(&self) -> &TincNetwork {
+        &self.network
     }
 
-    pub fn is_inited() -> bool {
-        unsafe {
-            if EL == 0 as *mut _ {
-                return false;
-            }
-        }
-        return true;
+    /// Path of `name` in the home of the network.
+    fn path(&self, name: &str) -> String {
+        self.network.home.join(name).to_string_lossy().to_string()
+    }
+
+    /// Pid file of tincd, also holding the cookie and port of its control socket.
+    pub fn pid_file(&self) -> String {
+        self.path(PID_FILENAME)
     }
 
     /// 启动tinc 返回duct::handle
-    pub fn start_tinc(&mut self) -> Result<()> {
-        let conf_tinc_home = "--config=".to_string() + &self.tinc_home;
-        let conf_pidfile = "--pidfile=".to_string() + &self.tinc_home + "/tinc.pid";
+    ///
+    /// The output of tincd is appended to `log_path`, or discarded without one.
+    pub fn start_tinc(&self, log_path: Option<&Path>) -> Result<()> {
+        self.remove_stale_pid_file()?;
+
+        let conf_tinc_home = "--config=".to_string() + &self.network.home.to_string_lossy();
+        let conf_pidfile = "--pidfile=".to_string() + &self.pid_file();
         let argument: Vec<&str> = vec![
             &conf_tinc_home,
             &conf_pidfile,
             "--no-detach",
         ];
         let duct_handle: duct::Expression = duct::cmd(
-            OsString::from(self.tinc_home.to_string() + "/" + TINC_BIN_FILENAME),
-            argument).unchecked();
-        self.tinc_handle = Some(duct_handle.stderr_null().stdout_null().start()
+            OsString::from(self.path(TINC_BIN_FILENAME)),
+            argument).unchecked().stdin_null();
+        let duct_handle = match log_path {
+            Some(log_path) => {
+                let log = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(log_path)
+                    .map_err(|e| Error::FileCreateError(
+                        log_path.to_string_lossy().to_string() + " " + &e.to_string()))?;
+                duct_handle.stderr_to_stdout().stdout_file(log)
+            }
+            None => duct_handle.stderr_null().stdout_null(),
+        };
+        let handle = duct_handle.start()
             .map_err(|e| {
                 log::error!("StartTincError {:?}", e.to_string());
                 Error::StartTincError
-            })?
-        );
+            })?;
+        *self.tinc_handle.lock().unwrap() = Some(handle);
+        Ok(())
+    }
+
+    /// A pid file left behind means tincd of a previous run didn't exit cleanly, and may still
+    /// be running. It is asked to stop, and its pid file removed once it has exited, so that it
+    /// can't be mistaken for the tincd about to start. Fails if it is still running after
+    /// `STALE_TINC_EXIT_TIMEOUT`.
+    fn remove_stale_pid_file(&self) -> Result<()> {
+        let pid_file = self.pid_file();
+        if !Path::new(&pid_file).exists() {
+            return Ok(());
+        }
+        log::warn!("Removing stale tinc pid file {}", pid_file);
+        if crate::control::stop(&pid_file).is_ok() {
+            log::warn!("Stopping tincd left running by a previous run");
+            let deadline = Instant::now() + STALE_TINC_EXIT_TIMEOUT;
+            // tincd closes its control socket and removes its pid file as it exits.
+            while Path::new(&pid_file).exists() && crate::control::is_running(&pid_file) {
+                if Instant::now() >= deadline {
+                    log::error!("tincd left running by a previous run didn't exit");
+                    return Err(Error::AnotherTincRunning);
+                }
+                thread::sleep(STALE_TINC_POLL_INTERVAL);
+            }
+        }
+        if let Err(e) = fs::remove_file(&pid_file) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::error!("Unable to remove {}: {}", pid_file, e);
+            }
+        }
         Ok(())
     }
 
-    pub fn get_tinc_handle(&mut self) -> Option<duct::Handle> {
-        self.tinc_handle.take()
+    pub fn get_tinc_handle(&self) -> Option<duct::Handle> {
+        self.tinc_handle.lock().unwrap().take()
     }
 
-    pub fn stop_tinc(&mut self) -> Result<()> {
-        if let Some(child) = &self.tinc_handle {
+    pub fn stop_tinc(&self) -> Result<()> {
+        let mut tinc_handle = self.tinc_handle.lock().unwrap();
+        if let Some(child) = &*tinc_handle {
             child.kill().map_err(|_|Error::StopTincError)?
         }
-        self.tinc_handle = None;
+        *tinc_handle = None;
         Ok(())
     }
 
-    pub fn check_tinc_status(&mut self) -> Result<()> {
-        if let Some(child) = &self.tinc_handle {
+    pub fn check_tinc_status(&self) -> Result<()> {
+        if let Some(child) = &*self.tinc_handle.lock().unwrap() {
             let out = child.try_wait()
                 .map_err(|_|Error::TincNotExist)?;
 
@@ -213,11 +283,11 @@ impl TincOperator {
         Err(Error::TincNotExist)
     }
 
-    pub fn restart_tinc(&mut self) -> Result<()> {
+    pub fn restart_tinc(&self) -> Result<()> {
         if let Ok(_) = self.check_tinc_status() {
             self.stop_tinc()?;
         }
-        self.start_tinc()
+        self.start_tinc(None)
     }
 
     /// 根据IP地址获取文件名
@@ -237,15 +307,19 @@ impl TincOperator {
         filename
     }
 
-    /// 添加子设备
+    /// 添加子设备. `pub_key` is host file text from the node, which may only hold its public keys:
+    /// addresses, subnets and other variables are rejected, and only the validated keys are
+    /// written.
     pub fn add_hosts(&self, host_name: &str, pub_key: &str) -> Result<()> {
+        let host = TincHost::parse(pub_key).map_err(Error::InvalidConf)?;
+        let keys_only = TincHost { keys: host.keys.clone(), ..TincHost::default() };
+        if host != keys_only {
+            return Err(Error::UnexpectedHostVariables);
+        }
+        let keys = host.keys.validate().map_err(Error::InvalidPublicKey)?;
+        let host = TincHost { keys, ..TincHost::default() };
         let _guard = self.mutex.lock().unwrap();
-        let path = format!("{}/{}/{}", self.tinc_home.clone() , "hosts", host_name);
-        let mut file = fs::File::create(&path)
-            .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))?;
-        file.write_all(pub_key.as_bytes())
-            .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))?;
-        drop(file);
+        self.write_host(host_name, &host)?;
         Ok(())
     }
 
@@ -256,11 +330,8 @@ impl TincOperator {
         if let Ok(key) = Rsa::generate(2048) {
             if let Ok(priv_key) = key.private_key_to_pem() {
                 if let Ok(priv_key) = String::from_utf8(priv_key) {
-                    let mut file = fs::File::create(
-                        self.tinc_home.to_string() + PRIV_KEY_FILENAME)
-                        .map_err(|e|
-                            Error::FileCreateError((self.tinc_home.to_string() + PRIV_KEY_FILENAME)
-                                + " " + &e.to_string()))?;
+                    let path = self.path(PRIV_KEY_FILENAME);
+                    let mut file = create_private_file(&path)?;
                     file.write_all(priv_key.as_bytes())
                         .map_err(|_|Error::CreatePubKeyError)?;
                     drop(file);
@@ -270,7 +341,7 @@ impl TincOperator {
             }
             if let Ok(pub_key) = key.public_key_to_pem() {
                 if let Ok(pub_key) = String::from_utf8(pub_key) {
-                    let path = self.tinc_home.to_string() + PUB_KEY_FILENAME;
+                    let path = self.path(PUB_KEY_FILENAME);
                     let mut file = fs::File::create(&path)
                         .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))?;
                     file.write_all(pub_key.as_bytes())
@@ -285,10 +356,54 @@ impl TincOperator {
         Err(Error::CreatePubKeyError)
     }
 
+    /// Creates an Ed25519 key pair, replacing the current one.
+    pub fn create_ed25519_key(&self) -> Result<()> {
+        let _guard = self.mutex.lock().unwrap();
+        let key = Ed25519Key::generate().map_err(|_| Error::CreatePubKeyError)?;
+
+        let path = self.path(ED25519_PRIV_KEY_FILENAME);
+        let mut file = create_private_file(&path)?;
+        file.write_all(key.to_pem().as_bytes())
+            .map_err(|_|Error::CreatePubKeyError)?;
+
+        let path = self.path(ED25519_PUB_KEY_FILENAME);
+        fs::write(&path, key.public_key())
+            .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))
+    }
+
+    /// Creates a key pair of `key_type`, replacing the current one.
+    pub fn create_key(&self, key_type: KeyType) -> Result<()> {
+        match key_type {
+            KeyType::Rsa => self.create_pub_key(),
+            KeyType::Ed25519 => self.create_ed25519_key(),
+        }
+    }
+
+    /// Public keys of all the key pairs there are.
+    pub fn get_local_pub_keys(&self) -> Result<PublicKeys> {
+        let _guard = self.mutex.lock().unwrap();
+        let read = |filename: &str| -> Result<Option<String>> {
+            let path = self.path(filename);
+            match fs::read_to_string(&path) {
+                Ok(key) => Ok(Some(key.trim_end().to_string())),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(Error::IoError(path + " " + &e.to_string())),
+            }
+        };
+        let keys = PublicKeys {
+            rsa: read(PUB_KEY_FILENAME)?,
+            ed25519: read(ED25519_PUB_KEY_FILENAME)?,
+        };
+        if keys.rsa.is_none() && keys.ed25519.is_none() {
+            return Err(Error::FileNotExist(self.path(PUB_KEY_FILENAME)));
+        }
+        Ok(keys)
+    }
+
     /// 从pub_key文件读取pub_key
     pub fn get_local_pub_key(&self) -> Result<String> {
         let _guard = self.mutex.lock().unwrap();
-        let path = self.tinc_home.clone() + PUB_KEY_FILENAME;
+        let path = self.path(PUB_KEY_FILENAME);
         let mut file =  fs::File::open(path.clone())
             .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
         let mut buf = String::new();
@@ -298,9 +413,9 @@ impl TincOperator {
     }
 
     /// 修改本地公钥
-    pub fn set_local_pub_key(&mut self, pub_key: &str) -> Result<()> {
+    pub fn set_local_pub_key(&self, pub_key: &str) -> Result<()> {
         let _guard = self.mutex.lock().unwrap();
-        let path = self.tinc_home.clone() + PUB_KEY_FILENAME;
+        let path = self.path(PUB_KEY_FILENAME);
         let mut file =  fs::File::create(path.clone())
             .map_err(|_|Error::CreatePubKeyError)?;
         file.write(pub_key.as_bytes())
@@ -309,103 +424,83 @@ impl TincOperator {
     }
 
     /// 获取本地tinc虚拟ip
+    ///
+    /// Without info written by this operator, e.g. on a proxy whose configuration is kept by
+    /// hand, the VIP is the single address subnet of the host file of this node.
     pub fn get_local_vip(&self) -> Result<IpAddr> {
-        let _guard = self.mutex.lock().unwrap();
-        let mut out = String::new();
-
-        let path = self.tinc_home.clone() + TINC_UP_FILENAME;
-        let mut file = fs::File::open(path.clone())
-            .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
-
-        let mut res = String::new();
-        file.read_to_string(&mut res)
-            .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
-        #[cfg(unix)]
-            let res: Vec<&str> = res.split("vpngw=").collect();
-        #[cfg(windows)]
-            let res: Vec<&str> = res.split("addr=").collect();
-        if res.len() > 1 {
-            let res = res[1].to_string();
-            #[cfg(unix)]
-            let res: Vec<&str> = res.split("\n").collect();
-            #[cfg(windows)]
-            let res: Vec<&str> = res.split(" mask").collect();
-            if res.len() > 1 {
-                out = res[0].to_string();
-            }
+        if let Some(info) = self.get_local_info() {
+            return Ok(info.vip);
         }
-        Ok(IpAddr::from(Ipv4Addr::from_str(&out).map_err(Error::ParseLocalVipError)?))
+        let host = self.get_node_name().and_then(|node| self.get_host(&node))?;
+        host.subnets
+            .iter()
+            .find_map(|subnet| single_address(subnet))
+            .ok_or(Error::TincInfoError)
+    }
+
+    /// Info the tinc configuration was last written from, if any.
+    pub fn get_local_info(&self) -> Option<TincInfo> {
+        self.info.lock().unwrap().clone()
     }
 
     /// 通过Info修改tinc.conf
-    fn set_tinc_conf_file(&self, tinc_info: &TincInfo) -> Result<()> {
+    fn set_tinc_conf_file(&self, tinc_info: &TincInfo, options: &TincOptions)
+        -> Result<ConfChange>
+    {
         let _guard = self.mutex.lock().unwrap();
 
-        let (is_proxy, name_ip) = match self.mode {
-            TincRunMode::Proxy => (true, tinc_info.ip.clone()),
-            TincRunMode::Client => (false, tinc_info.vip.clone()),
-        };
-
-        let name = Self::get_filename_by_ip(is_proxy,
-                                            &name_ip.to_string());
-
-        let mut connect_to: Vec<String> = vec![];
-        for online_proxy in tinc_info.connect_to.clone() {
-            let online_proxy_name = Self::get_filename_by_ip(true,
-                                                             &online_proxy.ip.to_string());
-            connect_to.push(online_proxy_name);
-        }
-
-
-        let mut buf_connect_to = String::new();
-        for other in connect_to {
-            let buf = "ConnectTo = ".to_string() + &other + "\n";
-            buf_connect_to += &buf;
-        }
-
-        let buf;
+        let device;
         #[cfg(target_os = "linux")]
         {
-            buf = "Name = ".to_string() + &name + "\n"
-                + &buf_connect_to
-                + "DeviceType=tap\n\
-                   Mode=switch\n\
-                   Interface=dnet\n\
-                   BindToAddress = * 50069\n\
-                   ProcessPriority = high\n\
-                   PingTimeout=10\n\
-                   Device = /dev/net/tun\n";
+            device = Some("/dev/net/tun".to_string());
         }
         #[cfg(target_os = "macos")]
         {
-            buf = "Name = ".to_string() + &name + "\n"
-                + &buf_connect_to
-                + "DeviceType=tap\n\
-                   Mode=switch\n\
-                   Interface=dnet\n\
-                   BindToAddress = * 50069\n\
-                   ProcessPriority = high\n\
-                   PingTimeout=10\n\
-                   Device = /dev/tap0\n";
+            device = Some("/dev/tap0".to_string());
         }
         #[cfg(windows)]
         {
-            buf = "Name = ".to_string() + &name + "\n"
-                + &buf_connect_to
-                + "DeviceType=tap\n\
-                   Mode=switch\n\
-                   Interface=dnet\n\
-                   BindToAddress = * 50069\n\
-                   ProcessPriority = high\n\
-                   PingTimeout=10";
+            device = None;
         }
 
-        let path = self.tinc_home.clone() + "/tinc.conf";
-        let mut file = fs::File::create(path.clone())
-            .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))?;
-        file.write(buf.as_bytes())
-            .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
-        return Ok(());
+        // tinc uses SPTPS with the nodes whose Ed25519 key it has, unless told otherwise.
+        let experimental_protocol = match (&self.mode, tinc_info.key_type) {
+            (TincRunMode::Client, KeyType::Rsa) => Some(false),
+            _ => None,
+        };
+
+        let conf = TincConf {
+            name: self.local_node_name(tinc_info),
+            connect_to: tinc_info.connect_to
+                .iter()
+                .map(ConnectTo::node_name)
+                .collect(),
+            mode: Some(TincMode::Switch),
+            device_type: Some("tap".to_string()),
+            device,
+            interface: Some(self.network.interface.clone()),
+            bind_to_address: Some(BindAddress {
+                address: None,
+                port: Some(options.port.unwrap_or(self.network.port)),
+            }),
+            process_priority: Some(options.process_priority.unwrap_or(ProcessPriority::High)),
+            ping_interval: options.ping_interval,
+            ping_timeout: Some(options.ping_timeout.unwrap_or(10)),
+            experimental_protocol,
+            other: vec![],
+        };
+
+        let path = self.path("tinc.conf");
+        let old = fs::read_to_string(&path).ok().and_then(|old| TincConf::parse(&old).ok());
+        let change = match old {
+            Some(old) => old.change_to(&conf),
+            None => ConfChange::Restart,
+        };
+        if change != ConfChange::Unchanged {
+            fs::write(&path, conf.to_string())
+                .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
+        }
+        Ok(change)
     }
 
     /// 检查info中的配置, 并与实际运行的tinc配置对比, 如果不同修改tinc配置,
@@ -456,28 +551,61 @@ impl TincOperator {
                      is_proxy: bool,
                      ip: &str,
                      pubkey: &str)
-        -> Result<()>
+        -> Result<ConfChange>
     {
-        let _guard = self.mutex.lock().unwrap();
-        {
-            let mut buf;
-            if is_proxy {
-                buf = "Address=".to_string() + ip + "\n"
-                    + "Port=50069\n"
-                    + pubkey;
-            }
-            else {
-                buf = pubkey.to_string();
-            }
-            let file_name = Self::get_filename_by_ip(is_proxy, ip);
+        let file_name = Self::get_filename_by_ip(is_proxy, ip);
+        if is_proxy {
+            self.set_proxy_host(&file_name, ip, pubkey)
+        }
+        else {
+            self.set_own_host(&file_name, pubkey, &TincOptions::default())
+        }
+    }
 
-            let path = self.tinc_home.clone() + "/hosts/" + &file_name;
-            let mut file = fs::File::create(path.clone())
-                .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))?;
-            file.write(buf.as_bytes())
-                .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
+    /// Writes the host file of the proxy `node`, reached at `ip`.
+    fn set_proxy_host(&self, node: &str, ip: &str, pubkey: &str) -> Result<ConfChange> {
+        let mut host = TincHost::parse(pubkey).map_err(Error::InvalidConf)?;
+        // Proxies listen on the port of the default network, whatever the port of this one.
+        host.addresses = vec![conf::HostAddress { host: ip.to_string(), port: None }];
+        host.port = Some(DEFAULT_PORT);
+        self.update_host(node, &host)
+    }
+
+    /// Writes the host file of this node, which holds the options peers use with it.
+    fn set_own_host(&self, node: &str, pubkey: &str, options: &TincOptions) -> Result<ConfChange> {
+        let mut host = TincHost::parse(pubkey).map_err(Error::InvalidConf)?;
+        host.compression = options.compression;
+        host.cipher = options.cipher.clone();
+        host.digest = options.digest.clone();
+        host.pmtu = options.mtu;
+        host.pmtu_discovery = options.pmtu_discovery;
+        host.tcp_only = options.tcp_only;
+        self.update_host(node, &host)
+    }
+
+    fn update_host(&self, host_name: &str, host: &TincHost) -> Result<ConfChange> {
+        let _guard = self.mutex.lock().unwrap();
+        let change = match self.read_host(host_name) {
+            Ok(old) => old.change_to(host),
+            Err(_) => ConfChange::Reload,
+        };
+        if change != ConfChange::Unchanged {
+            self.write_host(host_name, host)?;
         }
-        Ok(())
+        Ok(change)
+    }
+
+    fn read_host(&self, host_name: &str) -> Result<TincHost> {
+        let path = self.path(&format!("hosts/{}", host_name));
+        let contents = fs::read_to_string(&path)
+            .map_err(|_| Error::FileNotExist(path.clone()))?;
+        TincHost::parse(&contents).map_err(Error::InvalidConf)
+    }
+
+    fn write_host(&self, host_name: &str, host: &TincHost) -> Result<()> {
+        let path = self.path(&format!("hosts/{}", host_name));
+        fs::write(&path, host.to_string())
+            .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))
     }
 
     /// 检测自身hosts文件,是否正确
@@ -518,35 +646,81 @@ impl TincOperator {
 //        return Err(Error::L);
 //    }
 
-    pub fn set_info_to_local(&mut self, info: &TincInfo) -> Result<()> {
+    /// Writes the configuration of `info` and `options`, returning what it takes for a running
+    /// tincd to pick it up. Files whose content wouldn't change are left alone.
+    pub fn set_info_to_local(&self, info: &TincInfo, options: &TincOptions)
+        -> Result<ConfChange>
+    {
         self.create_tinc_dirs()?;
 
-        self.set_tinc_conf_file(info)?;
-        let is_proxy = match self.mode {
-            TincRunMode::Proxy => true,
-            TincRunMode::Client => false,
-        };
+        let mut change = self.set_tinc_conf_file(info, options)?;
 
         self.set_tinc_up(&info)?;
         self.set_tinc_down(info)?;
         self.set_host_up()?;
         self.set_host_down()?;
 
-        for online_proxy in info.connect_to.clone() {
-            self.set_hosts(true,
-                           &online_proxy.ip.to_string(),
-                           &online_proxy.pubkey)?;
+        for online_proxy in &info.connect_to {
+            change = change.max(self.set_proxy_host(&online_proxy.node_name(),
+                                                    &online_proxy.ip.to_string(),
+                                                    &online_proxy.pubkey)?);
         };
 
-        let ip;
-        if is_proxy {
-            ip = info.vip.to_string();
-        }
-        else {
-            ip = info.ip.to_string();
+        let node = self.local_node_name(info);
+        change = change.max(self.set_own_host(&node, &info.pub_key, options)?);
+        *self.info.lock().unwrap() = Some(info.clone());
+        Ok(change)
+    }
+
+    /// Name of this node: proxies are named after their IP, clients after their VIP.
+    fn local_node_name(&self, info: &TincInfo) -> String {
+        match self.mode {
+            TincRunMode::Proxy => Self::get_filename_by_ip(true, &info.ip.to_string()),
+            TincRunMode::Client => Self::get_filename_by_ip(false, &info.vip.to_string()),
         }
+    }
+
+    /// Gateway of a client, the VIP of the proxy it connects to first.
+    #[cfg(windows)]
+    fn gateway(tinc_info: &TincInfo) -> IpAddr {
+        tinc_info.connect_to
+            .first()
+            .map(|connect_to| connect_to.vip)
+            .unwrap_or(IpAddr::from(crate::PROXY_GATEWAY))
+    }
+
+    /// Name of this node, as written in `tinc.conf`.
+    pub fn get_node_name(&self) -> Result<String> {
+        let _guard = self.mutex.lock().unwrap();
+        self.read_conf().map(|conf| conf.name)
+    }
 
-        self.set_hosts(is_proxy, &ip, &info.pub_key)
+    /// The nodes named by `ConnectTo` in `tinc.conf`, for clients to fail over to. Nodes whose
+    /// host file has no IP `Address`, or no `Subnet` of a single VIP, are left out.
+    pub fn get_connect_to(&self) -> Result<Vec<ConnectTo>> {
+        let _guard = self.mutex.lock().unwrap();
+        let conf = self.read_conf()?;
+        let connect_to = conf.connect_to
+            .iter()
+            .filter_map(|node| {
+                let host = self.read_host(node).ok()?;
+                let ip = host.addresses
+                    .iter()
+                    .find_map(|address| IpAddr::from_str(&address.host).ok())?;
+                let vip = host.subnets.iter().find_map(|subnet| single_address(subnet))?;
+                let mut connect_to = ConnectTo::new(ip, vip, host.keys.to_host_config());
+                connect_to.node = Some(node.clone());
+                Some(connect_to)
+            })
+            .collect();
+        Ok(connect_to)
+    }
+
+    fn read_conf(&self) -> Result<TincConf> {
+        let path = self.path("tinc.conf");
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| Error::IoError(path.clone() + " " + &e.to_string()))?;
+        TincConf::parse(&contents).map_err(Error::InvalidConf)
     }
 
     fn set_tinc_up(&self, tinc_info: &TincInfo) -> Result<()> {
@@ -559,66 +733,50 @@ impl TincOperator {
 
         let mut buf;
 
+        // Clients get their address and routes from the tunnel monitor once tinc reports up.
         #[cfg(target_os = "linux")]
         {
-            buf = "#! /bin/bash\n\
-            dev=dnet\n\
-            vpngw=".to_string() + &tinc_info.vip.to_string() + "\n" +
-            "ifconfig ${dev} ${vpngw} netmask " + netmask;
-
-            if TincRunMode::Client == self.mode {
-                buf = buf + "\n"
-                    + "route add -host " + &tinc_info.connect_to[0].ip.to_string() + " gw _gateway";
-                buf = buf + "\n"
-                    + "route add -host 10.255.255.254 dev dnet";
-                buf = buf + "\n"
-                    + "route add default gw " + &tinc_info.connect_to[0].vip.to_string();
+            buf = "#! /bin/bash\n".to_string();
+            if TincRunMode::Proxy == self.mode {
+                buf = buf
+                    + "ifconfig " + &self.network.interface + " "
+                    + &tinc_info.vip.to_string() + " netmask " + netmask + "\n";
             }
-
-            buf = buf + "\n" + &self.tinc_home + "/tinc-report -u";
+            buf = buf + &self.path("tinc-report") + " -u";
         }
         #[cfg(target_os = "macos")]
         {
-            buf = "#! /bin/bash\n\
-                   dev=tap0\n\
-                   vpngw=".to_string()
-                   + &tinc_info.vip.to_string() + "\n"
-                   + "ifconfig ${dev} ${vpngw} netmask  " + netmask + "\n";
-
-            if TincRunMode::Client == self.mode {
-                let default_gateway = get_default_gateway()?.to_string();
+            buf = "#! /bin/bash\n".to_string();
+            if TincRunMode::Proxy == self.mode {
                 buf = buf
-                    + "route -q -n delete -net 0.0.0.0\n\
-                    route -q -n add -host " + &tinc_info.connect_to[0].ip.to_string()
-                    + " -gateway " + &default_gateway + "\n"
-                    + "route add -host 10.255.255.254 -interface tap0 -iface -cloning\n"
-                    + "route add -net 0.0.0.0 -gateway 10.255.255.254";
+                    + "ifconfig tap0 " + &tinc_info.vip.to_string() + " netmask " + netmask + "\n";
             }
-
-            buf = buf + &self.tinc_home + "/tinc-report -u\n";
+            buf = buf + &self.path("tinc-report") + " -u";
         }
         #[cfg(windows)]
         {
-            buf = "netsh interface ipv4 set address name=\"dnet\" source=static addr=".to_string() +
+            buf = "netsh interface ipv4 set address name=\"".to_string()
+                + &self.network.interface + "\" source=static addr=" +
                 &tinc_info.vip.to_string() + " mask=" + netmask + "\r\n";
 
             if TincRunMode::Client == self.mode {
                 let default_gateway = get_default_gateway()?.to_string();
-                let vnic_index = format!("{}", get_vnic_index()?);
+                let vnic_index = format!("{}", get_vnic_index(&self.network.interface)?);
+                let gateway = Self::gateway(tinc_info).to_string();
 
                 buf = buf
                     + "route add " + &tinc_info.connect_to[0].ip.to_string()
                         + " mask 255.255.255.255 " + &default_gateway + "\r\n"
-                    + "route add 10.255.255.254 mask 255.255.255.255 10.255.255.254 if "
-                        + &vnic_index + "\r\n"
-                    + "route add 0.0.0.0 mask 0.0.0.0 10.255.255.254 if "
+                    + "route add " + &gateway + " mask 255.255.255.255 "
+                        + &gateway + " if " + &vnic_index + "\r\n"
+                    + "route add 0.0.0.0 mask 0.0.0.0 " + &gateway + " if "
                         + &vnic_index + "\r\n";
             }
 
-            buf = buf + &self.tinc_home + "/tinc-report.exe -u";
+            buf = buf + &self.path("tinc-report.exe") + " -u";
         }
 
-        let path = self.tinc_home.clone() + "/" + TINC_UP_FILENAME;
+        let path = self.path(TINC_UP_FILENAME);
         let mut file = fs::File::create(path.clone())
             .map_err(|e|Error::FileCreateError(path.clone() + " " + &e.to_string()))?;
         file.write(buf.as_bytes())
@@ -631,27 +789,20 @@ impl TincOperator {
     fn set_tinc_down(&self, tinc_info: &TincInfo) -> Result<()> {
         let _guard = self.mutex.lock().unwrap();
         let buf;
-        #[cfg(target_os = "linux")]
-        {
-            buf = "#!/bin/bash\n".to_string() + &self.tinc_home + "/tinc-report -d";
-        }
-        #[cfg(target_os = "macos")]
+        #[cfg(unix)]
         {
-            let default_gateway = get_default_gateway()?.to_string();
-            buf = "#!/bin/bash\n".to_string() + &self.tinc_home + "/tinc-report -d"
-                + "route -n -q delete -host " + &tinc_info.connect_to[0].ip.to_string() + "\n"
-                + "route -n -q delete -net 0.0.0.0 \n\
-                   route -n -q add -net 0.0.0.0 -gateway " + &default_gateway;
+            buf = "#!/bin/bash\n".to_string() + &self.path("tinc-report") + " -d";
         }
         #[cfg(windows)]
         {
-            let vnic_index = format!("{}", get_vnic_index()?);
-            buf = "route delete 0.0.0.0 mask 0.0.0.0 10.255.255.254 if ".to_string()
-                + &vnic_index + "\r\n"
-                + &self.tinc_home.to_string() + "/tinc-report.exe -d";
+            let vnic_index = format!("{}", get_vnic_index(&self.network.interface)?);
+            buf = "route delete 0.0.0.0 mask 0.0.0.0 ".to_string()
+                + &Self::gateway(tinc_info).to_string()
+                + " if " + &vnic_index + "\r\n"
+                + &self.path("tinc-report.exe") + " -d";
         }
 
-        let path = self.tinc_home.clone() + "/" + TINC_DOWN_FILENAME;
+        let path = self.path(TINC_DOWN_FILENAME);
         let mut file = fs::File::create(path.clone())
             .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
         file.write(buf.as_bytes())
@@ -664,11 +815,11 @@ impl TincOperator {
     fn set_host_up(&self) -> Result<()> {
         let _guard = self.mutex.lock().unwrap();
         #[cfg(windows)]
-            let buf = &(self.tinc_home.to_string() + "/tinc-report.exe -hu ${NODE}");
+            let buf = &(self.path("tinc-report.exe") + " -hu ${NODE}");
         #[cfg(unix)]
-            let buf = "#!/bin/bash\n".to_string() + &self.tinc_home + "/tinc-report -hu ${NODE}";
+            let buf = "#!/bin/bash\n".to_string() + &self.path("tinc-report") + " -hu ${NODE}";
 
-        let path = self.tinc_home.clone() + "/" + HOST_UP_FILENAME;
+        let path = self.path(HOST_UP_FILENAME);
         let mut file = fs::File::create(path.clone())
             .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
         file.write(buf.as_bytes())
@@ -681,11 +832,11 @@ impl TincOperator {
     fn set_host_down(&self) -> Result<()> {
         let _guard = self.mutex.lock().unwrap();
         #[cfg(windows)]
-            let buf = &(self.tinc_home.to_string() + "/tinc-report.exe -hd ${NODE}");
+            let buf = &(self.path("tinc-report.exe") + " -hd ${NODE}");
         #[cfg(unix)]
-            let buf = "#!/bin/bash\n".to_string() + &self.tinc_home + "/tinc-report -hd ${NODE}";
+            let buf = "#!/bin/bash\n".to_string() + &self.path("tinc-report") + " -hd ${NODE}";
 
-        let path = self.tinc_home.clone() + "/" + HOST_DOWN_FILENAME;
+        let path = self.path(HOST_DOWN_FILENAME);
         let mut file = fs::File::create(path.clone())
             .map_err(|e|Error::IoError(path.clone() + " " + &e.to_string()))?;
         file.write(buf.as_bytes())
@@ -696,7 +847,7 @@ impl TincOperator {
     }
 
     fn create_tinc_dirs(&self) -> Result<()> {
-        let path_str = self.tinc_home.clone() + "/hosts";
+        let path_str = self.path("hosts");
         if !std::path::Path::new(&path_str).is_dir() {
             fs::create_dir_all(&path_str)
                 .map_err(|_| Error::IoError("Can't create tinc home dir".to_string()))?;
@@ -706,14 +857,13 @@ impl TincOperator {
 
     /// 获取子设备公钥
     pub fn get_host_pub_key(&self, host_name: &str) -> Result<String> {
+        Ok(self.get_host(host_name)?.keys.to_host_config())
+    }
+
+    /// The host file of `host_name`.
+    pub fn get_host(&self, host_name: &str) -> Result<TincHost> {
         let _guard = self.mutex.lock().unwrap();
-        let file_path = &(self.tinc_home.to_string() + "/hosts/" + host_name);
-        let mut file = fs::File::open(file_path)
-            .map_err(|_| Error::FileNotExist(file_path.to_string()))?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .map_err(|_| Error::FileNotExist(file_path.to_string()))?;
-        Ok(contents)
+        self.read_host(host_name)
     }
 
     // 写TINC_AUTH_PATH/TINC_AUTH_FILENAME(auth/auth.txt),用于tinc reporter C程序
@@ -746,6 +896,44 @@ impl TincOperator {
 
 }
 
+/// The address of a subnet holding only that address, e.g. `10.0.0.1/32#10`.
+fn single_address(subnet: &str) -> Option<IpAddr> {
+    let subnet = subnet.split('#').next()?.trim();
+    let (ip, prefix) = match subnet.find('/') {
+        Some(i) => (&subnet[..i], Some(subnet[i + 1..].parse::<u8>().ok()?)),
+        None => (subnet, None),
+    };
+    let ip = IpAddr::from_str(ip).ok()?;
+    let full_prefix = if ip.is_ipv4() { 32 } else { 128 };
+    if prefix.unwrap_or(full_prefix) == full_prefix {
+        Some(ip)
+    } else {
+        None
+    }
+}
+
+/// Creates or truncates a file only its owner may access, to write a private key to.
+fn create_private_file(path: &str) -> Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options
+            .open(path)
+            .map_err(|e|Error::FileCreateError(path.to_string() + " " + &e.to_string()))?;
+        // The mode only applies to new files.
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(Error::PermissionsError)?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options
+        .open(path)
+        .map_err(|e|Error::FileCreateError(path.to_string() + " " + &e.to_string()))
+}
+
 #[cfg(unix)]
 fn set_script_permissions(path: &str) -> Result<()>{
     use std::{fs, os::unix::fs::PermissionsExt};
@@ -755,29 +943,14 @@ fn set_script_permissions(path: &str) -> Result<()>{
 }
 
 #[cfg(windows)]
-fn get_vnic_index() -> Result<u32> {
+fn get_vnic_index(name: &str) -> Result<u32> {
     let adapters = ipconfig::get_adapters().unwrap();
     for interface in adapters {
-        if interface.friendly_name() == "dnet" {
+        if interface.friendly_name() == name {
             return Ok(interface.ipv6_if_index());
         }
     }
-    Err(Error::VnicNotFind("No Adapter name \"dnet\" find".to_string()))
-}
-
-#[cfg(target_os = "macos")]
-fn get_default_gateway() -> Result<IpAddr> {
-    let cmd = duct::cmd!(
-        "route", "-n", "get", "default"
-    );
-    let res = cmd.read().map_err(|e|Error::GetDefaultGatewayError(e.to_string()))?;
-    let res: Vec<&str> = res.split("gateway: ").collect();
-    if res.len() < 1 {
-        return Err(Error::GetDefaultGatewayError("route -n get default not find gateway:".to_string()));
-    }
-    let res: Vec<&str> = res[1].split("\n").collect();
-    let res = res[0];
-    IpAddr::from_str(res).map_err(|e|Error::GetDefaultGatewayError(e.to_string()))
+    Err(Error::VnicNotFind(format!("No Adapter name \"{}\" find", name)))
 }
 
 #[cfg(target_os = "windows")]
@@ -819,3 +992,17 @@ fn get_default_gateway() -> Result<IpAddr> {
             + "\n" + "route print not find 0.0.0.0 route"))?;
     return Ok(default_gateway);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_address() {
+        assert_eq!(single_address("10.0.0.1/32#10"), Some(IpAddr::from([10, 0, 0, 1])));
+        assert_eq!(single_address("10.0.0.1"), Some(IpAddr::from([10, 0, 0, 1])));
+        assert_eq!(single_address("fd00::1/128"), "fd00::1".parse().ok());
+        assert_eq!(single_address("10.0.0.0/8"), None);
+        assert_eq!(single_address("00:11:22:33:44:55"), None);
+    }
+}
@@ -2,7 +2,11 @@ use std::net::TcpStream;
 use std::io::Write;
 use std::net::SocketAddr;
 use std::fs::File;
-use std::io::{Error, ErrorKind, Result, Read};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Read};
+
+use crate::info::DEFAULT_PORT;
+use crate::dump::{self, Fields, TincConnection, TincEdge, TincGraph, TincNode, TincSubnet,
+                  TincTraffic};
 
 #[repr(i8)]
 pub enum  Request {
@@ -57,17 +61,28 @@ pub enum RequestType {
 
 pub struct TincStream {
     stream: TcpStream,
+    /// Replies are read line by line through this clone of `stream`.
+    reader: BufReader<TcpStream>,
 }
 impl TincStream {
     pub fn new(pid_path: &str) -> Result<Self> {
-        let control_cookie = Self::parse_control_cookie(pid_path)?;
+        let (control_cookie, port) = Self::parse_pid_file(pid_path)?;
         let buf = format!("{} ^{} {}\n", 0, control_cookie, 17);
-        let addr = SocketAddr::from(([127, 0, 0, 1], 50069));
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
         let stream = TcpStream::connect(&addr)?;
-        let mut tinc_stream = TincStream{stream};
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut tinc_stream = TincStream{stream, reader};
         tinc_stream.send_line(buf.as_bytes())?;
-        let _res = tinc_stream.recv()?;
-        return Ok(tinc_stream);
+
+        // tincd answers with its own id line, followed by an ack carrying the control protocol
+        // version and its pid.
+        let id = tinc_stream.recv()?;
+        let ack = tinc_stream.recv()?;
+        if id.split_whitespace().next() == Some("0")
+            && Self::check_res(&ack, Request::Ack as i8, 0) {
+            return Ok(tinc_stream);
+        }
+        return Err(Error::new(ErrorKind::InvalidData, "Control connection refused."));
     }
 
     fn send_line(&mut self, buf: &[u8]) -> Result<()> {
@@ -75,13 +90,19 @@ impl TincStream {
         return Ok(());
     }
 
-    fn parse_control_cookie(path: &str) -> Result<String> {
+    /// tincd writes `<pid> <cookie> <address> port <port>` to its pid file, where the port is
+    /// the one it accepts control connections on.
+    fn parse_pid_file(path: &str) -> Result<(String, u16)> {
         let mut file = File::open(path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        let iter: Vec<&str> = contents.split_whitespace().collect();
-        let control_cookie = iter[1];
-        return Ok(control_cookie.to_string());
+        let mut fields = contents.split_whitespace();
+        let control_cookie = fields.nth(1)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "No control cookie in pid file."))?;
+        let port = fields.nth(2)
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+        return Ok((control_cookie.to_string(), port));
     }
 
     pub fn stop(&mut self) -> Result<()> {
@@ -110,44 +131,27 @@ impl TincStream {
         return Err(Error::new(ErrorKind::InvalidData, "Restart failed."));
     }
 
-    pub fn dump_nodes(&mut self) -> Result<()> {
-        let cmd = format!("{} {}\n", Request::Control as i8, RequestType::ReqDumpNodes as i8);
-        self.send_line(cmd.as_bytes())?;
-        let res = self.recv()?;
-        if Self::check_res(&res, Request::Control as i8, RequestType::ReqDumpNodes as i8) {
-            return Ok(());
-        }
-        return Err(Error::new(ErrorKind::InvalidData, "Dump nodes failed."));
+    pub fn dump_nodes(&mut self) -> Result<Vec<TincNode>> {
+        self.dump(RequestType::ReqDumpNodes, dump::parse_node)
     }
 
-    pub fn dump_subnets(&mut self) -> Result<()> {
-        let cmd = format!("{} {}\n", Request::Control as i8, RequestType::ReqDumpSubnets as i8);
-        self.send_line(cmd.as_bytes())?;
-        let res = self.recv()?;
-        if Self::check_res(&res, Request::Control as i8, RequestType::ReqDumpSubnets as i8) {
-            return Ok(());
-        }
-        return Err(Error::new(ErrorKind::InvalidData, "Dump subnets failed."));
+    pub fn dump_edges(&mut self) -> Result<Vec<TincEdge>> {
+        self.dump(RequestType::ReqDumpEdges, dump::parse_edge)
     }
 
-    pub fn dump_connections(&mut self) -> Result<String> {
-        let cmd = format!("{} {}\n", Request::Control as i8, RequestType::ReqDumpConnections as i8);
-        self.send_line(cmd.as_bytes())?;
-        let res = self.recv()?;
-        if Self::check_res(&res, Request::Control as i8, RequestType::ReqDumpConnections as i8) {
-            return Ok(res);
-        }
-        return Err(Error::new(ErrorKind::InvalidData, "Dump connections failed."));
+    pub fn dump_subnets(&mut self) -> Result<Vec<TincSubnet>> {
+        self.dump(RequestType::ReqDumpSubnets, dump::parse_subnet)
     }
 
-    pub fn dump_graph(&mut self) -> Result<()> {
-        let cmd = format!("{} {}\n", Request::Control as i8, RequestType::ReqDumpGraph as i8);
-        self.send_line(cmd.as_bytes())?;
-        let res = self.recv()?;
-        if Self::check_res(&res, Request::Control as i8, RequestType::ReqDumpGraph as i8) {
-            return Ok(());
-        }
-        return Err(Error::new(ErrorKind::InvalidData, "Dump graph failed."));
+    pub fn dump_connections(&mut self) -> Result<Vec<TincConnection>> {
+        self.dump(RequestType::ReqDumpConnections, dump::parse_connection)
+    }
+
+    /// tincd has no graph request of its own, `tinc dump graph` asks for nodes and edges too.
+    pub fn dump_graph(&mut self) -> Result<TincGraph> {
+        let nodes = self.dump_nodes()?;
+        let edges = self.dump_edges()?;
+        Ok(TincGraph { nodes, edges })
     }
 
     pub fn purge(&mut self) -> Result<()> {
@@ -200,14 +204,9 @@ impl TincStream {
         return Err(Error::new(ErrorKind::InvalidData, "Disconnect failed."));
     }
 
-    pub fn dump_traffic(&mut self) -> Result<()> {
-        let cmd = format!("{} {}\n", Request::Control as i8, RequestType::ReqDumpTraffic as i8);
-        self.send_line(cmd.as_bytes())?;
-        let res = self.recv()?;
-        if Self::check_res(&res, Request::Control as i8, RequestType::ReqDumpTraffic as i8) {
-            return Ok(());
-        }
-        return Err(Error::new(ErrorKind::InvalidData, "Dump traffic failed."));
+    /// Packet and byte counters by node name.
+    pub fn dump_traffic(&mut self) -> Result<Vec<(String, TincTraffic)>> {
+        self.dump(RequestType::ReqDumpTraffic, dump::parse_traffic)
     }
 
     pub fn pcap(&mut self) -> Result<()> {
@@ -230,26 +229,37 @@ impl TincStream {
         return Err(Error::new(ErrorKind::InvalidData, "Log failed."));
     }
 
+    /// Sends a dump request and parses the reply lines until the one ending the dump.
+    fn dump<T>(
+        &mut self,
+        req_type: RequestType,
+        parse: fn(Fields<'_>) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let req_type = req_type as i8;
+        let cmd = format!("{} {}\n", Request::Control as i8, req_type);
+        self.send_line(cmd.as_bytes())?;
+        let mut records = vec![];
+        loop {
+            let line = self.recv()?;
+            match dump::reply_fields(&line, req_type)? {
+                Some(fields) => records.push(parse(fields)?),
+                None => return Ok(records),
+            }
+        }
+    }
+
     fn recv(&mut self) -> Result<String> {
-        let res = &mut[0; 64];
-        let _len = self.stream.read(res)?;
-        Ok(String::from_utf8_lossy(res).to_string())
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Control connection closed."));
+        }
+        Ok(line)
     }
 
     fn check_res(res: &str, req: i8, req_type: i8) -> bool{
-        let iter: Vec<&str> = res.split_whitespace().collect();
-        let control: i8 = match iter[0].parse() {
-            Ok(x) => x,
-            _ => -1,
-        };
-
-        let control_type: i8 = match iter[1].parse() {
-            Ok(x) => x,
-            _ => -1,
-        };
-        if control == req && control_type == req_type {
-            return true;
-        }
-        false
+        let mut iter = res.split_whitespace().map(|field| field.parse::<i8>().ok());
+        let control = iter.next().and_then(|x| x);
+        let control_type = iter.next().and_then(|x| x);
+        control == Some(req) && control_type == Some(req_type)
     }
-}
\ No newline at end of file
+}